@@ -0,0 +1,4 @@
+fn main() {
+    // The on-target tests log over defmt, which brings its own linker script.
+    println!("cargo:rustc-link-arg-tests=-Tdefmt.x");
+}
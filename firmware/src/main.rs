@@ -0,0 +1,284 @@
+#![no_main]
+#![no_std]
+
+use cortex_m_semihosting::hprint;
+use embedded_hal_bus::spi::ExclusiveDevice;
+
+#[cfg(not(debug_assertions))]
+use panic_halt as _;
+
+#[cfg(debug_assertions)]
+use panic_semihosting as _;
+
+use stm32f4xx_hal::{
+    self as hal,
+    hal::{
+        delay::DelayNs,
+        digital::{InputPin, OutputPin},
+        spi::SpiDevice,
+    },
+};
+
+use crate::hal::{
+    flash::FlashExt,
+    gpio::{Edge, Input, PA1},
+    interrupt, pac,
+    prelude::*,
+    rcc::Clocks,
+    spi,
+};
+use cortex_m_rt::entry;
+
+use router::bringup::{self, SpiLimits, SpiTuner};
+use router::enc28j60::{self, Enc28j60, Interrupts};
+use router::profile;
+use router::profiling::{self, Stage};
+use router::reset::{ResetButton, ResetConfig, ResetState};
+use router::sync::{InterruptFlag, Shared};
+use router::sysinfo::{self, UniqueId};
+
+/// Flash sector holding the stored configuration, right after the 256 KiB of firmware.
+const CONFIG_SECTOR: u8 = 6;
+
+/// Start of the flash memory, where the image is linked.
+const FLASH_START: usize = 0x0800_0000;
+
+/// SPI clock for this board's wiring to the ENC28J60, stepped down at boot if unreliable.
+/// The default clocks run SPI1 from a 16 MHz APB2, halved at most.
+const SPI_FREQUENCY_HZ: u32 = 8_000_000;
+
+/// The ENC28J60's INT pin, kept to clear its EXTI pending bit.
+static ENC28J60_INT: Shared<Option<PA1<Input>>> = Shared::new(None);
+
+/// INT fell since the main loop last looked.
+static ENC28J60_INTERRUPTED: InterruptFlag = InterruptFlag::new();
+
+const SPI_MODE: spi::Mode = spi::Mode {
+    polarity: spi::Polarity::IdleLow,
+    phase: spi::Phase::CaptureOnFirstTransition,
+};
+
+#[entry]
+fn main() -> ! {
+    let mut p = pac::Peripherals::take().unwrap();
+    #[allow(unused_mut)]
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    #[cfg(feature = "profiling")]
+    profiling::enable(&mut cp.DCB, &mut cp.DWT);
+
+    hprint!("{}", sysinfo::Banner);
+    hprint!(
+        "Device ID {}, flash {} of {} bytes",
+        unique_id(),
+        image_size(),
+        hal::signature::FlashSize::get().bytes()
+    );
+
+    let gpioa = p.GPIOA.split();
+    let gpiod = p.GPIOD.split();
+
+    let mut spi_nss = gpioa.pa4.into_push_pull_output();
+    spi_nss.set_high();
+
+    let spi_sck = gpioa.pa5;
+    let spi_miso = gpioa.pa6;
+    let spi_mosi = gpioa.pa7;
+    let rcc = p.RCC.constrain().cfgr.freeze();
+
+    // User button and green LED of the STM32F4DISCOVERY.
+    let reset_button = ResetButton::new(
+        gpioa.pa0.into_input(),
+        gpiod.pd12.into_push_pull_output(),
+        ResetConfig::default(),
+    );
+    check_factory_reset(reset_button, cp.SYST.delay(&rcc), p.FLASH);
+
+    let mut enc28j60 = profile::active::Enc28j60::with_erx_length((0x1f0u16).try_into().unwrap());
+
+    let spi = spi::Spi::new(
+        p.SPI1,
+        (spi_sck, spi_miso, spi_mosi),
+        SPI_MODE,
+        SPI_FREQUENCY_HZ.Hz(),
+        &rcc,
+    );
+    let spi = tune_spi(spi, &mut spi_nss, &rcc);
+
+    let mut spi_device = ExclusiveDevice::new_no_delay(spi, spi_nss).unwrap();
+
+    // INT is active low, see Enc28j60::enable_interrupts.
+    let mut syscfg = p.SYSCFG.constrain();
+    let mut int = gpioa.pa1.into_input();
+    int.make_interrupt_source(&mut syscfg);
+    int.trigger_on_edge(&mut p.EXTI, Edge::Falling);
+    int.enable_interrupt(&mut p.EXTI);
+    let int_interrupt = int.interrupt();
+    ENC28J60_INT.lock(|pin| *pin = Some(int));
+    // SAFETY: the handler only touches the pin and the flag, both shared safely.
+    unsafe { cortex_m::peripheral::NVIC::unmask(int_interrupt) };
+
+    enc28j60.init().unwrap();
+
+    run_pending_transactions(&mut enc28j60, &mut spi_device);
+
+    enc28j60.read_register(enc28j60::Register::EREVID).unwrap();
+
+    run_pending_transactions(&mut enc28j60, &mut spi_device);
+
+    enc28j60.poll_link().unwrap();
+    run_pending_transactions(&mut enc28j60, &mut spi_device);
+    report_link(&mut enc28j60);
+
+    enc28j60.enable_interrupts(Interrupts::ALL).unwrap();
+    run_pending_transactions(&mut enc28j60, &mut spi_device);
+
+    loop {
+        if ENC28J60_INTERRUPTED.take() {
+            enc28j60.on_interrupt().unwrap();
+            run_pending_transactions(&mut enc28j60, &mut spi_device);
+
+            let interrupts = enc28j60.take_interrupts();
+            if interrupts.tx_error || interrupts.rx_error {
+                hprint!("ENC28J60 {:?}, {:?}", interrupts, enc28j60.rx_drop_stats());
+            }
+            report_link(&mut enc28j60);
+            if let Some((header, frame)) = enc28j60.received() {
+                hprint!(
+                    "Received {} bytes, next at {}",
+                    frame.len(),
+                    header.next_packet
+                );
+                enc28j60.release_received();
+            }
+        }
+
+        // Checked with interrupts masked, an interrupt in between still ends the WFI.
+        cortex_m::interrupt::free(|_| {
+            if !ENC28J60_INTERRUPTED.is_raised() {
+                cortex_m::asm::wfi();
+            }
+        });
+    }
+}
+
+#[interrupt]
+fn EXTI1() {
+    ENC28J60_INT.lock(|int| {
+        if let Some(int) = int.as_mut() {
+            int.clear_interrupt_pending_bit();
+        }
+    });
+    ENC28J60_INTERRUPTED.raise();
+}
+
+fn report_link<const N: usize, const M: usize, const B: usize>(enc28j60: &mut Enc28j60<N, M, B>) {
+    if let Some(up) = enc28j60.take_link_change() {
+        hprint!("Link {}", if up { "up" } else { "down" });
+    }
+}
+
+/// Reads the MCU's unique device ID from its system memory.
+fn unique_id() -> UniqueId {
+    let uid = hal::signature::Uid::get();
+    let mut id = [0; 12];
+    id[..2].copy_from_slice(&uid.x().to_le_bytes());
+    id[2..4].copy_from_slice(&uid.y().to_le_bytes());
+    id[4] = uid.waf_num();
+    id[5..].copy_from_slice(uid.lot_num().as_bytes());
+    UniqueId(id)
+}
+
+/// Bytes of flash taken by the image: code and read-only data, followed by the initial
+/// values of `.data`, as laid out by cortex-m-rt's linker script.
+fn image_size() -> usize {
+    unsafe extern "C" {
+        static __sidata: u8;
+        static __sdata: u8;
+        static __edata: u8;
+    }
+
+    let data_len = (&raw const __edata) as usize - (&raw const __sdata) as usize;
+    (&raw const __sidata) as usize + data_len - FLASH_START
+}
+
+/// Steps the SPI clock down until register round-trips with the ENC28J60 are reliable.
+fn tune_spi(
+    mut spi: spi::Spi<pac::SPI1>,
+    nss: &mut impl OutputPin,
+    clocks: &Clocks,
+) -> spi::Spi<pac::SPI1> {
+    let mut tuner = SpiTuner::new(SPI_FREQUENCY_HZ);
+    let mut limits_applied = false;
+    loop {
+        let mut device = ExclusiveDevice::new_no_delay(&mut spi, &mut *nss).unwrap();
+        let mut reliable = bringup::check_round_trips(&mut device).unwrap_or(false);
+        if reliable && !limits_applied {
+            let revision = bringup::read_revision(&mut device).unwrap();
+            limits_applied = true;
+            reliable = tuner.apply_limits(SpiLimits::for_revision(revision));
+            hprint!("ENC28J60 revision {}", revision);
+        }
+
+        if reliable {
+            hprint!("SPI clock {} Hz", tuner.frequency());
+            return spi;
+        }
+
+        let Some(frequency) = tuner.step_down() else {
+            panic!("No reliable SPI clock within the ENC28J60 limits");
+        };
+        let (spi1, pins) = spi.release();
+        spi = spi::Spi::new(spi1, pins, SPI_MODE, frequency.Hz(), clocks);
+    }
+}
+
+/// Waits while the reset button is held, erasing the configuration and rebooting if it is held
+/// long enough.
+fn check_factory_reset<B: InputPin, L: OutputPin>(
+    mut button: ResetButton<B, L>,
+    mut delay: impl DelayNs,
+    mut flash: pac::FLASH,
+) {
+    const POLL_INTERVAL_MS: u32 = 10;
+
+    let mut now = 0u32;
+    loop {
+        match button.poll(now) {
+            ResetState::Released => return,
+            ResetState::Holding => {
+                delay.delay_ms(POLL_INTERVAL_MS);
+                now = now.wrapping_add(POLL_INTERVAL_MS);
+            }
+            ResetState::Confirmed => {
+                hprint!("Factory reset, erasing configuration");
+                flash.unlocked().erase(CONFIG_SECTOR).unwrap();
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+}
+
+fn run_pending_transactions<const N: usize, const M: usize, const B: usize>(
+    enc28j60: &mut Enc28j60<N, M, B>,
+    spi_device: &mut impl SpiDevice,
+) {
+    while let Some(mut transaction) = enc28j60.poll_pending_transaction() {
+        profiling::measure(Stage::SpiTransfer, || {
+            let mut spi_transaction =
+                heapless::Vec::<_, 3>::from_iter(transaction.spi_operations());
+            spi_device
+                .transaction(spi_transaction.as_mut_slice())
+                .unwrap();
+        });
+
+        hprint!("{:?}", transaction);
+        let handled = profiling::measure(Stage::HandleTransaction, || {
+            enc28j60.handle_transaction(transaction)
+        });
+        if let Err(violation) = handled {
+            // The chip stays unready and keeps being polled, a glitched read isn't fatal.
+            hprint!("ENC28J60: {}", violation);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Tests against the ENC28J60 wired to the board as for the firmware, run with
+//! `cargo test-target`. probe-rs flashes them and reports the results over RTT.
+
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+use router::enc28j60::{OperationKind, Transaction};
+
+/// Value read by a transaction made of a control register read, the RCR opcode followed by
+/// the read.
+fn register_value<const N: usize, const B: usize>(transaction: &Transaction<N, B>) -> Option<u8> {
+    let mut parts = transaction.iter();
+    match (parts.next(), parts.next()) {
+        (Some((OperationKind::Write, &[opcode])), Some((OperationKind::Read, read)))
+            if opcode >> 5 == 0 =>
+        {
+            read.last().copied()
+        }
+        _ => None,
+    }
+}
+
+#[defmt_test::tests]
+mod tests {
+    use cortex_m::peripheral::DWT;
+    use embedded_hal::{delay::DelayNs, spi::SpiDevice};
+    use embedded_hal_bus::spi::{ExclusiveDevice, NoDelay};
+    use router::{
+        bringup,
+        enc28j60::{PhyRegister, Register, RxFilterConfig},
+        ethernet::MacAddress,
+        profile,
+    };
+    use stm32f4xx_hal::{
+        gpio::{Output, PA4},
+        pac,
+        prelude::*,
+        spi::{self, Spi},
+        timer::SysDelay,
+    };
+
+    /// Locally administered, so it can't clash with anything on the link.
+    const MAC: MacAddress = MacAddress([0x02, 0x00, 0x5E, 0x10, 0x20, 0x30]);
+
+    /// Tries of the receive path while waiting for the looped back frame, a millisecond apart.
+    const LOOPBACK_TRIES: u32 = 100;
+
+    /// Length of the SysTick delay timed against the cycle counter.
+    const TIMER_CHECK_MS: u32 = 100;
+
+    // PHCON1 bits.
+    const PHCON1_PLOOPBK: u16 = 1 << 14;
+    const PHCON1_PDPXMD: u16 = 1 << 8;
+
+    struct Board {
+        enc28j60: profile::active::Enc28j60,
+        spi: ExclusiveDevice<Spi<pac::SPI1>, PA4<Output>, NoDelay>,
+        /// Core clock, counted by the DWT cycle counter.
+        sysclk_hz: u32,
+        /// The SysTick delay the firmware times with.
+        delay: SysDelay,
+    }
+
+    impl Board {
+        /// Runs the queued transactions, returning the values of the registers read.
+        fn run(&mut self) -> heapless::Vec<u8, 16> {
+            let mut values = heapless::Vec::new();
+            while let Some(mut transaction) = self.enc28j60.poll_pending_transaction() {
+                {
+                    let mut operations =
+                        heapless::Vec::<_, 3>::from_iter(transaction.spi_operations());
+                    self.spi.transaction(&mut operations).unwrap();
+                }
+
+                if let Some(value) = super::register_value(&transaction) {
+                    values.push(value).unwrap();
+                }
+                self.enc28j60.handle_transaction(transaction).unwrap();
+            }
+            values
+        }
+    }
+
+    #[init]
+    fn init() -> Board {
+        let p = pac::Peripherals::take().unwrap();
+        let mut cp = cortex_m::Peripherals::take().unwrap();
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+        let gpioa = p.GPIOA.split();
+        let mut nss = gpioa.pa4.into_push_pull_output();
+        nss.set_high();
+
+        let rcc = p.RCC.constrain().cfgr.freeze();
+        let mode = spi::Mode {
+            polarity: spi::Polarity::IdleLow,
+            phase: spi::Phase::CaptureOnFirstTransition,
+        };
+        // The slowest clock all silicon revisions are reliable at, see bringup.
+        let spi = Spi::new(
+            p.SPI1,
+            (gpioa.pa5, gpioa.pa6, gpioa.pa7),
+            mode,
+            bringup::ERRATA_MIN_SPI_HZ.Hz(),
+            &rcc,
+        );
+
+        let mut board = Board {
+            enc28j60: profile::active::Enc28j60::with_erx_length(0x1f0u16.try_into().unwrap()),
+            spi: ExclusiveDevice::new_no_delay(spi, nss).unwrap(),
+            sysclk_hz: rcc.sysclk().raw(),
+            delay: cp.SYST.delay(&rcc),
+        };
+        board.enc28j60.init().unwrap();
+        board.run();
+        board
+    }
+
+    #[test]
+    fn raw_register_round_trips(board: &mut Board) {
+        assert!(bringup::check_round_trips(&mut board.spi).unwrap());
+        // The round-trips switched banks behind the driver's back.
+        board.enc28j60.reselect_bank().unwrap();
+        board.run();
+    }
+
+    #[test]
+    fn systick_delay_matches_cycle_count(board: &mut Board) {
+        let start = DWT::cycle_count();
+        board.delay.delay_ms(TIMER_CHECK_MS);
+        let elapsed = DWT::cycle_count().wrapping_sub(start);
+
+        // Within 1%, the delay's own overhead being a few cycles.
+        let expected = board.sysclk_hz / 1000 * TIMER_CHECK_MS;
+        assert!(elapsed.abs_diff(expected) <= expected / 100);
+    }
+
+    #[test]
+    fn driver_register_round_trips(board: &mut Board) {
+        board
+            .enc28j60
+            .set_rx_filters(RxFilterConfig::new(MAC))
+            .unwrap();
+        board.run();
+
+        // MAC registers, read back after their dummy byte.
+        for register in [
+            Register::MAADR1,
+            Register::MAADR2,
+            Register::MAADR3,
+            Register::MAADR4,
+            Register::MAADR5,
+            Register::MAADR6,
+        ] {
+            board.enc28j60.read_register(register).unwrap();
+        }
+        assert_eq!(board.run().as_slice(), &MAC.0);
+
+        board.enc28j60.read_register(Register::ERXFCON).unwrap();
+        assert_eq!(
+            board.run().as_slice(),
+            &[RxFilterConfig::new(MAC).filter().erxfcon]
+        );
+    }
+
+    #[test]
+    fn loopback_frame(board: &mut Board) {
+        board
+            .enc28j60
+            .set_rx_filters(RxFilterConfig::new(MAC))
+            .unwrap();
+        board
+            .enc28j60
+            .write_phy(PhyRegister::PHCON1, PHCON1_PDPXMD | PHCON1_PLOOPBK)
+            .unwrap();
+        board.run();
+
+        let mut frame = [0; 64];
+        frame[..6].copy_from_slice(&MAC.0);
+        frame[6..12].copy_from_slice(&MAC.0);
+        // Local experimental EtherType.
+        frame[12..14].copy_from_slice(&0x88B5u16.to_be_bytes());
+        for (byte, value) in frame[14..].iter_mut().zip(0u8..) {
+            *byte = value;
+        }
+        board.enc28j60.transmit(&frame).unwrap();
+        board.run();
+
+        let mut received = [0; 64];
+        let mut len = None;
+        for _ in 0..LOOPBACK_TRIES {
+            board.enc28j60.receive().unwrap();
+            board.run();
+            if let Some((_, received_len)) = board.enc28j60.take_received(&mut received) {
+                len = Some(received_len);
+                break;
+            }
+            board.delay.delay_ms(1);
+        }
+
+        board.enc28j60.set_phy_power_down(false).unwrap();
+        board.run();
+
+        assert_eq!(len, Some(frame.len()));
+        assert_eq!(received, frame);
+    }
+}
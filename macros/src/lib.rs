@@ -1,77 +1,235 @@
 use proc_macro::TokenStream;
-use proc_macro2::Span;
 use quote::{format_ident, quote};
-use syn::{Error, Ident, LitInt, Result, Token, Visibility, parse::Parse, parse_macro_input};
+use syn::{
+    Attribute, Error, Ident, LitInt, Result, Token, bracketed,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+};
 
-struct Input {
-    vis: Visibility,
+/// First address shared by all banks, the registers from there on are in every bank.
+const COMMON_START: u8 = 0x1B;
+/// Last valid address of a control register.
+const LAST_ADDRESS: u8 = 0x1F;
+const BANKS: [&str; 4] = ["Bank0", "Bank1", "Bank2", "Bank3"];
+
+struct Entry {
+    attrs: Vec<Attribute>,
     name: Ident,
-    bits: LitInt,
+    /// `None` for registers in every bank.
+    bank: Option<Ident>,
+    address: u8,
+    address_lit: LitInt,
+    word: bool,
+    mac: bool,
 }
 
-impl Parse for Input {
-    fn parse(input: syn::parse::ParseStream) -> Result<Self> {
-        let vis = input.parse()?;
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
         let name: Ident = input.parse()?;
+        let _colon: Token![:] = input.parse()?;
+        let bank: Ident = input.parse()?;
+        let bank = match bank.to_string().as_str() {
+            "Common" => None,
+            name if BANKS.contains(&name) => Some(bank),
+            _ => {
+                return Err(Error::new(
+                    bank.span(),
+                    "bank must be one of Bank0 to Bank3, or Common",
+                ));
+            }
+        };
+
+        let content;
+        bracketed!(content in input);
+        let address_lit: LitInt = content.parse()?;
+        let address: u8 = address_lit.base10_parse()?;
+
+        let mut word = false;
+        let mut mac = false;
+        while !input.peek(Token![;]) {
+            let flag: Ident = input.parse()?;
+            match flag.to_string().as_str() {
+                "word" if !word => word = true,
+                "mac" if !mac => mac = true,
+                _ => {
+                    return Err(Error::new(
+                        flag.span(),
+                        "expected `word` or `mac`, once each",
+                    ));
+                }
+            }
+        }
+        let _semicolon: Token![;] = input.parse()?;
+
+        Ok(Self {
+            attrs,
+            name,
+            bank,
+            address,
+            address_lit,
+            word,
+            mac,
+        })
+    }
+}
+
+impl Entry {
+    /// Addresses taken, the low then the high byte for words.
+    fn addresses(&self) -> impl Iterator<Item = u8> {
+        let len = if self.word { 2 } else { 1 };
+        (self.address..).take(len)
+    }
+
+    fn check(&self) -> Result<()> {
+        let error = |message| Err(Error::new(self.address_lit.span(), message));
+        let last = self.addresses().last().unwrap_or(self.address);
+        match self.bank {
+            _ if last > LAST_ADDRESS => error("address past 0x1F"),
+            None if self.word => error("registers in every bank are single bytes"),
+            None if self.address < COMMON_START => error("only 0x1B to 0x1F are in every bank"),
+            Some(_) if last >= COMMON_START => error("0x1B to 0x1F are in every bank, use Common"),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Input {
+    register: Ident,
+    word_register: Ident,
+    entries: Vec<Entry>,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let register = input.parse()?;
         let _comma: Token![,] = input.parse()?;
-        let bits: LitInt = input.parse()?;
-        Ok(Self { vis, name, bits })
+        let word_register = input.parse()?;
+        let _semicolon: Token![;] = input.parse()?;
+        let mut entries = Vec::new();
+        while !input.is_empty() {
+            entries.push(input.parse()?);
+        }
+        Ok(Self {
+            register,
+            word_register,
+            entries,
+        })
     }
 }
 
-/// Creates an enum that reprsent all valid numbers for the number of bits in the input
+/// Generates the ENC28J60's named control registers as constants of a register type, each
+/// knowing its bank and whether it's a MAC or MII register, which reads back after a dummy
+/// byte. 16-bit pairs become a constant of a word register type, and one of the register type
+/// per byte suffixed with `L` and `H`.
+///
+/// ```ignore
+/// register_map! {
+///     Register, WordRegister;
+///     ERDPT: Bank0[0x00] word;
+///     MACON1: Bank2[0x00] mac;
+///     EIE: Common[0x1B];
+/// }
+/// ```
+///
+/// Both types must have the fields `bank: Option<Bank>`, `address: u8` and `mac: bool` for the
+/// register and `low` for the word. Registers overlapping each other, out of their bank's
+/// address range, or in the wrong bank for the shared addresses fail to compile.
 #[proc_macro]
-pub fn make_enum(input: TokenStream) -> TokenStream {
-    let Input { vis, name, bits } = parse_macro_input!(input as Input);
-
-    let bits_val: u32 = match bits.base10_parse() {
-        Ok(v) if v > 0 && v <= 128 => v,
-        Err(e) => return Error::new(bits.span(), e).to_compile_error().into(),
-        _ => {
-            return Error::new(bits.span(), "bits must be between 1 and 128")
-                .to_compile_error()
-                .into();
+pub fn register_map(input: TokenStream) -> TokenStream {
+    let Input {
+        register,
+        word_register,
+        entries,
+    } = parse_macro_input!(input as Input);
+
+    let mut errors = Vec::new();
+    let mut taken: Vec<(Option<String>, u8, &Ident)> = Vec::new();
+    for entry in &entries {
+        if let Err(error) = entry.check() {
+            errors.push(error);
+            continue;
         }
-    };
 
-    let max_value = 1u128
-        .checked_shl(bits_val)
-        .map(|v| v - 1)
-        .unwrap_or(u128::MAX);
-
-    let repr_ty = match max_value {
-        ..256 => quote!(u8),
-        ..65_536 => quote!(u16),
-        ..4_294_967_296 => quote!(u32),
-        ..18_446_744_073_709_551_615 => quote!(u64),
-        _ => quote!(u128),
-    };
+        let bank = entry.bank.as_ref().map(Ident::to_string);
+        for address in entry.addresses() {
+            if let Some((_, _, other)) = taken.iter().find(|(other_bank, other_address, _)| {
+                *other_bank == bank && *other_address == address
+            }) {
+                errors.push(Error::new(
+                    entry.name.span(),
+                    format!("{} overlaps {other} at {address:#04X}", entry.name),
+                ));
+            }
+            taken.push((bank.clone(), address, &entry.name));
+        }
+    }
+    if let Some(error) = errors.into_iter().reduce(|mut all, error| {
+        all.combine(error);
+        all
+    }) {
+        return error.to_compile_error().into();
+    }
 
-    let variants = (0..=max_value).map(|i| {
-        let ident = format_ident!("r{:02X}", i);
-        let val = syn::LitInt::new(&i.to_string(), Span::call_site());
-        quote!( #ident = #val, )
-    });
+    let mut registers = Vec::new();
+    let mut words = Vec::new();
+    for entry in &entries {
+        let Entry {
+            attrs,
+            name,
+            bank,
+            mac,
+            ..
+        } = entry;
+        let bank = match bank {
+            Some(bank) => quote!(Some(Bank::#bank)),
+            None => quote!(None),
+        };
+        let constant = |name: &Ident, address: u8, attrs: &[Attribute]| {
+            quote! {
+                #(#attrs)*
+                pub const #name: #register = #register {
+                    bank: #bank,
+                    address: #address,
+                    mac: #mac,
+                };
+            }
+        };
 
-    let next_arms = (0..=max_value).map(|i| {
-        let cur_ident = format_ident!("r{:02X}", i);
-        let next_ident = format_ident!("r{:02X}", if i == max_value { 0 } else { i + 1 });
-        quote!( Self::#cur_ident => Self::#next_ident, )
-    });
+        if !entry.word {
+            registers.push(constant(name, entry.address, attrs));
+            continue;
+        }
+
+        let low = format_ident!("{name}L");
+        let high = format_ident!("{name}H");
+        let low_doc = format!("Low byte of [`{word_register}::{name}`].");
+        let high_doc = format!("High byte of [`{word_register}::{name}`].");
+        registers.push(constant(
+            &low,
+            entry.address,
+            &[syn::parse_quote!(#[doc = #low_doc])],
+        ));
+        registers.push(constant(
+            &high,
+            entry.address + 1,
+            &[syn::parse_quote!(#[doc = #high_doc])],
+        ));
+        words.push(quote! {
+            #(#attrs)*
+            pub const #name: #word_register = #word_register {
+                low: #register::#low,
+            };
+        });
+    }
 
     let expanded = quote! {
-        #[repr(#repr_ty)]
-        #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
-        #vis enum #name {
-            #(#variants)*
+        impl #register {
+            #(#registers)*
         }
 
-        impl #name {
-            pub fn next(&self) -> Self {
-                match self {
-                    #(#next_arms)*
-                }
-            }
+        impl #word_register {
+            #(#words)*
         }
     };
 
@@ -0,0 +1,220 @@
+//! Announcements of an interface's addresses to its neighbors.
+//!
+//! When an address is configured or changes, neighbors still map it to whatever they last
+//! resolved, possibly another host or the router's previous MAC. Announcing it, with a
+//! gratuitous ARP for IPv4 (RFC 5227) or an unsolicited neighbor advertisement for IPv6
+//! (RFC 4861 section 7.2.6), updates their caches right away instead of once they time out.
+//! Announcements are repeated a few times, a single one is easily lost.
+//!
+//! The default timings assume one tick per second.
+
+use core::net::{IpAddr, Ipv6Addr};
+
+use crate::{
+    arp::ArpPacket,
+    checksum, clock,
+    ethernet::{MacAddress, ethertype},
+    interface::InterfaceId,
+};
+
+/// Length of an unsolicited neighbor advertisement: IPv6 header, ICMPv6 message and the target
+/// link-layer address option.
+pub const NA_LEN: usize = 40 + 24 + 8;
+
+const ICMPV6: u8 = 58;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+/// Router and override flags, unsolicited ones aren't solicited.
+const NA_FLAGS: u8 = 0b1010_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnnounceConfig {
+    /// Gratuitous ARPs sent per change and the ticks between them, RFC 5227's ANNOUNCE_NUM
+    /// and ANNOUNCE_INTERVAL.
+    pub arp_count: u8,
+    pub arp_interval: u32,
+    /// Same for neighbor advertisements, RFC 4861's MAX_NEIGHBOR_ADVERTISEMENT and
+    /// RETRANS_TIMER.
+    pub na_count: u8,
+    pub na_interval: u32,
+}
+
+impl Default for AnnounceConfig {
+    fn default() -> Self {
+        Self {
+            arp_count: 2,
+            arp_interval: 2,
+            na_count: 3,
+            na_interval: 1,
+        }
+    }
+}
+
+/// Neighbor advertisement sent to all nodes, overriding their cache entries for `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborAdvertisement {
+    pub mac: MacAddress,
+    pub address: Ipv6Addr,
+}
+
+impl NeighborAdvertisement {
+    /// Multicast MAC of the all-nodes address, ff02::1.
+    pub const DESTINATION: MacAddress = MacAddress([0x33, 0x33, 0, 0, 0, 1]);
+
+    /// Writes the IPv6 packet, returning its length or `None` if `buffer` is too small.
+    pub fn write(&self, buffer: &mut [u8]) -> Option<usize> {
+        let packet = buffer.get_mut(..NA_LEN)?;
+        let destination = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        let icmp_len = (NA_LEN - 40) as u16;
+
+        let (header, icmp) = packet.split_at_mut(40);
+        header[..4].copy_from_slice(&[0x60, 0, 0, 0]);
+        header[4..6].copy_from_slice(&icmp_len.to_be_bytes());
+        header[6] = ICMPV6;
+        // Receivers discard neighbor discovery messages that were forwarded.
+        header[7] = 255;
+        header[8..24].copy_from_slice(&self.address.octets());
+        header[24..40].copy_from_slice(&destination.octets());
+
+        icmp[..8].copy_from_slice(&[NEIGHBOR_ADVERTISEMENT, 0, 0, 0, NA_FLAGS, 0, 0, 0]);
+        icmp[8..24].copy_from_slice(&self.address.octets());
+        icmp[24..26].copy_from_slice(&[TARGET_LINK_LAYER_ADDRESS, 1]);
+        icmp[26..32].copy_from_slice(&self.mac.0);
+
+        // Checksummed along with a pseudo-header of the addresses, length and next header, as
+        // long as the IPv6 header.
+        let mut pseudo = [0; NA_LEN];
+        pseudo[..32].copy_from_slice(&header[8..40]);
+        pseudo[34..36].copy_from_slice(&icmp_len.to_be_bytes());
+        pseudo[39] = ICMPV6;
+        pseudo[40..].copy_from_slice(icmp);
+        icmp[2..4].copy_from_slice(&checksum::checksum(&pseudo).to_be_bytes());
+
+        Some(NA_LEN)
+    }
+}
+
+/// A packet announcing an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Announcement {
+    Arp(ArpPacket),
+    NeighborAdvertisement(NeighborAdvertisement),
+}
+
+impl Announcement {
+    pub fn destination(&self) -> MacAddress {
+        match self {
+            Announcement::Arp(_) => MacAddress::BROADCAST,
+            Announcement::NeighborAdvertisement(_) => NeighborAdvertisement::DESTINATION,
+        }
+    }
+
+    pub fn ethertype(&self) -> u16 {
+        match self {
+            Announcement::Arp(_) => ethertype::ARP,
+            Announcement::NeighborAdvertisement(_) => ethertype::IPV6,
+        }
+    }
+
+    /// Writes the frame's payload, returning its length or `None` if `buffer` is too small.
+    pub fn write(&self, buffer: &mut [u8]) -> Option<usize> {
+        match self {
+            Announcement::Arp(packet) => packet.write(buffer),
+            Announcement::NeighborAdvertisement(packet) => packet.write(buffer),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scheduled {
+    interface: InterfaceId,
+    mac: MacAddress,
+    address: IpAddr,
+    remaining: u8,
+    next_at: u32,
+}
+
+/// Schedule of the repeated announcements of up to `N` addresses.
+pub struct Announcer<const N: usize> {
+    config: AnnounceConfig,
+    scheduled: heapless::Vec<Scheduled, N>,
+}
+
+impl<const N: usize> Announcer<N> {
+    pub fn new(config: AnnounceConfig) -> Self {
+        Self {
+            config,
+            scheduled: heapless::Vec::new(),
+        }
+    }
+
+    /// Starts announcing `address` as being at `mac` on `interface`, the first announcement
+    /// being due right away. Call it whenever the address or the interface's MAC changes.
+    ///
+    /// When `N` addresses are already being announced, the one closest to done is cut short.
+    pub fn announce(&mut self, interface: InterfaceId, mac: MacAddress, address: IpAddr, now: u32) {
+        self.withdraw(interface, address);
+
+        let remaining = match address {
+            IpAddr::V4(_) => self.config.arp_count,
+            IpAddr::V6(_) => self.config.na_count,
+        };
+        if remaining == 0 {
+            return;
+        }
+
+        if self.scheduled.is_full()
+            && let Some(closest) = self
+                .scheduled
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, scheduled)| scheduled.remaining)
+                .map(|(i, _)| i)
+        {
+            self.scheduled.swap_remove(closest);
+        }
+
+        let _ = self.scheduled.push(Scheduled {
+            interface,
+            mac,
+            address,
+            remaining,
+            next_at: now,
+        });
+    }
+
+    /// Stops announcing `address` on `interface`, once it's no longer configured.
+    pub fn withdraw(&mut self, interface: InterfaceId, address: IpAddr) {
+        self.scheduled
+            .retain(|scheduled| scheduled.interface != interface || scheduled.address != address);
+    }
+
+    /// Announcement due at `now` with the interface to send it on, if any.
+    pub fn poll(&mut self, now: u32) -> Option<(InterfaceId, Announcement)> {
+        let index = self
+            .scheduled
+            .iter()
+            .position(|scheduled| clock::is_due(scheduled.next_at, now))?;
+
+        let scheduled = &mut self.scheduled[index];
+        let (interface, mac) = (scheduled.interface, scheduled.mac);
+        let (announcement, interval) = match scheduled.address {
+            IpAddr::V4(address) => (
+                Announcement::Arp(ArpPacket::announcement(mac, address)),
+                self.config.arp_interval,
+            ),
+            IpAddr::V6(address) => (
+                Announcement::NeighborAdvertisement(NeighborAdvertisement { mac, address }),
+                self.config.na_interval,
+            ),
+        };
+
+        scheduled.remaining -= 1;
+        scheduled.next_at = now.wrapping_add(interval);
+        if scheduled.remaining == 0 {
+            self.scheduled.swap_remove(index);
+        }
+
+        Some((interface, announcement))
+    }
+}
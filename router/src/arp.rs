@@ -0,0 +1,564 @@
+//! ARP packets and cache.
+//!
+//! Maps next-hop IPv4 addresses to MAC addresses, aging entries out and holding on to a few
+//! packets per next-hop while it's being resolved so they can be sent once the reply arrives,
+//! instead of dropping them (and making the first ping to every host fail).
+//!
+//! Static entries pin critical hosts, like the upstream gateway, to their MAC: they never age
+//! out nor get evicted, and ARP replies can't change them.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::ethernet::{MacAddress, ethertype};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ArpError {
+    #[error("ARP cache is full of static entries.")]
+    TableFull,
+}
+
+/// Length of an ARP packet for IPv4 over Ethernet.
+pub const PACKET_LEN: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Operation {
+    Request = 1,
+    Reply = 2,
+}
+
+/// ARP packet for IPv4 over Ethernet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: Operation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Probe asking whether `address` is in use (RFC 5227), the sender address is left zero so
+    /// other hosts' caches aren't polluted.
+    pub fn probe(mac: MacAddress, address: Ipv4Addr) -> Self {
+        Self {
+            operation: Operation::Request,
+            sender_mac: mac,
+            sender_ip: Ipv4Addr::UNSPECIFIED,
+            target_mac: MacAddress([0; 6]),
+            target_ip: address,
+        }
+    }
+
+    /// Announcement claiming `address`, a request with both sender and target set to it.
+    pub fn announcement(mac: MacAddress, address: Ipv4Addr) -> Self {
+        Self {
+            operation: Operation::Request,
+            sender_mac: mac,
+            sender_ip: address,
+            target_mac: MacAddress([0; 6]),
+            target_ip: address,
+        }
+    }
+
+    pub fn is_probe(&self) -> bool {
+        self.operation == Operation::Request && self.sender_ip.is_unspecified()
+    }
+
+    /// Parses the ARP payload of a frame, `None` for anything but IPv4 over Ethernet.
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let packet = payload.get(..PACKET_LEN)?;
+        let [ptype_high, ptype_low] = ethertype::IPV4.to_be_bytes();
+        if packet[..6] != [0x00, 0x01, ptype_high, ptype_low, 6, 4] || packet[6] != 0 {
+            return None;
+        }
+
+        let mac = |at: usize| MacAddress(packet[at..at + 6].try_into().unwrap());
+        let ip =
+            |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+        let operation = match packet[7] {
+            1 => Operation::Request,
+            2 => Operation::Reply,
+            _ => return None,
+        };
+
+        Some(Self {
+            operation,
+            sender_mac: mac(8),
+            sender_ip: ip(14),
+            target_mac: mac(18),
+            target_ip: ip(24),
+        })
+    }
+
+    /// Writes the packet, returning its length or `None` if `buffer` is too small.
+    pub fn write(&self, buffer: &mut [u8]) -> Option<usize> {
+        let packet = buffer.get_mut(..PACKET_LEN)?;
+        let [ptype_high, ptype_low] = ethertype::IPV4.to_be_bytes();
+        packet[..6].copy_from_slice(&[0x00, 0x01, ptype_high, ptype_low, 6, 4]);
+        packet[6..8].copy_from_slice(&(self.operation as u16).to_be_bytes());
+        packet[8..14].copy_from_slice(&self.sender_mac.0);
+        packet[14..18].copy_from_slice(&self.sender_ip.octets());
+        packet[18..24].copy_from_slice(&self.target_mac.0);
+        packet[24..28].copy_from_slice(&self.target_ip.octets());
+        Some(PACKET_LEN)
+    }
+}
+
+/// Counters of the cache's lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ArpStats {
+    /// Next-hops that didn't answer before the resolve timeout.
+    pub resolution_failures: u32,
+    /// Packets dropped because their next-hop queue was full or never resolved.
+    pub dropped_packets: u32,
+    /// Entries evicted to make room for a new next-hop.
+    pub evictions: u32,
+}
+
+/// What the caller needs to do after [`ArpCache::enqueue`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Enqueued<T> {
+    /// A new next-hop, an ARP request needs to be sent.
+    NeedsRequest,
+    /// The next-hop is already being resolved.
+    Pending,
+    /// The queue for the next-hop was full, its oldest packet was dropped. Also the packet
+    /// itself when the cache is full of static entries.
+    DroppedOldest(T),
+    /// The next-hop is already resolved, the packet is handed back to be sent right away.
+    Resolved(MacAddress, T),
+}
+
+enum State<T, const Q: usize> {
+    Resolved(MacAddress),
+    Pending(heapless::Deque<T, Q>),
+    Static(MacAddress),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EntryKind {
+    /// Learned from ARP.
+    Dynamic,
+    /// Being resolved.
+    Pending,
+    /// Added by the admin.
+    Static,
+}
+
+impl EntryKind {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            EntryKind::Dynamic => "dynamic",
+            EntryKind::Pending => "pending",
+            EntryKind::Static => "static",
+        }
+    }
+}
+
+/// An entry of the cache, as listed by [`ArpCache::entries`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpEntry {
+    pub address: Ipv4Addr,
+    /// `None` while pending.
+    pub mac: Option<MacAddress>,
+    pub kind: EntryKind,
+    /// Last time the entry was confirmed, created or changed.
+    pub updated_at: u32,
+}
+
+struct Entry<T, const Q: usize> {
+    address: Ipv4Addr,
+    state: State<T, Q>,
+    /// Last time the entry was confirmed or, when pending, created.
+    updated_at: u32,
+}
+
+/// ARP cache with room for `N` next-hops queueing up to `Q` packets of type `T` each.
+pub struct ArpCache<T, const N: usize, const Q: usize> {
+    entries: heapless::Vec<Entry<T, Q>, N>,
+    max_age: u32,
+    resolve_timeout: u32,
+    stats: ArpStats,
+}
+
+impl<T, const N: usize, const Q: usize> ArpCache<T, N, Q> {
+    /// Resolved entries live for `max_age` ticks, unresolved ones for `resolve_timeout` ticks.
+    pub fn new(max_age: u32, resolve_timeout: u32) -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+            max_age,
+            resolve_timeout,
+            stats: ArpStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ArpStats {
+        self.stats
+    }
+
+    /// Resolved entries, static ones included, with the time they were last confirmed.
+    pub fn resolved(&self) -> impl Iterator<Item = (Ipv4Addr, MacAddress, u32)> + '_ {
+        self.entries.iter().filter_map(|entry| match entry.state {
+            State::Resolved(mac) | State::Static(mac) => {
+                Some((entry.address, mac, entry.updated_at))
+            }
+            State::Pending(_) => None,
+        })
+    }
+
+    /// Every entry, pending ones included.
+    pub fn entries(&self) -> impl Iterator<Item = ArpEntry> + '_ {
+        self.entries.iter().map(|entry| {
+            let (mac, kind) = match entry.state {
+                State::Resolved(mac) => (Some(mac), EntryKind::Dynamic),
+                State::Pending(_) => (None, EntryKind::Pending),
+                State::Static(mac) => (Some(mac), EntryKind::Static),
+            };
+            ArpEntry {
+                address: entry.address,
+                mac,
+                kind,
+                updated_at: entry.updated_at,
+            }
+        })
+    }
+
+    pub fn lookup(&self, address: Ipv4Addr) -> Option<MacAddress> {
+        match self.entry(address)?.state {
+            State::Resolved(mac) | State::Static(mac) => Some(mac),
+            State::Pending(_) => None,
+        }
+    }
+
+    /// Queues `packet` until `address` is resolved.
+    pub fn enqueue(&mut self, address: Ipv4Addr, packet: T, now: u32) -> Enqueued<T> {
+        let Some(index) = self.position(address) else {
+            let mut queue = heapless::Deque::new();
+            if queue.push_back(packet).is_err() {
+                // Only with Q == 0, there's nowhere to keep the packet.
+                self.stats.dropped_packets += 1;
+            }
+
+            let inserted = self.insert_entry(Entry {
+                address,
+                state: State::Pending(queue),
+                updated_at: now,
+            });
+            if let Err(Entry {
+                state: State::Pending(mut queue),
+                ..
+            }) = inserted
+                && let Some(packet) = queue.pop_front()
+            {
+                self.stats.dropped_packets += 1;
+                return Enqueued::DroppedOldest(packet);
+            }
+            return Enqueued::NeedsRequest;
+        };
+
+        match &mut self.entries[index].state {
+            State::Resolved(mac) | State::Static(mac) => Enqueued::Resolved(*mac, packet),
+            State::Pending(queue) => {
+                let dropped = if queue.is_full() {
+                    self.stats.dropped_packets += 1;
+                    queue.pop_front()
+                } else {
+                    None
+                };
+
+                match (queue.push_back(packet), dropped) {
+                    (Ok(()), Some(dropped)) => Enqueued::DroppedOldest(dropped),
+                    (Ok(()), None) => Enqueued::Pending,
+                    (Err(packet), _) => Enqueued::DroppedOldest(packet),
+                }
+            }
+        }
+    }
+
+    /// Records that `address` is at `mac`, the sender of an ARP packet, returning the packets
+    /// that were waiting on it.
+    ///
+    /// As in RFC 826, the entry of the address is updated if there's one, pending or not, but a
+    /// new one is only made when the packet is for us, its target being our address: hosts
+    /// talking among themselves don't take room in the cache. Static entries are left as they
+    /// are.
+    pub fn insert(
+        &mut self,
+        address: Ipv4Addr,
+        mac: MacAddress,
+        for_us: bool,
+        now: u32,
+    ) -> heapless::Deque<T, Q> {
+        let Some(index) = self.position(address) else {
+            if !for_us {
+                return heapless::Deque::new();
+            }
+
+            // Not cached if the cache is full of static entries.
+            let _ = self.insert_entry(Entry {
+                address,
+                state: State::Resolved(mac),
+                updated_at: now,
+            });
+            return heapless::Deque::new();
+        };
+
+        let entry = &mut self.entries[index];
+        if let State::Static(_) = entry.state {
+            return heapless::Deque::new();
+        }
+        entry.updated_at = now;
+        match core::mem::replace(&mut entry.state, State::Resolved(mac)) {
+            State::Pending(queue) => queue,
+            State::Resolved(_) | State::Static(_) => heapless::Deque::new(),
+        }
+    }
+
+    /// Pins `address` to `mac`, replacing any entry of the address, and returns the packets
+    /// that were waiting on it.
+    ///
+    /// Static entries make room by evicting dynamic ones, but not other static ones.
+    pub fn insert_static(
+        &mut self,
+        address: Ipv4Addr,
+        mac: MacAddress,
+        now: u32,
+    ) -> Result<heapless::Deque<T, Q>, ArpError> {
+        if let Some(index) = self.position(address) {
+            let entry = &mut self.entries[index];
+            entry.updated_at = now;
+            return Ok(
+                match core::mem::replace(&mut entry.state, State::Static(mac)) {
+                    State::Pending(queue) => queue,
+                    State::Resolved(_) | State::Static(_) => heapless::Deque::new(),
+                },
+            );
+        }
+
+        let entry = Entry {
+            address,
+            state: State::Static(mac),
+            updated_at: now,
+        };
+        self.insert_entry(entry)
+            .map(|()| heapless::Deque::new())
+            .map_err(|_| ArpError::TableFull)
+    }
+
+    /// Removes the entry of `address`, static or not, returning whether there was one.
+    pub fn remove(&mut self, address: Ipv4Addr) -> bool {
+        let Some(index) = self.position(address) else {
+            return false;
+        };
+        if let State::Pending(queue) = &self.entries[index].state {
+            self.stats.dropped_packets += queue.len() as u32;
+        }
+        self.entries.swap_remove(index);
+        true
+    }
+
+    /// Drops every entry but the static ones, along with their queued packets, e.g. after the
+    /// interface was reset.
+    pub fn flush(&mut self) {
+        let stats = &mut self.stats;
+        self.entries.retain(|entry| match &entry.state {
+            State::Static(_) => true,
+            State::Resolved(_) => false,
+            State::Pending(queue) => {
+                stats.dropped_packets += queue.len() as u32;
+                false
+            }
+        });
+    }
+
+    /// Drops resolved entries older than the max age and pending ones past the resolve timeout,
+    /// along with their queued packets.
+    pub fn age(&mut self, now: u32) {
+        let (max_age, resolve_timeout) = (self.max_age, self.resolve_timeout);
+        let stats = &mut self.stats;
+        self.entries.retain(|entry| {
+            let age = now.wrapping_sub(entry.updated_at);
+            match &entry.state {
+                State::Static(_) => true,
+                State::Resolved(_) => age < max_age,
+                State::Pending(_) if age < resolve_timeout => true,
+                State::Pending(queue) => {
+                    stats.resolution_failures += 1;
+                    stats.dropped_packets += queue.len() as u32;
+                    false
+                }
+            }
+        });
+    }
+
+    fn entry(&self, address: Ipv4Addr) -> Option<&Entry<T, Q>> {
+        self.entries.iter().find(|entry| entry.address == address)
+    }
+
+    fn position(&self, address: Ipv4Addr) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.address == address)
+    }
+
+    /// Inserts `entry`, evicting the least recently updated dynamic one if the cache is full.
+    /// The entry is handed back if the cache is full of static entries.
+    fn insert_entry(&mut self, entry: Entry<T, Q>) -> Result<(), Entry<T, Q>> {
+        if self.entries.is_full() {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| !matches!(e.state, State::Static(_)))
+                .max_by_key(|(_, e)| entry.updated_at.wrapping_sub(e.updated_at))
+                .map(|(i, _)| i)
+            else {
+                return Err(entry);
+            };
+
+            if let State::Pending(queue) = &self.entries[oldest].state {
+                self.stats.dropped_packets += queue.len() as u32;
+            }
+            self.entries.swap_remove(oldest);
+            self.stats.evictions += 1;
+        }
+
+        self.entries.push(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    const MAX_AGE: u32 = 1200;
+    const RESOLVE_TIMEOUT: u32 = 3;
+
+    type Cache = ArpCache<u8, 4, 2>;
+
+    fn mac(last: u8) -> MacAddress {
+        MacAddress([0x02, 0, 0, 0, 0, last])
+    }
+
+    fn address(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 168, 1, last)
+    }
+
+    /// Resolves `address` to `mac` as when a reply to our request comes back.
+    fn resolve(cache: &mut Cache, address: Ipv4Addr, mac: MacAddress, now: u32) {
+        assert_eq!(cache.enqueue(address, 0, now), Enqueued::NeedsRequest);
+        assert_eq!(cache.insert(address, mac, true, now).len(), 1);
+    }
+
+    #[test]
+    fn resolved_entry_ages_out_after_max_age() {
+        let clock = MockClock::new(100);
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+        resolve(&mut cache, address(1), mac(1), clock.now());
+
+        clock.advance(MAX_AGE - 1);
+        cache.age(clock.now());
+        assert_eq!(cache.lookup(address(1)), Some(mac(1)));
+
+        clock.advance(1);
+        cache.age(clock.now());
+        assert_eq!(cache.lookup(address(1)), None);
+        assert_eq!(cache.stats().resolution_failures, 0);
+    }
+
+    #[test]
+    fn confirmation_restarts_the_age() {
+        let clock = MockClock::new(0);
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+        resolve(&mut cache, address(1), mac(1), clock.now());
+
+        clock.advance(MAX_AGE - 1);
+        cache.insert(address(1), mac(1), false, clock.now());
+        clock.advance(MAX_AGE - 1);
+        cache.age(clock.now());
+        assert_eq!(cache.lookup(address(1)), Some(mac(1)));
+        assert_eq!(cache.entries().next().unwrap().updated_at, MAX_AGE - 1);
+    }
+
+    #[test]
+    fn only_packets_for_us_create_entries() {
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+
+        // Another host's request, or its reply to someone else.
+        cache.insert(address(1), mac(1), false, 0);
+        assert_eq!(cache.entries().count(), 0);
+
+        cache.insert(address(1), mac(1), true, 0);
+        assert_eq!(cache.lookup(address(1)), Some(mac(1)));
+
+        // Known senders are updated whoever the packet is for.
+        cache.insert(address(1), mac(2), false, 1);
+        assert_eq!(cache.lookup(address(1)), Some(mac(2)));
+    }
+
+    #[test]
+    fn pending_entries_resolve_from_any_packet_of_their_address() {
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+        assert_eq!(cache.enqueue(address(1), 7, 0), Enqueued::NeedsRequest);
+
+        let waiting = cache.insert(address(1), mac(1), false, 1);
+        assert_eq!(waiting.into_iter().collect::<Vec<_>>(), [7]);
+        assert_eq!(cache.lookup(address(1)), Some(mac(1)));
+    }
+
+    #[test]
+    fn unanswered_resolution_times_out_with_its_packets() {
+        let clock = MockClock::new(0);
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+        assert_eq!(
+            cache.enqueue(address(1), 1, clock.now()),
+            Enqueued::NeedsRequest
+        );
+        assert_eq!(cache.enqueue(address(1), 2, clock.now()), Enqueued::Pending);
+
+        clock.advance(RESOLVE_TIMEOUT - 1);
+        cache.age(clock.now());
+        assert_eq!(cache.entries().next().unwrap().kind, EntryKind::Pending);
+
+        clock.advance(1);
+        cache.age(clock.now());
+        assert_eq!(cache.entries().count(), 0);
+        let stats = cache.stats();
+        assert_eq!((stats.resolution_failures, stats.dropped_packets), (1, 2));
+    }
+
+    #[test]
+    fn static_entries_never_age() {
+        let clock = MockClock::new(0);
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+        cache
+            .insert_static(address(1), mac(1), clock.now())
+            .unwrap();
+
+        clock.advance(u32::MAX / 2);
+        cache.age(clock.now());
+        assert_eq!(cache.lookup(address(1)), Some(mac(1)));
+    }
+
+    #[test]
+    fn age_wraps_with_the_clock() {
+        let clock = MockClock::new(u32::MAX - 100);
+        let mut cache = Cache::new(MAX_AGE, RESOLVE_TIMEOUT);
+        resolve(&mut cache, address(1), mac(1), clock.now());
+
+        clock.advance(MAX_AGE - 1);
+        cache.age(clock.now());
+        assert_eq!(cache.lookup(address(1)), Some(mac(1)));
+
+        clock.advance(1);
+        cache.age(clock.now());
+        assert_eq!(cache.lookup(address(1)), None);
+    }
+}
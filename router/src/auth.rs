@@ -0,0 +1,438 @@
+//! Authentication for the management interfaces (CLI over TCP, HTTP).
+//!
+//! Users are stored as a salted PBKDF2 hash, in the form kept in config flash. A successful
+//! login opens a session identified by a random token that expires after some idle time.
+//! Sources failing to log in too often are locked out for a while. While every tracked source
+//! is locked out, logins from untracked sources are refused too, rather than left unlimited.
+//!
+//! Salts and tokens come from the caller, which owns the hardware RNG. The default timings assume
+//! one tick per second.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::{
+    clock,
+    sha256::{self, DIGEST_LEN},
+};
+
+pub const SALT_LEN: usize = 16;
+pub const TOKEN_LEN: usize = 16;
+pub const MAX_USERNAME_LEN: usize = 16;
+
+/// Length of a [`Credential`] encoded for config flash.
+pub const CREDENTIAL_LEN: usize = 1 + MAX_USERNAME_LEN + SALT_LEN + 4 + DIGEST_LEN;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthError {
+    #[error("Username or password is wrong.")]
+    InvalidCredentials,
+    #[error("Too many failed logins, try again later.")]
+    LockedOut,
+    #[error("Session doesn't exist or expired.")]
+    InvalidSession,
+    #[error("Username is empty or too long.")]
+    InvalidUsername,
+    #[error("Store ran out of memory for additional users.")]
+    UsersOutOfMemory,
+    #[error("Stored credential is corrupted.")]
+    CorruptedCredential,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthConfig {
+    /// PBKDF2 iterations for new passwords, a login takes about 0.1 s per thousand.
+    pub iterations: u32,
+    /// Sessions idle for longer than this expire.
+    pub session_timeout: u32,
+    /// Failed logins from a source within `failure_window` before it gets locked out.
+    pub max_failures: u8,
+    pub failure_window: u32,
+    pub lockout_duration: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            iterations: 1000,
+            session_timeout: 900,
+            max_failures: 5,
+            failure_window: 300,
+            lockout_duration: 900,
+        }
+    }
+}
+
+/// A user's salted password hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub username: heapless::String<MAX_USERNAME_LEN>,
+    salt: [u8; SALT_LEN],
+    iterations: u32,
+    hash: [u8; DIGEST_LEN],
+}
+
+impl Credential {
+    pub fn new(
+        username: &str,
+        password: &str,
+        salt: [u8; SALT_LEN],
+        iterations: u32,
+    ) -> Result<Self, AuthError> {
+        let username = heapless::String::try_from(username)
+            .ok()
+            .filter(|username| !username.is_empty())
+            .ok_or(AuthError::InvalidUsername)?;
+
+        Ok(Self {
+            username,
+            salt,
+            iterations,
+            hash: sha256::pbkdf2(password.as_bytes(), &salt, iterations),
+        })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = sha256::pbkdf2(password.as_bytes(), &self.salt, self.iterations);
+        sha256::constant_time_eq(&hash, &self.hash)
+    }
+
+    /// Encodes as username length, username padded with zeros, salt, iterations and hash.
+    pub fn encode(&self) -> [u8; CREDENTIAL_LEN] {
+        let mut encoded = [0; CREDENTIAL_LEN];
+        let (len, rest) = encoded.split_at_mut(1);
+        let (username, rest) = rest.split_at_mut(MAX_USERNAME_LEN);
+        let (salt, rest) = rest.split_at_mut(SALT_LEN);
+        let (iterations, hash) = rest.split_at_mut(4);
+
+        len[0] = self.username.len() as u8;
+        username[..self.username.len()].copy_from_slice(self.username.as_bytes());
+        salt.copy_from_slice(&self.salt);
+        iterations.copy_from_slice(&self.iterations.to_be_bytes());
+        hash.copy_from_slice(&self.hash);
+        encoded
+    }
+
+    pub fn decode(encoded: &[u8; CREDENTIAL_LEN]) -> Result<Self, AuthError> {
+        let (len, rest) = encoded.split_at(1);
+        let (username, rest) = rest.split_at(MAX_USERNAME_LEN);
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (iterations, hash) = rest.split_at(4);
+
+        let username = username
+            .get(..len[0] as usize)
+            .and_then(|username| core::str::from_utf8(username).ok())
+            .and_then(|username| heapless::String::try_from(username).ok())
+            .filter(|username| !username.is_empty())
+            .ok_or(AuthError::CorruptedCredential)?;
+
+        Ok(Self {
+            username,
+            salt: salt.try_into().unwrap(),
+            iterations: u32::from_be_bytes(iterations.try_into().unwrap()),
+            hash: hash.try_into().unwrap(),
+        })
+    }
+}
+
+/// Identifies a logged in session, handed to the client as a cookie or after a CLI login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionToken(pub [u8; TOKEN_LEN]);
+
+struct Session {
+    token: SessionToken,
+    user: usize,
+    last_used: u32,
+}
+
+struct Failures {
+    source: Ipv4Addr,
+    count: u8,
+    window_start: u32,
+    locked_until: Option<u32>,
+}
+
+/// Credential store for `U` users with up to `S` open sessions, tracking failed logins from
+/// `F` sources.
+pub struct Authenticator<const U: usize, const S: usize, const F: usize> {
+    config: AuthConfig,
+    users: heapless::Vec<Credential, U>,
+    sessions: heapless::Vec<Session, S>,
+    failures: heapless::Vec<Failures, F>,
+}
+
+impl<const U: usize, const S: usize, const F: usize> Authenticator<U, S, F> {
+    pub fn new(config: AuthConfig) -> Self {
+        Self {
+            config,
+            users: heapless::Vec::new(),
+            sessions: heapless::Vec::new(),
+            failures: heapless::Vec::new(),
+        }
+    }
+
+    pub fn users(&self) -> &[Credential] {
+        &self.users
+    }
+
+    /// Adds a credential, e.g. loaded from config flash, replacing any of the same user.
+    pub fn insert(&mut self, credential: Credential) -> Result<(), AuthError> {
+        if let Some(existing) = self
+            .users
+            .iter_mut()
+            .find(|user| user.username == credential.username)
+        {
+            *existing = credential;
+            return Ok(());
+        }
+
+        self.users
+            .push(credential)
+            .map_err(|_| AuthError::UsersOutOfMemory)
+    }
+
+    /// Sets the password of `username`, adding the user if needed. Their sessions are closed.
+    pub fn set_password(
+        &mut self,
+        username: &str,
+        password: &str,
+        salt: [u8; SALT_LEN],
+    ) -> Result<(), AuthError> {
+        let credential = Credential::new(username, password, salt, self.config.iterations)?;
+        if let Some(user) = self.find_user(username) {
+            self.sessions.retain(|session| session.user != user);
+        }
+        self.insert(credential)
+    }
+
+    /// Removes `username` and closes their sessions.
+    pub fn remove(&mut self, username: &str) {
+        let Some(user) = self.find_user(username) else {
+            return;
+        };
+
+        self.users.remove(user);
+        self.sessions.retain(|session| session.user != user);
+        for session in &mut self.sessions {
+            if session.user > user {
+                session.user -= 1;
+            }
+        }
+    }
+
+    /// Checks a login from `source`, opening a session identified by `token` on success.
+    ///
+    /// When all sessions are in use the one idle for longest is closed.
+    pub fn login(
+        &mut self,
+        source: Ipv4Addr,
+        username: &str,
+        password: &str,
+        token: SessionToken,
+        now: u32,
+    ) -> Result<SessionToken, AuthError> {
+        self.expire(now);
+        if self.is_locked_out(source, now) || !self.can_track(source) {
+            return Err(AuthError::LockedOut);
+        }
+
+        // Unknown users cost as much as wrong passwords, not to reveal which usernames exist.
+        let user = self.find_user(username);
+        let valid = match user {
+            Some(user) => self.users[user].verify(password),
+            None => {
+                let _ = sha256::pbkdf2(password.as_bytes(), &[0; SALT_LEN], self.config.iterations);
+                false
+            }
+        };
+
+        let (true, Some(user)) = (valid, user) else {
+            self.record_failure(source, now);
+            return Err(AuthError::InvalidCredentials);
+        };
+
+        self.failures.retain(|failures| failures.source != source);
+        if self.sessions.is_full()
+            && let Some(idle) = self
+                .sessions
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, session)| now.wrapping_sub(session.last_used))
+                .map(|(i, _)| i)
+        {
+            self.sessions.swap_remove(idle);
+        }
+
+        let _ = self.sessions.push(Session {
+            token,
+            user,
+            last_used: now,
+        });
+        Ok(token)
+    }
+
+    /// Username of the session `token`, refreshing its expiry.
+    pub fn authenticate(&mut self, token: &SessionToken, now: u32) -> Result<&str, AuthError> {
+        self.expire(now);
+
+        let session = self
+            .sessions
+            .iter_mut()
+            .find(|session| sha256::constant_time_eq(&session.token.0, &token.0))
+            .ok_or(AuthError::InvalidSession)?;
+        session.last_used = now;
+
+        Ok(&self.users[session.user].username)
+    }
+
+    pub fn logout(&mut self, token: &SessionToken) {
+        self.sessions.retain(|session| session.token != *token);
+    }
+
+    /// Closes idle sessions and forgets old failures.
+    pub fn expire(&mut self, now: u32) {
+        let config = self.config;
+        self.sessions
+            .retain(|session| now.wrapping_sub(session.last_used) < config.session_timeout);
+        self.failures
+            .retain(|failures| match failures.locked_until {
+                Some(until) => !clock::is_due(until, now),
+                None => now.wrapping_sub(failures.window_start) < config.failure_window,
+            });
+    }
+
+    pub fn is_locked_out(&self, source: Ipv4Addr, now: u32) -> bool {
+        self.failures.iter().any(|failures| {
+            failures.source == source
+                && failures
+                    .locked_until
+                    .is_some_and(|until| !clock::is_due(until, now))
+        })
+    }
+
+    /// Whether a failure of `source` would be counted: it's tracked already, or there's a slot
+    /// that isn't held by a locked out source.
+    fn can_track(&self, source: Ipv4Addr) -> bool {
+        !self.failures.is_full()
+            || self
+                .failures
+                .iter()
+                .any(|failures| failures.source == source || failures.locked_until.is_none())
+    }
+
+    fn find_user(&self, username: &str) -> Option<usize> {
+        self.users.iter().position(|user| user.username == username)
+    }
+
+    fn record_failure(&mut self, source: Ipv4Addr, now: u32) {
+        self.expire(now);
+
+        let index = match self.failures.iter().position(|f| f.source == source) {
+            Some(index) => index,
+            None => {
+                if self.failures.is_full()
+                    && let Some(oldest) = self
+                        .failures
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, failures)| failures.locked_until.is_none())
+                        .max_by_key(|(_, failures)| now.wrapping_sub(failures.window_start))
+                        .map(|(i, _)| i)
+                {
+                    self.failures.swap_remove(oldest);
+                }
+
+                let failures = Failures {
+                    source,
+                    count: 0,
+                    window_start: now,
+                    locked_until: None,
+                };
+                if self.failures.push(failures).is_err() {
+                    // Every slot holds a locked out source, login refused it already.
+                    return;
+                }
+                self.failures.len() - 1
+            }
+        };
+
+        let failures = &mut self.failures[index];
+        failures.count = failures.count.saturating_add(1);
+        if failures.count >= self.config.max_failures {
+            failures.locked_until = Some(now.wrapping_add(self.config.lockout_duration));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: AuthConfig = AuthConfig {
+        iterations: 1,
+        session_timeout: 900,
+        max_failures: 2,
+        failure_window: 300,
+        lockout_duration: 900,
+    };
+
+    fn source(host: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 168, 1, host)
+    }
+
+    fn authenticator() -> Authenticator<1, 1, 2> {
+        let mut auth = Authenticator::new(CONFIG);
+        auth.set_password("admin", "secret", [1; SALT_LEN]).unwrap();
+        auth
+    }
+
+    fn login(
+        auth: &mut Authenticator<1, 1, 2>,
+        host: u8,
+        password: &str,
+        now: u32,
+    ) -> Result<SessionToken, AuthError> {
+        let token = SessionToken([host; TOKEN_LEN]);
+        auth.login(source(host), "admin", password, token, now)
+    }
+
+    #[test]
+    fn sources_failing_too_often_are_locked_out() {
+        let mut auth = authenticator();
+
+        assert_eq!(
+            login(&mut auth, 1, "wrong", 0),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(
+            login(&mut auth, 1, "wrong", 1),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(login(&mut auth, 1, "secret", 2), Err(AuthError::LockedOut));
+
+        assert!(login(&mut auth, 1, "secret", 2 + CONFIG.lockout_duration).is_ok());
+    }
+
+    #[test]
+    fn untracked_sources_are_refused_while_every_slot_is_locked_out() {
+        let mut auth = authenticator();
+        for host in [1, 2] {
+            for now in 0..2 {
+                let _ = login(&mut auth, host, "wrong", now);
+            }
+            assert!(auth.is_locked_out(source(host), 2));
+        }
+
+        // Guessing from a third source isn't left unlimited.
+        assert_eq!(login(&mut auth, 3, "wrong", 2), Err(AuthError::LockedOut));
+        assert_eq!(login(&mut auth, 3, "secret", 3), Err(AuthError::LockedOut));
+
+        // Counted again once a lockout ends.
+        assert_eq!(
+            login(&mut auth, 3, "wrong", 1 + CONFIG.lockout_duration),
+            Err(AuthError::InvalidCredentials)
+        );
+    }
+}
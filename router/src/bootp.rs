@@ -0,0 +1,226 @@
+//! BOOTP message header and where DHCP messages get delivered (RFC 951, RFC 2131).
+//!
+//! Real devices are messy. Printers and boot ROMs still speak plain BOOTP, without a message
+//! type option, and take a fixed address that they never renew. Some clients can't receive
+//! unicast before they're configured and set the broadcast flag; some servers unicast their
+//! offer to the offered address anyway. The server follows RFC 2131 section 4.1 to pick a
+//! reply's destination, and the client accepts replies sent either way.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::{dhcp, ethernet::MacAddress};
+
+/// Fixed header, up to the vendor area.
+pub const HEADER_LEN: usize = 236;
+/// Marks the vendor area as holding DHCP options (RFC 1048).
+pub const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Where the options start, after the magic cookie.
+pub const OPTIONS_OFFSET: usize = HEADER_LEN + MAGIC_COOKIE.len();
+/// Servers listen on this port, clients on the next one.
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+/// Longest lease the lease table can hold, given to BOOTP clients since they never renew.
+pub const BOOTP_LEASE: u32 = i32::MAX as u32;
+
+const ETHERNET: u8 = 1;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootpError {
+    #[error("Message is shorter than the BOOTP header.")]
+    Truncated,
+    #[error("Operation {0} is neither a request nor a reply.")]
+    InvalidOp(u8),
+    #[error("Hardware address isn't an Ethernet one.")]
+    UnsupportedHardware,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Op {
+    Request = 1,
+    Reply = 2,
+}
+
+/// Fixed header of a BOOTP or DHCP message, server name and boot file aside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub op: Op,
+    pub hops: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    /// Client's address, when it's configured.
+    pub ciaddr: Ipv4Addr,
+    /// Address offered or assigned to the client.
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    /// Relay agent the message went through, if any.
+    pub giaddr: Ipv4Addr,
+    pub chaddr: MacAddress,
+}
+
+impl Header {
+    /// Flag of clients that can't receive unicast before they're configured.
+    pub const BROADCAST: u16 = 0x8000;
+
+    pub fn parse(message: &[u8]) -> Result<Self, BootpError> {
+        let header = message.get(..HEADER_LEN).ok_or(BootpError::Truncated)?;
+        let op = match header[0] {
+            1 => Op::Request,
+            2 => Op::Reply,
+            op => return Err(BootpError::InvalidOp(op)),
+        };
+        if header[1] != ETHERNET || header[2] != 6 {
+            return Err(BootpError::UnsupportedHardware);
+        }
+
+        let address = |offset: usize| {
+            Ipv4Addr::new(
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            )
+        };
+        let mut chaddr = [0; 6];
+        chaddr.copy_from_slice(&header[28..34]);
+
+        Ok(Self {
+            op,
+            hops: header[3],
+            xid: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            secs: u16::from_be_bytes([header[8], header[9]]),
+            flags: u16::from_be_bytes([header[10], header[11]]),
+            ciaddr: address(12),
+            yiaddr: address(16),
+            siaddr: address(20),
+            giaddr: address(24),
+            chaddr: MacAddress(chaddr),
+        })
+    }
+
+    /// Writes the header and the magic cookie, returning their length or `None` if `buffer`
+    /// is too small. The options follow.
+    pub fn write(&self, buffer: &mut [u8]) -> Option<usize> {
+        let header = buffer.get_mut(..OPTIONS_OFFSET)?;
+        header.fill(0);
+        header[..4].copy_from_slice(&[self.op as u8, ETHERNET, 6, self.hops]);
+        header[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        header[8..10].copy_from_slice(&self.secs.to_be_bytes());
+        header[10..12].copy_from_slice(&self.flags.to_be_bytes());
+        header[12..16].copy_from_slice(&self.ciaddr.octets());
+        header[16..20].copy_from_slice(&self.yiaddr.octets());
+        header[20..24].copy_from_slice(&self.siaddr.octets());
+        header[24..28].copy_from_slice(&self.giaddr.octets());
+        header[28..34].copy_from_slice(&self.chaddr.0);
+        header[HEADER_LEN..].copy_from_slice(&MAGIC_COOKIE);
+        Some(OPTIONS_OFFSET)
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & Self::BROADCAST != 0
+    }
+
+    /// Reply to this request, with the transaction, flags, relay and client hardware address
+    /// carried over. The server fills in the addresses.
+    pub fn reply(&self) -> Self {
+        Self {
+            op: Op::Reply,
+            hops: 0,
+            secs: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            ..*self
+        }
+    }
+
+    /// Where the server sends its reply to this request (RFC 2131 section 4.1). `nak` replies
+    /// are broadcast unless relayed, the client may be on the wrong subnet.
+    ///
+    /// Unicast replies to an unconfigured client go to `yiaddr` at `chaddr`, without ARP,
+    /// which the client couldn't answer yet.
+    pub fn reply_destination(&self, reply: &Header, nak: bool) -> ReplyDestination {
+        if !self.giaddr.is_unspecified() {
+            ReplyDestination::Relay(self.giaddr)
+        } else if nak {
+            ReplyDestination::Broadcast
+        } else if !self.ciaddr.is_unspecified() {
+            ReplyDestination::Unicast {
+                address: self.ciaddr,
+                mac: None,
+            }
+        } else if self.is_broadcast() || reply.yiaddr.is_unspecified() {
+            ReplyDestination::Broadcast
+        } else {
+            ReplyDestination::Unicast {
+                address: reply.yiaddr,
+                mac: Some(self.chaddr),
+            }
+        }
+    }
+
+    /// Whether a client at `mac`, waiting on transaction `xid`, takes this reply that was
+    /// sent to `destination`. `own_address` is the client's address once bound.
+    ///
+    /// Besides broadcast and the bound address, replies unicast to the address being offered
+    /// are taken, some servers send those before the client has the address.
+    pub fn is_reply_for(
+        &self,
+        mac: MacAddress,
+        xid: u32,
+        destination: Ipv4Addr,
+        own_address: Option<Ipv4Addr>,
+    ) -> bool {
+        self.op == Op::Reply
+            && self.xid == xid
+            && self.chaddr == mac
+            && (destination.is_broadcast()
+                || Some(destination) == own_address
+                || (destination == self.yiaddr && !destination.is_unspecified()))
+    }
+}
+
+/// Destination of a server's reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyDestination {
+    /// To the relay agent's server port.
+    Relay(Ipv4Addr),
+    /// To the client's port at `address`, at `mac` when it can't be resolved with ARP.
+    Unicast {
+        address: Ipv4Addr,
+        mac: Option<MacAddress>,
+    },
+    /// To 255.255.255.255 and the broadcast MAC.
+    Broadcast,
+}
+
+/// Kind of a message, from its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageKind {
+    /// Plain BOOTP, without a message type: the reply carries none either, and the client
+    /// gets a [`BOOTP_LEASE`].
+    Bootp,
+    /// DHCP message of the given type.
+    Dhcp(u8),
+}
+
+impl MessageKind {
+    /// Kind of `message`, header included. Malformed options make it BOOTP, as if the vendor
+    /// area held something else.
+    pub fn of(message: &[u8]) -> Self {
+        options(message)
+            .and_then(|options| dhcp::find(options, dhcp::code::MESSAGE_TYPE))
+            .and_then(|option| option.data.first().copied())
+            .map_or(MessageKind::Bootp, MessageKind::Dhcp)
+    }
+}
+
+/// Options area of `message`, `None` if its vendor area doesn't start with the magic cookie.
+pub fn options(message: &[u8]) -> Option<&[u8]> {
+    (message.get(HEADER_LEN..OPTIONS_OFFSET)? == MAGIC_COOKIE).then(|| &message[OPTIONS_OFFSET..])
+}
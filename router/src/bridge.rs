@@ -0,0 +1,128 @@
+//! MAC learning bridge forwarding.
+//!
+//! Learns which port each source MAC lives behind and forwards frames to that port only,
+//! flooding broadcast, multicast and unknown destinations.
+//!
+//! Ports can be marked isolated: they can reach non-isolated ports (the uplink) but not each
+//! other, e.g. for a guest port.
+
+use crate::ethernet::MacAddress;
+
+/// Where a frame goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Forward {
+    Port(u8),
+    /// Every port whose bit is set.
+    Flood(u32),
+    Drop,
+}
+
+struct Entry {
+    mac: MacAddress,
+    port: u8,
+    last_seen: u32,
+}
+
+/// Forwarding database of up to `N` MACs for up to 32 ports.
+pub struct MacTable<const N: usize> {
+    entries: heapless::Vec<Entry, N>,
+    /// Ports in the bridge, one bit each.
+    ports: u32,
+    isolated: u32,
+    max_age: u32,
+}
+
+impl<const N: usize> MacTable<N> {
+    /// A bridge over the ports set in `ports`, learned MACs are forgotten after `max_age` ticks.
+    pub fn new(ports: u32, max_age: u32) -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+            ports,
+            isolated: 0,
+            max_age,
+        }
+    }
+
+    pub fn set_isolated(&mut self, port: u8, isolated: bool) {
+        if isolated {
+            self.isolated |= 1 << port;
+        } else {
+            self.isolated &= !(1 << port);
+        }
+    }
+
+    pub fn is_isolated(&self, port: u8) -> bool {
+        self.isolated & (1 << port) != 0
+    }
+
+    /// Records that `source` was seen on `port`.
+    pub fn learn(&mut self, source: MacAddress, port: u8, now: u32) {
+        if source.is_multicast() {
+            return;
+        }
+
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.mac == source) {
+            entry.port = port;
+            entry.last_seen = now;
+            return;
+        }
+
+        if self.entries.is_full() {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, e)| now.wrapping_sub(e.last_seen))
+                .map(|(i, _)| i)
+            else {
+                return;
+            };
+            self.entries.swap_remove(oldest);
+        }
+
+        let _ = self.entries.push(Entry {
+            mac: source,
+            port,
+            last_seen: now,
+        });
+    }
+
+    /// Forwarding decision for a frame to `destination` received on `ingress`.
+    pub fn forward(&self, ingress: u8, destination: MacAddress) -> Forward {
+        let reachable = self.reachable_from(ingress);
+
+        let known = (!destination.is_multicast())
+            .then(|| self.entries.iter().find(|e| e.mac == destination))
+            .flatten();
+
+        match known {
+            Some(entry) if reachable & (1 << entry.port) != 0 => Forward::Port(entry.port),
+            Some(_) => Forward::Drop,
+            None if reachable == 0 => Forward::Drop,
+            None => Forward::Flood(reachable),
+        }
+    }
+
+    /// Forgets MACs not seen for longer than the max age.
+    pub fn age(&mut self, now: u32) {
+        let max_age = self.max_age;
+        self.entries
+            .retain(|e| now.wrapping_sub(e.last_seen) < max_age);
+    }
+
+    /// Forgets every MAC learned on `port`, e.g. when its link goes down.
+    pub fn flush_port(&mut self, port: u8) {
+        self.entries.retain(|e| e.port != port);
+    }
+
+    /// Ports a frame from `ingress` may go out of.
+    fn reachable_from(&self, ingress: u8) -> u32 {
+        let mut ports = self.ports & !(1 << ingress);
+        if self.is_isolated(ingress) {
+            ports &= !self.isolated;
+        }
+
+        ports
+    }
+}
@@ -0,0 +1,125 @@
+//! SPI bring-up of the ENC28J60.
+//!
+//! Before the driver takes over the bus, the board's SPI clock is checked with register
+//! round-trips, stepping down until they're reliable. The clock is kept within what the chip
+//! supports: at most 20 MHz, and at least 8 MHz, as the errata of every silicon revision (B1,
+//! B4, B5 and B7) make slower clocks unreliable. Unlike the driver this talks to the bus
+//! directly, it runs once at boot before anything else uses it.
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+/// Fastest SPI clock of the data sheet.
+pub const MAX_SPI_HZ: u32 = 20_000_000;
+/// Slowest SPI clock the errata allow, on every revision.
+pub const ERRATA_MIN_SPI_HZ: u32 = 8_000_000;
+
+/// Round-trips making up a check, each pattern is written and read back once.
+const PATTERNS: [u8; 8] = [0x55, 0xAA, 0x00, 0xFF, 0x0F, 0xF0, 0x33, 0xCC];
+
+// Registers and opcodes, the driver keeps its own copies behind its transaction queue.
+const ECON1: u8 = 0x1F;
+const EWRPTL: u8 = 0x02;
+const EREVID: u8 = 0x12;
+const BANK_BITS: u8 = 0b11;
+const BANK3: u8 = 0b11;
+const RCR: u8 = 0b000_00000;
+const WCR: u8 = 0b010_00000;
+const BFS: u8 = 0b100_00000;
+const BFC: u8 = 0b101_00000;
+
+/// Clock range usable with a chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SpiLimits {
+    pub min_hz: u32,
+    pub max_hz: u32,
+}
+
+impl SpiLimits {
+    /// Limits for the silicon revision read from EREVID.
+    ///
+    /// B1, B4, B5 and B7, reading as 2, 4, 5 and 6, all have the errata's minimum clock. A
+    /// revision unknown here gets it too, until its errata say otherwise.
+    pub fn for_revision(_revision: u8) -> Self {
+        Self {
+            min_hz: ERRATA_MIN_SPI_HZ,
+            max_hz: MAX_SPI_HZ,
+        }
+    }
+}
+
+/// Steps the SPI clock down from the board's setting until round-trips are reliable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SpiTuner {
+    frequency: u32,
+    min_hz: u32,
+}
+
+impl SpiTuner {
+    /// Starts at `requested_hz`, capped to the data sheet's maximum.
+    pub fn new(requested_hz: u32) -> Self {
+        Self {
+            frequency: requested_hz.min(MAX_SPI_HZ),
+            min_hz: 0,
+        }
+    }
+
+    /// Clock to try next.
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    /// Applies the limits of the chip once its revision is known, `false` if the current
+    /// clock is outside of them.
+    pub fn apply_limits(&mut self, limits: SpiLimits) -> bool {
+        self.min_hz = limits.min_hz;
+        self.frequency = self.frequency.min(limits.max_hz);
+        self.frequency >= self.min_hz
+    }
+
+    /// Halves the clock after a failed check, as SPI prescalers go in powers of two.
+    ///
+    /// `None` once it would fall below the chip's minimum or reach zero.
+    pub fn step_down(&mut self) -> Option<u32> {
+        let next = self.frequency / 2;
+        if next == 0 || next < self.min_hz {
+            return None;
+        }
+
+        self.frequency = next;
+        Some(next)
+    }
+}
+
+/// Reads EREVID, leaving bank 0 selected.
+pub fn read_revision<S: SpiDevice>(spi: &mut S) -> Result<u8, S::Error> {
+    spi.write(&[BFS | ECON1, BANK3])?;
+    let revision = read(spi, EREVID);
+    spi.write(&[BFC | ECON1, BANK_BITS])?;
+    revision
+}
+
+/// Writes patterns to EWRPTL and reads them back, `true` if all of them came back intact.
+///
+/// Leaves bank 0 selected and EWRPTL with garbage, the driver sets it before using it.
+pub fn check_round_trips<S: SpiDevice>(spi: &mut S) -> Result<bool, S::Error> {
+    spi.write(&[BFC | ECON1, BANK_BITS])?;
+    for pattern in PATTERNS {
+        spi.write(&[WCR | EWRPTL, pattern])?;
+        if read(spi, EWRPTL)? != pattern {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn read<S: SpiDevice>(spi: &mut S, address: u8) -> Result<u8, S::Error> {
+    let mut value = [0];
+    spi.transaction(&mut [
+        Operation::Write(&[RCR | address]),
+        Operation::Read(&mut value),
+    ])?;
+    Ok(value[0])
+}
@@ -0,0 +1,100 @@
+//! Captive portal used until the router is configured.
+//!
+//! After booting with factory defaults every DNS query is answered with the router's own
+//! address and every HTTP request for another host is redirected to the setup page. Once the
+//! admin completes the initial configuration the portal steps aside and DNS goes back to normal
+//! forwarding.
+
+use core::net::Ipv4Addr;
+
+use crate::{
+    dns::{self, DnsError},
+    format, text,
+};
+
+/// Path of the setup page on the router's web interface.
+pub const SETUP_PATH: &str = "/setup";
+
+/// Short TTL so clients don't keep the router's address once the portal is gone.
+const DNS_TTL: u32 = 10;
+
+pub struct CaptivePortal {
+    address: Ipv4Addr,
+    active: bool,
+}
+
+impl CaptivePortal {
+    /// Portal for a router reachable at `address`, active if it booted with `factory_defaults`.
+    pub fn new(address: Ipv4Addr, factory_defaults: bool) -> Self {
+        Self {
+            address,
+            active: factory_defaults,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_address(&mut self, address: Ipv4Addr) {
+        self.address = address;
+    }
+
+    /// Initial configuration was completed, switches to normal forwarding.
+    pub fn complete(&mut self) {
+        self.active = false;
+    }
+
+    /// Answers `query` with the router's address while active.
+    ///
+    /// Returns `None` when the query must take the normal forwarding path.
+    pub fn dns_answer(&self, query: &[u8], response: &mut [u8]) -> Result<Option<usize>, DnsError> {
+        if !self.active {
+            return Ok(None);
+        }
+
+        dns::answer_any(query, self.address, DNS_TTL, response)
+    }
+
+    /// Writes a redirect to the setup page for an HTTP `request` while active.
+    ///
+    /// Returns `None` for requests the web interface must serve itself, those addressed to the
+    /// router, or when `response` is too small.
+    pub fn http_redirect(&self, request: &[u8], response: &mut [u8]) -> Option<usize> {
+        if !self.active || self.is_for_router(request) {
+            return None;
+        }
+
+        // At most "http://255.255.255.255" and the path.
+        let location =
+            format::to_string::<40>(format_args!("http://{}{SETUP_PATH}", self.address)).ok()?;
+
+        let mut len = 0;
+        for part in [
+            "HTTP/1.1 302 Found\r\nLocation: ",
+            location.as_str(),
+            "\r\nCache-Control: no-store\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ] {
+            response
+                .get_mut(len..len + part.len())?
+                .copy_from_slice(part.as_bytes());
+            len += part.len();
+        }
+
+        Some(len)
+    }
+
+    /// True if the request's Host header names the router, requests without one are assumed
+    /// to be for whoever received them.
+    fn is_for_router(&self, request: &[u8]) -> bool {
+        let Some(host) =
+            text::split_once(request, b'\n').and_then(|(_, head)| text::header(head, b"host"))
+        else {
+            return true;
+        };
+
+        // Drops the port if any.
+        let host = text::split_once(host, b':').map_or(host, |(host, _)| host);
+        host == format::to_string::<15>(self.address).unwrap().as_bytes()
+    }
+}
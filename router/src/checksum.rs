@@ -0,0 +1,225 @@
+//! Internet checksum (RFC 1071) with incremental updates (RFC 1624).
+//!
+//! The incremental variants let NAT, TTL decrement and MSS clamping patch a header checksum
+//! from the modified fields alone instead of summing the whole packet again.
+
+/// Ones' complement sum of `data` as big endian 16-bit words, folded to 16 bits.
+///
+/// An odd trailing byte is padded with zero.
+fn sum(data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = chunks
+        .by_ref()
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .fold(0u32, |acc, word| fold(acc + word));
+
+    if let [last] = chunks.remainder() {
+        sum = fold(sum + ((*last as u32) << 8));
+    }
+
+    sum
+}
+
+fn fold(mut sum: u32) -> u32 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    sum
+}
+
+/// Internet checksum of `data`.
+pub fn checksum(data: &[u8]) -> u16 {
+    !(sum(data) as u16)
+}
+
+/// Internet checksum of `pseudo_header` followed by `data`, as TCP and UDP checksum their
+/// segment along with fields of the IP header. `pseudo_header` must have an even length.
+pub fn checksum_with(pseudo_header: &[u8], data: &[u8]) -> u16 {
+    !(fold(sum(pseudo_header) + sum(data)) as u16)
+}
+
+/// Extends `checksum` of some data, e.g. as computed by the ENC28J60's DMA unit, with
+/// `pseudo_header` in front of it. `pseudo_header` must have an even length.
+pub fn extend(checksum: u16, pseudo_header: &[u8]) -> u16 {
+    !(fold((!checksum) as u32 + sum(pseudo_header)) as u16)
+}
+
+/// Updates `checksum` after a 16-bit field changed from `old` to `new`.
+///
+/// Uses eqn. 3 of RFC 1624, `HC' = ~(~HC + ~m + m')`, which never produces the `-0` (`0xFFFF`
+/// sum) that the older RFC 1141 formula could.
+pub fn update_u16(checksum: u16, old: u16, new: u16) -> u16 {
+    let sum = (!checksum) as u32 + (!old) as u32 + new as u32;
+    !(fold(sum) as u16)
+}
+
+/// Updates `checksum` after a 32-bit field, like an IPv4 address, changed from `old` to `new`.
+pub fn update_u32(checksum: u16, old: u32, new: u32) -> u16 {
+    let checksum = update_u16(checksum, (old >> 16) as u16, (new >> 16) as u16);
+    update_u16(checksum, old as u16, new as u16)
+}
+
+/// Updates `checksum` after the bytes `old` were replaced by `new`.
+///
+/// Both slices must have the same length and start at an even offset of the checksummed data.
+pub fn update_bytes(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    debug_assert_eq!(
+        old.len(),
+        new.len(),
+        "Replaced bytes must keep their length"
+    );
+
+    let sum = (!checksum) as u32 + (!(sum(old) as u16)) as u32 + sum(new);
+    !(fold(sum) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Even-length data with the index of a word in it.
+    fn data_and_word() -> impl Strategy<Value = (Vec<u8>, usize)> {
+        (1usize..32)
+            .prop_flat_map(|words| (prop::collection::vec(any::<u8>(), words * 2), 0..words))
+    }
+
+    /// Even-length data whose checksum is 0x0000, its sum being 0xFFFF, with the index of a
+    /// word in it.
+    fn zero_checksum_data_and_word() -> impl Strategy<Value = (Vec<u8>, usize)> {
+        data_and_word().prop_map(|(mut data, index)| {
+            let last = data.len() / 2 - 1;
+            set_word(&mut data, last, 0);
+            let rest = sum(&data) as u16;
+            set_word(&mut data, last, !rest);
+            (data, index)
+        })
+    }
+
+    fn word(data: &[u8], index: usize) -> u16 {
+        u16::from_be_bytes([data[index * 2], data[index * 2 + 1]])
+    }
+
+    fn set_word(data: &mut [u8], index: usize, value: u16) {
+        data[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Checks the incremental update of `index` to `new` against summing the data again.
+    fn check_update(mut data: Vec<u8>, index: usize, new: u16) -> Result<(), TestCaseError> {
+        let before = checksum(&data);
+        let old = word(&data, index);
+        set_word(&mut data, index, new);
+        // Only data summing to +0 recomputes to 0xFFFF, see updating_to_all_zeros.
+        prop_assume!(data.iter().any(|byte| *byte != 0));
+        prop_assert_eq!(update_u16(before, old, new), checksum(&data));
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn update_u16_matches_recomputing((data, index) in data_and_word(), new in any::<u16>()) {
+            check_update(data, index, new)?;
+        }
+
+        #[test]
+        fn update_u16_from_a_zero_checksum(
+            (data, index) in zero_checksum_data_and_word(),
+            new in any::<u16>(),
+        ) {
+            prop_assert_eq!(checksum(&data), 0x0000);
+            check_update(data, index, new)?;
+        }
+
+        #[test]
+        fn update_u16_to_and_from_both_zeros(
+            (data, index) in data_and_word(),
+            old in prop::sample::select(vec![0x0000u16, 0xFFFF]),
+            new in prop::sample::select(vec![0x0000u16, 0xFFFF]),
+        ) {
+            let mut data = data;
+            set_word(&mut data, index, old);
+            check_update(data, index, new)?;
+        }
+
+        #[test]
+        fn update_u32_matches_recomputing(
+            (mut data, index) in data_and_word(),
+            new in any::<u32>(),
+        ) {
+            data.extend_from_slice(&[0; 2]);
+            let old = u32::from(word(&data, index)) << 16 | u32::from(word(&data, index + 1));
+            let before = checksum(&data);
+            set_word(&mut data, index, (new >> 16) as u16);
+            set_word(&mut data, index + 1, new as u16);
+            prop_assume!(data.iter().any(|byte| *byte != 0));
+            prop_assert_eq!(update_u32(before, old, new), checksum(&data));
+        }
+
+        #[test]
+        fn update_bytes_matches_recomputing(
+            (mut data, index) in data_and_word(),
+            new in prop::collection::vec(any::<u8>(), 0..8),
+        ) {
+            let start = index * 2;
+            let end = (start + new.len()).min(data.len());
+            let new = &new[..end - start];
+            let old = data[start..end].to_vec();
+            let before = checksum(&data);
+            data[start..end].copy_from_slice(new);
+            prop_assume!(data.iter().any(|byte| *byte != 0));
+            prop_assert_eq!(update_bytes(before, &old, new), checksum(&data));
+        }
+
+        #[test]
+        fn pseudo_header_checksums_match_the_concatenation(
+            pseudo_header in prop::collection::vec(any::<u8>(), 12),
+            data in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let whole: Vec<u8> = pseudo_header.iter().chain(&data).copied().collect();
+            prop_assert_eq!(checksum_with(&pseudo_header, &data), checksum(&whole));
+            prop_assert_eq!(extend(checksum(&data), &pseudo_header), checksum(&whole));
+        }
+    }
+
+    #[test]
+    fn rfc_1071_example() {
+        // Section 3 of RFC 1071: the words sum to 0xDDF2.
+        let data = [0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7];
+        assert_eq!(checksum(&data), !0xDDF2);
+    }
+
+    #[test]
+    fn odd_trailing_byte_is_padded() {
+        assert_eq!(
+            checksum(&[0x12, 0x34, 0x56]),
+            checksum(&[0x12, 0x34, 0x56, 0x00])
+        );
+    }
+
+    #[test]
+    fn rfc_1624_example() {
+        // Section 4 of RFC 1624: the field changing from 0x5555 to 0x3285 in data checksummed
+        // 0xDD2F yields 0x0000, where RFC 1141 gave 0xFFFF.
+        assert_eq!(update_u16(0xDD2F, 0x5555, 0x3285), 0x0000);
+    }
+
+    #[test]
+    fn updating_to_all_zeros() {
+        // +0 and -0 are the same ones' complement number. Summing all-zero data gives +0 and a
+        // checksum of 0xFFFF, the update can't tell it from -0 and gives 0x0000.
+        let before = checksum(&[0xFF, 0xFF]);
+        assert_eq!(before, 0x0000);
+        assert_eq!(checksum(&[0x00, 0x00]), 0xFFFF);
+        assert_eq!(update_u16(before, 0xFFFF, 0x0000), 0x0000);
+    }
+
+    #[test]
+    fn unchanged_field_keeps_the_checksum() {
+        for checksum in [0x0000, 0x1234, 0xFFFE] {
+            for value in [0x0000, 0x8000, 0xFFFF] {
+                assert_eq!(update_u16(checksum, value, value), checksum);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! IPv4 prefixes in CIDR notation.
+//!
+//! Addresses themselves are `core::net::Ipv4Addr`, which is already const-constructible.
+
+use core::{fmt, net::Ipv4Addr, str::FromStr};
+
+use crate::text;
+
+/// An address with a prefix length, like `192.168.1.1/24`.
+///
+/// The host bits of the address are kept, so the same type describes both an interface
+/// address and the subnet it's in; [`Ipv4Cidr::network`] drops them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Cidr {
+    address: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Matches every address.
+    pub const ANY: Ipv4Cidr = Ipv4Cidr {
+        address: Ipv4Addr::UNSPECIFIED,
+        prefix_len: 0,
+    };
+
+    /// `None` if `prefix_len` is over 32.
+    pub const fn new(address: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        if prefix_len > 32 {
+            return None;
+        }
+
+        Some(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    /// A single address, `/32`.
+    pub const fn host(address: Ipv4Addr) -> Self {
+        Self {
+            address,
+            prefix_len: 32,
+        }
+    }
+
+    pub const fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    pub const fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub const fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from_bits(self.mask())
+    }
+
+    /// The prefix with the host bits cleared.
+    pub const fn network(&self) -> Self {
+        Self {
+            address: Ipv4Addr::from_bits(self.address.to_bits() & self.mask()),
+            prefix_len: self.prefix_len,
+        }
+    }
+
+    /// Last address of the prefix.
+    pub const fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_bits(self.address.to_bits() | !self.mask())
+    }
+
+    pub const fn contains(&self, address: Ipv4Addr) -> bool {
+        (address.to_bits() ^ self.address.to_bits()) & self.mask() == 0
+    }
+
+    /// True if every address of `other` is in `self`.
+    pub const fn contains_cidr(&self, other: &Ipv4Cidr) -> bool {
+        other.prefix_len >= self.prefix_len && self.contains(other.address)
+    }
+
+    /// True if the two prefixes share any address.
+    pub const fn overlaps(&self, other: &Ipv4Cidr) -> bool {
+        self.contains_cidr(other) || other.contains_cidr(self)
+    }
+
+    /// Addresses usable by hosts, all but the network and broadcast addresses except for
+    /// `/31` and `/32` which have none to spare.
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> + use<> {
+        let first = self.network().address.to_bits();
+        let last = self.broadcast().to_bits();
+        let range = if self.prefix_len >= 31 {
+            first..=last
+        } else {
+            first + 1..=last - 1
+        };
+
+        range.map(Ipv4Addr::from_bits)
+    }
+
+    const fn mask(&self) -> u32 {
+        match u32::MAX.checked_shl(32 - self.prefix_len as u32) {
+            Some(mask) => mask,
+            None => 0,
+        }
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Ipv4Cidr {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        let [a, b, c, d] = self.address.octets();
+        defmt::write!(f, "{}.{}.{}.{}/{}", a, b, c, d, self.prefix_len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseCidrError;
+
+/// Parses `a.b.c.d/len`, a bare address being a `/32`.
+impl FromStr for Ipv4Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => {
+                let prefix_len = text::parse_decimal(prefix_len.as_bytes(), 32u8);
+                (address, prefix_len.ok_or(ParseCidrError)?)
+            }
+            None => (s, 32),
+        };
+
+        let address = address.parse().map_err(|_| ParseCidrError)?;
+        Self::new(address, prefix_len).ok_or(ParseCidrError)
+    }
+}
@@ -0,0 +1,259 @@
+//! Management command line.
+//!
+//! Commands are parsed from a single line of whitespace separated words, executing them is left
+//! to the subsystems they target.
+//!
+//! Configuration commands can also be run as a batch, one per line, for provisioning a router
+//! in one go rather than line by line over the serial console. A batch is pasted after a
+//! `batch` command, or posted over HTTP, and runs as a whole: the configuration only changes if
+//! every command succeeds.
+
+use thiserror::Error;
+
+use core::{net::Ipv4Addr, str::FromStr};
+
+use crate::{
+    config::{Config, ConfigError},
+    conntrack::{FlowKey, Protocol},
+    ethernet::MacAddress,
+    log::{Level, Module},
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CliError {
+    #[error("Unknown command.")]
+    UnknownCommand,
+    #[error("Command is missing an argument.")]
+    MissingArgument,
+    #[error("Command has an invalid or extra argument.")]
+    InvalidArgument,
+    #[error("Setting is unknown or its value invalid.")]
+    InvalidSetting,
+    #[error("Command can't be run in a batch.")]
+    NotInBatch,
+    #[error("Batch is too large.")]
+    BatchTooLarge,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BatchError {
+    #[error("Line {line}: {error}")]
+    Command { line: usize, error: CliError },
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// `interface <name>`
+    ShowInterface { name: &'a str },
+    /// `interface <name> up|down`
+    SetInterfaceAdmin { name: &'a str, up: bool },
+    /// `show config`
+    ShowConfig,
+    /// `show version`, see [`crate::sysinfo`]
+    ShowVersion,
+    /// `show memory`, the fill and high-water marks of the static pools
+    ShowMemory,
+    /// `show profiling`, the cycles spent in each stage of the packet path, see
+    /// [`crate::profiling`]
+    ShowProfiling,
+    /// `set <key> <value>`, as in the configuration's text form
+    Set { key: &'a str, value: &'a str },
+    /// `batch`, the following lines up to `end` being a batch
+    Batch,
+    /// `log <module>`
+    ShowLogLevel { module: Module },
+    /// `log <module> off|error|warn|info|debug|trace`
+    SetLogLevel { module: Module, level: Level },
+    /// `trace`
+    ShowTrace,
+    /// `trace tcp|udp|icmp <source> <destination>`, addresses as `a.b.c.d:port`
+    StartTrace { key: FlowKey },
+    /// `trace off`
+    StopTrace,
+    /// `arp`
+    ShowArp,
+    /// `arp add <address> <mac>`, a static entry
+    AddArp { address: Ipv4Addr, mac: MacAddress },
+    /// `arp del <address>`
+    RemoveArp { address: Ipv4Addr },
+    /// `l2ping <interface> <mac>`, see [`crate::l2ping`]
+    L2Ping {
+        interface: &'a str,
+        target: MacAddress,
+    },
+}
+
+pub fn parse(line: &str) -> Result<Command<'_>, CliError> {
+    let mut words = line.split_ascii_whitespace();
+    let command = match words.next().ok_or(CliError::UnknownCommand)? {
+        "interface" => {
+            let name = words.next().ok_or(CliError::MissingArgument)?;
+            match words.next() {
+                None => Command::ShowInterface { name },
+                Some("up") => Command::SetInterfaceAdmin { name, up: true },
+                Some("down") => Command::SetInterfaceAdmin { name, up: false },
+                Some(_) => return Err(CliError::InvalidArgument),
+            }
+        }
+        "log" => {
+            let module = words.next().ok_or(CliError::MissingArgument)?;
+            let module = module.parse().map_err(|_| CliError::InvalidArgument)?;
+            match words.next() {
+                None => Command::ShowLogLevel { module },
+                Some(level) => Command::SetLogLevel {
+                    module,
+                    level: level.parse().map_err(|_| CliError::InvalidArgument)?,
+                },
+            }
+        }
+        "trace" => match words.next() {
+            None => Command::ShowTrace,
+            Some("off") => Command::StopTrace,
+            Some(protocol) => {
+                let protocol = match protocol {
+                    "tcp" => Protocol::Tcp,
+                    "udp" => Protocol::Udp,
+                    "icmp" => Protocol::Icmp,
+                    _ => return Err(CliError::InvalidArgument),
+                };
+                let source = parse_word(words.next())?;
+                let destination = parse_word(words.next())?;
+                Command::StartTrace {
+                    key: FlowKey {
+                        protocol,
+                        source,
+                        destination,
+                    },
+                }
+            }
+        },
+        "arp" => match words.next() {
+            None => Command::ShowArp,
+            Some("add") => Command::AddArp {
+                address: parse_word(words.next())?,
+                mac: parse_word(words.next())?,
+            },
+            Some("del") => Command::RemoveArp {
+                address: parse_word(words.next())?,
+            },
+            Some(_) => return Err(CliError::InvalidArgument),
+        },
+        "l2ping" => Command::L2Ping {
+            interface: words.next().ok_or(CliError::MissingArgument)?,
+            target: parse_word(words.next())?,
+        },
+        "set" => {
+            let key = words.next().ok_or(CliError::MissingArgument)?;
+            let value = words.next().ok_or(CliError::MissingArgument)?;
+            Command::Set { key, value }
+        }
+        "batch" => Command::Batch,
+        "show" => match words.next().ok_or(CliError::MissingArgument)? {
+            "config" => Command::ShowConfig,
+            "version" => Command::ShowVersion,
+            "memory" => Command::ShowMemory,
+            "profiling" => Command::ShowProfiling,
+            _ => return Err(CliError::InvalidArgument),
+        },
+        _ => return Err(CliError::UnknownCommand),
+    };
+
+    if words.next().is_some() {
+        return Err(CliError::InvalidArgument);
+    }
+
+    Ok(command)
+}
+
+fn parse_word<T: FromStr>(word: Option<&str>) -> Result<T, CliError> {
+    word.ok_or(CliError::MissingArgument)?
+        .parse()
+        .map_err(|_| CliError::InvalidArgument)
+}
+
+/// Runs the configuration commands in `text`, one per line, `#` starting a comment, on
+/// `config` as a whole: either every command succeeds and `config` is updated, or it's left
+/// untouched. Returns the number of commands run.
+///
+/// Only `set` and `log` commands can be batched, other commands act on running subsystems
+/// and couldn't be rolled back.
+pub fn run_batch(text: &str, config: &mut Config) -> Result<usize, BatchError> {
+    let mut staged = config.clone();
+    let mut count = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let failed = |error| BatchError::Command {
+            line: index + 1,
+            error,
+        };
+        match parse(line).map_err(failed)? {
+            Command::Set { key, value } => staged
+                .set(key, value)
+                .map_err(|_| failed(CliError::InvalidSetting))?,
+            Command::SetLogLevel { module, level } => staged.log_levels[module as usize] = level,
+            _ => return Err(failed(CliError::NotInBatch)),
+        }
+        count += 1;
+    }
+
+    staged.validate()?;
+    *config = staged;
+    Ok(count)
+}
+
+/// Collects the lines pasted after a `batch` command, up to `N` bytes, until a line with
+/// just `end`.
+pub struct PasteBuffer<const N: usize> {
+    text: heapless::String<N>,
+    /// Lines didn't fit, the batch is refused once it ends.
+    overflowed: bool,
+}
+
+impl<const N: usize> Default for PasteBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PasteBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            text: heapless::String::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Adds a pasted line, returning the batch once `line` ends it, for [`run_batch`].
+    ///
+    /// A batch too large is refused as a whole once it ends, rather than cut short.
+    pub fn push_line(&mut self, line: &str) -> Result<Option<&str>, CliError> {
+        if line.trim() == "end" {
+            if core::mem::take(&mut self.overflowed) {
+                self.text.clear();
+                return Err(CliError::BatchTooLarge);
+            }
+            return Ok(Some(&self.text));
+        }
+
+        if !self.overflowed && (self.text.push_str(line).is_err() || self.text.push('\n').is_err())
+        {
+            self.overflowed = true;
+        }
+        Ok(None)
+    }
+
+    /// Gets ready for the next batch.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.overflowed = false;
+    }
+}
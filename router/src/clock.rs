@@ -0,0 +1,76 @@
+//! Time source of the services.
+//!
+//! The services take the current tick as a `now` argument rather than reading a clock, the
+//! main loop reads its [`Clock`] once per pass and hands the same tick to all of them. On host
+//! builds a [`MockClock`] stands in for the hardware one: it only moves when told to, so lease
+//! expiry, ARP aging, retransmissions or the main loop's service gaps can be stepped through
+//! without waiting, and end up the same on every run. The firewall has no time-based rules to
+//! step through.
+//!
+//! A tick is whatever the caller's clock counts, the services don't assume a length: their
+//! durations, timeouts and intervals are all in ticks, and their defaults document the tick
+//! length they were picked for.
+//!
+//! Ticks are `u32` and wrap, deadlines are compared with wrapping arithmetic as everywhere in
+//! the firmware, see [`is_due`].
+
+use core::cell::Cell;
+
+/// Source of the current tick.
+pub trait Clock {
+    fn now(&self) -> u32;
+
+    /// Ticks since `since`.
+    fn elapsed(&self, since: u32) -> u32 {
+        self.now().wrapping_sub(since)
+    }
+
+    /// Whether `deadline` is reached.
+    fn is_due(&self, deadline: u32) -> bool {
+        is_due(deadline, self.now())
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> u32 {
+        (**self).now()
+    }
+}
+
+/// Whether `deadline` is reached at `now`, for deadlines up to half the tick range ahead.
+pub fn is_due(deadline: u32, now: u32) -> bool {
+    (deadline.wrapping_sub(now) as i32) <= 0
+}
+
+/// Clock moved by hand.
+///
+/// It's shared by reference with whatever reads it and moved through that same reference,
+/// hence the interior mutability.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Cell<u32>,
+}
+
+impl MockClock {
+    pub const fn new(now: u32) -> Self {
+        Self {
+            now: Cell::new(now),
+        }
+    }
+
+    /// Moves the clock `ticks` forward, wrapping like a hardware counter.
+    pub fn advance(&self, ticks: u32) {
+        self.now.set(self.now.get().wrapping_add(ticks));
+    }
+
+    /// Moves the clock to `now`, backward too, e.g. to replay a sequence of events.
+    pub fn set(&self, now: u32) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+}
@@ -0,0 +1,193 @@
+//! Coalescing of repeated data-plane log messages.
+//!
+//! Firewall drops or checksum errors can come in by the thousand every second, a message each
+//! would drown the log sinks and the CPU formatting them. The first event of a kind from a
+//! source is logged right away, the ones following it within the window are only counted and
+//! summed up in a single message when the window ends, like `firewall drop from 203.0.113.5
+//! repeated 1432 times in 10000 ticks`.
+//!
+//! The default window assumes one tick per millisecond.
+
+use core::{fmt, net::Ipv4Addr};
+
+use crate::log::{Level, Module};
+
+/// Data-plane events that get coalesced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DataPlaneEvent {
+    FirewallDrop,
+    ChecksumError,
+    /// Dropped for its IPv4 options, see [`crate::ipopts`].
+    OptionsDrop,
+}
+
+impl DataPlaneEvent {
+    pub const COUNT: usize = 3;
+    pub const ALL: [DataPlaneEvent; Self::COUNT] = [
+        DataPlaneEvent::FirewallDrop,
+        DataPlaneEvent::ChecksumError,
+        DataPlaneEvent::OptionsDrop,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            DataPlaneEvent::FirewallDrop => "firewall drop",
+            DataPlaneEvent::ChecksumError => "checksum error",
+            DataPlaneEvent::OptionsDrop => "options drop",
+        }
+    }
+
+    fn module(&self) -> Module {
+        match self {
+            DataPlaneEvent::FirewallDrop | DataPlaneEvent::OptionsDrop => Module::Firewall,
+            DataPlaneEvent::ChecksumError => Module::Driver,
+        }
+    }
+
+    fn level(&self) -> Level {
+        match self {
+            DataPlaneEvent::FirewallDrop | DataPlaneEvent::OptionsDrop => Level::Info,
+            DataPlaneEvent::ChecksumError => Level::Warn,
+        }
+    }
+}
+
+impl fmt::Display for DataPlaneEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Counters of the events recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CoalesceStats {
+    /// Events recorded, indexed by [`DataPlaneEvent`].
+    pub events: [u32; DataPlaneEvent::COUNT],
+    /// Messages logged, first occurrences and summaries.
+    pub messages: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    event: DataPlaneEvent,
+    source: Ipv4Addr,
+    started_at: u32,
+    /// Events since the logged one.
+    repeated: u32,
+}
+
+/// Coalesces repeated events from up to `N` sources at a time.
+///
+/// Once `N` sources are in a window, events from other sources are counted per kind and summed
+/// up along with the next window to end.
+pub struct Coalescer<const N: usize> {
+    window: u32,
+    windows: heapless::Vec<Window, N>,
+    /// Events that found no free window, indexed by [`DataPlaneEvent`].
+    overflow: [u32; DataPlaneEvent::COUNT],
+    stats: CoalesceStats,
+}
+
+impl<const N: usize> Coalescer<N> {
+    pub const DEFAULT_WINDOW: u32 = 10_000;
+
+    pub fn new(window: u32) -> Self {
+        Self {
+            window,
+            windows: heapless::Vec::new(),
+            overflow: [0; DataPlaneEvent::COUNT],
+            stats: CoalesceStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CoalesceStats {
+        self.stats
+    }
+
+    /// Records an event from `source`, logging it unless it repeats one in its window.
+    pub fn record(&mut self, event: DataPlaneEvent, source: Ipv4Addr, now: u32) {
+        self.stats.events[event as usize] += 1;
+        if !crate::log::enabled(event.module(), event.level()) {
+            return;
+        }
+
+        if let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|window| window.event == event && window.source == source)
+        {
+            window.repeated += 1;
+            return;
+        }
+
+        let opened = self.windows.push(Window {
+            event,
+            source,
+            started_at: now,
+            repeated: 0,
+        });
+        if opened.is_err() {
+            self.overflow[event as usize] += 1;
+            return;
+        }
+
+        self.stats.messages += 1;
+        crate::log!(
+            event.module(),
+            event.level(),
+            "{} from {}",
+            event,
+            source.octets()
+        );
+    }
+
+    /// Ends the windows that are over, logging a summary of those with repeated events. Call it
+    /// periodically, a window only ends on a call.
+    pub fn flush(&mut self, now: u32) {
+        let length = self.window;
+        let mut ended = false;
+        let mut messages = 0;
+
+        self.windows.retain(|window| {
+            let elapsed = now.wrapping_sub(window.started_at);
+            if elapsed < length {
+                return true;
+            }
+
+            ended = true;
+            if window.repeated > 0 {
+                messages += 1;
+                crate::log!(
+                    window.event.module(),
+                    window.event.level(),
+                    "{} from {} repeated {} times in {} ticks",
+                    window.event,
+                    window.source.octets(),
+                    window.repeated,
+                    elapsed
+                );
+            }
+            false
+        });
+
+        if ended {
+            for event in DataPlaneEvent::ALL {
+                let count = core::mem::take(&mut self.overflow[event as usize]);
+                if count > 0 {
+                    messages += 1;
+                    crate::log!(
+                        event.module(),
+                        event.level(),
+                        "{} from other sources {} times",
+                        event,
+                        count
+                    );
+                }
+            }
+        }
+
+        self.stats.messages += messages;
+    }
+}
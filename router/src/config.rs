@@ -0,0 +1,603 @@
+//! Router configuration and its text form.
+//!
+//! The text form is one `key value` setting per line, `#` starting a comment. Export writes
+//! every setting so the text is a complete configuration that can be versioned or copied to
+//! another device. Import parses the whole text into a new configuration and validates it
+//! before anything is applied, so a bad line never leaves the router half-configured.
+//!
+//! The text form starts with its version. When a firmware upgrade renames, removes or changes
+//! the meaning of settings, it bumps [`VERSION`] and adds a [`Migration`], and settings of
+//! older configurations are migrated on import instead of being refused, which would have the
+//! router fall back to its defaults.
+
+use core::{
+    fmt::{self, Write},
+    net::Ipv4Addr,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{
+    cidr::Ipv4Cidr,
+    conntrack::AlgConfig,
+    enc28j60::RxBatch,
+    firewall::Action,
+    format::Duration,
+    icmp::{QueryAction, QueryPolicy},
+    identity::{Domain, Hostname, SearchList},
+    ipopts::{OptionAction, OptionsPolicy},
+    log::{self, Level, Module},
+    rxcsum::{ChecksumPolicy, ChecksumProtocol, VerifyMode},
+    text,
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    #[error("Line {0}: unknown setting.")]
+    UnknownKey(usize),
+    #[error("Line {0}: invalid value.")]
+    InvalidValue(usize),
+    #[error("DHCP pool isn't a range within the LAN subnet.")]
+    InvalidDhcpPool,
+    #[error("Configuration version {0} is newer than this firmware's.")]
+    UnsupportedVersion(u16),
+}
+
+/// Version of the text form written by [`Config::export`].
+pub const VERSION: u16 = 1;
+
+/// Key of the line holding the version, which comes before any setting.
+const VERSION_KEY: &str = "config.version";
+
+/// A setting as read from the text form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setting<'a> {
+    pub key: &'a str,
+    pub value: &'a str,
+}
+
+/// Upgrade of settings from one version of the text form to the next.
+pub struct Migration {
+    /// Version the settings are upgraded from.
+    pub from: u16,
+    /// Returns the setting as of the next version, `None` to drop a removed setting.
+    pub apply: fn(Setting<'_>) -> Option<Setting<'_>>,
+}
+
+/// Migrations in version order, the setting of a configuration at version `n` going through
+/// every one from `n` on.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Applies the migrations from `version` on to `setting`.
+fn migrate(version: u16, setting: Setting<'_>) -> Option<Setting<'_>> {
+    migrate_with(MIGRATIONS, version, setting)
+}
+
+fn migrate_with<'a>(
+    migrations: &[Migration],
+    version: u16,
+    setting: Setting<'a>,
+) -> Option<Setting<'a>> {
+    migrations
+        .iter()
+        .filter(|migration| migration.from >= version)
+        .try_fold(setting, |setting, migration| (migration.apply)(setting))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub hostname: Hostname,
+    /// LAN domain, see [`crate::identity`].
+    pub domain: Domain,
+    /// Search domains handed out after the LAN domain, the WAN's when empty.
+    pub search_domains: SearchList,
+    /// Router's LAN address and subnet.
+    pub lan_address: Ipv4Cidr,
+    pub dhcp_pool_start: Ipv4Addr,
+    pub dhcp_pool_end: Ipv4Addr,
+    pub dhcp_lease_time: Duration,
+    pub dns_rebind_protection: bool,
+    pub firewall_default: Action,
+    /// Whether LAN clients reach port forwards through the WAN address, see [`crate::nat`].
+    pub nat_hairpin: bool,
+    /// Which application level gateways run, see [`crate::ftp`] and [`crate::sip`].
+    pub algs: AlgConfig,
+    pub wan_preempt: bool,
+    pub wan_down_after: u8,
+    pub wan_up_after: u8,
+    pub session_timeout: Duration,
+    /// Action on forwarded packets with source routing options.
+    pub ip_source_route: OptionAction,
+    /// Action on forwarded packets with any other option.
+    pub ip_options: OptionAction,
+    /// Action on ICMP timestamp requests to the router.
+    pub icmp_timestamp: QueryAction,
+    /// Action on ICMP address mask requests to the router, from the LAN only.
+    pub icmp_address_mask: QueryAction,
+    /// Frames received per interface and pass of the main loop, see [`crate::sched`].
+    pub rx_batch: u8,
+    /// Whether applications may use raw sockets, see [`crate::rawsock`].
+    pub raw_sockets: bool,
+    /// Whether received frames with header anomalies are rejected, see [`crate::rxstrict`].
+    pub strict_rx: bool,
+    /// How received checksums are verified, indexed by [`ChecksumProtocol`].
+    pub checksum_modes: [VerifyMode; ChecksumProtocol::COUNT],
+    /// Indexed by [`Module`].
+    pub log_levels: [Level; Module::COUNT],
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hostname: Hostname::default(),
+            domain: Domain::default(),
+            search_domains: SearchList::new(),
+            lan_address: Ipv4Cidr::new(Ipv4Addr::new(192, 168, 1, 1), 24).unwrap(),
+            dhcp_pool_start: Ipv4Addr::new(192, 168, 1, 100),
+            dhcp_pool_end: Ipv4Addr::new(192, 168, 1, 199),
+            dhcp_lease_time: Duration(86_400),
+            dns_rebind_protection: true,
+            firewall_default: Action::Drop,
+            nat_hairpin: false,
+            algs: AlgConfig::default(),
+            wan_preempt: true,
+            wan_down_after: 3,
+            wan_up_after: 5,
+            session_timeout: Duration(900),
+            ip_source_route: OptionAction::Drop,
+            ip_options: OptionAction::Pass,
+            icmp_timestamp: QueryAction::Drop,
+            icmp_address_mask: QueryAction::Drop,
+            rx_batch: RxBatch::DEFAULT_BUDGET,
+            raw_sockets: false,
+            strict_rx: false,
+            checksum_modes: ChecksumPolicy::default().modes,
+            log_levels: [log::DEFAULT_LEVEL; Module::COUNT],
+        }
+    }
+}
+
+/// Keys in export order, followed by a `checksum.<protocol>` key per [`ChecksumProtocol`] and a
+/// `log.<module>` key per [`Module`].
+const KEYS: [&str; 23] = [
+    "system.hostname",
+    "system.domain",
+    "lan.address",
+    "dhcp.pool_start",
+    "dhcp.pool_end",
+    "dhcp.lease_time",
+    "dhcp.search_domains",
+    "dns.rebind_protection",
+    "firewall.default",
+    "nat.hairpin",
+    "alg.ftp",
+    "alg.sip",
+    "wan.preempt",
+    "wan.down_after",
+    "wan.up_after",
+    "auth.session_timeout",
+    "ip.source_route",
+    "ip.options",
+    "icmp.timestamp",
+    "icmp.address_mask",
+    "eth.rx_batch",
+    "eth.raw_sockets",
+    "eth.strict_rx",
+];
+
+impl Config {
+    /// Writes the version and every setting, one per line.
+    pub fn export(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "{VERSION_KEY} {VERSION}")?;
+        for key in KEYS {
+            write!(out, "{key} ")?;
+            self.write_value(key, out)?;
+            out.write_char('\n')?;
+        }
+        for protocol in ChecksumProtocol::ALL {
+            let mode = self.checksum_modes[protocol as usize];
+            writeln!(out, "checksum.{protocol} {mode}")?;
+        }
+        for module in Module::ALL {
+            writeln!(out, "log.{module} {}", self.log_levels[module as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses a complete configuration, settings missing from `text` keep their defaults.
+    ///
+    /// Configurations of older versions are migrated, those without a version predate
+    /// versioning and are at version 1.
+    pub fn import(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut version = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            if key == VERSION_KEY {
+                let parsed = text::parse_decimal(value.as_bytes(), u16::MAX)
+                    .filter(|parsed| *parsed > 0 && version.is_none())
+                    .ok_or(ConfigError::InvalidValue(index + 1))?;
+                if parsed > VERSION {
+                    return Err(ConfigError::UnsupportedVersion(parsed));
+                }
+                version = Some(parsed);
+                continue;
+            }
+
+            // Settings before a version line are read as version 1, the line can't come after.
+            let Some(setting) = migrate(*version.get_or_insert(1), Setting { key, value }) else {
+                continue;
+            };
+            config
+                .set(setting.key, setting.value)
+                .map_err(|error| match error {
+                    SetError::UnknownKey => ConfigError::UnknownKey(index + 1),
+                    SetError::InvalidValue => ConfigError::InvalidValue(index + 1),
+                })?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks settings depending on each other.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let subnet = self.lan_address.network();
+        let pool_valid = self.dhcp_pool_start <= self.dhcp_pool_end
+            && subnet.contains(self.dhcp_pool_start)
+            && subnet.contains(self.dhcp_pool_end);
+
+        pool_valid.then_some(()).ok_or(ConfigError::InvalidDhcpPool)
+    }
+
+    /// Policy for the IPv4 options of forwarded packets.
+    pub fn options_policy(&self) -> OptionsPolicy {
+        OptionsPolicy::new(self.ip_source_route, self.ip_options)
+    }
+
+    /// Policy for the ICMP queries addressed to the router.
+    pub fn icmp_policy(&self) -> QueryPolicy {
+        QueryPolicy::new(self.icmp_timestamp, self.icmp_address_mask)
+    }
+
+    /// Policy for verifying the checksums of received packets.
+    pub fn checksum_policy(&self) -> ChecksumPolicy {
+        ChecksumPolicy {
+            modes: self.checksum_modes,
+        }
+    }
+
+    /// Changes a single setting, as in the text form.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SetError> {
+        match key {
+            "system.hostname" => self.hostname = parse(value)?,
+            "system.domain" => self.domain = parse(value)?,
+            "lan.address" => self.lan_address = parse(value)?,
+            "dhcp.pool_start" => self.dhcp_pool_start = parse(value)?,
+            "dhcp.pool_end" => self.dhcp_pool_end = parse(value)?,
+            "dhcp.lease_time" => self.dhcp_lease_time = parse(value)?,
+            "dhcp.search_domains" => self.search_domains = parse(value)?,
+            "dns.rebind_protection" => self.dns_rebind_protection = parse_switch(value)?,
+            "firewall.default" => {
+                self.firewall_default = match value {
+                    "accept" => Action::Accept,
+                    "drop" => Action::Drop,
+                    _ => return Err(SetError::InvalidValue),
+                }
+            }
+            "nat.hairpin" => self.nat_hairpin = parse_switch(value)?,
+            "alg.ftp" => self.algs.ftp = parse_switch(value)?,
+            "alg.sip" => self.algs.sip = parse_switch(value)?,
+            "wan.preempt" => self.wan_preempt = parse_switch(value)?,
+            "wan.down_after" => self.wan_down_after = parse(value)?,
+            "wan.up_after" => self.wan_up_after = parse(value)?,
+            "auth.session_timeout" => self.session_timeout = parse(value)?,
+            "ip.source_route" => self.ip_source_route = parse(value)?,
+            "ip.options" => self.ip_options = parse(value)?,
+            "icmp.timestamp" => self.icmp_timestamp = parse(value)?,
+            "icmp.address_mask" => self.icmp_address_mask = parse(value)?,
+            "eth.rx_batch" => {
+                let budget = parse(value)?;
+                if budget == 0 {
+                    return Err(SetError::InvalidValue);
+                }
+                self.rx_batch = budget;
+            }
+            "eth.raw_sockets" => self.raw_sockets = parse_switch(value)?,
+            "eth.strict_rx" => self.strict_rx = parse_switch(value)?,
+            _ => {
+                if let Some(protocol) = checksum_protocol(key) {
+                    self.checksum_modes[protocol as usize] = parse(value)?;
+                } else {
+                    let module = log_module(key).ok_or(SetError::UnknownKey)?;
+                    self.log_levels[module as usize] = parse(value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the value of `key` as in the text form.
+    pub fn write_value(&self, key: &str, out: &mut impl Write) -> fmt::Result {
+        let switch = |on: bool| if on { "on" } else { "off" };
+        match key {
+            "system.hostname" => write!(out, "{}", self.hostname),
+            "system.domain" => write!(out, "{}", self.domain),
+            "lan.address" => write!(out, "{}", self.lan_address),
+            "dhcp.pool_start" => write!(out, "{}", self.dhcp_pool_start),
+            "dhcp.pool_end" => write!(out, "{}", self.dhcp_pool_end),
+            "dhcp.lease_time" => write!(out, "{}s", self.dhcp_lease_time.0),
+            "dhcp.search_domains" => write!(out, "{}", self.search_domains),
+            "dns.rebind_protection" => out.write_str(switch(self.dns_rebind_protection)),
+            "firewall.default" => out.write_str(match self.firewall_default {
+                Action::Accept => "accept",
+                Action::Drop => "drop",
+            }),
+            "nat.hairpin" => out.write_str(switch(self.nat_hairpin)),
+            "alg.ftp" => out.write_str(switch(self.algs.ftp)),
+            "alg.sip" => out.write_str(switch(self.algs.sip)),
+            "wan.preempt" => out.write_str(switch(self.wan_preempt)),
+            "wan.down_after" => write!(out, "{}", self.wan_down_after),
+            "wan.up_after" => write!(out, "{}", self.wan_up_after),
+            "auth.session_timeout" => write!(out, "{}s", self.session_timeout.0),
+            "ip.source_route" => write!(out, "{}", self.ip_source_route),
+            "ip.options" => write!(out, "{}", self.ip_options),
+            "icmp.timestamp" => write!(out, "{}", self.icmp_timestamp),
+            "icmp.address_mask" => write!(out, "{}", self.icmp_address_mask),
+            "eth.rx_batch" => write!(out, "{}", self.rx_batch),
+            "eth.raw_sockets" => out.write_str(switch(self.raw_sockets)),
+            "eth.strict_rx" => out.write_str(switch(self.strict_rx)),
+            _ => {
+                if let Some(protocol) = checksum_protocol(key) {
+                    return write!(out, "{}", self.checksum_modes[protocol as usize]);
+                }
+                let module = log_module(key).ok_or(fmt::Error)?;
+                write!(out, "{}", self.log_levels[module as usize])
+            }
+        }
+    }
+}
+
+/// Why a single setting couldn't be changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SetError {
+    UnknownKey,
+    InvalidValue,
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, SetError> {
+    value.parse().map_err(|_| SetError::InvalidValue)
+}
+
+/// Protocol of a `checksum.<protocol>` key.
+fn checksum_protocol(key: &str) -> Option<ChecksumProtocol> {
+    key.strip_prefix("checksum.")?.parse().ok()
+}
+
+/// Module of a `log.<module>` key.
+fn log_module(key: &str) -> Option<Module> {
+    key.strip_prefix("log.")?.parse().ok()
+}
+
+fn parse_switch(value: &str) -> Result<bool, SetError> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(SetError::InvalidValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(config: &Config) -> heapless::String<2048> {
+        let mut text = heapless::String::new();
+        config.export(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn sip_alg_is_off_unless_set() {
+        assert!(!Config::default().algs.sip);
+
+        let config = Config::import("alg.sip on\n").unwrap();
+        assert!(config.algs.sip);
+        assert!(config.algs.ftp);
+        assert!(export(&config).lines().any(|line| line == "alg.sip on"));
+        assert_eq!(
+            Config::import("alg.sip yes\n"),
+            Err(ConfigError::InvalidValue(1))
+        );
+    }
+
+    #[test]
+    fn export_then_import_gives_the_same_configuration() {
+        let mut config = Config::default();
+        for (key, value) in [
+            ("system.hostname", "gateway"),
+            ("system.domain", "home.example"),
+            ("lan.address", "10.1.0.1/16"),
+            ("dhcp.pool_start", "10.1.2.10"),
+            ("dhcp.pool_end", "10.1.2.250"),
+            ("dhcp.lease_time", "2h"),
+            ("dhcp.search_domains", "home.example,example.net"),
+            ("firewall.default", "accept"),
+            ("nat.hairpin", "on"),
+            ("wan.up_after", "9"),
+            ("eth.rx_batch", "16"),
+            ("checksum.udp", "skip"),
+            ("log.nat", "trace"),
+        ] {
+            config.set(key, value).unwrap();
+        }
+
+        let text = export(&config);
+        assert_eq!(text.lines().next(), Some("config.version 1"));
+        assert_eq!(Config::import(&text), Ok(config));
+    }
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        let config = Config::import("# Only the batch size.\n\neth.rx_batch 4\n").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                rx_batch: 4,
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn import_reports_the_line_of_an_unknown_key() {
+        let text = "config.version 1\n# Comment\n\nwan.preempt on\nwan.flap_after 3\n";
+        assert_eq!(Config::import(text), Err(ConfigError::UnknownKey(5)));
+        assert_eq!(
+            Config::import("log.kernel debug\n"),
+            Err(ConfigError::UnknownKey(1))
+        );
+    }
+
+    #[test]
+    fn import_rejects_invalid_values() {
+        for text in [
+            "wan.up_after 256\n",
+            "dns.rebind_protection maybe\n",
+            "lan.address 10.1.0.1/33\n",
+            "eth.rx_batch 0\n",
+            "firewall.default reject\n",
+            "checksum.tcp never\n",
+            "system.hostname -gateway\n",
+        ] {
+            assert_eq!(
+                Config::import(text),
+                Err(ConfigError::InvalidValue(1)),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn import_validates_the_pool_against_the_lan() {
+        // The default pool is left in 192.168.1.0/24.
+        assert_eq!(
+            Config::import("lan.address 10.0.0.1/24\n"),
+            Err(ConfigError::InvalidDhcpPool)
+        );
+        assert_eq!(
+            Config::import("dhcp.pool_start 192.168.1.200\ndhcp.pool_end 192.168.1.100\n"),
+            Err(ConfigError::InvalidDhcpPool)
+        );
+    }
+
+    #[test]
+    fn failed_set_leaves_the_setting_as_it_was() {
+        let mut config = Config::default();
+        assert_eq!(config.set("eth.rx_batch", "0"), Err(SetError::InvalidValue));
+        assert_eq!(config.set("eth.rx_budget", "8"), Err(SetError::UnknownKey));
+        assert_eq!(config, Config::default());
+    }
+
+    /// Version 1 renamed `eth.rxbatch` to `eth.batch` and dropped `wan.legacy`, version 2
+    /// renamed `eth.batch` to `eth.rx_batch`.
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            from: 1,
+            apply: from_1,
+        },
+        Migration {
+            from: 2,
+            apply: from_2,
+        },
+    ];
+
+    fn from_1(setting: Setting<'_>) -> Option<Setting<'_>> {
+        match setting.key {
+            "eth.rxbatch" => Some(Setting {
+                key: "eth.batch",
+                ..setting
+            }),
+            "wan.legacy" => None,
+            _ => Some(setting),
+        }
+    }
+
+    fn from_2(setting: Setting<'_>) -> Option<Setting<'_>> {
+        match setting.key {
+            "eth.batch" => Some(Setting {
+                key: "eth.rx_batch",
+                ..setting
+            }),
+            _ => Some(setting),
+        }
+    }
+
+    fn setting<'a>(key: &'a str, value: &'a str) -> Setting<'a> {
+        Setting { key, value }
+    }
+
+    #[test]
+    fn settings_go_through_every_migration_from_their_version() {
+        let migrate = |version, key| migrate_with(TEST_MIGRATIONS, version, setting(key, "8"));
+
+        assert_eq!(
+            migrate(1, "eth.rxbatch"),
+            Some(setting("eth.rx_batch", "8"))
+        );
+        assert_eq!(migrate(1, "wan.legacy"), None);
+        assert_eq!(migrate(1, "wan.preempt"), Some(setting("wan.preempt", "8")));
+        // Already past the first migration.
+        assert_eq!(migrate(2, "eth.rxbatch"), Some(setting("eth.rxbatch", "8")));
+        assert_eq!(migrate(2, "eth.batch"), Some(setting("eth.rx_batch", "8")));
+        assert_eq!(migrate(3, "eth.batch"), Some(setting("eth.batch", "8")));
+    }
+
+    #[test]
+    fn current_version_has_nothing_to_migrate() {
+        for key in KEYS {
+            assert_eq!(migrate(VERSION, setting(key, "")), Some(setting(key, "")));
+        }
+    }
+
+    #[test]
+    fn version_comes_first_and_once() {
+        assert_eq!(
+            Config::import("wan.preempt off\nconfig.version 1\n"),
+            Err(ConfigError::InvalidValue(2))
+        );
+        assert_eq!(
+            Config::import("config.version 1\nconfig.version 1\n"),
+            Err(ConfigError::InvalidValue(2))
+        );
+        assert_eq!(
+            Config::import("config.version 0\n"),
+            Err(ConfigError::InvalidValue(1))
+        );
+        assert_eq!(
+            Config::import("# Exported earlier.\nconfig.version 1\nwan.preempt off\n")
+                .map(|config| config.wan_preempt),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        assert_eq!(
+            Config::import("config.version 2\nwan.preempt off\n"),
+            Err(ConfigError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+}
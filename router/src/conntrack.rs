@@ -0,0 +1,450 @@
+//! Connection tracking table.
+//!
+//! Tracks flows by their 5-tuple with per-protocol timeouts. When the table is full the
+//! [`EvictionPolicy`] decides whether new flows are refused or the least recently used one
+//! makes room.
+//!
+//! The default timeouts assume one tick per second.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::{
+    clock,
+    profiling::{self, Stage},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    /// ICMP queries, the identifier takes the place of both ports.
+    Icmp,
+}
+
+impl Protocol {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Icmp => "icmp",
+        }
+    }
+}
+
+/// Identifies a flow in the direction it was first seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub protocol: Protocol,
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+}
+
+impl FlowKey {
+    /// Key of the packets flowing back.
+    pub fn reversed(&self) -> Self {
+        Self {
+            protocol: self.protocol,
+            source: self.destination,
+            destination: self.source,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TcpState {
+    /// Handshake in progress.
+    Opening,
+    Established,
+    /// A FIN was seen.
+    Closing,
+    /// Both sides closed, waiting for stray segments.
+    TimeWait,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlowState {
+    Tcp(TcpState),
+    Udp,
+    Icmp,
+}
+
+impl FlowState {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            FlowState::Tcp(TcpState::Opening) => "opening",
+            FlowState::Tcp(TcpState::Established) => "established",
+            FlowState::Tcp(TcpState::Closing) => "closing",
+            FlowState::Tcp(TcpState::TimeWait) => "time_wait",
+            FlowState::Udp => "udp",
+            FlowState::Icmp => "icmp",
+        }
+    }
+}
+
+/// Ticks a flow can stay idle in each state before it expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutProfile {
+    pub tcp_opening: u32,
+    pub tcp_established: u32,
+    pub tcp_closing: u32,
+    pub tcp_time_wait: u32,
+    pub udp: u32,
+    pub icmp: u32,
+}
+
+impl Default for TimeoutProfile {
+    fn default() -> Self {
+        Self {
+            tcp_opening: 120,
+            tcp_established: 7200,
+            tcp_closing: 120,
+            tcp_time_wait: 120,
+            udp: 180,
+            icmp: 30,
+        }
+    }
+}
+
+impl TimeoutProfile {
+    pub fn timeout(&self, state: FlowState) -> u32 {
+        match state {
+            FlowState::Tcp(TcpState::Opening) => self.tcp_opening,
+            FlowState::Tcp(TcpState::Established) => self.tcp_established,
+            FlowState::Tcp(TcpState::Closing) => self.tcp_closing,
+            FlowState::Tcp(TcpState::TimeWait) => self.tcp_time_wait,
+            FlowState::Udp => self.udp,
+            FlowState::Icmp => self.icmp,
+        }
+    }
+}
+
+/// What to do with a new flow when the table is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Refuse the new flow.
+    RefuseNew,
+    /// Evict the flow idle for longest.
+    #[default]
+    LeastRecentlyUsed,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConntrackError {
+    #[error("Connection tracking table is full.")]
+    TableFull,
+}
+
+/// Counters of the table's lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConntrackStats {
+    pub evictions: u32,
+    pub expirations: u32,
+    pub refused: u32,
+    /// Most flows tracked at once.
+    pub high_water: usize,
+}
+
+/// What [`Conntrack::insert`] did with the flow.
+#[derive(Debug)]
+pub enum Inserted<T> {
+    /// Tracked in a free slot.
+    New,
+    /// The flow of the same key was tracked already and is replaced, handed back.
+    Replaced(Flow<T>),
+    /// The table was full, another flow was evicted to make room, handed back.
+    Evicted(Flow<T>),
+}
+
+/// A tracked flow carrying some per-flow `data`, like a NAT binding.
+#[derive(Debug, Clone, Copy)]
+pub struct Flow<T> {
+    pub key: FlowKey,
+    pub state: FlowState,
+    pub last_seen: u32,
+    pub data: T,
+}
+
+/// Table of up to `N` flows.
+pub struct Conntrack<T, const N: usize> {
+    flows: heapless::Vec<Flow<T>, N>,
+    timeouts: TimeoutProfile,
+    policy: EvictionPolicy,
+    stats: ConntrackStats,
+}
+
+impl<T, const N: usize> Conntrack<T, N> {
+    pub fn new(timeouts: TimeoutProfile, policy: EvictionPolicy) -> Self {
+        Self {
+            flows: heapless::Vec::new(),
+            timeouts,
+            policy,
+            stats: ConntrackStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ConntrackStats {
+        self.stats
+    }
+
+    pub fn timeouts(&self) -> &TimeoutProfile {
+        &self.timeouts
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: TimeoutProfile) {
+        self.timeouts = timeouts;
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn get(&self, key: &FlowKey) -> Option<&Flow<T>> {
+        profiling::measure(Stage::NatLookup, || {
+            self.flows.iter().find(|flow| flow.key == *key)
+        })
+    }
+
+    pub fn get_mut(&mut self, key: &FlowKey) -> Option<&mut Flow<T>> {
+        profiling::measure(Stage::NatLookup, || {
+            self.flows.iter_mut().find(|flow| flow.key == *key)
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Flow<T>> {
+        self.flows.iter()
+    }
+
+    /// Starts tracking a new flow, making room according to the [`EvictionPolicy`] if needed.
+    ///
+    /// A flow of the same key is replaced, whatever the policy.
+    pub fn insert(
+        &mut self,
+        key: FlowKey,
+        state: FlowState,
+        data: T,
+        now: u32,
+    ) -> Result<Inserted<T>, ConntrackError> {
+        profiling::measure(Stage::NatLookup, || self.insert_flow(key, state, data, now))
+    }
+
+    fn insert_flow(
+        &mut self,
+        key: FlowKey,
+        state: FlowState,
+        data: T,
+        now: u32,
+    ) -> Result<Inserted<T>, ConntrackError> {
+        let flow = Flow {
+            key,
+            state,
+            last_seen: now,
+            data,
+        };
+
+        if let Some(existing) = self.flows.iter_mut().find(|flow| flow.key == key) {
+            return Ok(Inserted::Replaced(core::mem::replace(existing, flow)));
+        }
+
+        let evicted = if self.flows.is_full() {
+            let Some(index) = self.least_recently_used(now) else {
+                // Only with N == 0.
+                self.stats.refused += 1;
+                return Err(ConntrackError::TableFull);
+            };
+
+            match self.policy {
+                EvictionPolicy::RefuseNew => {
+                    self.stats.refused += 1;
+                    return Err(ConntrackError::TableFull);
+                }
+                EvictionPolicy::LeastRecentlyUsed => {
+                    self.stats.evictions += 1;
+                    Inserted::Evicted(self.flows.swap_remove(index))
+                }
+            }
+        } else {
+            Inserted::New
+        };
+
+        let _ = self.flows.push(flow);
+        self.stats.high_water = self.stats.high_water.max(self.flows.len());
+        Ok(evicted)
+    }
+
+    /// Records activity on the flow moving it to `state`, returns false if it isn't tracked.
+    pub fn touch(&mut self, key: &FlowKey, state: FlowState, now: u32) -> bool {
+        let Some(flow) = self.get_mut(key) else {
+            return false;
+        };
+
+        flow.state = state;
+        flow.last_seen = now;
+        true
+    }
+
+    pub fn remove(&mut self, key: &FlowKey) -> Option<Flow<T>> {
+        let index = self.flows.iter().position(|flow| flow.key == *key)?;
+        Some(self.flows.swap_remove(index))
+    }
+
+    /// Drops every flow idle for longer than its state's timeout.
+    pub fn expire(&mut self, now: u32) {
+        let timeouts = self.timeouts;
+        let before = self.flows.len();
+        self.flows
+            .retain(|flow| now.wrapping_sub(flow.last_seen) < timeouts.timeout(flow.state));
+        self.stats.expirations += (before - self.flows.len()) as u32;
+    }
+
+    /// Keeps only the flows for which `f` returns true.
+    pub fn retain(&mut self, f: impl FnMut(&Flow<T>) -> bool) {
+        self.flows.retain(f);
+    }
+
+    /// Drops every flow.
+    pub fn flush(&mut self) {
+        self.flows.clear();
+    }
+
+    fn least_recently_used(&self, now: u32) -> Option<usize> {
+        self.flows
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, flow)| now.wrapping_sub(flow.last_seen))
+            .map(|(i, _)| i)
+    }
+}
+
+/// A flow an application level gateway expects to be opened, like an FTP data connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expectation {
+    pub protocol: Protocol,
+    /// The expected flow comes from this address, from any port.
+    pub source: Ipv4Addr,
+    pub destination: SocketAddrV4,
+    pub expires_at: u32,
+}
+
+impl Expectation {
+    pub fn matches(&self, key: &FlowKey) -> bool {
+        self.protocol == key.protocol
+            && self.source == *key.source.ip()
+            && self.destination == key.destination
+    }
+}
+
+/// Up to `N` pending expectations.
+pub struct Expectations<const N: usize> {
+    expectations: heapless::Vec<Expectation, N>,
+}
+
+impl<const N: usize> Default for Expectations<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Expectations<N> {
+    pub const fn new() -> Self {
+        Self {
+            expectations: heapless::Vec::new(),
+        }
+    }
+
+    pub fn expect(&mut self, expectation: Expectation) -> Result<(), ConntrackError> {
+        self.expectations
+            .push(expectation)
+            .map_err(|_| ConntrackError::TableFull)
+    }
+
+    /// Removes and returns the expectation matching the new flow `key`, if it hasn't expired.
+    pub fn take(&mut self, key: &FlowKey, now: u32) -> Option<Expectation> {
+        self.expire(now);
+        let index = self.expectations.iter().position(|e| e.matches(key))?;
+        Some(self.expectations.swap_remove(index))
+    }
+
+    pub fn expire(&mut self, now: u32) {
+        self.expectations
+            .retain(|e| !clock::is_due(e.expires_at, now));
+    }
+}
+
+/// Which application level gateways run on tracked flows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlgConfig {
+    pub ftp: bool,
+    /// Off by default, SIP ALGs often break clients that already handle NAT themselves.
+    pub sip: bool,
+}
+
+impl Default for AlgConfig {
+    fn default() -> Self {
+        Self {
+            ftp: true,
+            sip: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(port: u16) -> FlowKey {
+        FlowKey {
+            protocol: Protocol::Udp,
+            source: SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), port),
+            destination: SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53),
+        }
+    }
+
+    fn table(policy: EvictionPolicy) -> Conntrack<u8, 2> {
+        let mut conntrack = Conntrack::new(TimeoutProfile::default(), policy);
+        for port in [1, 2] {
+            let inserted = conntrack.insert(key(port), FlowState::Udp, port as u8, 0);
+            assert!(matches!(inserted, Ok(Inserted::New)));
+        }
+        conntrack
+    }
+
+    #[test]
+    fn inserting_a_tracked_key_replaces_its_flow() {
+        for policy in [EvictionPolicy::RefuseNew, EvictionPolicy::LeastRecentlyUsed] {
+            let mut conntrack = table(policy);
+
+            let inserted = conntrack.insert(key(1), FlowState::Udp, 10, 5);
+            assert!(matches!(inserted, Ok(Inserted::Replaced(flow)) if flow.data == 1));
+            assert_eq!(conntrack.get_mut(&key(1)).unwrap().data, 10);
+            assert_eq!(conntrack.stats().evictions, 0);
+        }
+    }
+
+    #[test]
+    fn full_table_evicts_or_refuses_new_keys() {
+        let mut conntrack = table(EvictionPolicy::LeastRecentlyUsed);
+        conntrack.touch(&key(1), FlowState::Udp, 5);
+        let inserted = conntrack.insert(key(3), FlowState::Udp, 3, 6);
+        assert!(matches!(inserted, Ok(Inserted::Evicted(flow)) if flow.key == key(2)));
+        assert_eq!(conntrack.stats().evictions, 1);
+
+        let mut conntrack = table(EvictionPolicy::RefuseNew);
+        let inserted = conntrack.insert(key(3), FlowState::Udp, 3, 6);
+        assert!(matches!(inserted, Err(ConntrackError::TableFull)));
+    }
+}
@@ -0,0 +1,203 @@
+//! IPv4 address conflict detection (RFC 5227).
+//!
+//! Before an address, static or offered by DHCP, is bound it's probed with ARP. Nobody
+//! answering, it's announced and from then on defended against other hosts claiming it. A
+//! conflict while probing means the address must not be used: a DHCP offer gets declined, a
+//! static address gets logged as misconfigured.
+//!
+//! The default timings assume one tick per second.
+
+use core::net::Ipv4Addr;
+
+use crate::{arp::ArpPacket, clock, ethernet::MacAddress};
+
+/// Protocol timings, RFC 5227 section 1.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeConfig {
+    /// Maximum random wait before the first probe.
+    pub probe_wait: u32,
+    /// Probes sent and the random spacing between them.
+    pub probe_num: u8,
+    pub probe_min: u32,
+    pub probe_max: u32,
+    /// Delay between the last probe and the first announcement.
+    pub announce_wait: u32,
+    pub announce_num: u8,
+    pub announce_interval: u32,
+    /// A second conflict within this interval makes a bound address be given up.
+    pub defend_interval: u32,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            probe_wait: 1,
+            probe_num: 3,
+            probe_min: 1,
+            probe_max: 2,
+            announce_wait: 2,
+            announce_num: 2,
+            announce_interval: 2,
+            defend_interval: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClaimState {
+    Probing {
+        sent: u8,
+    },
+    Announcing {
+        sent: u8,
+    },
+    Bound,
+    /// Another host uses the address, it must not be used.
+    Conflict,
+}
+
+/// Claim on a single address, from probing through defending it.
+pub struct AddressClaim {
+    config: ProbeConfig,
+    mac: MacAddress,
+    address: Ipv4Addr,
+    state: ClaimState,
+    /// Time the next packet is due.
+    next_at: u32,
+    last_defended_at: Option<u32>,
+    /// xorshift32 state.
+    random: u32,
+}
+
+impl AddressClaim {
+    /// Starts claiming `address`, probing begins within `probe_wait` ticks of `start_at`.
+    pub fn new(config: ProbeConfig, mac: MacAddress, address: Ipv4Addr, start_at: u32) -> Self {
+        let seed = mac
+            .0
+            .iter()
+            .chain(&address.octets())
+            .fold(0x811c_9dc5u32, |hash, byte| {
+                (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+            });
+
+        let mut claim = Self {
+            config,
+            mac,
+            address,
+            state: ClaimState::Probing { sent: 0 },
+            next_at: start_at,
+            last_defended_at: None,
+            random: seed.max(1),
+        };
+        // Random initial wait so hosts powered on together don't probe in lockstep.
+        claim.next_at = start_at.wrapping_add(claim.random_between(0, config.probe_wait));
+        claim
+    }
+
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    pub fn state(&self) -> ClaimState {
+        self.state
+    }
+
+    /// True once the address may be used, from the first announcement on.
+    pub fn is_usable(&self) -> bool {
+        matches!(
+            self.state,
+            ClaimState::Announcing { sent: 1.. } | ClaimState::Bound
+        )
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        self.state == ClaimState::Conflict
+    }
+
+    /// ARP packet to send now, if any.
+    pub fn poll(&mut self, now: u32) -> Option<ArpPacket> {
+        if !clock::is_due(self.next_at, now) {
+            return None;
+        }
+
+        match self.state {
+            ClaimState::Bound | ClaimState::Conflict => None,
+            ClaimState::Probing { sent } => {
+                let sent = sent + 1;
+                let (state, delay) = if sent < self.config.probe_num {
+                    let delay = self.random_between(self.config.probe_min, self.config.probe_max);
+                    (ClaimState::Probing { sent }, delay)
+                } else {
+                    (
+                        ClaimState::Announcing { sent: 0 },
+                        self.config.announce_wait,
+                    )
+                };
+
+                self.state = state;
+                self.next_at = now.wrapping_add(delay);
+                Some(ArpPacket::probe(self.mac, self.address))
+            }
+            ClaimState::Announcing { sent } => {
+                let sent = sent + 1;
+                self.state = if sent < self.config.announce_num {
+                    ClaimState::Announcing { sent }
+                } else {
+                    ClaimState::Bound
+                };
+                self.next_at = now.wrapping_add(self.config.announce_interval);
+                Some(ArpPacket::announcement(self.mac, self.address))
+            }
+        }
+    }
+
+    /// Checks a received ARP packet for conflicts, returning an announcement to send when the
+    /// address gets defended.
+    ///
+    /// On conflict the claim moves to [`ClaimState::Conflict`] and the address must be dropped.
+    pub fn handle_arp(&mut self, packet: &ArpPacket, now: u32) -> Option<ArpPacket> {
+        if packet.sender_mac == self.mac {
+            return None;
+        }
+
+        match self.state {
+            ClaimState::Conflict => None,
+            ClaimState::Probing { .. } | ClaimState::Announcing { sent: 0 } => {
+                // Someone uses the address, or probes for it at the same time.
+                if packet.sender_ip == self.address
+                    || (packet.is_probe() && packet.target_ip == self.address)
+                {
+                    self.state = ClaimState::Conflict;
+                }
+                None
+            }
+            ClaimState::Announcing { .. } | ClaimState::Bound => {
+                if packet.sender_ip != self.address {
+                    return None;
+                }
+
+                let recently_defended = self
+                    .last_defended_at
+                    .is_some_and(|at| now.wrapping_sub(at) < self.config.defend_interval);
+                if recently_defended {
+                    self.state = ClaimState::Conflict;
+                    None
+                } else {
+                    self.last_defended_at = Some(now);
+                    Some(ArpPacket::announcement(self.mac, self.address))
+                }
+            }
+        }
+    }
+
+    fn random_between(&mut self, min: u32, max: u32) -> u32 {
+        let mut x = self.random;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random = x;
+
+        min + x % max.saturating_sub(min).saturating_add(1)
+    }
+}
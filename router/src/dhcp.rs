@@ -0,0 +1,574 @@
+//! DHCP options (RFC 2132 and extensions).
+//!
+//! Options are TLV encoded after the fixed BOOTP header and the magic cookie; these helpers
+//! work on that options area only.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::cidr::Ipv4Cidr;
+
+/// Option codes.
+pub mod code {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const DOMAIN_NAME_SERVER: u8 = 6;
+    pub const HOST_NAME: u8 = 12;
+    pub const DOMAIN_NAME: u8 = 15;
+    pub const INTERFACE_MTU: u8 = 26;
+    pub const NTP_SERVERS: u8 = 42;
+    pub const VENDOR_SPECIFIC: u8 = 43;
+    pub const REQUESTED_ADDRESS: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_IDENTIFIER: u8 = 54;
+    pub const PARAMETER_REQUEST_LIST: u8 = 55;
+    pub const TFTP_SERVER_NAME: u8 = 66;
+    pub const BOOTFILE_NAME: u8 = 67;
+    pub const CLIENT_FQDN: u8 = 81;
+    pub const DOMAIN_SEARCH: u8 = 119;
+    pub const CLASSLESS_STATIC_ROUTE: u8 = 121;
+    pub const END: u8 = 255;
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DhcpError {
+    #[error("Option runs past the end of the message.")]
+    Truncated,
+    #[error("Option {0} has an invalid payload.")]
+    InvalidOption(u8),
+    #[error("Buffer ran out of memory for additional options.")]
+    OptionsOutOfMemory,
+}
+
+/// A single option as found on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawOption<'a> {
+    pub code: u8,
+    pub data: &'a [u8],
+}
+
+/// Iterator over the options in an options area, stopping at the end option.
+pub struct Options<'a> {
+    rest: &'a [u8],
+}
+
+pub fn options(data: &[u8]) -> Options<'_> {
+    Options { rest: data }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = Result<RawOption<'a>, DhcpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&code, rest) = self.rest.split_first()?;
+            match code {
+                code::PAD => {
+                    self.rest = rest;
+                }
+                code::END => {
+                    self.rest = &[];
+                    return None;
+                }
+                code => {
+                    let Some((&len, rest)) = rest.split_first() else {
+                        self.rest = &[];
+                        return Some(Err(DhcpError::Truncated));
+                    };
+                    let Some((data, rest)) = rest.split_at_checked(len as usize) else {
+                        self.rest = &[];
+                        return Some(Err(DhcpError::Truncated));
+                    };
+
+                    self.rest = rest;
+                    return Some(Ok(RawOption { code, data }));
+                }
+            }
+        }
+    }
+}
+
+/// First option with `code`, truncated option areas are searched up to the truncation.
+pub fn find(data: &[u8], code: u8) -> Option<RawOption<'_>> {
+    options(data)
+        .map_while(Result::ok)
+        .find(|option| option.code == code)
+}
+
+/// Addresses in an option holding a list of them, like routers or NTP servers.
+pub fn addresses(option: RawOption<'_>) -> Result<impl Iterator<Item = Ipv4Addr> + '_, DhcpError> {
+    if option.data.is_empty() || !option.data.len().is_multiple_of(4) {
+        return Err(DhcpError::InvalidOption(option.code));
+    }
+
+    Ok(option
+        .data
+        .chunks_exact(4)
+        .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3])))
+}
+
+/// Interface MTU, RFC 2132 requires at least 68.
+pub fn mtu(option: RawOption<'_>) -> Result<u16, DhcpError> {
+    let data: [u8; 2] = option
+        .data
+        .try_into()
+        .map_err(|_| DhcpError::InvalidOption(option.code))?;
+
+    match u16::from_be_bytes(data) {
+        mtu @ 68.. => Ok(mtu),
+        _ => Err(DhcpError::InvalidOption(option.code)),
+    }
+}
+
+/// A route from the classless static route option (RFC 3442).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticRoute {
+    pub destination: Ipv4Cidr,
+    pub router: Ipv4Addr,
+}
+
+/// Routes of a classless static route option.
+pub fn static_routes(option: RawOption<'_>) -> StaticRoutes<'_> {
+    StaticRoutes {
+        code: option.code,
+        rest: option.data,
+    }
+}
+
+pub struct StaticRoutes<'a> {
+    code: u8,
+    rest: &'a [u8],
+}
+
+impl Iterator for StaticRoutes<'_> {
+    type Item = Result<StaticRoute, DhcpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&prefix_len, rest) = self.rest.split_first()?;
+        // Only the significant octets of the destination are sent.
+        let significant = (prefix_len as usize).div_ceil(8);
+
+        if prefix_len > 32 || rest.len() < significant + 4 {
+            self.rest = &[];
+            return Some(Err(DhcpError::InvalidOption(self.code)));
+        }
+
+        let mut destination = [0; 4];
+        destination[..significant].copy_from_slice(&rest[..significant]);
+        let router = &rest[significant..significant + 4];
+        self.rest = &rest[significant + 4..];
+
+        Some(Ok(StaticRoute {
+            // Prefix length checked above.
+            destination: Ipv4Cidr::new(Ipv4Addr::from(destination), prefix_len).unwrap(),
+            router: Ipv4Addr::new(router[0], router[1], router[2], router[3]),
+        }))
+    }
+}
+
+/// Writes options into a buffer, ending them with the end option on [`Self::finish`].
+pub struct OptionsBuilder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> OptionsBuilder<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+
+    /// Appends an option with an arbitrary payload.
+    pub fn raw(&mut self, code: u8, data: &[u8]) -> Result<&mut Self, DhcpError> {
+        let len = u8::try_from(data.len()).map_err(|_| DhcpError::InvalidOption(code))?;
+        let option = self
+            .buffer
+            .get_mut(self.len..self.len + data.len() + 2)
+            .ok_or(DhcpError::OptionsOutOfMemory)?;
+
+        option[0] = code;
+        option[1] = len;
+        option[2..].copy_from_slice(data);
+        self.len += option.len();
+        Ok(self)
+    }
+
+    pub fn u8(&mut self, code: u8, value: u8) -> Result<&mut Self, DhcpError> {
+        self.raw(code, &[value])
+    }
+
+    pub fn u16(&mut self, code: u8, value: u16) -> Result<&mut Self, DhcpError> {
+        self.raw(code, &value.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, code: u8, value: u32) -> Result<&mut Self, DhcpError> {
+        self.raw(code, &value.to_be_bytes())
+    }
+
+    pub fn address(&mut self, code: u8, address: Ipv4Addr) -> Result<&mut Self, DhcpError> {
+        self.raw(code, &address.octets())
+    }
+
+    pub fn addresses(&mut self, code: u8, addresses: &[Ipv4Addr]) -> Result<&mut Self, DhcpError> {
+        if addresses.is_empty() {
+            return Err(DhcpError::InvalidOption(code));
+        }
+
+        self.encapsulated(code, |builder| {
+            for address in addresses {
+                builder.push_bytes(&address.octets())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Appends a string option, strings aren't NUL terminated.
+    pub fn string(&mut self, code: u8, value: &str) -> Result<&mut Self, DhcpError> {
+        if value.is_empty() {
+            return Err(DhcpError::InvalidOption(code));
+        }
+
+        self.raw(code, value.as_bytes())
+    }
+
+    /// Appends an option whose payload is written by `f`, e.g. vendor-specific sub-options
+    /// encoded as options themselves.
+    pub fn encapsulated(
+        &mut self,
+        code: u8,
+        f: impl FnOnce(&mut OptionsBuilder<'_>) -> Result<(), DhcpError>,
+    ) -> Result<&mut Self, DhcpError> {
+        let payload = self
+            .buffer
+            .get_mut(self.len + 2..)
+            .ok_or(DhcpError::OptionsOutOfMemory)?;
+        let mut inner = OptionsBuilder::new(payload);
+        f(&mut inner)?;
+
+        let len = u8::try_from(inner.len).map_err(|_| DhcpError::InvalidOption(code))?;
+        self.buffer[self.len] = code;
+        self.buffer[self.len + 1] = len;
+        self.len += len as usize + 2;
+        Ok(self)
+    }
+
+    /// Appends every option in `options`.
+    pub fn options<'o>(
+        &mut self,
+        options: impl IntoIterator<Item = RawOption<'o>>,
+    ) -> Result<&mut Self, DhcpError> {
+        for option in options {
+            self.raw(option.code, option.data)?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn host_name(&mut self, host_name: &str) -> Result<&mut Self, DhcpError> {
+        self.string(code::HOST_NAME, host_name)
+    }
+
+    /// Appends the client FQDN option (RFC 4702) with the name in canonical wire format.
+    ///
+    /// With `server_updates` the server is asked to update the A record on the client's behalf.
+    pub fn client_fqdn(
+        &mut self,
+        fqdn: &str,
+        server_updates: bool,
+    ) -> Result<&mut Self, DhcpError> {
+        const S: u8 = 0b0001;
+        const E: u8 = 0b0100;
+
+        self.encapsulated(code::CLIENT_FQDN, |builder| {
+            // Flags and two deprecated RCODE bytes, then the name.
+            let flags = E | if server_updates { S } else { 0 };
+            builder.push_bytes(&[flags, 0, 0])?;
+
+            for label in fqdn.trim_end_matches('.').split('.') {
+                if label.is_empty() || label.len() > 63 {
+                    return Err(DhcpError::InvalidOption(code::CLIENT_FQDN));
+                }
+
+                builder.push_bytes(&[label.len() as u8])?;
+                builder.push_bytes(label.as_bytes())?;
+            }
+            builder.push_bytes(&[0])
+        })
+    }
+
+    /// Terminates the options with the end option, returning the written bytes.
+    pub fn finish(self) -> Result<&'a [u8], DhcpError> {
+        let end = self
+            .buffer
+            .get_mut(self.len)
+            .ok_or(DhcpError::OptionsOutOfMemory)?;
+        *end = code::END;
+
+        Ok(&self.buffer[..self.len + 1])
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<(), DhcpError> {
+        self.buffer
+            .get_mut(self.len..self.len + bytes.len())
+            .ok_or(DhcpError::OptionsOutOfMemory)?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+/// Configured options to hand out, e.g. for an address pool or a static lease.
+///
+/// Options are kept TLV encoded in `B` bytes.
+#[derive(Debug, Clone, Default)]
+pub struct OptionSet<const B: usize> {
+    encoded: heapless::Vec<u8, B>,
+}
+
+impl<const B: usize> OptionSet<B> {
+    pub fn new() -> Self {
+        Self {
+            encoded: heapless::Vec::new(),
+        }
+    }
+
+    /// Sets option `code` to `data`, replacing any previous value.
+    pub fn set(&mut self, code: u8, data: &[u8]) -> Result<(), DhcpError> {
+        if matches!(code, code::PAD | code::END) || data.len() > u8::MAX as usize {
+            return Err(DhcpError::InvalidOption(code));
+        }
+
+        let previous = self.get(code).map_or(0, |option| option.data.len() + 2);
+        if self.encoded.len() - previous + data.len() + 2 > B {
+            return Err(DhcpError::OptionsOutOfMemory);
+        }
+
+        self.remove(code);
+        self.encoded
+            .extend_from_slice(&[code, data.len() as u8])
+            .unwrap();
+        self.encoded.extend_from_slice(data).unwrap();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, code: u8) {
+        let mut offset = 0;
+        while let Some(option) = options(&self.encoded[offset..]).next() {
+            // Only valid options are ever stored.
+            let option = option.unwrap();
+            let len = option.data.len() + 2;
+            if option.code == code {
+                self.encoded[offset..].rotate_left(len);
+                self.encoded.truncate(self.encoded.len() - len);
+                return;
+            }
+
+            offset += len;
+        }
+    }
+
+    pub fn get(&self, code: u8) -> Option<RawOption<'_>> {
+        find(&self.encoded, code)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = RawOption<'_>> {
+        options(&self.encoded).map_while(Result::ok)
+    }
+
+    /// Options of `self` followed by those of `base` it doesn't override,
+    /// e.g. a static lease's options layered over its pool's.
+    pub fn layered_over<'a, const C: usize>(
+        &'a self,
+        base: &'a OptionSet<C>,
+    ) -> impl Iterator<Item = RawOption<'a>> {
+        self.iter()
+            .chain(base.iter().filter(|option| self.get(option.code).is_none()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_padding_and_stops_at_the_end_option() {
+        // Pads around a message type and a router, then a lease time after the end option.
+        let data = b"\0\x35\x01\x05\0\0\x03\x04\xc0\xa8\x01\x01\xff\x33\x04\0\0\x0e\x10";
+        let mut options = options(data);
+
+        assert_eq!(
+            options.next(),
+            Some(Ok(RawOption {
+                code: code::MESSAGE_TYPE,
+                data: &[5]
+            }))
+        );
+        assert_eq!(
+            options.next(),
+            Some(Ok(RawOption {
+                code: code::ROUTER,
+                data: &[192, 168, 1, 1]
+            }))
+        );
+        assert_eq!(options.next(), None);
+        assert_eq!(find(data, code::LEASE_TIME), None);
+    }
+
+    #[test]
+    fn reports_options_running_past_the_end() {
+        for data in [&[code::MESSAGE_TYPE][..], &[code::ROUTER, 4, 192, 168]] {
+            let mut options = options(data);
+            assert_eq!(options.next(), Some(Err(DhcpError::Truncated)));
+            assert_eq!(options.next(), None);
+        }
+
+        // Options before the truncation are still found.
+        let data = [code::MESSAGE_TYPE, 1, 3, code::ROUTER, 8, 10, 0];
+        assert_eq!(
+            find(&data, code::MESSAGE_TYPE),
+            Some(RawOption {
+                code: code::MESSAGE_TYPE,
+                data: &[3]
+            })
+        );
+        assert_eq!(find(&data, code::ROUTER), None);
+    }
+
+    #[test]
+    fn checks_address_lists_and_mtu() {
+        let servers = RawOption {
+            code: code::DOMAIN_NAME_SERVER,
+            data: &[10, 0, 0, 1, 10, 0, 0, 2],
+        };
+        let mut addresses_found = addresses(servers).unwrap();
+        assert_eq!(addresses_found.next(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(addresses_found.next(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(addresses_found.next(), None);
+
+        for data in [&[][..], &[10, 0, 0]] {
+            let option = RawOption {
+                code: code::ROUTER,
+                data,
+            };
+            assert!(matches!(
+                addresses(option),
+                Err(DhcpError::InvalidOption(code::ROUTER))
+            ));
+        }
+
+        let mtu_of = |data| {
+            mtu(RawOption {
+                code: code::INTERFACE_MTU,
+                data,
+            })
+        };
+        assert_eq!(mtu_of(&[0x05, 0xDC]), Ok(1500));
+        assert_eq!(mtu_of(&[0, 68]), Ok(68));
+        assert_eq!(
+            mtu_of(&[0, 67]),
+            Err(DhcpError::InvalidOption(code::INTERFACE_MTU))
+        );
+        assert_eq!(
+            mtu_of(&[5]),
+            Err(DhcpError::InvalidOption(code::INTERFACE_MTU))
+        );
+    }
+
+    #[test]
+    fn reads_classless_static_routes() {
+        let option = RawOption {
+            code: code::CLASSLESS_STATIC_ROUTE,
+            // A default route, 10.1.2.0/24, then a prefix too long.
+            data: b"\0\xc0\xa8\x01\x01\x18\x0a\x01\x02\xc0\xa8\x01\x02\x21\x0a\0\0\0\0",
+        };
+        let mut routes = static_routes(option);
+
+        assert_eq!(
+            routes.next(),
+            Some(Ok(StaticRoute {
+                destination: Ipv4Cidr::new(Ipv4Addr::UNSPECIFIED, 0).unwrap(),
+                router: Ipv4Addr::new(192, 168, 1, 1),
+            }))
+        );
+        assert_eq!(
+            routes.next(),
+            Some(Ok(StaticRoute {
+                destination: Ipv4Cidr::new(Ipv4Addr::new(10, 1, 2, 0), 24).unwrap(),
+                router: Ipv4Addr::new(192, 168, 1, 2),
+            }))
+        );
+        assert_eq!(
+            routes.next(),
+            Some(Err(DhcpError::InvalidOption(code::CLASSLESS_STATIC_ROUTE)))
+        );
+        assert_eq!(routes.next(), None);
+
+        // A router cut short.
+        let option = RawOption {
+            code: code::CLASSLESS_STATIC_ROUTE,
+            data: &[8, 10, 192, 168],
+        };
+        assert_eq!(
+            static_routes(option).next(),
+            Some(Err(DhcpError::InvalidOption(code::CLASSLESS_STATIC_ROUTE)))
+        );
+    }
+
+    #[test]
+    fn built_options_parse_back() {
+        let mut buffer = [0; 64];
+        let mut builder = OptionsBuilder::new(&mut buffer);
+        builder
+            .u8(code::MESSAGE_TYPE, 2)
+            .unwrap()
+            .u32(code::LEASE_TIME, 3600)
+            .unwrap()
+            .client_fqdn("host.example.", true)
+            .unwrap();
+        let data = builder.finish().unwrap();
+
+        assert_eq!(
+            find(data, code::LEASE_TIME).map(|option| option.data),
+            Some(&3600u32.to_be_bytes()[..])
+        );
+        assert_eq!(
+            find(data, code::CLIENT_FQDN).map(|option| option.data),
+            Some(&b"\x05\0\0\x04host\x07example\0"[..])
+        );
+        assert_eq!(data.last(), Some(&code::END));
+        assert!(options(data).all(|option| option.is_ok()));
+    }
+
+    #[test]
+    fn refuses_options_that_dont_fit() {
+        let mut buffer = [0; 6];
+        let mut builder = OptionsBuilder::new(&mut buffer);
+        assert_eq!(
+            builder.string(code::HOST_NAME, "router").err(),
+            Some(DhcpError::OptionsOutOfMemory)
+        );
+        assert_eq!(
+            builder.string(code::HOST_NAME, "").err(),
+            Some(DhcpError::InvalidOption(code::HOST_NAME))
+        );
+        assert_eq!(
+            builder.raw(code::VENDOR_SPECIFIC, &[0; 256]).err(),
+            Some(DhcpError::InvalidOption(code::VENDOR_SPECIFIC))
+        );
+
+        builder.u8(code::MESSAGE_TYPE, 1).unwrap();
+        builder.u8(code::MESSAGE_TYPE, 1).unwrap();
+        assert_eq!(builder.finish(), Err(DhcpError::OptionsOutOfMemory));
+
+        let mut buffer = [0; 64];
+        assert_eq!(
+            OptionsBuilder::new(&mut buffer)
+                .client_fqdn("bad..name", false)
+                .err(),
+            Some(DhcpError::InvalidOption(code::CLIENT_FQDN))
+        );
+    }
+}
@@ -0,0 +1,1440 @@
+//! DNS messages (RFC 1035) and the policies the forwarder applies to them.
+//!
+//! Messages are parsed in place, names are only decoded while walking their labels so
+//! compression pointers cost nothing until they are followed. Pointers must go strictly
+//! backward, see [`Name::parse`], and responses written locally compress their names with a
+//! [`NameCompressor`].
+
+use core::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::ratelimit::RateLimiter;
+
+pub const HEADER_LEN: usize = 12;
+
+/// Longest name in wire format.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Record types.
+pub mod rtype {
+    pub const A: u16 = 1;
+    pub const NS: u16 = 2;
+    pub const CNAME: u16 = 5;
+    pub const SOA: u16 = 6;
+    pub const PTR: u16 = 12;
+    pub const MX: u16 = 15;
+    pub const TXT: u16 = 16;
+    pub const AAAA: u16 = 28;
+    pub const OPT: u16 = 41;
+}
+
+pub const CLASS_IN: u16 = 1;
+
+/// Largest UDP payload without EDNS0.
+pub const MIN_UDP_PAYLOAD: u16 = 512;
+
+/// Largest UDP payload advertised upstream: what fits in an Ethernet frame, as fragments aren't
+/// reassembled.
+pub const MAX_UDP_PAYLOAD: u16 = 1500 - 20 - 8;
+
+/// Length of an OPT record without options.
+const OPT_LEN: usize = 11;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DnsError {
+    #[error("Message ends in the middle of a field.")]
+    Truncated,
+    #[error("Name at offset {0} is malformed.")]
+    InvalidName(usize),
+    #[error("Configured name is empty, too long or has an invalid label.")]
+    InvalidConfigName,
+    #[error("Table ran out of memory for additional local records.")]
+    RecordsOutOfMemory,
+    #[error("Message is larger than the buffer reassembling it.")]
+    MessageTooLarge,
+    #[error("Client sent queries faster than its rate limit.")]
+    RateLimited,
+    #[error("Too many queries are already waiting on upstream.")]
+    TooManyOutstanding,
+}
+
+/// Fixed header of every message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: u16,
+    pub answers: u16,
+    pub authorities: u16,
+    pub additionals: u16,
+}
+
+impl Header {
+    pub const RESPONSE: u16 = 1 << 15;
+    pub const AUTHORITATIVE: u16 = 1 << 10;
+    pub const TRUNCATED: u16 = 1 << 9;
+    pub const RECURSION_DESIRED: u16 = 1 << 8;
+    pub const RECURSION_AVAILABLE: u16 = 1 << 7;
+
+    pub fn parse(message: &[u8]) -> Result<Self, DnsError> {
+        let header = message.get(..HEADER_LEN).ok_or(DnsError::Truncated)?;
+        let word = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+
+        Ok(Self {
+            id: word(0),
+            flags: word(2),
+            questions: word(4),
+            answers: word(6),
+            authorities: word(8),
+            additionals: word(10),
+        })
+    }
+
+    pub fn write(&self, message: &mut [u8]) -> Result<(), DnsError> {
+        let header = message.get_mut(..HEADER_LEN).ok_or(DnsError::Truncated)?;
+        let words = [
+            self.id,
+            self.flags,
+            self.questions,
+            self.answers,
+            self.authorities,
+            self.additionals,
+        ];
+        for (chunk, word) in header.chunks_exact_mut(2).zip(words) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+
+        Ok(())
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & Self::RESPONSE != 0
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0xF) as u8
+    }
+}
+
+/// A possibly compressed name inside a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Name<'a> {
+    message: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Name<'a> {
+    /// Validates the name at `offset`, returning it and the offset right after it.
+    ///
+    /// Each compression pointer must point before the start of the labels it follows, the
+    /// name's own start for the first one: names compress against earlier names, and a
+    /// crafted message can't make a name loop or grow past [`MAX_NAME_LEN`] however its
+    /// pointers are laid out.
+    pub fn parse(message: &'a [u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let name = Self { message, offset };
+        let mut len = 0;
+
+        let mut labels = name.raw_labels();
+        for label in &mut labels {
+            len += label?.len() + 1;
+            if len > MAX_NAME_LEN {
+                return Err(DnsError::InvalidName(offset));
+            }
+        }
+
+        Ok((name, labels.first_pointer_end.unwrap_or(labels.offset)))
+    }
+
+    /// Labels from the leftmost one, without the final empty label.
+    pub fn labels(&self) -> impl Iterator<Item = &'a [u8]> {
+        // Names are validated on parse.
+        self.raw_labels().map_while(Result::ok)
+    }
+
+    /// Compares against a dotted name, ignoring ASCII case and a trailing dot.
+    pub fn eq_str(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        if name.is_empty() {
+            return self.labels().next().is_none();
+        }
+
+        let mut labels = self.labels();
+        name.split('.').all(|part| {
+            labels
+                .next()
+                .is_some_and(|l| l.eq_ignore_ascii_case(part.as_bytes()))
+        }) && labels.next().is_none()
+    }
+
+    /// True if the name is `domain` or below it.
+    pub fn is_subdomain_of(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        let count = self.labels().count();
+        let domain_count = if domain.is_empty() {
+            0
+        } else {
+            domain.split('.').count()
+        };
+
+        count >= domain_count
+            && self
+                .labels()
+                .skip(count - domain_count)
+                .zip(domain.split('.'))
+                .all(|(label, part)| label.eq_ignore_ascii_case(part.as_bytes()))
+    }
+
+    /// Length once written without compression.
+    pub fn wire_len(&self) -> usize {
+        self.labels().map(|label| label.len() + 1).sum::<usize>() + 1
+    }
+
+    /// Writes the name uncompressed, returning the written length.
+    pub fn write_uncompressed(&self, buffer: &mut [u8]) -> Result<usize, DnsError> {
+        let mut len = 0;
+        for label in self.labels() {
+            buffer
+                .get_mut(len..len + label.len() + 1)
+                .ok_or(DnsError::Truncated)?
+                .iter_mut()
+                .zip(core::iter::once(label.len() as u8).chain(label.iter().copied()))
+                .for_each(|(byte, value)| *byte = value);
+            len += label.len() + 1;
+        }
+
+        *buffer.get_mut(len).ok_or(DnsError::Truncated)? = 0;
+        Ok(len + 1)
+    }
+
+    fn raw_labels(&self) -> RawLabels<'a> {
+        RawLabels {
+            message: self.message,
+            offset: self.offset,
+            first_pointer_end: None,
+            limit: self.offset,
+            done: false,
+        }
+    }
+}
+
+struct RawLabels<'a> {
+    message: &'a [u8],
+    offset: usize,
+    /// Where the name ends in place once a pointer was followed.
+    first_pointer_end: Option<usize>,
+    /// Start of the labels being walked, pointers must point before it.
+    limit: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for RawLabels<'a> {
+    type Item = Result<&'a [u8], DnsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let Some(&len) = self.message.get(self.offset) else {
+                self.done = true;
+                return Some(Err(DnsError::Truncated));
+            };
+
+            match len {
+                0 => {
+                    self.offset += 1;
+                    self.done = true;
+                }
+                0xC0.. => {
+                    let Some(&low) = self.message.get(self.offset + 1) else {
+                        self.done = true;
+                        return Some(Err(DnsError::Truncated));
+                    };
+
+                    let target = u16::from_be_bytes([len & 0x3F, low]) as usize;
+                    self.first_pointer_end.get_or_insert(self.offset + 2);
+                    // Strictly decreasing, so every jump lands on labels not walked yet.
+                    if target >= self.limit {
+                        self.done = true;
+                        return Some(Err(DnsError::InvalidName(self.offset)));
+                    }
+                    self.limit = target;
+                    self.offset = target;
+                }
+                1..=63 => {
+                    let start = self.offset + 1;
+                    let Some(label) = self.message.get(start..start + len as usize) else {
+                        self.done = true;
+                        return Some(Err(DnsError::Truncated));
+                    };
+
+                    self.offset = start + len as usize;
+                    return Some(Ok(label));
+                }
+                _ => {
+                    self.done = true;
+                    return Some(Err(DnsError::InvalidName(self.offset)));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Question<'a> {
+    pub name: Name<'a>,
+    pub rtype: u16,
+    pub class: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub section: Section,
+    pub name: Name<'a>,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: &'a [u8],
+    /// Offset of `data` in the message, to resolve names inside it.
+    pub data_offset: usize,
+}
+
+impl Record<'_> {
+    /// Address of an A record.
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        let octets: [u8; 4] = self.data.try_into().ok()?;
+        (self.rtype == rtype::A).then_some(Ipv4Addr::from(octets))
+    }
+
+    /// Address of an AAAA record.
+    pub fn ipv6(&self) -> Option<Ipv6Addr> {
+        let octets: [u8; 16] = self.data.try_into().ok()?;
+        (self.rtype == rtype::AAAA).then_some(Ipv6Addr::from(octets))
+    }
+}
+
+/// EDNS0 parameters of a message, from its OPT pseudo-record (RFC 6891).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Edns {
+    /// Largest UDP payload the sender can receive.
+    pub udp_payload_size: u16,
+    /// Upper 8 bits of the 12-bit response code.
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+}
+
+impl Edns {
+    /// Parameters carried by `record`, `None` if it isn't an OPT record.
+    pub fn from_record(record: &Record<'_>) -> Option<Self> {
+        if record.rtype != rtype::OPT {
+            return None;
+        }
+
+        let [extended_rcode, version, flags, _] = record.ttl.to_be_bytes();
+        Some(Self {
+            // Values below 512 are treated as 512.
+            udp_payload_size: record.class.max(MIN_UDP_PAYLOAD),
+            extended_rcode,
+            version,
+            dnssec_ok: flags & 0x80 != 0,
+        })
+    }
+}
+
+/// A message with a valid header.
+#[derive(Debug, Clone, Copy)]
+pub struct Message<'a> {
+    pub bytes: &'a [u8],
+    pub header: Header,
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, DnsError> {
+        Ok(Self {
+            bytes,
+            header: Header::parse(bytes)?,
+        })
+    }
+
+    pub fn questions(&self) -> Questions<'a> {
+        Questions {
+            message: self.bytes,
+            offset: HEADER_LEN,
+            remaining: self.header.questions,
+        }
+    }
+
+    /// First question, the only one in practice.
+    pub fn question(&self) -> Result<Question<'a>, DnsError> {
+        self.questions().next().ok_or(DnsError::Truncated)?
+    }
+
+    /// Offset right after the question section.
+    pub fn questions_end(&self) -> Result<usize, DnsError> {
+        let mut questions = self.questions();
+        for question in &mut questions {
+            question?;
+        }
+
+        Ok(questions.offset)
+    }
+
+    /// EDNS0 parameters, `None` without an OPT record.
+    pub fn edns(&self) -> Result<Option<Edns>, DnsError> {
+        for record in self.records()? {
+            let record = record?;
+            if record.section == Section::Additional
+                && let Some(edns) = Edns::from_record(&record)
+            {
+                return Ok(Some(edns));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Largest UDP response the sender of this query accepts.
+    pub fn udp_response_limit(&self) -> Result<usize, DnsError> {
+        let edns = self.edns()?;
+        Ok(edns.map_or(MIN_UDP_PAYLOAD, |edns| edns.udp_payload_size) as usize)
+    }
+
+    /// Records of the answer, authority and additional sections in order.
+    pub fn records(&self) -> Result<Records<'a>, DnsError> {
+        Ok(Records {
+            message: self.bytes,
+            offset: self.questions_end()?,
+            remaining: [
+                self.header.answers,
+                self.header.authorities,
+                self.header.additionals,
+            ],
+        })
+    }
+}
+
+pub struct Questions<'a> {
+    message: &'a [u8],
+    offset: usize,
+    remaining: u16,
+}
+
+impl<'a> Iterator for Questions<'a> {
+    type Item = Result<Question<'a>, DnsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let parsed = Name::parse(self.message, self.offset).and_then(|(name, end)| {
+            let fixed = self.message.get(end..end + 4).ok_or(DnsError::Truncated)?;
+            self.offset = end + 4;
+            Ok(Question {
+                name,
+                rtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+            })
+        });
+
+        if parsed.is_err() {
+            self.remaining = 0;
+        }
+        Some(parsed)
+    }
+}
+
+pub struct Records<'a> {
+    message: &'a [u8],
+    offset: usize,
+    remaining: [u16; 3],
+}
+
+impl<'a> Records<'a> {
+    /// Offset of the next record.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, DnsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, remaining) = self
+            .remaining
+            .iter_mut()
+            .enumerate()
+            .find(|(_, remaining)| **remaining > 0)?;
+        *remaining -= 1;
+        let section = [Section::Answer, Section::Authority, Section::Additional][index];
+
+        let parsed = Name::parse(self.message, self.offset).and_then(|(name, end)| {
+            let fixed = self.message.get(end..end + 10).ok_or(DnsError::Truncated)?;
+            let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            let data_offset = end + 10;
+            let data = self
+                .message
+                .get(data_offset..data_offset + len)
+                .ok_or(DnsError::Truncated)?;
+
+            self.offset = data_offset + len;
+            Ok(Record {
+                section,
+                name,
+                rtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+                ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+                data,
+                data_offset,
+            })
+        });
+
+        if parsed.is_err() {
+            self.remaining = [0; 3];
+        }
+        Some(parsed)
+    }
+}
+
+/// Advertises a UDP payload size of at most `max_payload` in the query taking the first
+/// `len` bytes of `buffer`, returning its new length.
+///
+/// An existing OPT record has its size clamped in place, keeping the client's flags and
+/// options. Without one, an OPT record is appended, the client having asked for nothing but
+/// upstream still being spared truncating answers for the forwarder.
+pub fn advertise_edns(buffer: &mut [u8], len: usize, max_payload: u16) -> Result<usize, DnsError> {
+    let message = Message::parse(buffer.get(..len).ok_or(DnsError::Truncated)?)?;
+
+    let header = message.header;
+
+    let mut records = message.records()?;
+    let end = loop {
+        let offset = records.offset();
+        let Some(record) = records.next() else {
+            break offset;
+        };
+        let record = record?;
+        if record.section == Section::Additional && record.rtype == rtype::OPT {
+            // The class follows the type, after the root name.
+            let (_, at) = Name::parse(message.bytes, offset)?;
+            let size = record.class.min(max_payload).max(MIN_UDP_PAYLOAD);
+            buffer[at + 2..at + 4].copy_from_slice(&size.to_be_bytes());
+            return Ok(len);
+        }
+    };
+
+    // Anything trailing the records is dropped.
+    let opt = buffer
+        .get_mut(end..end + OPT_LEN)
+        .ok_or(DnsError::Truncated)?;
+    opt[..3].copy_from_slice(&[0, 0, rtype::OPT as u8]);
+    opt[3..5].copy_from_slice(&max_payload.max(MIN_UDP_PAYLOAD).to_be_bytes());
+    opt[5..].fill(0);
+
+    Header {
+        additionals: header.additionals + 1,
+        ..header
+    }
+    .write(buffer)?;
+    Ok(end + OPT_LEN)
+}
+
+/// True for addresses that must not be handed out for public names: RFC 1918 private ranges,
+/// loopback, link-local and the unspecified network.
+pub fn is_rebind_target(address: Ipv4Addr) -> bool {
+    address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.octets()[0] == 0
+}
+
+/// [`is_rebind_target`] for IPv6: loopback, unspecified, unique local (fc00::/7), link-local
+/// (fe80::/10) and IPv4-mapped addresses of IPv4 rebind targets.
+pub fn is_rebind_target_v6(address: Ipv6Addr) -> bool {
+    address.is_loopback()
+        || address.is_unspecified()
+        || address.is_unique_local()
+        || address.is_unicast_link_local()
+        || address.to_ipv4_mapped().is_some_and(is_rebind_target)
+}
+
+/// Rejects upstream answers resolving public names to private addresses (DNS rebinding).
+///
+/// Domains in the whitelist, and their subdomains, may resolve to anything, e.g. a local
+/// domain served by an upstream resolver on the LAN. Up to `W` domains of `L` bytes.
+#[derive(Debug, Clone, Default)]
+pub struct RebindProtection<const W: usize, const L: usize> {
+    pub enabled: bool,
+    whitelist: heapless::Vec<heapless::String<L>, W>,
+}
+
+impl<const W: usize, const L: usize> RebindProtection<W, L> {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            whitelist: heapless::Vec::new(),
+        }
+    }
+
+    /// Allows `domain` and its subdomains, returns false if out of room.
+    pub fn allow(&mut self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        if self.is_whitelisted(domain) {
+            return true;
+        }
+
+        let Ok(domain) = heapless::String::try_from(domain) else {
+            return false;
+        };
+        self.whitelist.push(domain).is_ok()
+    }
+
+    pub fn disallow(&mut self, domain: &str) {
+        let domain = domain.trim_end_matches('.');
+        self.whitelist
+            .retain(|allowed| !allowed.eq_ignore_ascii_case(domain));
+    }
+
+    pub fn whitelist(&self) -> impl Iterator<Item = &str> {
+        self.whitelist.iter().map(|domain| domain.as_str())
+    }
+
+    /// True if `response` answers a non whitelisted name with a private address, IPv4 or IPv6.
+    pub fn is_rebinding(&self, response: &Message<'_>) -> Result<bool, DnsError> {
+        if !self.enabled {
+            return Ok(false);
+        }
+
+        let question = response.question()?;
+        if self
+            .whitelist
+            .iter()
+            .any(|domain| question.name.is_subdomain_of(domain))
+        {
+            return Ok(false);
+        }
+
+        for record in response.records()? {
+            let record = record?;
+            if record.section == Section::Answer
+                && (record.ipv4().is_some_and(is_rebind_target)
+                    || record.ipv6().is_some_and(is_rebind_target_v6))
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Strips every answer from a rebinding `response` in place, returning its new length.
+    ///
+    /// Only the question is kept, the client sees an empty answer. Responses that aren't
+    /// rebinding are left alone.
+    pub fn filter(&self, response: &mut [u8]) -> Result<usize, DnsError> {
+        let message = Message::parse(response)?;
+        if !self.is_rebinding(&message)? {
+            return Ok(response.len());
+        }
+
+        let end = message.questions_end()?;
+        let header = Header {
+            answers: 0,
+            authorities: 0,
+            additionals: 0,
+            ..message.header
+        };
+        header.write(response)?;
+        Ok(end)
+    }
+
+    fn is_whitelisted(&self, domain: &str) -> bool {
+        self.whitelist
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+    }
+}
+
+/// Data of a locally configured record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalData<const L: usize> {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    /// Alias, takes precedence over any other record of the same name.
+    Cname(heapless::String<L>),
+}
+
+impl<const L: usize> LocalData<L> {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            LocalData::A(_) => rtype::A,
+            LocalData::Aaaa(_) => rtype::AAAA,
+            LocalData::Cname(_) => rtype::CNAME,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalRecord<const L: usize> {
+    pub name: heapless::String<L>,
+    pub data: LocalData<L>,
+    pub ttl: u32,
+}
+
+/// Static records answered authoritatively before asking upstream, for local services or
+/// split-horizon names. Up to `N` records with names of `L` bytes.
+///
+/// A name with any local record is fully owned locally: queries for types it has no record of
+/// get an empty answer instead of being forwarded.
+#[derive(Debug, Clone, Default)]
+pub struct LocalRecords<const N: usize, const L: usize> {
+    records: heapless::Vec<LocalRecord<L>, N>,
+}
+
+impl<const N: usize, const L: usize> LocalRecords<N, L> {
+    /// CNAME chains followed before giving up.
+    const MAX_ALIASES: usize = 4;
+
+    pub const fn new() -> Self {
+        Self {
+            records: heapless::Vec::new(),
+        }
+    }
+
+    /// Adds a record, an identical one already present isn't duplicated.
+    pub fn insert(&mut self, name: &str, data: LocalData<L>, ttl: u32) -> Result<(), DnsError> {
+        let name = name.trim_end_matches('.');
+        if let LocalData::Cname(target) = &data {
+            validate_name(target)?;
+        }
+        validate_name(name)?;
+
+        let name = heapless::String::try_from(name).map_err(|_| DnsError::InvalidConfigName)?;
+        if let Some(existing) = self
+            .records
+            .iter_mut()
+            .find(|record| record.name.eq_ignore_ascii_case(&name) && record.data == data)
+        {
+            existing.ttl = ttl;
+            return Ok(());
+        }
+
+        self.records
+            .push(LocalRecord { name, data, ttl })
+            .map_err(|_| DnsError::RecordsOutOfMemory)
+    }
+
+    /// Removes the records of `name`, only those of type `rtype` if given.
+    pub fn remove(&mut self, name: &str, rtype: Option<u16>) {
+        let name = name.trim_end_matches('.');
+        self.records.retain(|record| {
+            !(record.name.eq_ignore_ascii_case(name)
+                && rtype.is_none_or(|rtype| record.data.rtype() == rtype))
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LocalRecord<L>> {
+        self.records.iter()
+    }
+
+    /// Writes the answer to `query` into `response` if its name is configured locally.
+    ///
+    /// Returns the response length, or `None` if the query must be forwarded upstream.
+    pub fn answer(&self, query: &[u8], response: &mut [u8]) -> Result<Option<usize>, DnsError> {
+        let message = Message::parse(query)?;
+        let question = message.question()?;
+        if message.header.is_response() || question.class != CLASS_IN {
+            return Ok(None);
+        }
+
+        let Some(owner) = self
+            .records
+            .iter()
+            .find(|record| question.name.eq_str(&record.name))
+        else {
+            return Ok(None);
+        };
+
+        let mut writer = RecordWriter::respond(&message, response)?;
+        let mut name = owner.name.as_str();
+        let mut first = true;
+
+        for _ in 0..=Self::MAX_ALIASES {
+            let matching = || {
+                self.records
+                    .iter()
+                    .filter(|record| record.name.eq_ignore_ascii_case(name))
+            };
+            let alias = matching().find_map(|record| match &record.data {
+                LocalData::Cname(target) if question.rtype != rtype::CNAME => {
+                    Some((record, target))
+                }
+                _ => None,
+            });
+
+            if let Some((record, target)) = alias {
+                writer.record((!first).then_some(name), &record.data, record.ttl)?;
+                name = target;
+                first = false;
+                continue;
+            }
+
+            for record in matching().filter(|record| record.data.rtype() == question.rtype) {
+                writer.record((!first).then_some(name), &record.data, record.ttl)?;
+            }
+            break;
+        }
+
+        writer.finish(&message).map(Some)
+    }
+}
+
+/// Answers any A query with `address` and queries of other types with an empty answer, like a
+/// captive portal does.
+///
+/// Returns the response length, or `None` if `query` isn't an IN class query.
+pub fn answer_any(
+    query: &[u8],
+    address: Ipv4Addr,
+    ttl: u32,
+    response: &mut [u8],
+) -> Result<Option<usize>, DnsError> {
+    let message = Message::parse(query)?;
+    let question = message.question()?;
+    if message.header.is_response() || question.class != CLASS_IN {
+        return Ok(None);
+    }
+
+    let mut writer = RecordWriter::respond(&message, response)?;
+    if question.rtype == rtype::A {
+        writer.record(None, &LocalData::<0>::A(address), ttl)?;
+    }
+
+    writer.finish(&message).map(Some)
+}
+
+/// Largest offset a compression pointer can hold.
+const MAX_POINTER: usize = 0x3FFF;
+
+/// Compresses the names written to a message against the names already in it (RFC 1035
+/// 4.1.4).
+///
+/// It remembers where the names written through it, or registered with it, and each of their
+/// suffixes start. A name ending with one of those is written as its leading labels followed by
+/// a pointer to it. Up to `P` suffixes are remembered, the names past that are still written,
+/// compressed against the first ones only.
+#[derive(Debug, Clone, Default)]
+pub struct NameCompressor<const P: usize> {
+    suffixes: heapless::Vec<u16, P>,
+}
+
+impl<const P: usize> NameCompressor<P> {
+    pub const fn new() -> Self {
+        Self {
+            suffixes: heapless::Vec::new(),
+        }
+    }
+
+    /// Remembers the name at `offset` of `message` and its suffixes, e.g. a question copied
+    /// from the query.
+    pub fn register(&mut self, message: &[u8], offset: usize) -> Result<(), DnsError> {
+        let (name, _) = Name::parse(message, offset)?;
+        let mut labels = name.raw_labels();
+        while let Some(label) = labels.next() {
+            // The walk is right past the label.
+            self.remember(labels.offset - label?.len() - 1);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the dotted name `name` at `offset` of `message`, returning its wire length.
+    pub fn write(
+        &mut self,
+        message: &mut [u8],
+        offset: usize,
+        name: &str,
+    ) -> Result<usize, DnsError> {
+        let mut rest = name.trim_end_matches('.');
+        let mut len = 0;
+        while !rest.is_empty() {
+            let start = offset + len;
+            let written = message.get(..start).ok_or(DnsError::Truncated)?;
+            if let Some(target) = self.find(written, rest) {
+                message
+                    .get_mut(start..start + 2)
+                    .ok_or(DnsError::Truncated)?
+                    .copy_from_slice(&(0xC000 | target).to_be_bytes());
+                return Ok(len + 2);
+            }
+
+            let (label, tail) = rest.split_once('.').unwrap_or((rest, ""));
+            if !(1..=63).contains(&label.len()) {
+                return Err(DnsError::InvalidConfigName);
+            }
+            let out = message
+                .get_mut(start..start + label.len() + 1)
+                .ok_or(DnsError::Truncated)?;
+            out[0] = label.len() as u8;
+            out[1..].copy_from_slice(label.as_bytes());
+
+            self.remember(start);
+            len += label.len() + 1;
+            rest = tail;
+        }
+
+        *message.get_mut(offset + len).ok_or(DnsError::Truncated)? = 0;
+        Ok(len + 1)
+    }
+
+    /// Offset of a remembered suffix equal to `suffix`.
+    fn find(&self, message: &[u8], suffix: &str) -> Option<u16> {
+        self.suffixes.iter().copied().find(|&offset| {
+            Name::parse(message, offset as usize).is_ok_and(|(name, _)| name.eq_str(suffix))
+        })
+    }
+
+    fn remember(&mut self, offset: usize) {
+        if offset <= MAX_POINTER {
+            let _ = self.suffixes.push(offset as u16);
+        }
+    }
+}
+
+/// Appends answer records after the question of a response.
+struct RecordWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    count: u16,
+    names: NameCompressor<16>,
+}
+
+impl<'a> RecordWriter<'a> {
+    /// Starts an authoritative response to `query`, echoing its first question.
+    fn respond(query: &Message<'_>, response: &'a mut [u8]) -> Result<Self, DnsError> {
+        let (_, name_end) = Name::parse(query.bytes, HEADER_LEN)?;
+        let question_end = name_end + 4;
+        response
+            .get_mut(..question_end)
+            .ok_or(DnsError::Truncated)?
+            .copy_from_slice(query.bytes.get(..question_end).ok_or(DnsError::Truncated)?);
+
+        let mut names = NameCompressor::new();
+        names.register(response, HEADER_LEN)?;
+
+        Ok(Self {
+            buffer: response,
+            len: question_end,
+            count: 0,
+            names,
+        })
+    }
+
+    /// Writes the header, returning the response length.
+    fn finish(self, query: &Message<'_>) -> Result<usize, DnsError> {
+        let flags = Header::RESPONSE
+            | Header::AUTHORITATIVE
+            | Header::RECURSION_AVAILABLE
+            | (query.header.flags & Header::RECURSION_DESIRED);
+        Header {
+            id: query.header.id,
+            flags,
+            questions: 1,
+            answers: self.count,
+            authorities: 0,
+            additionals: 0,
+        }
+        .write(self.buffer)?;
+
+        Ok(self.len)
+    }
+
+    /// Writes a record owned by `name`, or by the question's name through a pointer if `None`.
+    fn record<const L: usize>(
+        &mut self,
+        name: Option<&str>,
+        data: &LocalData<L>,
+        ttl: u32,
+    ) -> Result<(), DnsError> {
+        match name {
+            Some(name) => {
+                self.name(name)?;
+            }
+            None => self.push(&[0xC0, HEADER_LEN as u8])?,
+        }
+
+        self.push(&data.rtype().to_be_bytes())?;
+        self.push(&CLASS_IN.to_be_bytes())?;
+        self.push(&ttl.to_be_bytes())?;
+
+        match data {
+            LocalData::A(address) => {
+                self.push(&4u16.to_be_bytes())?;
+                self.push(&address.octets())?;
+            }
+            LocalData::Aaaa(address) => {
+                self.push(&16u16.to_be_bytes())?;
+                self.push(&address.octets())?;
+            }
+            LocalData::Cname(target) => {
+                let len_offset = self.len;
+                self.push(&[0, 0])?;
+                let len = self.name(target)? as u16;
+                self.buffer[len_offset..len_offset + 2].copy_from_slice(&len.to_be_bytes());
+            }
+        }
+
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Writes a dotted name, compressed against the names before it, returning its wire
+    /// length.
+    fn name(&mut self, name: &str) -> Result<usize, DnsError> {
+        let len = self.names.write(self.buffer, self.len, name)?;
+        self.len += len;
+        Ok(len)
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), DnsError> {
+        self.buffer
+            .get_mut(self.len..self.len + bytes.len())
+            .ok_or(DnsError::Truncated)?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+/// Checks a dotted name, without trailing dot, fits the wire format.
+fn validate_name(name: &str) -> Result<(), DnsError> {
+    let valid = !name.is_empty()
+        && name.len() + 2 <= MAX_NAME_LEN
+        && name.split('.').all(|label| (1..=63).contains(&label.len()));
+
+    valid.then_some(()).ok_or(DnsError::InvalidConfigName)
+}
+
+/// Limits of the [`Forwarder`], in ticks of the caller's clock. The defaults assume one tick
+/// per millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwarderConfig {
+    /// Queries a client can send in a burst.
+    pub client_burst: u32,
+    /// Sustained rate of a client, one query per that many ticks.
+    pub client_ticks_per_query: u32,
+    /// How long a query waits on upstream before it's given up on.
+    pub upstream_timeout: u32,
+}
+
+impl Default for ForwarderConfig {
+    fn default() -> Self {
+        Self {
+            client_burst: 20,
+            client_ticks_per_query: 100,
+            upstream_timeout: 5000,
+        }
+    }
+}
+
+/// Counters of the forwarder's lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ForwarderStats {
+    pub forwarded: u32,
+    /// Queries refused by the per-client rate limit.
+    pub rate_limited: u32,
+    /// Queries refused because `P` were already waiting on upstream.
+    pub over_cap: u32,
+    /// Responses matching no outstanding query, by port and ID: late, duplicated or spoofed.
+    pub unmatched: u32,
+    pub timed_out: u32,
+}
+
+/// Where a query sent upstream goes out from, see [`Forwarder::query`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Upstream {
+    /// Local UDP port to send from, the response comes back to it.
+    pub source_port: u16,
+    /// ID the query was rewritten with.
+    pub id: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Outstanding {
+    client: SocketAddrV4,
+    client_id: u16,
+    upstream: Upstream,
+    sent_at: u32,
+}
+
+/// Relays queries from LAN clients to the upstream resolver over UDP, keeping a misbehaving
+/// client from exhausting it and off-path attackers from poisoning its answers.
+///
+/// - Each client address is rate limited, up to `C` clients being tracked.
+/// - At most `P` queries wait on upstream at once, each taking a local port.
+/// - Each query goes out from a random port with a random ID, both of which the response must
+///   match, so a spoofed response has to guess 32 bits rather than the 16 of the ID.
+///
+/// Random words come from the caller, which owns the hardware RNG.
+pub struct Forwarder<const C: usize, const P: usize> {
+    config: ForwarderConfig,
+    clients: RateLimiter<Ipv4Addr, C>,
+    outstanding: heapless::Vec<Outstanding, P>,
+    stats: ForwarderStats,
+}
+
+impl<const C: usize, const P: usize> Forwarder<C, P> {
+    /// Source ports are picked above the well-known ones (RFC 6056).
+    pub const MIN_SOURCE_PORT: u16 = 1024;
+
+    pub fn new(config: ForwarderConfig) -> Self {
+        Self {
+            config,
+            clients: RateLimiter::new(config.client_burst, config.client_ticks_per_query),
+            outstanding: heapless::Vec::new(),
+            stats: ForwarderStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ForwarderStats {
+        self.stats
+    }
+
+    /// Queries waiting on upstream.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Takes `query` from `client` for upstream, rewriting its ID, and returns the port to send
+    /// it from. `random` is a fresh word from the hardware RNG.
+    ///
+    /// Fails with [`DnsError::RateLimited`] or [`DnsError::TooManyOutstanding`] when the query
+    /// is to be dropped.
+    pub fn query(
+        &mut self,
+        client: SocketAddrV4,
+        query: &mut [u8],
+        random: u32,
+        now: u32,
+    ) -> Result<Upstream, DnsError> {
+        let header = Header::parse(query)?;
+        if !self.clients.check(*client.ip(), now) {
+            self.stats.rate_limited += 1;
+            return Err(DnsError::RateLimited);
+        }
+
+        self.expire(now);
+        if self.outstanding.is_full() {
+            self.stats.over_cap += 1;
+            return Err(DnsError::TooManyOutstanding);
+        }
+
+        let upstream = Upstream {
+            source_port: self.free_port(random as u16),
+            id: (random >> 16) as u16,
+        };
+        Header {
+            id: upstream.id,
+            ..header
+        }
+        .write(query)?;
+
+        // Room was checked above.
+        let _ = self.outstanding.push(Outstanding {
+            client,
+            client_id: header.id,
+            upstream,
+            sent_at: now,
+        });
+        self.stats.forwarded += 1;
+        Ok(upstream)
+    }
+
+    /// Takes `response` from upstream, received on `port`, restoring the client's ID, and
+    /// returns the client to relay it to. `None` when it matches no outstanding query, the
+    /// response is then dropped.
+    pub fn response(&mut self, port: u16, response: &mut [u8]) -> Option<SocketAddrV4> {
+        let header = Header::parse(response).ok()?;
+        let index = self.outstanding.iter().position(|outstanding| {
+            outstanding.upstream
+                == Upstream {
+                    source_port: port,
+                    id: header.id,
+                }
+        });
+        let Some(index) = index.filter(|_| header.is_response()) else {
+            self.stats.unmatched += 1;
+            return None;
+        };
+
+        let outstanding = self.outstanding.swap_remove(index);
+        Header {
+            id: outstanding.client_id,
+            ..header
+        }
+        .write(response)
+        .ok()?;
+        Some(outstanding.client)
+    }
+
+    /// Gives up on the queries upstream didn't answer in time, freeing their ports.
+    pub fn expire(&mut self, now: u32) {
+        let timeout = self.config.upstream_timeout;
+        let before = self.outstanding.len();
+        self.outstanding
+            .retain(|outstanding| now.wrapping_sub(outstanding.sent_at) < timeout);
+        self.stats.timed_out += (before - self.outstanding.len()) as u32;
+    }
+
+    /// `random` mapped to the source port range, moved on to the next port while it's taken.
+    fn free_port(&self, random: u16) -> u16 {
+        let range = u32::from(u16::MAX - Self::MIN_SOURCE_PORT) + 1;
+        let mut port = u32::from(random) % range;
+        // At most P ports are taken, far fewer than the range holds.
+        loop {
+            let candidate = Self::MIN_SOURCE_PORT + port as u16;
+            if self
+                .outstanding
+                .iter()
+                .all(|outstanding| outstanding.upstream.source_port != candidate)
+            {
+                return candidate;
+            }
+            port = (port + 1) % range;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Forwarder = super::Forwarder<4, 2>;
+
+    fn client(host: u8) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, host), 5353)
+    }
+
+    /// Header of a query with ID `id`, flags and counts left out.
+    fn query(id: u16) -> [u8; HEADER_LEN] {
+        let mut query = [0; HEADER_LEN];
+        Header {
+            id,
+            flags: Header::RECURSION_DESIRED,
+            questions: 0,
+            answers: 0,
+            authorities: 0,
+            additionals: 0,
+        }
+        .write(&mut query)
+        .unwrap();
+        query
+    }
+
+    /// Response upstream would send to `query`.
+    fn response(query: &[u8]) -> [u8; HEADER_LEN] {
+        let mut response: [u8; HEADER_LEN] = query.try_into().unwrap();
+        response[2] |= (Header::RESPONSE >> 8) as u8;
+        response
+    }
+
+    #[test]
+    fn response_goes_back_with_the_client_id() {
+        let mut forwarder = Forwarder::new(ForwarderConfig::default());
+        let mut message = query(0x1234);
+        let upstream = forwarder
+            .query(client(10), &mut message, 0xBEEF_C0DE, 0)
+            .unwrap();
+        assert_eq!(upstream.id, 0xBEEF);
+        assert_eq!(upstream.source_port, Forwarder::MIN_SOURCE_PORT + 0xC0DE);
+        assert_eq!(Header::parse(&message).unwrap().id, 0xBEEF);
+
+        let mut answer = response(&message);
+        assert_eq!(
+            forwarder.response(upstream.source_port, &mut answer),
+            Some(client(10))
+        );
+        assert_eq!(Header::parse(&answer).unwrap().id, 0x1234);
+        assert_eq!(forwarder.outstanding(), 0);
+    }
+
+    #[test]
+    fn response_must_match_port_and_id() {
+        let mut forwarder = Forwarder::new(ForwarderConfig::default());
+        let mut message = query(1);
+        let upstream = forwarder
+            .query(client(10), &mut message, 0x1111_2222, 0)
+            .unwrap();
+
+        let mut answer = response(&message);
+        assert_eq!(
+            forwarder.response(upstream.source_port + 1, &mut answer),
+            None
+        );
+        let mut spoofed = response(&query(upstream.id ^ 1));
+        assert_eq!(forwarder.response(upstream.source_port, &mut spoofed), None);
+        // A query isn't an answer.
+        assert_eq!(forwarder.response(upstream.source_port, &mut message), None);
+        assert_eq!(forwarder.stats().unmatched, 3);
+
+        assert!(
+            forwarder
+                .response(upstream.source_port, &mut answer)
+                .is_some()
+        );
+        // Nor is it taken twice.
+        assert_eq!(forwarder.response(upstream.source_port, &mut answer), None);
+    }
+
+    #[test]
+    fn source_ports_are_above_well_known_and_distinct() {
+        // Ports from 1024 up to 65535.
+        let range = 65536 - u32::from(Forwarder::MIN_SOURCE_PORT);
+        let mut forwarder = Forwarder::new(ForwarderConfig::default());
+        let first = forwarder.query(client(10), &mut query(1), 0, 0).unwrap();
+        assert_eq!(first.source_port, Forwarder::MIN_SOURCE_PORT);
+
+        // Maps to the same port, which is taken.
+        let mut forwarder = super::Forwarder::<4, 4>::new(ForwarderConfig::default());
+        forwarder.query(client(10), &mut query(1), 0, 0).unwrap();
+        let second = forwarder
+            .query(client(10), &mut query(2), range, 0)
+            .unwrap();
+        assert_eq!(second.source_port, Forwarder::MIN_SOURCE_PORT + 1);
+
+        let last = forwarder
+            .query(client(10), &mut query(3), range - 1, 0)
+            .unwrap();
+        assert_eq!(last.source_port, u16::MAX);
+        let wrapped = forwarder
+            .query(client(10), &mut query(4), 0xFFFF, 0)
+            .unwrap();
+        assert_eq!(wrapped.source_port, Forwarder::MIN_SOURCE_PORT + 1023);
+    }
+
+    #[test]
+    fn outstanding_queries_are_capped_until_answered_or_timed_out() {
+        let config = ForwarderConfig::default();
+        let mut forwarder = Forwarder::new(config);
+        let first = forwarder.query(client(10), &mut query(1), 1, 0).unwrap();
+        forwarder.query(client(11), &mut query(2), 2, 0).unwrap();
+        assert_eq!(
+            forwarder.query(client(12), &mut query(3), 3, 0),
+            Err(DnsError::TooManyOutstanding)
+        );
+        assert_eq!(forwarder.stats().over_cap, 1);
+
+        let mut answer = response(&query(first.id));
+        forwarder.response(first.source_port, &mut answer).unwrap();
+        forwarder.query(client(12), &mut query(3), 3, 1).unwrap();
+
+        // Both left time out.
+        forwarder.expire(config.upstream_timeout + 1);
+        assert_eq!(forwarder.outstanding(), 0);
+        assert_eq!(forwarder.stats().timed_out, 2);
+    }
+
+    #[test]
+    fn flooding_client_is_rate_limited_alone() {
+        let config = ForwarderConfig {
+            client_burst: 3,
+            client_ticks_per_query: 100,
+            // Frees each query's port right away.
+            upstream_timeout: 1,
+        };
+        let mut forwarder = super::Forwarder::<4, 8>::new(config);
+        let mut now = 0;
+        for _ in 0..3 {
+            forwarder
+                .query(client(10), &mut query(1), now, now)
+                .unwrap();
+            now += 1;
+        }
+        assert_eq!(
+            forwarder.query(client(10), &mut query(1), now, now),
+            Err(DnsError::RateLimited)
+        );
+        forwarder
+            .query(client(11), &mut query(1), now, now)
+            .unwrap();
+
+        now += 100;
+        forwarder
+            .query(client(10), &mut query(1), now, now)
+            .unwrap();
+        assert_eq!(forwarder.stats().rate_limited, 1);
+        assert_eq!(forwarder.stats().forwarded, 5);
+    }
+
+    /// Response answering `example.com` with a record of `rtype` holding `data`.
+    fn answer(rtype: u16, data: &[u8]) -> Vec<u8> {
+        let mut message = response(&query(1)).to_vec();
+        message[5] = 1;
+        message[7] = 1;
+        message.extend_from_slice(b"\x07example\x03com\x00");
+        message.extend_from_slice(&rtype.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        // Pointer to the question's name.
+        message.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        message.extend_from_slice(&rtype.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&300u32.to_be_bytes());
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    #[test]
+    fn rebinding_covers_aaaa_answers() {
+        let protection = RebindProtection::<1, 16>::new(true);
+        let is_rebinding = |address: &str| {
+            let address: Ipv6Addr = address.parse().unwrap();
+            let message = answer(rtype::AAAA, &address.octets());
+            protection
+                .is_rebinding(&Message::parse(&message).unwrap())
+                .unwrap()
+        };
+
+        for address in [
+            "::1",
+            "::",
+            "fd12:3456::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_rebinding(address), "{address}");
+        }
+        for address in [
+            "2001:db8::1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+            "fec0::1",
+        ] {
+            assert!(!is_rebinding(address), "{address}");
+        }
+
+        let message = answer(rtype::A, &[10, 0, 0, 1]);
+        assert!(
+            protection
+                .is_rebinding(&Message::parse(&message).unwrap())
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! DNS over TCP (RFC 7766) for responses that don't fit in UDP.
+//!
+//! A truncated UDP response from upstream is relayed as is, TC bit included, which makes the
+//! client retry over TCP 53. The forwarder relays that connection to upstream over TCP: the
+//! client's query is reassembled and sent on, then the response is reassembled too, checked
+//! against [`RebindProtection`] like responses over UDP, and sent back. Responses larger than
+//! the reassembly buffer are refused.
+
+use crate::dns::{DnsError, Header, Message, RebindProtection};
+
+/// Length of the prefix framing each message on a TCP connection.
+pub const PREFIX_LEN: usize = 2;
+
+/// Whether an upstream UDP response was truncated, the query needing a retry over TCP.
+pub fn is_truncated(response: &[u8]) -> bool {
+    Header::parse(response).is_ok_and(|header| header.flags & Header::TRUNCATED != 0)
+}
+
+/// Writes `message` framed for a TCP connection, returning the framed length or `None` if
+/// `out` is too small or the message too long.
+pub fn write_frame(message: &[u8], out: &mut [u8]) -> Option<usize> {
+    let len = u16::try_from(message.len()).ok()?;
+    let framed = out.get_mut(..PREFIX_LEN + message.len())?;
+    framed[..PREFIX_LEN].copy_from_slice(&len.to_be_bytes());
+    framed[PREFIX_LEN..].copy_from_slice(message);
+    Some(framed.len())
+}
+
+/// Rewrites the prefix of a message in `out` shortened to `len`, which fit in the prefix
+/// before. Returns the framed length.
+fn write_frame_prefix(len: usize, out: &mut [u8]) -> usize {
+    out[..PREFIX_LEN].copy_from_slice(&(len as u16).to_be_bytes());
+    PREFIX_LEN + len
+}
+
+/// Reassembles a single framed message of up to `B` bytes from the segments of a connection.
+#[derive(Debug, Default)]
+pub struct FrameReader<const B: usize> {
+    prefix: heapless::Vec<u8, PREFIX_LEN>,
+    message: heapless::Vec<u8, B>,
+}
+
+impl<const B: usize> FrameReader<B> {
+    pub const fn new() -> Self {
+        Self {
+            prefix: heapless::Vec::new(),
+            message: heapless::Vec::new(),
+        }
+    }
+
+    /// Takes bytes from `segment`, returning how many were used: the rest belongs to the next
+    /// message.
+    pub fn push(&mut self, segment: &[u8]) -> Result<usize, DnsError> {
+        let mut used = 0;
+        while !self.prefix.is_full()
+            && let Some(byte) = segment.get(used)
+        {
+            let _ = self.prefix.push(*byte);
+            used += 1;
+        }
+
+        let Some(expected) = self.expected() else {
+            return Ok(used);
+        };
+        if expected > B {
+            return Err(DnsError::MessageTooLarge);
+        }
+
+        let take = (expected - self.message.len()).min(segment.len() - used);
+        let _ = self.message.extend_from_slice(&segment[used..used + take]);
+        Ok(used + take)
+    }
+
+    /// The message, once complete.
+    pub fn message(&self) -> Option<&[u8]> {
+        (self.expected() == Some(self.message.len())).then_some(&self.message)
+    }
+
+    /// Gets ready for the next message.
+    pub fn reset(&mut self) {
+        self.prefix.clear();
+        self.message.clear();
+    }
+
+    fn expected(&self) -> Option<usize> {
+        let prefix: [u8; PREFIX_LEN] = self.prefix.as_slice().try_into().ok()?;
+        Some(u16::from_be_bytes(prefix) as usize)
+    }
+}
+
+/// Relays the framed messages of a connection, reassembling each, of up to `B` bytes, to check
+/// it against rebind protection before it's passed on.
+#[derive(Debug, Default)]
+pub struct StreamRelay<const B: usize> {
+    reader: FrameReader<B>,
+    messages: u32,
+    rebinding: u32,
+}
+
+impl<const B: usize> StreamRelay<B> {
+    pub const fn new() -> Self {
+        Self {
+            reader: FrameReader::new(),
+            messages: 0,
+            rebinding: 0,
+        }
+    }
+
+    /// Takes bytes from `segment`, returning how many were used: the rest belongs to the next
+    /// message, to be pushed once this one was taken with [`Self::take`].
+    pub fn push(&mut self, segment: &[u8]) -> Result<usize, DnsError> {
+        self.reader.push(segment)
+    }
+
+    /// Writes the message, once complete, framed into `out` and returns the framed length.
+    ///
+    /// A message [`RebindProtection::is_rebinding`] refuses has its answers stripped, as over
+    /// UDP, so the client sees an empty answer.
+    pub fn take<const W: usize, const L: usize>(
+        &mut self,
+        protection: &RebindProtection<W, L>,
+        out: &mut [u8],
+    ) -> Result<Option<usize>, DnsError> {
+        let Some(message) = self.reader.message() else {
+            return Ok(None);
+        };
+
+        let rebinding = protection.is_rebinding(&Message::parse(message)?)?;
+        let mut framed = write_frame(message, out).ok_or(DnsError::MessageTooLarge)?;
+        if rebinding {
+            let len = protection.filter(&mut out[PREFIX_LEN..framed])?;
+            framed = write_frame_prefix(len, out);
+            self.rebinding += 1;
+        }
+
+        self.reader.reset();
+        self.messages += 1;
+        Ok(Some(framed))
+    }
+
+    /// Messages relayed so far.
+    pub fn messages(&self) -> u32 {
+        self.messages
+    }
+
+    /// Messages whose answers were stripped so far.
+    pub fn rebinding(&self) -> u32 {
+        self.rebinding
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RelayState {
+    /// Reassembling the client's query.
+    Query,
+    /// Query sent upstream, streaming the response back.
+    Response,
+    /// Response complete, both connections can be closed.
+    Done,
+}
+
+/// Relay of a client's TCP connection to upstream, for a single query and its response, each
+/// of up to `B` bytes.
+#[derive(Debug)]
+pub struct TcpRelay<const B: usize> {
+    query: FrameReader<B>,
+    response: StreamRelay<B>,
+    state: RelayState,
+}
+
+impl<const B: usize> Default for TcpRelay<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const B: usize> TcpRelay<B> {
+    pub const fn new() -> Self {
+        Self {
+            query: FrameReader::new(),
+            response: StreamRelay::new(),
+            state: RelayState::Query,
+        }
+    }
+
+    pub fn state(&self) -> RelayState {
+        self.state
+    }
+
+    /// Takes a segment from the client, returning the query once complete, to be written
+    /// upstream with [`write_frame`] after the forwarder's policies were applied.
+    pub fn client_data(&mut self, segment: &[u8]) -> Result<Option<&[u8]>, DnsError> {
+        if self.state != RelayState::Query {
+            // Pipelined queries wait for a connection of their own.
+            return Ok(None);
+        }
+
+        self.query.push(segment)?;
+        let Some(query) = self.query.message() else {
+            return Ok(None);
+        };
+
+        self.state = RelayState::Response;
+        Ok(Some(query))
+    }
+
+    /// Takes a segment from upstream, returning the response framed into `out` for the client
+    /// once complete and checked against `protection`, see [`StreamRelay::take`].
+    pub fn upstream_data<const W: usize, const L: usize>(
+        &mut self,
+        segment: &[u8],
+        protection: &RebindProtection<W, L>,
+        out: &mut [u8],
+    ) -> Result<Option<usize>, DnsError> {
+        if self.state != RelayState::Response {
+            return Ok(None);
+        }
+
+        self.response.push(segment)?;
+        let framed = self.response.take(protection, out)?;
+        if framed.is_some() {
+            self.state = RelayState::Done;
+        }
+        Ok(framed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{CLASS_IN, HEADER_LEN, rtype};
+
+    type Protection = RebindProtection<1, 16>;
+
+    /// Response answering `example.com` with `address`.
+    fn response(address: [u8; 4]) -> Vec<u8> {
+        let mut message = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        message.extend_from_slice(b"\x07example\x03com\x00");
+        message.extend_from_slice(&rtype::A.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        message.extend_from_slice(&rtype::A.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&300u32.to_be_bytes());
+        message.extend_from_slice(&4u16.to_be_bytes());
+        message.extend_from_slice(&address);
+        message
+    }
+
+    fn framed(message: &[u8]) -> Vec<u8> {
+        let mut out = vec![0; PREFIX_LEN + message.len()];
+        write_frame(message, &mut out).unwrap();
+        out
+    }
+
+    /// Relay past the client's query.
+    fn relay() -> TcpRelay<512> {
+        let mut relay = TcpRelay::new();
+        // The header and question, enough of a query for the relay.
+        let query = framed(&response([0; 4])[..29]);
+        assert!(relay.client_data(&query).unwrap().is_some());
+        relay
+    }
+
+    #[test]
+    fn response_is_relayed_once_reassembled() {
+        let protection = Protection::new(true);
+        let response = framed(&response([93, 184, 216, 34]));
+        let mut relay = relay();
+        let mut out = [0; 512];
+
+        let (first, rest) = response.split_at(7);
+        assert_eq!(relay.upstream_data(first, &protection, &mut out), Ok(None));
+        assert_eq!(relay.state(), RelayState::Response);
+        assert_eq!(
+            relay.upstream_data(rest, &protection, &mut out),
+            Ok(Some(response.len()))
+        );
+        assert_eq!(out[..response.len()], response);
+        assert_eq!(relay.state(), RelayState::Done);
+    }
+
+    #[test]
+    fn rebinding_response_has_its_answers_stripped() {
+        let protection = Protection::new(true);
+        let mut relay = StreamRelay::<512>::new();
+        let response = response([192, 168, 1, 10]);
+        let mut out = [0; 512];
+
+        assert_eq!(
+            relay.push(&framed(&response)),
+            Ok(PREFIX_LEN + response.len())
+        );
+        let len = relay.take(&protection, &mut out).unwrap().unwrap();
+
+        // Header and question only, no answer.
+        let stripped = &out[PREFIX_LEN..len];
+        assert_eq!(out[..PREFIX_LEN], ((len - PREFIX_LEN) as u16).to_be_bytes());
+        assert_eq!(stripped.len(), 29);
+        assert_eq!(Header::parse(stripped).unwrap().answers, 0);
+        assert_eq!((relay.messages(), relay.rebinding()), (1, 1));
+
+        // Left alone with protection off.
+        assert_eq!(
+            relay.push(&framed(&response)),
+            Ok(PREFIX_LEN + response.len())
+        );
+        let len = relay.take(&Protection::new(false), &mut out).unwrap();
+        assert_eq!(len, Some(PREFIX_LEN + response.len()));
+        assert_eq!(relay.rebinding(), 1);
+    }
+
+    #[test]
+    fn responses_larger_than_the_buffer_are_refused() {
+        let protection = Protection::new(true);
+        let mut relay = StreamRelay::<16>::new();
+        let response = framed(&response([93, 184, 216, 34]));
+
+        assert_eq!(relay.push(&response), Err(DnsError::MessageTooLarge));
+        assert_eq!(relay.take(&protection, &mut [0; 64]), Ok(None));
+    }
+}
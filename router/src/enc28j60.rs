@@ -1,271 +1,1968 @@
-use core::ops::RangeInclusive;
+use core::{net::Ipv4Addr, ops::RangeInclusive};
 
-use macros::make_enum;
+use macros::register_map;
 use thiserror::Error;
 
-pub struct Enc28j60<const N: usize = 50, const M: usize = 10> {
+use crate::{checksum, clock, ethernet::MacAddress, peek::PEEK_LEN};
+
+/// Driver for the ENC28J60.
+///
+/// `N` is the number of operations and `M` the number of transactions that can be queued at once,
+/// `B` is the size in bytes of the arena holding the operations' payloads.
+/// Sizes too small to queue the driver's own sequences, like [`Self::init`], fail to compile.
+pub struct Enc28j60<const N: usize = 50, const M: usize = 30, const B: usize = 100> {
     current_bank: Bank,
-    pending_transactions: Transactions<N, M>,
+    pending_transactions: Transactions<N, M, B>,
     erx_range: RangeInclusive<ux::u9>,
     ready: bool,
+    reset: ResetState,
+    /// Filter last queued for programming, `None` until the first one.
+    rx_filter: Option<RxFilter>,
+    rx_drops: RxDropStats,
+    rx: RxState,
+    /// Buffer address of the next received packet's header.
+    rx_next: u16,
+    rx_frames: RxFrames,
+    tx_policy: TxPolicy,
+    /// A PHY register access is running, MISTAT is polled until it's done.
+    mii_busy: bool,
+    phy_read: PhyReadState,
+    dma: DmaState,
+    /// Link state last read from PHSTAT2, `None` until the first read.
+    link: Option<bool>,
+    /// The link state changed since [`Self::take_link_change`] was last called.
+    link_changed: bool,
+    /// Interrupts decoded from EIR since [`Self::take_interrupts`] was last called.
+    interrupts: Interrupts,
+    /// EIE.INTIE goes back on once what the last EIR read called for is done.
+    interrupt_rearm: bool,
 }
 
-//// One of 4 memory banks for control registers.
-#[repr(u8)]
+/// Length of the next packet pointer and receive status vector preceding each received frame.
+pub const RX_HEADER_LEN: usize = 6;
+/// Longest frame read out of the chip, without its CRC.
+pub const RX_FRAME_CAPACITY: usize = 1518;
+
+/// Where the receive path is at, see [`Enc28j60::receive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RxState {
+    Idle,
+    /// EPKTCNT read queued.
+    Counting,
+    /// Read of the header at `rx_next` queued.
+    Header,
+    /// Frame being read a chunk at a time, `remaining` bytes of it not read yet.
+    Frame {
+        header: RxHeader,
+        remaining: usize,
+    },
+}
+
+/// Buffers of the receive path: while the stack works on a frame read earlier, the next one
+/// is read into the other buffer.
+#[derive(Debug)]
+struct RxFrames {
+    buffers: [heapless::Vec<u8, RX_FRAME_CAPACITY>; 2],
+    /// Headers of the frames read and not released yet, the oldest one's in `buffers[head]`.
+    ready: heapless::Deque<RxHeader, 2>,
+    head: usize,
+}
+
+impl RxFrames {
+    const fn new() -> Self {
+        Self {
+            buffers: [heapless::Vec::new(), heapless::Vec::new()],
+            ready: heapless::Deque::new(),
+            head: 0,
+        }
+    }
+
+    /// Buffer the next frame is read into, `None` while both hold frames.
+    fn filling(&mut self) -> Option<&mut heapless::Vec<u8, RX_FRAME_CAPACITY>> {
+        if self.ready.is_full() {
+            return None;
+        }
+
+        Some(&mut self.buffers[(self.head + self.ready.len()) % 2])
+    }
+
+    /// Marks the frame read into [`Self::filling`] as ready.
+    fn push(&mut self, header: RxHeader) {
+        let _ = self.ready.push_back(header);
+    }
+
+    fn front(&self) -> Option<(RxHeader, &[u8])> {
+        let header = *self.ready.front()?;
+        Some((header, &self.buffers[self.head]))
+    }
+
+    fn pop(&mut self) {
+        if self.ready.pop_front().is_some() {
+            self.buffers[self.head].clear();
+            self.head = (self.head + 1) % 2;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffers.iter_mut().for_each(|buffer| buffer.clear());
+        self.ready.clear();
+        self.head = 0;
+    }
+}
+
+/// Where a PHY register read is at, see [`Enc28j60::read_phy`]. `take` is false for reads the
+/// driver makes for itself, like [`Enc28j60::poll_link`], whose value isn't kept.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PhyReadState {
+    Idle,
+    /// Read queued, MIRDL next.
+    Pending {
+        register: PhyRegister,
+        take: bool,
+    },
+    /// MIRDL read, MIRDH next.
+    Low {
+        register: PhyRegister,
+        take: bool,
+        low: u8,
+    },
+    /// Value read, waiting to be taken.
+    Ready(PhyRegister, u16),
+}
+
+/// Where a system reset is at, see [`Enc28j60::reset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResetState {
+    Idle,
+    /// SRC goes out before anything else queued.
+    Pending,
+    /// SRC went out, ESTAT isn't polled before the deadline, set by the first
+    /// [`Enc28j60::settle_reset`] after it.
+    Settling(Option<u32>),
+}
+
+/// Where a DMA checksum is at, see [`Enc28j60::start_dma_checksum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DmaState {
+    Idle,
+    /// Started, ECON1 read queued.
+    Running,
+    /// ECON1.DMAST found set, ECON1 is polled until it clears.
+    Busy,
+    /// Done, EDMACSL next.
+    Done,
+    /// EDMACSL read, EDMACSH next.
+    Low(u8),
+    /// Checksum read, waiting to be taken.
+    Ready(u16),
+}
+
+/// Header the chip writes before each received frame: where the next one starts and the
+/// receive status vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxHeader {
+    /// Buffer address of the next frame's header.
+    pub next_packet: u16,
+    /// Length of the frame including its CRC.
+    pub byte_count: u16,
+    /// Bits 31:16 of the receive status vector.
+    status: u16,
+}
+
+impl RxHeader {
+    const LONG_EVENT: u16 = 1 << 0;
+    const CRC_ERROR: u16 = 1 << 4;
+    const LENGTH_CHECK_ERROR: u16 = 1 << 5;
+    const RECEIVED_OK: u16 = 1 << 7;
+    const MULTICAST: u16 = 1 << 8;
+    const BROADCAST: u16 = 1 << 9;
+
+    /// Shortest valid frame, CRC included.
+    const MIN_FRAME_LEN: u16 = 64;
+
+    pub fn parse(bytes: &[u8; RX_HEADER_LEN]) -> Self {
+        Self {
+            next_packet: u16::from_le_bytes([bytes[0], bytes[1]]),
+            byte_count: u16::from_le_bytes([bytes[2], bytes[3]]),
+            status: u16::from_le_bytes([bytes[4], bytes[5]]),
+        }
+    }
+
+    /// Length of the frame without its CRC.
+    pub fn frame_len(&self) -> usize {
+        self.byte_count.saturating_sub(4) as usize
+    }
+
+    /// Bytes to read first to decide on the frame, see [`crate::peek`]. The rest of the frame,
+    /// if any, is only read when it's wanted.
+    pub fn peek_len(&self) -> usize {
+        self.frame_len().min(PEEK_LEN)
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.status & Self::MULTICAST != 0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.status & Self::BROADCAST != 0
+    }
+
+    /// Checks whether the frame is worth reading, so bad ones can be skipped by moving the read
+    /// pointer to [`RxHeader::next_packet`] without spending SPI time on their payload.
+    ///
+    /// `max_len` is the longest frame accepted, CRC included, e.g. 1522 for tagged frames.
+    pub fn check(&self, max_len: u16) -> Result<(), RxDropReason> {
+        if self.status & Self::CRC_ERROR != 0 {
+            Err(RxDropReason::Crc)
+        } else if self.status & Self::LENGTH_CHECK_ERROR != 0 {
+            Err(RxDropReason::LengthMismatch)
+        } else if self.status & Self::LONG_EVENT != 0 || self.byte_count > max_len {
+            Err(RxDropReason::Oversized)
+        } else if self.byte_count < Self::MIN_FRAME_LEN {
+            Err(RxDropReason::Runt)
+        } else if self.status & Self::RECEIVED_OK == 0 {
+            // Symbol errors and anything else the chip didn't like.
+            Err(RxDropReason::NotOk)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Why a received frame was dropped without reading its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RxDropReason {
+    Crc,
+    /// The length field doesn't match the payload.
+    LengthMismatch,
+    Oversized,
+    Runt,
+    NotOk,
+}
+
+/// Frames dropped on their status vector, by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxDropStats {
+    pub crc: u32,
+    pub length_mismatch: u32,
+    pub oversized: u32,
+    pub runt: u32,
+    pub not_ok: u32,
+    /// Receive errors reported by EIR.RXERIF: frames lost to a full buffer or to EPKTCNT
+    /// reaching 255. Counted per interrupt, which may have lost several.
+    pub buffer_full: u32,
+}
+
+/// Interrupt sources of the chip, their flags in EIR and enable bits in EIE.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Interrupts {
+    /// PKTIF: packets are waiting in the receive buffer.
+    pub packet: bool,
+    /// LINKIF: the link went up or down.
+    pub link: bool,
+    /// TXIF: the last transmission ended.
+    pub tx: bool,
+    /// TXERIF: the last transmission was aborted.
+    pub tx_error: bool,
+    /// RXERIF: a frame was lost, see [`RxDropStats::buffer_full`].
+    pub rx_error: bool,
+}
+
+impl Interrupts {
+    /// Every source the driver handles.
+    pub const ALL: Interrupts = Interrupts {
+        packet: true,
+        link: true,
+        tx: true,
+        tx_error: true,
+        rx_error: true,
+    };
+
+    const PKTIF: u8 = 0b0100_0000;
+    const LINKIF: u8 = 0b0001_0000;
+    const TXIF: u8 = 0b0000_1000;
+    const TXERIF: u8 = 0b0000_0010;
+    const RXERIF: u8 = 0b0000_0001;
+
+    /// Decodes EIR, or EIE whose bits are at the same places.
+    const fn from_bits(bits: u8) -> Self {
+        Self {
+            packet: bits & Self::PKTIF != 0,
+            link: bits & Self::LINKIF != 0,
+            tx: bits & Self::TXIF != 0,
+            tx_error: bits & Self::TXERIF != 0,
+            rx_error: bits & Self::RXERIF != 0,
+        }
+    }
+
+    const fn bits(&self) -> u8 {
+        let mut bits = 0;
+        if self.packet {
+            bits |= Self::PKTIF;
+        }
+        if self.link {
+            bits |= Self::LINKIF;
+        }
+        if self.tx {
+            bits |= Self::TXIF;
+        }
+        if self.tx_error {
+            bits |= Self::TXERIF;
+        }
+        if self.rx_error {
+            bits |= Self::RXERIF;
+        }
+        bits
+    }
+
+    pub const fn any(&self) -> bool {
+        self.bits() != 0
+    }
+}
+
+/// Frames taken out of the chip in one service pass of the interrupt line.
+///
+/// A pass starts from EPKTCNT and drains it up to a budget, rather than taking a single frame
+/// per interrupt, which under load would mean an interrupt per frame. Frames left over keep the
+/// line asserted, so they're served by the next pass after the rest of the main loop had its
+/// turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxBatch {
+    remaining: u8,
+    left_over: u8,
+}
+
+impl RxBatch {
+    /// Default for [`RxBatch::start`]'s budget.
+    pub const DEFAULT_BUDGET: u8 = 8;
+
+    /// Starts a pass with `packet_count` frames waiting, as read from EPKTCNT.
+    pub fn start(packet_count: u8, budget: u8) -> Self {
+        let taken = packet_count.min(budget);
+        Self {
+            remaining: taken,
+            left_over: packet_count - taken,
+        }
+    }
+
+    /// Whether another frame should be read in this pass, counting it if so.
+    pub fn take(&mut self) -> bool {
+        let Some(remaining) = self.remaining.checked_sub(1) else {
+            return false;
+        };
+
+        self.remaining = remaining;
+        true
+    }
+
+    /// Frames the budget left for the next pass.
+    pub fn left_over(&self) -> u8 {
+        self.left_over
+    }
+}
+
+/// Receive filter of the chip: which frames make it into the receive buffer.
+///
+/// Derived from what the stack needs with [`RxFilter::for_stack`], or chosen with an
+/// [`RxFilterConfig`], and applied with [`Enc28j60::reconcile_rx_filter`], which only
+/// reprograms what changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxFilter {
+    /// Station address matched by the unicast and Magic Packet filters (MAADR).
+    pub mac: MacAddress,
+    /// ERXFCON value.
+    pub erxfcon: u8,
+    /// Hash table for multicast groups (EHT0 to EHT7).
+    pub hash_table: [u8; 8],
+    /// Pattern of the pattern match filter, programmed when ERXFCON.PMEN is set.
+    pub pattern: Option<PatternMatch>,
+}
+
+impl RxFilter {
+    // ERXFCON bits.
+    const UCEN: u8 = 0b1000_0000;
+    const ANDOR: u8 = 0b0100_0000;
+    const CRCEN: u8 = 0b0010_0000;
+    const PMEN: u8 = 0b0001_0000;
+    const MPEN: u8 = 0b0000_1000;
+    const HTEN: u8 = 0b0000_0100;
+    const MCEN: u8 = 0b0000_0010;
+    const BCEN: u8 = 0b0000_0001;
+
+    /// Accepts everything, needed when bridging.
+    pub fn promiscuous(mac: MacAddress) -> Self {
+        RxFilterConfig::promiscuous(mac).filter()
+    }
+
+    /// Filter for a stack at `mac` that joined `groups`, or wants every multicast frame with
+    /// `all_multicast` (e.g. as IGMP querier). Bridging needs every frame and overrides the rest.
+    pub fn for_stack(
+        mac: MacAddress,
+        groups: impl IntoIterator<Item = Ipv4Addr>,
+        all_multicast: bool,
+        bridged: bool,
+    ) -> Self {
+        if bridged {
+            return Self::promiscuous(mac);
+        }
+
+        let mut config = RxFilterConfig::new(mac);
+        if all_multicast {
+            return config.all_multicast(true).filter();
+        }
+
+        for group in groups {
+            config = config.multicast_group(MacAddress::ipv4_multicast(group));
+        }
+        config.filter()
+    }
+
+    /// Hash table bit a destination address maps to: bits 28:23 of its CRC-32.
+    fn hash_pointer(mac: MacAddress) -> usize {
+        let crc = mac.0.iter().fold(u32::MAX, |crc, byte| {
+            (0..8).fold(crc ^ *byte as u32, |crc, _| {
+                (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+            })
+        });
+        ((crc >> 23) & 0x3f) as usize
+    }
+}
+
+/// Pattern match filter: a frame passes when the checksum of the bytes `mask` selects in the
+/// 64 bytes at `offset` is `checksum`, e.g. to wake on a specific packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PatternMatch {
+    /// Offset of the window from the start of the frame (EPMO).
+    pub offset: u16,
+    /// Bit `n` selects byte `n` of the window (EPMM0 to EPMM7).
+    pub mask: u64,
+    /// Internet checksum of the selected bytes, back to back (EPMCS).
+    pub checksum: u16,
+}
+
+impl PatternMatch {
+    /// Matches the bytes `mask` selects in `window`, the frame's 64 bytes at `offset`.
+    pub fn new(offset: u16, window: &[u8; 64], mask: u64) -> Self {
+        let mut selected = heapless::Vec::<u8, 64>::new();
+        for (index, byte) in window.iter().enumerate() {
+            if mask & (1 << index) != 0 {
+                // At most 64 bytes are selected.
+                let _ = selected.push(*byte);
+            }
+        }
+
+        Self {
+            offset,
+            mask,
+            checksum: checksum::checksum(&selected),
+        }
+    }
+}
+
+/// Receive filters to let frames in with, see [`Enc28j60::set_rx_filters`].
+///
+/// [`RxFilterConfig::new`] is a sane default for a host: frames to its address and broadcasts,
+/// with a valid CRC. Each filter is then turned on or off with the methods, frames passing any
+/// enabled filter being let in, or all of them with [`RxFilterConfig::require_all`]. With
+/// every filter off, every frame is let in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxFilterConfig {
+    mac: MacAddress,
+    erxfcon: u8,
+    hash_table: [u8; 8],
+    pattern: Option<PatternMatch>,
+}
+
+impl RxFilterConfig {
+    /// Unicast to `mac`, broadcast and CRC check.
+    pub const fn new(mac: MacAddress) -> Self {
+        Self {
+            mac,
+            erxfcon: RxFilter::UCEN | RxFilter::CRCEN | RxFilter::BCEN,
+            hash_table: [0; 8],
+            pattern: None,
+        }
+    }
+
+    /// Every filter off, every frame let in, even those with a bad CRC.
+    pub const fn promiscuous(mac: MacAddress) -> Self {
+        Self {
+            mac,
+            erxfcon: 0,
+            hash_table: [0; 8],
+            pattern: None,
+        }
+    }
+
+    /// Frames to the station address.
+    pub const fn unicast(self, enabled: bool) -> Self {
+        self.with(RxFilter::UCEN, enabled)
+    }
+
+    pub const fn broadcast(self, enabled: bool) -> Self {
+        self.with(RxFilter::BCEN, enabled)
+    }
+
+    /// Every multicast frame, whatever its group.
+    pub const fn all_multicast(self, enabled: bool) -> Self {
+        self.with(RxFilter::MCEN, enabled)
+    }
+
+    /// Multicast frames to `group`, through the hash table: other groups sharing its hash
+    /// bit get in too.
+    pub fn multicast_group(mut self, group: MacAddress) -> Self {
+        let pointer = RxFilter::hash_pointer(group);
+        self.hash_table[pointer / 8] |= 1 << (pointer % 8);
+        self.with(RxFilter::HTEN, true)
+    }
+
+    /// Drops frames with a bad CRC, whatever the other filters say.
+    pub const fn crc_check(self, enabled: bool) -> Self {
+        self.with(RxFilter::CRCEN, enabled)
+    }
+
+    /// Frames matching `pattern`, or none if `None`.
+    pub const fn pattern(mut self, pattern: Option<PatternMatch>) -> Self {
+        self.pattern = pattern;
+        self.with(RxFilter::PMEN, pattern.is_some())
+    }
+
+    /// Magic Packets to the station address, for Wake-on-LAN.
+    pub const fn magic_packet(self, enabled: bool) -> Self {
+        self.with(RxFilter::MPEN, enabled)
+    }
+
+    /// Lets in only the frames passing every enabled filter, rather than any of them.
+    pub const fn require_all(self, enabled: bool) -> Self {
+        self.with(RxFilter::ANDOR, enabled)
+    }
+
+    pub const fn filter(&self) -> RxFilter {
+        RxFilter {
+            mac: self.mac,
+            erxfcon: self.erxfcon,
+            hash_table: self.hash_table,
+            pattern: self.pattern,
+        }
+    }
+
+    const fn with(mut self, bit: u8, enabled: bool) -> Self {
+        if enabled {
+            self.erxfcon |= bit;
+        } else {
+            self.erxfcon &= !bit;
+        }
+        self
+    }
+}
+
+/// Shortest frame on the wire without its CRC, receivers discard shorter ones as runts.
+pub const MIN_FRAME_LEN: usize = 60;
+/// Length of the CRC ending each frame.
+pub const CRC_LEN: usize = 4;
+/// Destination, source and EtherType, the least a frame can be before padding.
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Longest frame transmitted without its CRC: 1514 bytes plus a VLAN tag.
+pub const MAX_TX_FRAME_LEN: usize = 1518;
+
+/// Length of the status vector the chip writes after each transmitted frame.
+const TX_STATUS_LEN: usize = 7;
+
+/// Who pads short frames and appends the CRC on transmission.
+///
+/// Written as the per-packet control byte preceding each frame in the transmit buffer, which
+/// overrides MACON3 so the frame goes out the same way whatever init programmed there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxPolicy {
+    /// Pad frames shorter than [`MIN_FRAME_LEN`] with zeros in [`Self::prepare`] rather than
+    /// leaving it to the chip.
+    pub software_padding: bool,
+    /// Have the chip append the CRC. Off for frames already carrying theirs, e.g. relayed
+    /// unchanged, which then can't be padded.
+    pub append_crc: bool,
+}
+
+impl Default for TxPolicy {
+    fn default() -> Self {
+        Self {
+            software_padding: true,
+            append_crc: true,
+        }
+    }
+}
+
+impl TxPolicy {
+    // Per-packet control byte bits.
+    const POVERRIDE: u8 = 0b0000_0001;
+    const PCRCEN: u8 = 0b0000_0010;
+    const PPADEN: u8 = 0b0000_0100;
+
+    /// Per-packet control byte to write before the frame.
+    pub fn control_byte(&self) -> u8 {
+        let mut control = Self::POVERRIDE;
+        if self.append_crc {
+            control |= Self::PCRCEN;
+            // The chip pads before appending the CRC, a frame carrying its own can't be padded.
+            if !self.software_padding {
+                control |= Self::PPADEN;
+            }
+        }
+        control
+    }
+
+    /// Readies the frame in the first `len` bytes of `buffer` for transmission, padding it if
+    /// needed, and returns its length. Frames too short to go out as they are fail with
+    /// [`TxFrameError::Runt`], and those over [`MAX_TX_FRAME_LEN`] with
+    /// [`TxFrameError::TooLong`], so the chip is never handed one.
+    pub fn prepare(&self, buffer: &mut [u8], len: usize) -> Result<usize, TxFrameError> {
+        if len > buffer.len() {
+            return Err(TxFrameError::BufferTooSmall);
+        }
+
+        let padded_len = self.padded_len(len)?;
+        buffer
+            .get_mut(len..padded_len)
+            .ok_or(TxFrameError::BufferTooSmall)?
+            .fill(0);
+        Ok(padded_len)
+    }
+
+    /// Length a frame of `len` bytes goes out with, once padded in software if needed.
+    fn padded_len(&self, len: usize) -> Result<usize, TxFrameError> {
+        // Without a CRC to append, padding would have to go before the one the frame carries.
+        let (min_len, max_len) = if self.append_crc {
+            (ETHERNET_HEADER_LEN, MAX_TX_FRAME_LEN)
+        } else {
+            (MIN_FRAME_LEN + CRC_LEN, MAX_TX_FRAME_LEN + CRC_LEN)
+        };
+        if len < min_len {
+            return Err(TxFrameError::Runt(len));
+        }
+        if len > max_len {
+            return Err(TxFrameError::TooLong(len));
+        }
+
+        if !self.append_crc || !self.software_padding {
+            return Ok(len);
+        }
+        Ok(len.max(MIN_FRAME_LEN))
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TxFrameError {
+    #[error("Frame of {0} bytes is too short to transmit.")]
+    Runt(usize),
+    #[error("Buffer is too small to hold the padded frame.")]
+    BufferTooSmall,
+    #[error("Frame of {0} bytes is too long to transmit.")]
+    TooLong(usize),
+    #[error("Transmit buffer doesn't fit the frame after the receive buffer.")]
+    TxBufferTooSmall,
+    #[error("Transaction queue has no room for the frame.")]
+    QueueFull,
+}
+
+/// One of 4 memory banks for control registers.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Bank {
+    #[default]
     Bank0 = 0b00,
     Bank1 = 0b01,
     Bank2 = 0b10,
     Bank3 = 0b11,
 }
 
-impl Default for Bank {
-    fn default() -> Self {
-        Bank::Bank0
+/// Control register, one of the named constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Register {
+    /// `None` for the registers at the end of the address space, present in every bank.
+    bank: Option<Bank>,
+    address: u8,
+    /// MAC and MII registers read back after a dummy byte, and take no bit field operations.
+    mac: bool,
+}
+
+impl Register {
+    pub const fn bank(&self) -> Option<Bank> {
+        self.bank
+    }
+
+    /// Address within the bank, 5 bits.
+    pub const fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Whether it's a MAC or MII register, whose reads start with a dummy byte.
+    pub const fn is_mac(&self) -> bool {
+        self.mac
+    }
+
+    /// Bytes clocked out when reading it.
+    const fn read_len(&self) -> usize {
+        if self.mac { 2 } else { 1 }
+    }
+}
+
+/// Pair of control registers holding a 16-bit value, low byte first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WordRegister {
+    low: Register,
+}
+
+impl WordRegister {
+    pub const fn low(&self) -> Register {
+        self.low
+    }
+
+    pub const fn high(&self) -> Register {
+        Register {
+            address: self.low.address + 1,
+            ..self.low
+        }
+    }
+}
+
+register_map! {
+    Register, WordRegister;
+
+    ERDPT: Bank0[0x00] word;
+    EWRPT: Bank0[0x02] word;
+    ETXST: Bank0[0x04] word;
+    ETXND: Bank0[0x06] word;
+    ERXST: Bank0[0x08] word;
+    ERXND: Bank0[0x0A] word;
+    ERXRDPT: Bank0[0x0C] word;
+    ERXWRPT: Bank0[0x0E] word;
+    EDMAST: Bank0[0x10] word;
+    EDMAND: Bank0[0x12] word;
+    EDMADST: Bank0[0x14] word;
+    EDMACS: Bank0[0x16] word;
+
+    EHT0: Bank1[0x00];
+    EHT1: Bank1[0x01];
+    EHT2: Bank1[0x02];
+    EHT3: Bank1[0x03];
+    EHT4: Bank1[0x04];
+    EHT5: Bank1[0x05];
+    EHT6: Bank1[0x06];
+    EHT7: Bank1[0x07];
+    EPMM0: Bank1[0x08];
+    EPMM1: Bank1[0x09];
+    EPMM2: Bank1[0x0A];
+    EPMM3: Bank1[0x0B];
+    EPMM4: Bank1[0x0C];
+    EPMM5: Bank1[0x0D];
+    EPMM6: Bank1[0x0E];
+    EPMM7: Bank1[0x0F];
+    EPMCS: Bank1[0x10] word;
+    EPMO: Bank1[0x14] word;
+    ERXFCON: Bank1[0x18];
+    EPKTCNT: Bank1[0x19];
+
+    MACON1: Bank2[0x00] mac;
+    MACON3: Bank2[0x02] mac;
+    MACON4: Bank2[0x03] mac;
+    MABBIPG: Bank2[0x04] mac;
+    MAIPG: Bank2[0x06] word mac;
+    MACLCON1: Bank2[0x08] mac;
+    MACLCON2: Bank2[0x09] mac;
+    MAMXFL: Bank2[0x0A] word mac;
+    MICMD: Bank2[0x12] mac;
+    MIREGADR: Bank2[0x14] mac;
+    MIWR: Bank2[0x16] word mac;
+    MIRD: Bank2[0x18] word mac;
+
+    MAADR5: Bank3[0x00] mac;
+    MAADR6: Bank3[0x01] mac;
+    MAADR3: Bank3[0x02] mac;
+    MAADR4: Bank3[0x03] mac;
+    MAADR1: Bank3[0x04] mac;
+    MAADR2: Bank3[0x05] mac;
+    EBSTSD: Bank3[0x06];
+    EBSTCON: Bank3[0x07];
+    EBSTCS: Bank3[0x08] word;
+    MISTAT: Bank3[0x0A] mac;
+    EREVID: Bank3[0x12];
+    ECOCON: Bank3[0x15];
+    EFLOCON: Bank3[0x17];
+    EPAUS: Bank3[0x18] word;
+
+    EIE: Common[0x1B];
+    EIR: Common[0x1C];
+    ESTAT: Common[0x1D];
+    ECON2: Common[0x1E];
+    ECON1: Common[0x1F];
+}
+
+/// PHY register, reached through the MII registers rather than addressed directly.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PhyRegister {
+    PHCON1 = 0x00,
+    PHSTAT1 = 0x01,
+    PHID1 = 0x02,
+    PHID2 = 0x03,
+    PHCON2 = 0x10,
+    PHSTAT2 = 0x11,
+    PHIE = 0x12,
+    PHIR = 0x13,
+    PHLCON = 0x14,
+}
+
+/// Operation Code for interfacing with ENC28j60.
+// TODO: is there a way in the type system to represent that some of these are 3-bits + 5-bit address vs other that are just 8 bits?
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms, dead_code)]
+enum OpCode {
+    /// Read control register.
+    RCR = 0b000_00000,
+    /// Read buffer memory.
+    RBM = 0b001_11010,
+    /// Write control register.
+    WCR = 0b010_00000,
+    /// Write buffer memory.
+    WBM = 0b011_11010,
+    /// Bit field set.
+    BFS = 0b100_00000,
+    /// Bit field clear.
+    BFC = 0b101_00000,
+    /// System reset command.
+    SRC = 0b111_11111,
+}
+
+/// Queue of pending transactions.
+///
+/// Operations are stored as one byte [`OperationDescriptor`]s with their payloads packed back to
+/// back in a shared byte arena, so each queued operation costs 1 byte of `N` plus its payload in `B`.
+/// Control register operations carry at most 2 bytes, making `B = 2 * N` always enough for them.
+/// On top of that, each transaction takes a `usize` of `M` to remember how many operations it spans.
+#[derive(Default)]
+struct Transactions<const N: usize, const M: usize, const B: usize> {
+    operations: heapless::Deque<OperationDescriptor, N>,
+    bytes: heapless::Deque<u8, B>,
+    bounds: heapless::Deque<usize, M>,
+    /// Register each transaction reads, alongside `bounds`.
+    reads: heapless::Deque<Option<Register>, M>,
+    /// Most room taken at once, each field on its own.
+    high_water: QueueUsage,
+}
+
+/// Room taken in the queue of pending transactions, against `N`, `M` and `B`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QueueUsage {
+    pub operations: usize,
+    pub transactions: usize,
+    pub bytes: usize,
+}
+
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransactionError {
+    #[error("Buffer ran out of memory for additional operations.")]
+    OperationsOutOfMemory,
+    #[error("Buffer ran out of memory for additional transactions.")]
+    TransactionOutOfMemory,
+}
+
+/// Replies from the chip that don't fit the transaction that produced them.
+///
+/// A glitch on the bus can corrupt what comes back, the driver reports it rather than acting on
+/// it and leaves resetting the chip to its owner.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolViolation {
+    #[error("Register read without a read buffer.")]
+    MissingReadBuffer,
+    #[error("Register read with an empty read buffer.")]
+    EmptyReadBuffer,
+}
+
+impl<const N: usize, const M: usize, const B: usize> Transactions<N, M, B> {
+    fn push_write(&mut self, payload: &[u8]) -> Result<(), TransactionError> {
+        self.push_operation(
+            OperationDescriptor::new(OperationKind::Write, payload.len()),
+            payload.iter().copied(),
+        )
+    }
+
+    fn push_read(&mut self, len: usize) -> Result<(), TransactionError> {
+        self.push_operation(
+            OperationDescriptor::new(OperationKind::Read, len),
+            core::iter::repeat_n(0, len),
+        )
+    }
+
+    fn push_operation(
+        &mut self,
+        descriptor: OperationDescriptor,
+        payload: impl Iterator<Item = u8>,
+    ) -> Result<(), TransactionError> {
+        if self.operations.is_full() || self.bytes.capacity() - self.bytes.len() < descriptor.len()
+        {
+            // A half-queued transaction would hit the bus without its trailing operations,
+            // so the whole transaction is dropped instead.
+            self.abort_transaction();
+            return Err(TransactionError::OperationsOutOfMemory);
+        }
+
+        if self.bounds.is_empty() {
+            self.new_transaction()?;
+        }
+        let Some(bound) = self.bounds.back_mut() else {
+            return Err(TransactionError::TransactionOutOfMemory);
+        };
+        *bound += 1;
+
+        // Room was checked above.
+        let _ = self.operations.push_back(descriptor);
+        for byte in payload {
+            let _ = self.bytes.push_back(byte);
+        }
+
+        self.record_high_water();
+        self.debug_check_invariants();
+        Ok(())
+    }
+
+    fn new_transaction(&mut self) -> Result<(), TransactionError> {
+        self.bounds
+            .push_back(0)
+            .map_err(|_| TransactionError::TransactionOutOfMemory)?;
+        // As long as `bounds`.
+        let _ = self.reads.push_back(None);
+        self.record_high_water();
+        Ok(())
+    }
+
+    /// Records the transaction being built as a read of `register`.
+    fn set_read(&mut self, register: Register) {
+        if let Some(read) = self.reads.back_mut() {
+            *read = Some(register);
+        }
+    }
+
+    /// Removes the transaction currently being built along with all of its operations.
+    fn abort_transaction(&mut self) {
+        let Some(boundary) = self.bounds.pop_back() else {
+            return;
+        };
+        self.reads.pop_back();
+
+        for _ in 0..boundary {
+            let Some(descriptor) = self.operations.pop_back() else {
+                break;
+            };
+            for _ in 0..descriptor.len() {
+                self.bytes.pop_back();
+            }
+        }
+
+        self.debug_check_invariants();
+    }
+
+    fn pop_transaction(&mut self) -> Option<Transaction<N, B>> {
+        let boundary = self.bounds.pop_front()?;
+        let mut result = Transaction {
+            read: self.reads.pop_front().flatten(),
+            ..Transaction::default()
+        };
+        // A transaction holds at most the whole queue, which `result` has the room for.
+        for _ in 0..boundary {
+            let Some(descriptor) = self.operations.pop_front() else {
+                break;
+            };
+            let _ = result.operations.push(descriptor);
+            for _ in 0..descriptor.len() {
+                if let Some(byte) = self.bytes.pop_front() {
+                    let _ = result.bytes.push(byte);
+                }
+            }
+        }
+
+        self.debug_check_invariants();
+        Some(result)
+    }
+
+    fn usage(&self) -> QueueUsage {
+        QueueUsage {
+            operations: self.operations.len(),
+            transactions: self.bounds.len(),
+            bytes: self.bytes.len(),
+        }
+    }
+
+    /// Drops what was queued after the queue was at `usage`.
+    fn truncate(&mut self, usage: QueueUsage) {
+        while self.operations.len() > usage.operations {
+            self.operations.pop_back();
+        }
+        while self.bytes.len() > usage.bytes {
+            self.bytes.pop_back();
+        }
+        while self.bounds.len() > usage.transactions {
+            self.bounds.pop_back();
+            self.reads.pop_back();
+        }
+
+        self.debug_check_invariants();
+    }
+
+    fn record_high_water(&mut self) {
+        let usage = self.usage();
+        self.high_water = QueueUsage {
+            operations: self.high_water.operations.max(usage.operations),
+            transactions: self.high_water.transactions.max(usage.transactions),
+            bytes: self.high_water.bytes.max(usage.bytes),
+        };
+    }
+
+    /// Every queued operation belongs to exactly one transaction and owns exactly its payload.
+    fn debug_check_invariants(&self) {
+        debug_assert_eq!(
+            self.bounds.iter().sum::<usize>(),
+            self.operations.len(),
+            "Transaction bounds out of sync with the operations buffer"
+        );
+        debug_assert_eq!(
+            self.operations.iter().map(|o| o.len()).sum::<usize>(),
+            self.bytes.len(),
+            "Operation descriptors out of sync with the payload arena"
+        );
+        debug_assert_eq!(
+            self.bounds.len(),
+            self.reads.len(),
+            "Transaction reads out of sync with the bounds"
+        );
+    }
+}
+
+impl<const N: usize, const M: usize, const B: usize> Enc28j60<N, M, B> {
+    // ECON1 bits.
+    const TXRST: u8 = 0b1000_0000;
+    const DMAST: u8 = 0b0010_0000;
+    const CSUMEN: u8 = 0b0001_0000;
+    const TXRTS: u8 = 0b0000_1000;
+    const RXEN: u8 = 0b0000_0100;
+
+    // ECON2 bits.
+    const PKTDEC: u8 = 0b0100_0000;
+
+    // EIE bits, the others enable the sources of the same EIR bits, see Interrupts.
+    const INTIE: u8 = 0b1000_0000;
+
+    /// Shortest wait after a system reset before ESTAT is polled, in microseconds.
+    pub const RESET_SETTLE_US: u32 = 1000;
+
+    /// Last address of the chip's 8 KiB buffer memory.
+    const BUFFER_END: u16 = 0x1FFF;
+    /// Longest buffer memory read or write in a transaction, with the opcode in its own
+    /// operation.
+    const BUFFER_CHUNK_LEN: usize = OperationDescriptor::MAX_LEN;
+
+    // MICMD bits.
+    const MIIRD: u8 = 0b0000_0001;
+
+    // MISTAT bits.
+    const BUSY: u8 = 0b0000_0001;
+
+    /// PHY power down.
+    const PHCON1_PPWRSV: u16 = 1 << 11;
+    /// Full duplex, matching MACON3.FULDPX as programmed by init.
+    const PHCON1_PDPXMD: u16 = 1 << 8;
+    /// Keeps transmitted frames from looping back in half duplex, recommended whatever the
+    /// duplex.
+    const PHCON2_HDLDIS: u16 = 1 << 8;
+    /// Link up, as it is now rather than latched like PHSTAT1.LLSTAT.
+    const PHSTAT2_LSTAT: u16 = 1 << 10;
+    /// PHY interrupts reach EIR.LINKIF.
+    const PHIE_PGEIE: u16 = 1 << 1;
+    /// Link changes raise a PHY interrupt.
+    const PHIE_PLNKIE: u16 = 1 << 4;
+
+    /// MAADR registers in order of the address bytes, they aren't laid out sequentially.
+    const MAADR: [Register; 6] = [
+        Register::MAADR1,
+        Register::MAADR2,
+        Register::MAADR3,
+        Register::MAADR4,
+        Register::MAADR5,
+        Register::MAADR6,
+    ];
+    const EHT: [Register; 8] = [
+        Register::EHT0,
+        Register::EHT1,
+        Register::EHT2,
+        Register::EHT3,
+        Register::EHT4,
+        Register::EHT5,
+        Register::EHT6,
+        Register::EHT7,
+    ];
+    const EPMM: [Register; 8] = [
+        Register::EPMM0,
+        Register::EPMM1,
+        Register::EPMM2,
+        Register::EPMM3,
+        Register::EPMM4,
+        Register::EPMM5,
+        Register::EPMM6,
+        Register::EPMM7,
+    ];
+
+    /// Queue space taken by [`Self::init`], keep in sync when adding registers to it.
+    const INIT_USAGE: QueueUsage = QueueUsage {
+        operations: 27,
+        transactions: 25,
+        bytes: 52,
+    };
+
+    const VALID_QUEUE_SIZES: () = {
+        assert!(
+            M <= N,
+            "Every transaction holds at least one operation, M can't be larger than N"
+        );
+        assert!(
+            N >= Self::INIT_USAGE.operations,
+            "N is too small to queue the operations of init"
+        );
+        assert!(
+            M >= Self::INIT_USAGE.transactions,
+            "M is too small to queue the transactions of init"
+        );
+        assert!(
+            B >= Self::INIT_USAGE.bytes,
+            "B is too small to hold the payloads of init"
+        );
+    };
+
+    pub fn with_erx_range(erx_range: RangeInclusive<ux::u9>) -> Self {
+        let () = Self::VALID_QUEUE_SIZES;
+        Self {
+            current_bank: Default::default(),
+            pending_transactions: Default::default(),
+            ready: false,
+            reset: ResetState::Idle,
+            rx_filter: None,
+            rx_drops: RxDropStats::default(),
+            rx: RxState::Idle,
+            rx_next: (*erx_range.start()).into(),
+            rx_frames: RxFrames::new(),
+            tx_policy: TxPolicy::default(),
+            mii_busy: false,
+            phy_read: PhyReadState::Idle,
+            dma: DmaState::Idle,
+            link: None,
+            link_changed: false,
+            interrupts: Interrupts::default(),
+            interrupt_rearm: false,
+            erx_range,
+        }
+    }
+
+    pub fn with_erx_length(length: ux::u9) -> Self {
+        let () = Self::VALID_QUEUE_SIZES;
+        Self {
+            current_bank: Default::default(),
+            pending_transactions: Default::default(),
+            erx_range: (ux::u9::min_value())..=length,
+            ready: false,
+            reset: ResetState::Idle,
+            rx_filter: None,
+            rx_drops: RxDropStats::default(),
+            rx: RxState::Idle,
+            rx_next: 0,
+            rx_frames: RxFrames::new(),
+            tx_policy: TxPolicy::default(),
+            mii_busy: false,
+            phy_read: PhyReadState::Idle,
+            dma: DmaState::Idle,
+            link: None,
+            link_changed: false,
+            interrupts: Interrupts::default(),
+            interrupt_rearm: false,
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), TransactionError> {
+        let before = self.pending_transactions.usage();
+        self.queue_init()?;
+        let after = self.pending_transactions.usage();
+
+        debug_assert!(
+            after.operations - before.operations <= Self::INIT_USAGE.operations
+                && after.transactions - before.transactions <= Self::INIT_USAGE.transactions
+                && after.bytes - before.bytes <= Self::INIT_USAGE.bytes,
+            "init queued more than INIT_USAGE accounts for"
+        );
+
+        Ok(())
+    }
+
+    /// Queues a selection of bank 0, for when something other than the driver wrote ECON1 and
+    /// the selected bank is no longer the one the driver tracks.
+    pub fn reselect_bank(&mut self) -> Result<(), TransactionError> {
+        self.queue_all(|driver| {
+            driver.bit_field_clear(Register::ECON1, Bank::Bank3 as u8)?;
+            driver.current_bank = Bank::Bank0;
+            Ok(())
+        })
+    }
+
+    /// Resets the chip and queues its initialization again, dropping whatever was queued.
+    ///
+    /// Registers the stack programmed, like the receive filter or the enabled interrupts, need
+    /// programming again. Once the SRC went out, nothing is handed out until
+    /// [`Self::settle_reset`] says the oscillator had time to restart.
+    pub fn reset(&mut self) -> Result<(), TransactionError> {
+        self.pending_transactions = Transactions {
+            high_water: self.pending_transactions.high_water,
+            ..Transactions::default()
+        };
+        self.reset = ResetState::Pending;
+        // The reset clears ECON1.
+        self.current_bank = Bank::default();
+        self.rx_filter = None;
+        // The reset empties the receive buffer.
+        self.rx = RxState::Idle;
+        self.rx_next = (*self.erx_range.start()).into();
+        self.rx_frames.clear();
+        self.mii_busy = false;
+        self.phy_read = PhyReadState::Idle;
+        self.dma = DmaState::Idle;
+        self.interrupts = Interrupts::default();
+        self.interrupt_rearm = false;
+        // Kept as is: the link comes back with the PHY, the next poll tells if it didn't.
+        self.init()
+    }
+
+    /// Whether a system reset went out and [`Self::poll_pending_transaction`] holds everything
+    /// back until [`Self::settle_reset`] lets it go on.
+    pub fn is_settling(&self) -> bool {
+        matches!(self.reset, ResetState::Settling(_))
+    }
+
+    /// Lets the driver go on after a system reset once `now` reaches the deadline, set
+    /// `wait` ticks after the first call following the SRC. Call it from the main loop after
+    /// running the transactions, it does nothing when no reset is settling.
+    ///
+    /// Errata: ESTAT.CLKRDY isn't cleared by a reset issued over SPI, it may still read set
+    /// while the oscillator restarts. ESTAT is only polled once `wait` spans at least
+    /// [`Self::RESET_SETTLE_US`], e.g. 2 ticks of a millisecond clock as the first one may be
+    /// partial.
+    pub fn settle_reset(&mut self, now: u32, wait: u32) {
+        match self.reset {
+            ResetState::Settling(None) => {
+                self.reset = ResetState::Settling(Some(now.wrapping_add(wait)));
+            }
+            ResetState::Settling(Some(deadline)) if clock::is_due(deadline, now) => {
+                self.reset = ResetState::Idle;
+            }
+            _ => {}
+        }
+    }
+
+    fn queue_init(&mut self) -> Result<(), TransactionError> {
+        let start = (*self.erx_range.start()).into();
+        let end = (*self.erx_range.end()).into();
+
+        // Initialize receive buffer
+        // NOTE: Waiting for osc is baked in poll_pending.
+        // it could be done after ETH config, which would be ideal
+        // but it's kept there for simplicity right now.
+        self.write_word(WordRegister::ERXST, start)?;
+        self.write_word(WordRegister::ERXND, end)?;
+        self.write_word(WordRegister::ERXRDPT, start)?;
+
+        // Initialize Receieve filters
+        // Promiscuous until the station address is known, see set_rx_filters.
+        self.write_register(Register::ERXFCON, 0x00)?;
+
+        // Initialize MAC
+        // TODO: expose config
+        self.write_register(Register::MACON1, 0b0000_1101)?;
+        self.write_register(Register::MACON3, 0b1111_0111)?;
+        self.write_register(Register::MACON4, 0b0_0_0_0_0_0)?;
+
+        // Initialize PHY
+        self.write_phy(PhyRegister::PHCON1, Self::PHCON1_PDPXMD)?;
+        self.write_phy(PhyRegister::PHCON2, Self::PHCON2_HDLDIS)?;
+
+        self.bit_field_set(Register::ECON1, Self::RXEN)
+    }
+
+    /// Queues the writes programming `filter`, skipping the registers already holding the
+    /// values of the previously queued filter. Call it whenever the stack's needs may have
+    /// changed: address, joined groups or bridging. Nothing is queued when the writes don't all
+    /// fit.
+    pub fn reconcile_rx_filter(&mut self, filter: RxFilter) -> Result<(), TransactionError> {
+        let previous = self.rx_filter;
+        if previous == Some(filter) {
+            return Ok(());
+        }
+
+        // Until all writes are queued, the programmed filter is unknown.
+        self.rx_filter = None;
+        self.queue_all(|driver| driver.queue_rx_filter(previous, filter))?;
+        self.rx_filter = Some(filter);
+        Ok(())
+    }
+
+    /// Queues the writes of [`Self::reconcile_rx_filter`] going from `previous` to `filter`.
+    fn queue_rx_filter(
+        &mut self,
+        previous: Option<RxFilter>,
+        filter: RxFilter,
+    ) -> Result<(), TransactionError> {
+        if previous.is_none_or(|previous| previous.mac != filter.mac) {
+            for (register, byte) in Self::MAADR.into_iter().zip(filter.mac.0) {
+                self.write_register(register, byte)?;
+            }
+        }
+        if previous.is_none_or(|previous| previous.hash_table != filter.hash_table) {
+            for (register, byte) in Self::EHT.into_iter().zip(filter.hash_table) {
+                self.write_register(register, byte)?;
+            }
+        }
+        if let Some(pattern) = filter.pattern
+            && previous.is_none_or(|previous| previous.pattern != filter.pattern)
+        {
+            for (register, byte) in Self::EPMM.into_iter().zip(pattern.mask.to_le_bytes()) {
+                self.write_register(register, byte)?;
+            }
+            self.write_word(WordRegister::EPMCS, pattern.checksum)?;
+            self.write_word(WordRegister::EPMO, pattern.offset)?;
+        }
+        if previous.is_none_or(|previous| previous.erxfcon != filter.erxfcon) {
+            self.write_register(Register::ERXFCON, filter.erxfcon)?;
+        }
+        Ok(())
+    }
+
+    /// Lets in the frames `config` selects from now on, in place of the promiscuous filter
+    /// [`Self::init`] leaves. Only the registers that changed are written, as with
+    /// [`Self::reconcile_rx_filter`].
+    pub fn set_rx_filters(&mut self, config: RxFilterConfig) -> Result<(), TransactionError> {
+        self.reconcile_rx_filter(config.filter())
+    }
+
+    /// Powers the PHY down, or back up. While it's down no link can be detected, see
+    /// [`crate::phypower`].
+    pub fn set_phy_power_down(&mut self, down: bool) -> Result<(), TransactionError> {
+        let mut phcon1 = Self::PHCON1_PDPXMD;
+        if down {
+            phcon1 |= Self::PHCON1_PPWRSV;
+        }
+        self.write_phy(PhyRegister::PHCON1, phcon1)
+    }
+
+    /// Queues a PHY register write, which the chip carries out on its own once MIWRH is
+    /// written. Transactions queued after it wait for MISTAT.BUSY to clear.
+    pub fn write_phy(&mut self, register: PhyRegister, value: u16) -> Result<(), TransactionError> {
+        self.queue_all(|driver| {
+            driver.write_register(Register::MIREGADR, register as u8)?;
+            // MIWRL then MIWRH, the write starts on the latter.
+            driver.write_word(WordRegister::MIWR, value)?;
+            driver.queue_mii_wait()
+        })
+    }
+
+    /// Queues a PHY register read, its value is then returned by [`Self::take_phy_read`].
+    /// Does nothing while a previous read wasn't taken.
+    ///
+    /// MICMD.MIIRD starts the read, MISTAT.BUSY is polled until the value is in MIRD, then
+    /// MIIRD is cleared and MIRDL and MIRDH are read.
+    pub fn read_phy(&mut self, register: PhyRegister) -> Result<(), TransactionError> {
+        self.queue_phy_read(register, true)
+    }
+
+    /// Queues a read of PHSTAT2, updating [`Self::link_up`] once handled. Call it
+    /// periodically, the link state is only as fresh as the last poll. Does nothing while
+    /// another PHY read is in progress, as [`Self::read_phy`].
+    pub fn poll_link(&mut self) -> Result<(), TransactionError> {
+        self.queue_phy_read(PhyRegister::PHSTAT2, false)
+    }
+
+    /// Whether the link was up at the last [`Self::poll_link`], false before the first one.
+    pub fn link_up(&self) -> bool {
+        self.link.unwrap_or(false)
+    }
+
+    /// The new link state if it changed since the last call, the first poll counting as a
+    /// change. The main loop passes it on, e.g. to [`crate::interface::Interfaces::set_link`].
+    pub fn take_link_change(&mut self) -> Option<bool> {
+        let changed = core::mem::take(&mut self.link_changed);
+        changed.then(|| self.link_up())
+    }
+
+    /// Enables `interrupts` on the INT pin, disabling the others. Link changes are enabled in
+    /// the PHY too.
+    ///
+    /// INT is active low and stays asserted while an enabled flag is set, wire it to an input
+    /// interrupting on its falling edge and call [`Self::on_interrupt`] from there.
+    pub fn enable_interrupts(&mut self, interrupts: Interrupts) -> Result<(), TransactionError> {
+        self.queue_all(|driver| {
+            let phie = if interrupts.link {
+                Self::PHIE_PGEIE | Self::PHIE_PLNKIE
+            } else {
+                0
+            };
+            driver.write_phy(PhyRegister::PHIE, phie)?;
+            let mut eie = interrupts.bits();
+            if interrupts.any() {
+                eie |= Self::INTIE;
+            }
+            driver.write_register(Register::EIE, eie)
+        })
+    }
+
+    /// Services the INT pin, call it when it fell.
+    ///
+    /// EIE.INTIE is cleared, releasing INT, and EIR is read. Once handled, the flags are
+    /// decoded into [`Self::take_interrupts`] and acted upon: waiting packets start
+    /// [`Self::receive`], a link change clears PHIR then polls the link, transmit and receive
+    /// errors are counted and cleared. INTIE goes back on once the queue drained, so if a flag
+    /// is still set, e.g. more packets arrived or something couldn't be queued, INT falls
+    /// again and the next call picks it up: no edge is missed.
+    pub fn on_interrupt(&mut self) -> Result<(), TransactionError> {
+        self.queue_all(|driver| {
+            driver.bit_field_clear(Register::EIE, Self::INTIE)?;
+            driver.read_register(Register::EIR)
+        })
+    }
+
+    /// Interrupts decoded since the last call, e.g. [`Interrupts::tx`] for
+    /// [`crate::txqueue::TxPacer::tx_done`].
+    pub fn take_interrupts(&mut self) -> Interrupts {
+        core::mem::take(&mut self.interrupts)
+    }
+
+    /// Handles the EIR value read by [`Self::on_interrupt`].
+    fn handle_interrupt_flags(&mut self, eir: u8) {
+        let flags = Interrupts::from_bits(eir);
+        self.interrupts = Interrupts::from_bits(self.interrupts.bits() | flags.bits());
+        self.interrupt_rearm = true;
+
+        if flags.rx_error {
+            self.rx_drops.buffer_full += 1;
+        }
+        // PKTIF clears as EPKTCNT reaches 0 and LINKIF as PHIR is read, the others by hand.
+        let clear = eir & (Interrupts::TXIF | Interrupts::TXERIF | Interrupts::RXERIF);
+        if clear != 0 {
+            let _ = self.queue_all(|driver| driver.bit_field_clear(Register::EIR, clear));
+        }
+        // Whatever can't be queued now is retried when INT falls again.
+        if flags.link {
+            let _ = self.queue_phy_read(PhyRegister::PHIR, false);
+        }
+        if flags.packet {
+            let _ = self.receive();
+        }
+    }
+
+    fn queue_phy_read(
+        &mut self,
+        register: PhyRegister,
+        take: bool,
+    ) -> Result<(), TransactionError> {
+        if self.phy_read != PhyReadState::Idle {
+            return Ok(());
+        }
+
+        self.queue_all(|driver| {
+            driver.write_register(Register::MIREGADR, register as u8)?;
+            // MICMD is a MII register, written whole as bit field operations don't apply.
+            driver.write_register(Register::MICMD, Self::MIIRD)?;
+            driver.queue_mii_wait()?;
+            driver.write_register(Register::MICMD, 0)?;
+            driver.read_register(Register::MIRDL)?;
+            driver.read_register(Register::MIRDH)
+        })?;
+        self.phy_read = PhyReadState::Pending { register, take };
+        Ok(())
+    }
+
+    /// Takes the value read by [`Self::read_phy`], with the register it was read from.
+    pub fn take_phy_read(&mut self) -> Option<(PhyRegister, u16)> {
+        let PhyReadState::Ready(register, value) = self.phy_read else {
+            return None;
+        };
+
+        self.phy_read = PhyReadState::Idle;
+        Some((register, value))
+    }
+
+    /// Handles the value of a PHY register read.
+    fn handle_phy_value(&mut self, register: PhyRegister, take: bool, value: u16) {
+        if register == PhyRegister::PHSTAT2 {
+            let up = value & Self::PHSTAT2_LSTAT != 0;
+            if self.link != Some(up) {
+                self.link = Some(up);
+                self.link_changed = true;
+            }
+        }
+
+        self.phy_read = if take {
+            PhyReadState::Ready(register, value)
+        } else {
+            PhyReadState::Idle
+        };
+
+        // PHIR was read to clear a link interrupt, PHSTAT2 tells what changed.
+        if register == PhyRegister::PHIR && !take {
+            let _ = self.poll_link();
+        }
+    }
+
+    /// Queues a MISTAT read. When it finds BUSY set, [`Self::poll_pending_transaction`] polls
+    /// MISTAT until the MII access is done before handing out anything queued after it.
+    fn queue_mii_wait(&mut self) -> Result<(), TransactionError> {
+        self.read_register(Register::MISTAT)
+    }
+
+    /// Starts the DMA checksum unit over `range` of the buffer memory, e.g. a received
+    /// packet's IPv4 header, its result is then returned by [`Self::take_dma_checksum`].
+    /// Does nothing while a previous checksum wasn't taken.
+    ///
+    /// ECON1 is read once DMAST is set, and polled until the chip clears it: transactions
+    /// queued after it, starting with the reads of EDMACS, wait for the checksum to be done.
+    pub fn start_dma_checksum(
+        &mut self,
+        range: RangeInclusive<u16>,
+    ) -> Result<(), TransactionError> {
+        if self.dma != DmaState::Idle {
+            return Ok(());
+        }
+
+        self.queue_all(|driver| {
+            driver.write_word(WordRegister::EDMAST, *range.start())?;
+            driver.write_word(WordRegister::EDMAND, *range.end())?;
+            driver.bit_field_set(Register::ECON1, Self::CSUMEN | Self::DMAST)?;
+            driver.read_register(Register::ECON1)?;
+            driver.read_register(WordRegister::EDMACS.low())?;
+            driver.read_register(WordRegister::EDMACS.high())
+        })?;
+        self.dma = DmaState::Running;
+        Ok(())
+    }
+
+    /// Takes the checksum computed by [`Self::start_dma_checksum`]. It's 0 over data ending
+    /// with a valid checksum of the data before it.
+    pub fn take_dma_checksum(&mut self) -> Option<u16> {
+        let DmaState::Ready(checksum) = self.dma else {
+            return None;
+        };
+
+        self.dma = DmaState::Idle;
+        Some(checksum)
+    }
+
+    /// Starts taking the next received frame out of the chip, if there is one and a buffer is
+    /// free: one frame can be read while the stack still holds the previous one, see
+    /// [`Self::received`]. Does nothing while a frame is on its way or both buffers are held.
+    ///
+    /// EPKTCNT is read first. If a packet is waiting, its header is read from the receive
+    /// buffer with RBM, then, once [`Self::screen_rx_header`] accepted it, the frame a chunk
+    /// at a time, each fitting a transaction, so the queue never holds a whole frame. Its buffer memory is then freed by moving ERXRDPT past it
+    /// and decrementing EPKTCNT with ECON2.PKTDEC. Each step is queued as the previous one's
+    /// transaction is handled; when the queue has no room for one, the packet stays in the
+    /// chip and the next call starts over.
+    pub fn receive(&mut self) -> Result<(), TransactionError> {
+        if self.rx != RxState::Idle || self.rx_frames.filling().is_none() {
+            return Ok(());
+        }
+
+        self.queue_all(|driver| driver.read_register(Register::EPKTCNT))?;
+        self.rx = RxState::Counting;
+        Ok(())
+    }
+
+    /// Oldest frame read by [`Self::receive`] and not released yet, with its header.
+    ///
+    /// Transactions are handed out by value, so the next frame's can run, e.g. over DMA, while
+    /// the stack works on this one in place. [`Self::release_received`] frees its buffer.
+    pub fn received(&self) -> Option<(RxHeader, &[u8])> {
+        self.rx_frames.front()
+    }
+
+    /// Frees the buffer of the frame [`Self::received`] returns, for the next one to be read.
+    pub fn release_received(&mut self) {
+        self.rx_frames.pop();
+    }
+
+    /// Takes the oldest frame read by [`Self::receive`], copying it into `buffer` and returning
+    /// its header and length. Frames longer than `buffer` are truncated.
+    pub fn take_received(&mut self, buffer: &mut [u8]) -> Option<(RxHeader, usize)> {
+        let (header, frame) = self.received()?;
+        let len = frame.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&frame[..len]);
+        self.release_received();
+        Some((header, len))
+    }
+
+    /// Handles the packet count read by [`Self::receive`].
+    fn handle_packet_count(&mut self, count: u8) {
+        self.rx = RxState::Idle;
+        if count == 0 {
+            return;
+        }
+
+        let next = self.rx_next;
+        if self
+            .queue_all(|driver| {
+                driver.write_word(WordRegister::ERDPT, next)?;
+                driver.queue_buffer_read(RX_HEADER_LEN)
+            })
+            .is_ok()
+        {
+            self.rx = RxState::Header;
+        }
     }
-}
 
-make_enum!(pub RegisterAddress, 5);
+    /// Handles bytes read from the receive buffer.
+    fn handle_rx_data(&mut self, data: &[u8]) {
+        match self.rx {
+            RxState::Header => {
+                self.rx = RxState::Idle;
+                let Ok(bytes) = data.try_into() else {
+                    return;
+                };
 
-/// Represents a single control register
-#[derive(Debug, Clone, Copy)]
-pub struct ControlRegister {
-    pub bank: Bank,
-    pub address: RegisterAddress,
-}
+                let header = RxHeader::parse(bytes);
+                if let Some(frame) = self.rx_frames.filling() {
+                    frame.clear();
+                }
+                let len = header.frame_len().min(RX_FRAME_CAPACITY);
+                let queued = if self.screen_rx_header(&header) {
+                    self.queue_all(|driver| driver.queue_buffer_read(len))
+                        .map(|()| RxState::Frame {
+                            header,
+                            remaining: len,
+                        })
+                } else {
+                    self.queue_all(|driver| driver.queue_rx_release(header.next_packet))
+                        .map(|()| {
+                            self.rx_next = header.next_packet;
+                            RxState::Idle
+                        })
+                };
+                if let Ok(state) = queued {
+                    self.rx = state;
+                }
+            }
+            RxState::Frame { header, remaining } => {
+                if let Some(frame) = self.rx_frames.filling() {
+                    let _ = frame.extend_from_slice(data);
+                }
+                let remaining = remaining.saturating_sub(data.len());
+                if remaining > 0 {
+                    self.rx = match self.queue_all(|driver| driver.queue_buffer_read(remaining)) {
+                        Ok(()) => RxState::Frame { header, remaining },
+                        Err(_) => RxState::Idle,
+                    };
+                    return;
+                }
 
-impl ControlRegister {
-    fn next(&self) -> ControlRegister {
-        ControlRegister {
-            address: self.address.next(),
-            ..*self
+                self.rx = match self.queue_all(|driver| driver.queue_rx_release(header.next_packet))
+                {
+                    Ok(()) => {
+                        self.rx_next = header.next_packet;
+                        self.rx_frames.push(header);
+                        RxState::Idle
+                    }
+                    Err(_) => RxState::Idle,
+                };
+            }
+            // Reads left over from before a reset.
+            _ => {}
         }
     }
-}
-
-/// Operation Code for interfacing with ENC28j60.
-// TODO: is there a way in the type system to represent that some of these are 3-bits + 5-bit address vs other that are just 8 bits?
-#[repr(u8)]
-enum OpCode {
-    /// Read control register.
-    RCR = 0b000_00000,
-    /// Read buffer memory.
-    RBM = 0b001_11010,
-    /// Write control register.
-    WCR = 0b010_00000,
-    /// Write buffer memory.
-    WBM = 0b011_11010,
-    /// Bit field set.
-    BFS = 0b100_00000,
-    /// Bit field clear.
-    BFC = 0b101_00000,
-    /// System reset command.
-    SRC = 0b111_11111,
-}
 
-#[derive(Default)]
-struct Transactions<const N: usize, const M: usize> {
-    buffer: heapless::Deque<ControlRegisterOperation, N>,
-    bounds: heapless::Deque<usize, M>,
-}
+    /// Queues a read of up to `len` bytes from the buffer memory at ERDPT, as many as fit a
+    /// transaction. The read pointer moves along, wrapping within the receive buffer, so the
+    /// next read picks up where this one stopped.
+    fn queue_buffer_read(&mut self, len: usize) -> Result<(), TransactionError> {
+        let len = len.min(Self::BUFFER_CHUNK_LEN).min(B - 1);
+        self.pending_transactions.new_transaction()?;
+        self.pending_transactions.push_write(&[OpCode::RBM as u8])?;
+        self.pending_transactions.push_read(len)
+    }
 
-#[derive(Error, Debug)]
-pub enum TransactionError {
-    #[error("Buffer ran out of memory for additional operations.")]
-    OperationsOutOfMemory,
-    #[error("Buffer ran out of memory for additional transactions.")]
-    TransactionOutOfMemory,
-}
+    /// Frees the buffer memory up to the packet at `next_packet`.
+    fn queue_rx_release(&mut self, next_packet: u16) -> Result<(), TransactionError> {
+        let start = u16::from(*self.erx_range.start());
+        let end = u16::from(*self.erx_range.end());
+        // Errata: ERXRDPT must be odd, so it's set right before the next packet, wrapping to
+        // the end of the receive buffer.
+        let read_pointer = if next_packet <= start || next_packet > end {
+            end
+        } else {
+            next_packet - 1
+        };
+        self.write_word(WordRegister::ERXRDPT, read_pointer)?;
+        self.bit_field_set(Register::ECON2, Self::PKTDEC)
+    }
 
-impl<'a, const N: usize, const M: usize> Transactions<N, M> {
-    fn push_operation(
+    /// Runs `queue`, dropping whatever it queued if it fails partway, so the chip never gets
+    /// half of a sequence of writes.
+    fn queue_all(
         &mut self,
-        operation: ControlRegisterOperation,
+        queue: impl FnOnce(&mut Self) -> Result<(), TransactionError>,
     ) -> Result<(), TransactionError> {
-        self.buffer
-            .push_back(operation)
-            .map_err(|_| TransactionError::OperationsOutOfMemory)?;
+        let usage = self.pending_transactions.usage();
+        let bank = self.current_bank;
+        queue(self).inspect_err(|_| {
+            self.pending_transactions.truncate(usage);
+            self.current_bank = bank;
+        })
+    }
 
-        if self.bounds.is_empty() {
-            self.bounds.push_back(0).unwrap();
-        }
+    pub fn tx_policy(&self) -> TxPolicy {
+        self.tx_policy
+    }
 
-        let bound = self.bounds.back_mut().unwrap();
-        *bound += 1;
+    /// Changes how frames are padded and whether the chip appends their CRC, from the next
+    /// [`Self::transmit`] on.
+    pub fn set_tx_policy(&mut self, policy: TxPolicy) {
+        self.tx_policy = policy;
+    }
 
-        Ok(())
+    /// Queues the transmission of `frame`, Ethernet header included and CRC excluded unless
+    /// the [`TxPolicy`] says otherwise, padded as it says.
+    ///
+    /// The frame goes to the transmit buffer, right after the receive buffer, through buffer
+    /// memory writes: the per-packet control byte then the frame. ETXST and ETXND are set
+    /// around it and ECON1.TXRTS starts the transmission, once the transmit logic was reset as
+    /// the errata recommends. Only one frame is in the buffer at a time: call it again once
+    /// EIR.TXIF reported the previous one sent, e.g. from a [`crate::txqueue::TxPacer`].
+    ///
+    /// The whole frame sits in the transaction queue until it's polled, so `B` must hold it
+    /// along with a byte per chunk of up to `B - 1` bytes. Nothing is queued when it doesn't
+    /// fit.
+    pub fn transmit(&mut self, frame: &[u8]) -> Result<(), TxFrameError> {
+        let padded_len = self.tx_policy.padded_len(frame.len())?;
+        let start = u16::from(*self.erx_range.end()) + 1;
+        let end = Self::tx_end(start, padded_len)?;
+
+        self.queue_all(|driver| driver.queue_transmit(frame, padded_len, start, end))
+            .map_err(|_| TxFrameError::QueueFull)
     }
 
-    fn new_transaction(&mut self) -> Result<(), TransactionError> {
-        self.bounds
-            .push_back(0)
-            .map_err(|_| TransactionError::TransactionOutOfMemory)?;
-        Ok(())
+    /// ETXND of a frame of `padded_len` bytes whose control byte is at `start`, the frame's
+    /// last byte. The chip writes the transmit status vector right after it, which must fit in
+    /// the buffer memory too.
+    fn tx_end(start: u16, padded_len: usize) -> Result<u16, TxFrameError> {
+        let end = start as usize + padded_len;
+        if end + TX_STATUS_LEN > Self::BUFFER_END as usize {
+            return Err(TxFrameError::TxBufferTooSmall);
+        }
+        Ok(end as u16)
     }
 
-    fn pop_transaction(&mut self) -> Option<heapless::Deque<ControlRegisterOperation, N>> {
-        let boundary = self.bounds.pop_front()?;
-        let mut result = heapless::Deque::new();
-        for _ in 0..boundary {
-            result.push_back(self.buffer.pop_front().unwrap()).unwrap();
+    fn queue_transmit(
+        &mut self,
+        frame: &[u8],
+        padded_len: usize,
+        start: u16,
+        end: u16,
+    ) -> Result<(), TransactionError> {
+        // Errata: the transmit logic can stall after an error, resetting it first is harmless.
+        self.bit_field_set(Register::ECON1, Self::TXRST)?;
+        self.bit_field_clear(Register::ECON1, Self::TXRST)?;
+        self.bit_field_clear(Register::EIR, Interrupts::TXIF | Interrupts::TXERIF)?;
+
+        self.write_word(WordRegister::EWRPT, start)?;
+        self.write_word(WordRegister::ETXST, start)?;
+        self.write_word(WordRegister::ETXND, end)?;
+
+        let padding = core::iter::repeat_n(0, padded_len - frame.len());
+        let mut bytes = core::iter::once(self.tx_policy.control_byte())
+            .chain(frame.iter().copied())
+            .chain(padding);
+        let mut left = padded_len + 1;
+        // The write pointer moves along with each byte written, each chunk picks up where the
+        // previous one stopped.
+        let chunk_len = Self::BUFFER_CHUNK_LEN.min(B - 1);
+        while left > 0 {
+            let len = left.min(chunk_len);
+            self.pending_transactions.new_transaction()?;
+            self.pending_transactions.push_write(&[OpCode::WBM as u8])?;
+            self.pending_transactions.push_operation(
+                OperationDescriptor::new(OperationKind::Write, len),
+                bytes.by_ref().take(len),
+            )?;
+            left -= len;
         }
 
-        Some(result)
+        self.bit_field_set(Register::ECON1, Self::TXRTS)
     }
-}
 
-impl<const N: usize, const M: usize> Enc28j60<N, M> {
-    const ECON: RegisterAddress = RegisterAddress::r1F;
-    const ESTAT: RegisterAddress = RegisterAddress::r1D;
+    /// Longest frame accepted by [`Self::screen_rx_header`]: 1518 bytes plus a VLAN tag.
+    pub const MAX_FRAME_LEN: u16 = 1522;
 
-    // TODO: better represent that these are words
-    const ERXSTL: ControlRegister = ControlRegister {
-        bank: Bank::Bank0,
-        address: RegisterAddress::r08,
-    };
-    const ERXNDL: ControlRegister = ControlRegister {
-        bank: Bank::Bank0,
-        address: RegisterAddress::r0A,
-    };
-    const ERXDPTL: ControlRegister = ControlRegister {
-        bank: Bank::Bank0,
-        address: RegisterAddress::r0C,
-    };
+    /// Decides from its header whether a received frame's payload should be read, counting
+    /// the frames that shouldn't.
+    pub fn screen_rx_header(&mut self, header: &RxHeader) -> bool {
+        let Err(reason) = header.check(Self::MAX_FRAME_LEN) else {
+            return true;
+        };
 
-    const ERXFCON: ControlRegister = ControlRegister {
-        bank: Bank::Bank1,
-        address: RegisterAddress::r18,
-    };
+        let counter = match reason {
+            RxDropReason::Crc => &mut self.rx_drops.crc,
+            RxDropReason::LengthMismatch => &mut self.rx_drops.length_mismatch,
+            RxDropReason::Oversized => &mut self.rx_drops.oversized,
+            RxDropReason::Runt => &mut self.rx_drops.runt,
+            RxDropReason::NotOk => &mut self.rx_drops.not_ok,
+        };
+        *counter += 1;
+        false
+    }
 
-    const MACON1: ControlRegister = ControlRegister {
-        bank: Bank::Bank2,
-        address: RegisterAddress::r00,
-    };
+    pub fn rx_drop_stats(&self) -> RxDropStats {
+        self.rx_drops
+    }
 
-    const MACON3: ControlRegister = ControlRegister {
-        bank: Bank::Bank2,
-        address: RegisterAddress::r02,
-    };
-    const MACON4: ControlRegister = ControlRegister {
-        bank: Bank::Bank2,
-        address: RegisterAddress::r03,
-    };
+    /// Room taken in the queue of pending transactions.
+    pub fn queue_usage(&self) -> QueueUsage {
+        self.pending_transactions.usage()
+    }
 
-    pub fn with_erx_range(erx_range: RangeInclusive<ux::u9>) -> Self {
-        Self {
-            current_bank: Default::default(),
-            pending_transactions: Default::default(),
-            erx_range,
-            ready: false,
-        }
+    /// Most room taken in the queue since boot, to size `N`, `M` and `B`. Kept across resets.
+    pub fn queue_high_water(&self) -> QueueUsage {
+        self.pending_transactions.high_water
     }
 
-    pub fn with_erx_length(length: ux::u9) -> Self {
-        Self {
-            current_bank: Default::default(),
-            pending_transactions: Default::default(),
-            erx_range: (ux::u9::min_value())..=length,
-            ready: false,
+    pub const fn queue_capacity(&self) -> QueueUsage {
+        QueueUsage {
+            operations: N,
+            transactions: M,
+            bytes: B,
         }
     }
 
-    pub fn init(&mut self) -> Result<(), TransactionError> {
-        let start = (*self.erx_range.start()).into();
-        let end = (*self.erx_range.end()).into();
-
-        // Initialize receive buffer
-        // NOTE: Waiting for osc is baked in poll_pending.
-        // it could be done after ETH config, which would be ideal
-        // but it's kept there for simplicity right now.
-        self.write_word(Self::ERXSTL, start)?;
-        self.write_word(Self::ERXNDL, end)?;
-        self.write_word(Self::ERXDPTL, start)?;
+    pub fn poll_pending_transaction(&mut self) -> Option<Transaction<N, B>> {
+        if self.reset == ResetState::Pending {
+            // The oscillator restarts, queued operations wait for it again.
+            self.reset = ResetState::Settling(None);
+            self.ready = false;
+            let mut result = Transaction::default();
+            result
+                .push(OperationKind::Write, &[OpCode::SRC as u8])
+                .ok()?;
 
-        // Initialize Receieve filters
-        // TODO: for now we go promiscuous 😏
-        self.write_register(Self::ERXFCON, 0x00)?;
+            return Some(result);
+        }
 
-        // Initialize MAC
-        // TODO: expose config
-        self.write_register(Self::MACON1, 0b0000_1101)?;
-        self.write_word(Self::MACON3, 0b111_1_0_1_1_1)?;
-        self.write_word(Self::MACON4, 0b0_0_0_0_0_0)?;
+        if let ResetState::Settling(_) = self.reset {
+            return None;
+        }
 
-        // TODO: Phy initialize?
+        if !self.ready {
+            return Transaction::register_read(Register::ESTAT);
+        }
 
-        Ok(())
-    }
+        if self.mii_busy {
+            // The bank is still the one of the MISTAT read that found BUSY set.
+            return Transaction::register_read(Register::MISTAT);
+        }
 
-    pub fn poll_pending_transaction(
-        &mut self,
-    ) -> Option<heapless::Deque<ControlRegisterOperation, N>> {
-        if !self.ready {
-            let mut result = heapless::Deque::new();
+        if self.dma == DmaState::Busy {
+            // ECON1 is in every bank.
+            return Transaction::register_read(Register::ECON1);
+        }
 
-            let mut read_buffer = heapless::Vec::new();
-            read_buffer.push(0).unwrap();
+        if let Some(transaction) = self.pending_transactions.pop_transaction() {
+            return Some(transaction);
+        }
 
+        if self.interrupt_rearm {
+            // EIE is in every bank.
+            self.interrupt_rearm = false;
+            let mut result = Transaction::default();
             result
-                .push_back(ControlRegisterOperation::Write(heapless::Vec::from_iter(
-                    [OpCode::RCR as u8 | Self::ESTAT as u8].into_iter(),
-                )))
-                .unwrap();
-            result
-                .push_back(ControlRegisterOperation::Read(read_buffer))
-                .unwrap();
+                .push(
+                    OperationKind::Write,
+                    &[OpCode::BFS as u8 | Register::EIE.address(), Self::INTIE],
+                )
+                .ok()?;
 
             return Some(result);
         }
 
-        self.pending_transactions.pop_transaction()
+        None
     }
 
-    fn write_to_control_register_address(
-        &mut self,
-        address: RegisterAddress,
-        value: u8,
-    ) -> Result<(), TransactionError> {
-        self.pending_transactions.new_transaction()?;
-        self.pending_transactions
-            .push_operation(ControlRegisterOperation::Write(heapless::Vec::from_iter(
-                [OpCode::WCR as u8 | address as u8, value].into_iter(),
-            )))?;
-        Ok(())
+    fn bit_field_set(&mut self, register: Register, bits: u8) -> Result<(), TransactionError> {
+        self.queue_command(OpCode::BFS, register, bits)
+    }
+
+    fn bit_field_clear(&mut self, register: Register, bits: u8) -> Result<(), TransactionError> {
+        self.queue_command(OpCode::BFC, register, bits)
     }
 
-    fn bit_field_set_to_control_register_address(
+    /// Queues a command writing `value` to `register`, in its bank.
+    fn queue_command(
         &mut self,
-        address: RegisterAddress,
+        opcode: OpCode,
+        register: Register,
         value: u8,
     ) -> Result<(), TransactionError> {
+        debug_assert!(
+            opcode == OpCode::WCR || !register.mac,
+            "bit field operations only apply to ETH registers"
+        );
+        self.select_bank(register)?;
         self.pending_transactions.new_transaction()?;
         self.pending_transactions
-            .push_operation(ControlRegisterOperation::Write(heapless::Vec::from_iter(
-                [OpCode::BFS as u8 | address as u8, value].into_iter(),
-            )))?;
+            .push_write(&[opcode as u8 | register.address, value])?;
         Ok(())
     }
 
+    /// Switches to the bank of `register`, if it isn't in every bank.
+    fn select_bank(&mut self, register: Register) -> Result<(), TransactionError> {
+        match register.bank {
+            Some(bank) => self.set_bank(bank),
+            None => Ok(()),
+        }
+    }
+
     fn set_bank(&mut self, bank: Bank) -> Result<(), TransactionError> {
         if bank == self.current_bank {
             return Ok(());
         }
 
-        self.bit_field_set_to_control_register_address(Self::ECON, bank as u8)?;
+        // BFS only sets bits, the ones of the current bank the new one lacks need clearing.
+        let clear = self.current_bank as u8 & !(bank as u8);
+        let set = bank as u8 & !(self.current_bank as u8);
+        if clear != 0 {
+            self.bit_field_clear(Register::ECON1, clear)?;
+        }
+        if set != 0 {
+            self.bit_field_set(Register::ECON1, set)?;
+        }
 
         self.current_bank = bank;
         Ok(())
     }
 
-    fn write_word(
-        &mut self,
-        register: ControlRegister,
-        value: u16,
-    ) -> Result<(), TransactionError> {
-        let [low, high] = value.to_be_bytes();
-        self.write_register(register, low)?;
-        self.write_register(register.next(), high)?;
+    fn write_word(&mut self, register: WordRegister, value: u16) -> Result<(), TransactionError> {
+        let [low, high] = value.to_le_bytes();
+        self.write_register(register.low(), low)?;
+        self.write_register(register.high(), high)?;
         Ok(())
     }
 
@@ -274,77 +1971,544 @@ impl<const N: usize, const M: usize> Enc28j60<N, M> {
         // TODO: feeding operations like this is awful as we need to match over the transactions
         // what we ideally would want is to keep some struct with all the details of the original operations with references to buffers
         // this function here shows also how we could actually update buffers here and never copy operations around.
-        mut transaction: heapless::Deque<ControlRegisterOperation, N>,
-    ) {
-        match transaction.pop_front() {
-            Some(ControlRegisterOperation::Write(b)) => {
-                if b.contains(&(OpCode::RCR as u8 | Self::ESTAT as u8)) {
-                    let Some(ControlRegisterOperation::Read(operation)) = transaction.pop_front()
-                    else {
-                        // TODO: with a good operation wrapper we wouldn't need to panic here.
-                        panic!("Inconsistent transaction: reading ESTAT without a read buffer");
-                    };
+        transaction: Transaction<N, B>,
+    ) -> Result<(), ProtocolViolation> {
+        let mut operations = transaction.iter();
+        // Registers are told apart by the one the transaction was queued to read rather than
+        // by the address in the opcode, which registers of different banks share.
+        let Some(register) = transaction.read else {
+            if let Some((OperationKind::Write, &[opcode])) = operations.next()
+                && opcode == OpCode::RBM as u8
+            {
+                let Some((OperationKind::Read, data)) = operations.next() else {
+                    return Err(ProtocolViolation::MissingReadBuffer);
+                };
+                self.handle_rx_data(data);
+            }
+            return Ok(());
+        };
 
-                    if operation[0] & 0b0000_0001 == 1 {
-                        self.ready = true;
-                    }
+        // A register's value is the last byte read, after the dummy byte of MAC and MII ones.
+        let Some((OperationKind::Read, operation)) = operations.nth(1) else {
+            return Err(ProtocolViolation::MissingReadBuffer);
+        };
+        let value = *operation.last().ok_or(ProtocolViolation::EmptyReadBuffer)?;
+
+        match register {
+            Register::ESTAT if value & 0b0000_0001 == 1 => self.ready = true,
+            Register::EPKTCNT if self.rx == RxState::Counting => self.handle_packet_count(value),
+            Register::MISTAT => self.mii_busy = value & Self::BUSY != 0,
+            Register::MIRDL => {
+                if let PhyReadState::Pending { register, take } = self.phy_read {
+                    self.phy_read = PhyReadState::Low {
+                        register,
+                        take,
+                        low: value,
+                    };
+                }
+            }
+            Register::MIRDH => {
+                if let PhyReadState::Low {
+                    register,
+                    take,
+                    low,
+                } = self.phy_read
+                {
+                    self.handle_phy_value(register, take, u16::from_le_bytes([low, value]));
                 }
             }
-            Some(_) => {}
-            None => {
-                return;
+            Register::ECON1 if matches!(self.dma, DmaState::Running | DmaState::Busy) => {
+                self.dma = if value & Self::DMAST != 0 {
+                    DmaState::Busy
+                } else {
+                    DmaState::Done
+                };
             }
+            Register::EDMACSL if self.dma == DmaState::Done => self.dma = DmaState::Low(value),
+            Register::EDMACSH => {
+                if let DmaState::Low(low) = self.dma {
+                    self.dma = DmaState::Ready(u16::from_le_bytes([low, value]));
+                }
+            }
+            Register::EIR => self.handle_interrupt_flags(value),
+            _ => {}
         }
+
+        Ok(())
     }
 
-    fn write_register(
-        &mut self,
-        register: ControlRegister,
-        value: u8,
-    ) -> Result<(), TransactionError> {
-        self.set_bank(register.bank)?;
-        self.write_to_control_register_address(register.address, value)
+    fn write_register(&mut self, register: Register, value: u8) -> Result<(), TransactionError> {
+        self.queue_command(OpCode::WCR, register, value)
     }
 
     // TODO: internally buffer operations?
-    /// Requires at least 2 positions for operations.
-    pub fn read_register(&mut self, register: ControlRegister) -> Result<(), TransactionError> {
-        self.set_bank(register.bank)?;
+    /// Requires at least 2 positions for operations. The value is the read's last byte, MAC
+    /// and MII registers clocking out a dummy byte before it.
+    pub fn read_register(&mut self, register: Register) -> Result<(), TransactionError> {
+        // The bank selection isn't left queued without the read.
+        self.queue_all(|driver| {
+            driver.select_bank(register)?;
+            driver.pending_transactions.new_transaction()?;
+            driver
+                .pending_transactions
+                .push_write(&[OpCode::RCR as u8 | register.address])?;
+            driver.pending_transactions.push_read(register.read_len())?;
+            driver.pending_transactions.set_read(register);
+            Ok(())
+        })
+    }
+}
 
-        self.pending_transactions.new_transaction()?;
-        self.pending_transactions
-            .push_operation(ControlRegisterOperation::Write(heapless::Vec::from_iter(
-                [OpCode::RCR as u8 | register.address as u8].into_iter(),
-            )))?;
-        // TODO: oh no no no
-        let mut read_buffer = heapless::Vec::new();
-        read_buffer.push(0).unwrap();
-        self.pending_transactions
-            .push_operation(ControlRegisterOperation::Read(read_buffer))?;
-        Ok(())
+/// Direction of a single SPI operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Read,
+    Write,
+}
+
+/// Packed description of a queued operation: the top bit is the [`OperationKind`]
+/// and the remaining bits the length of its payload.
+#[derive(Debug, Clone, Copy)]
+struct OperationDescriptor(u8);
+
+impl OperationDescriptor {
+    const READ: u8 = 0b1000_0000;
+    const MAX_LEN: usize = 0b0111_1111;
+
+    fn new(kind: OperationKind, len: usize) -> Self {
+        debug_assert!(len <= Self::MAX_LEN, "Operation payload too long");
+        let kind = match kind {
+            OperationKind::Read => Self::READ,
+            OperationKind::Write => 0,
+        };
+
+        Self(kind | len as u8)
+    }
+
+    fn kind(&self) -> OperationKind {
+        if self.0 & Self::READ != 0 {
+            OperationKind::Read
+        } else {
+            OperationKind::Write
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.0 & !Self::READ) as usize
     }
 }
 
-/// Control register operations are treated separatedly to own the buffers.
+/// Operations that need to run under a single chip select assertion, owning their buffers.
 /// TODO: I don't really want to think right now how to deal with the write/read memory buffer operations yet but they might be simpler,
 /// as they might need single packets
 /// DMA is a whole other beast.
 /// This is just to continue prototyping
-#[derive(Debug, PartialEq, Eq)]
-pub enum ControlRegisterOperation {
-    Read(heapless::Vec<u8, 2>),
-    Write(heapless::Vec<u8, 2>),
+#[derive(Default)]
+pub struct Transaction<const N: usize, const B: usize> {
+    operations: heapless::Vec<OperationDescriptor, N>,
+    bytes: heapless::Vec<u8, B>,
+    /// Register read, its value being the last byte read.
+    read: Option<Register>,
+}
+
+impl<const N: usize, const B: usize> Transaction<N, B> {
+    /// Read of `register`, the RCR opcode then the read, for the registers polled outside of
+    /// the queue. Fits in any queue able to hold init.
+    fn register_read(register: Register) -> Option<Self> {
+        let mut result = Self {
+            read: Some(register),
+            ..Self::default()
+        };
+        result
+            .push(
+                OperationKind::Write,
+                &[OpCode::RCR as u8 | register.address],
+            )
+            .ok()?;
+        result
+            .push(OperationKind::Read, &[0; 2][..register.read_len()])
+            .ok()?;
+        Some(result)
+    }
+
+    fn push(&mut self, kind: OperationKind, payload: &[u8]) -> Result<(), TransactionError> {
+        if self.bytes.capacity() - self.bytes.len() < payload.len() {
+            return Err(TransactionError::OperationsOutOfMemory);
+        }
+        self.operations
+            .push(OperationDescriptor::new(kind, payload.len()))
+            .map_err(|_| TransactionError::OperationsOutOfMemory)?;
+        let _ = self.bytes.extend_from_slice(payload);
+        Ok(())
+    }
+
+    /// Iterates over each operation along with its payload.
+    pub fn iter(&self) -> impl Iterator<Item = (OperationKind, &[u8])> {
+        let mut rest = self.bytes.as_slice();
+        self.operations.iter().map(move |descriptor| {
+            let (payload, tail) = rest.split_at(descriptor.len());
+            rest = tail;
+            (descriptor.kind(), payload)
+        })
+    }
+
+    /// Operations to hand over to the SPI bus, read buffers are updated in place.
+    pub fn spi_operations(&mut self) -> impl Iterator<Item = embedded_hal::spi::Operation<'_, u8>> {
+        let mut rest = self.bytes.as_mut_slice();
+        self.operations.iter().map(move |descriptor| {
+            let (payload, tail) = core::mem::take(&mut rest).split_at_mut(descriptor.len());
+            rest = tail;
+            match descriptor.kind() {
+                OperationKind::Read => embedded_hal::spi::Operation::Read(payload),
+                OperationKind::Write => embedded_hal::spi::Operation::Write(payload),
+            }
+        })
+    }
 }
 
-impl<'a> From<&'a mut ControlRegisterOperation> for embedded_hal::spi::Operation<'a, u8> {
-    fn from(value: &'a mut ControlRegisterOperation) -> Self {
-        match value {
-            ControlRegisterOperation::Read(buffer) => {
-                embedded_hal::spi::Operation::Read(buffer.as_mut_slice())
+impl<const N: usize, const B: usize> core::fmt::Debug for Transaction<N, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOFTWARE_PADDING: TxPolicy = TxPolicy {
+        software_padding: true,
+        append_crc: true,
+    };
+    const CHIP_PADDING: TxPolicy = TxPolicy {
+        software_padding: false,
+        append_crc: true,
+    };
+    const OWN_CRC: TxPolicy = TxPolicy {
+        software_padding: true,
+        append_crc: false,
+    };
+    const OWN_CRC_CHIP_PADDING: TxPolicy = TxPolicy {
+        software_padding: false,
+        append_crc: false,
+    };
+
+    /// Frame lengths checked against each policy: empty, one byte short of the minimum, the
+    /// minimum and the longest untagged frame.
+    const LENS: [usize; 4] = [0, 59, 60, 1514];
+
+    fn check(policy: TxPolicy, expected: [Result<usize, TxFrameError>; 4]) {
+        for (len, expected) in LENS.into_iter().zip(expected) {
+            assert_eq!(policy.padded_len(len), expected, "{policy:?}, {len} bytes");
+
+            let mut buffer = [0xA5; MAX_TX_FRAME_LEN + CRC_LEN];
+            let prepared = policy.prepare(&mut buffer, len);
+            assert_eq!(prepared, expected, "{policy:?}, {len} bytes");
+            if let Ok(padded_len) = prepared {
+                assert!(buffer[..len].iter().all(|byte| *byte == 0xA5));
+                assert!(buffer[len..padded_len].iter().all(|byte| *byte == 0));
+                assert!(buffer[padded_len..].iter().all(|byte| *byte == 0xA5));
+            }
+        }
+    }
+
+    #[test]
+    fn software_padding_pads_to_the_minimum() {
+        check(
+            SOFTWARE_PADDING,
+            [Err(TxFrameError::Runt(0)), Ok(60), Ok(60), Ok(1514)],
+        );
+        assert_eq!(SOFTWARE_PADDING.control_byte(), 0b011);
+    }
+
+    #[test]
+    fn chip_padding_leaves_frames_as_they_are() {
+        check(
+            CHIP_PADDING,
+            [Err(TxFrameError::Runt(0)), Ok(59), Ok(60), Ok(1514)],
+        );
+        assert_eq!(CHIP_PADDING.control_byte(), 0b111);
+    }
+
+    #[test]
+    fn frames_carrying_their_crc_must_already_be_long_enough() {
+        for policy in [OWN_CRC, OWN_CRC_CHIP_PADDING] {
+            check(
+                policy,
+                [
+                    Err(TxFrameError::Runt(0)),
+                    Err(TxFrameError::Runt(59)),
+                    Err(TxFrameError::Runt(60)),
+                    Ok(1514),
+                ],
+            );
+            assert_eq!(policy.padded_len(64), Ok(64));
+            // Neither padded nor given a CRC by the chip.
+            assert_eq!(policy.control_byte(), 0b001);
+        }
+    }
+
+    #[test]
+    fn longest_frames() {
+        for policy in [SOFTWARE_PADDING, CHIP_PADDING] {
+            assert_eq!(policy.padded_len(MAX_TX_FRAME_LEN), Ok(MAX_TX_FRAME_LEN));
+            assert_eq!(
+                policy.padded_len(MAX_TX_FRAME_LEN + 1),
+                Err(TxFrameError::TooLong(MAX_TX_FRAME_LEN + 1))
+            );
+        }
+        for policy in [OWN_CRC, OWN_CRC_CHIP_PADDING] {
+            let max_len = MAX_TX_FRAME_LEN + CRC_LEN;
+            assert_eq!(policy.padded_len(max_len), Ok(max_len));
+            assert_eq!(
+                policy.padded_len(max_len + 1),
+                Err(TxFrameError::TooLong(max_len + 1))
+            );
+        }
+    }
+
+    type Driver = Enc28j60<40, 32, 128>;
+
+    /// Runs the queued transactions, answering register reads with `read`, and returns the
+    /// registers read in order.
+    fn run(driver: &mut Driver, mut read: impl FnMut(Register) -> u8) -> Vec<Register> {
+        let mut registers = Vec::new();
+        while let Some(mut transaction) = driver.poll_pending_transaction() {
+            if let Some(register) = transaction.read {
+                registers.push(register);
+                let value = read(register);
+                for operation in transaction.spi_operations() {
+                    if let embedded_hal::spi::Operation::Read(bytes) = operation {
+                        bytes.fill(value);
+                    }
+                }
+            }
+            driver.handle_transaction(transaction).unwrap();
+        }
+        registers
+    }
+
+    fn ready_driver() -> Driver {
+        let mut driver = Driver::with_erx_length(0x1f0u16.try_into().unwrap());
+        // CLKRDY.
+        run(&mut driver, |_| 0x01);
+        driver
+    }
+
+    #[test]
+    fn dma_checksum_is_read_once_the_chip_is_done() {
+        let mut driver = ready_driver();
+        driver.start_dma_checksum(0x100..=0x113).unwrap();
+        assert_eq!(driver.take_dma_checksum(), None);
+
+        let mut busy_polls = 3;
+        let registers = run(&mut driver, |register| match register {
+            Register::ECON1 if busy_polls > 0 => {
+                busy_polls -= 1;
+                Driver::DMAST
             }
-            ControlRegisterOperation::Write(buffer) => {
-                embedded_hal::spi::Operation::Write(buffer.as_slice())
+            Register::EDMACSL => 0x34,
+            Register::EDMACSH => 0x12,
+            _ => 0,
+        });
+
+        let econ1 = Register::ECON1;
+        assert_eq!(
+            registers,
+            [
+                econ1,
+                econ1,
+                econ1,
+                econ1,
+                Register::EDMACSL,
+                Register::EDMACSH
+            ]
+        );
+        assert_eq!(driver.take_dma_checksum(), Some(0x1234));
+        assert_eq!(driver.take_dma_checksum(), None);
+    }
+
+    #[test]
+    fn dma_checksum_waits_to_be_taken() {
+        let mut driver = ready_driver();
+        driver.start_dma_checksum(0x100..=0x113).unwrap();
+        // DMAST clear.
+        run(&mut driver, |_| 0x5A);
+
+        // Nothing is started over a checksum not taken yet.
+        driver.start_dma_checksum(0x200..=0x213).unwrap();
+        assert_eq!(driver.queue_usage(), QueueUsage::default());
+        assert_eq!(driver.take_dma_checksum(), Some(0x5A5A));
+
+        driver.start_dma_checksum(0x200..=0x213).unwrap();
+        assert_ne!(driver.queue_usage(), QueueUsage::default());
+    }
+
+    #[test]
+    fn registers_sharing_misstat_address_are_told_apart() {
+        let mut driver = ready_driver();
+        // All at 0x0A, where MISTAT is in bank 3, with BUSY's bit set.
+        for register in [Register::ERXNDL, Register::EPMM2, Register::MAMXFLL] {
+            assert_eq!(register.address(), Register::MISTAT.address());
+            driver.read_register(register).unwrap();
+        }
+        let registers = run(&mut driver, |_| 0x01);
+
+        // No MISTAT poll followed.
+        assert_eq!(
+            registers,
+            [Register::ERXNDL, Register::EPMM2, Register::MAMXFLL]
+        );
+    }
+
+    #[test]
+    fn packet_count_and_phy_read_share_an_address() {
+        let mut driver = ready_driver();
+        assert_eq!(Register::EPKTCNT.address(), Register::MIRDH.address());
+        driver.read_phy(PhyRegister::PHSTAT2).unwrap();
+        // Queued along with the PHY read, nothing to take.
+        driver.receive().unwrap();
+        let registers = run(&mut driver, |register| match register {
+            Register::MIRDL => 0x34,
+            Register::MIRDH => 0x12,
+            _ => 0,
+        });
+
+        assert!(registers.contains(&Register::EPKTCNT));
+        assert_eq!(driver.take_phy_read(), Some((PhyRegister::PHSTAT2, 0x1234)));
+        assert_eq!(driver.take_received(&mut [0; 64]), None);
+        // The count was handled: a new pass can start.
+        driver.receive().unwrap();
+        assert_ne!(driver.queue_usage(), QueueUsage::default());
+    }
+
+    /// Runs the queued transactions like [`run`], with `packets` waiting and the receive buffer
+    /// read from `buffer`.
+    fn run_rx(driver: &mut Driver, packets: u8, buffer: &mut std::collections::VecDeque<u8>) {
+        while let Some(mut transaction) = driver.poll_pending_transaction() {
+            let register = transaction.read;
+            for operation in transaction.spi_operations() {
+                if let embedded_hal::spi::Operation::Read(bytes) = operation {
+                    match register {
+                        Some(Register::EPKTCNT) => bytes.fill(packets),
+                        Some(_) => bytes.fill(0),
+                        None => bytes.fill_with(|| buffer.pop_front().unwrap_or_default()),
+                    }
+                }
             }
+            driver.handle_transaction(transaction).unwrap();
         }
     }
+
+    /// Receive buffer bytes of a received 60 bytes frame filled with `fill`, and its header.
+    fn rx_packet(fill: u8) -> Vec<u8> {
+        let mut packet = vec![0x42, 0x00, 64, 0x00, 0x80, 0x00];
+        packet.extend([fill; 60]);
+        packet
+    }
+
+    #[test]
+    fn next_frame_is_read_while_the_previous_one_is_held() {
+        let mut driver = ready_driver();
+        let mut buffer: std::collections::VecDeque<u8> =
+            [rx_packet(1), rx_packet(2), rx_packet(3)].concat().into();
+
+        driver.receive().unwrap();
+        run_rx(&mut driver, 3, &mut buffer);
+        let (_, first) = driver.received().unwrap();
+        assert_eq!(first, [1; 60]);
+
+        // Read into the other buffer.
+        driver.receive().unwrap();
+        run_rx(&mut driver, 2, &mut buffer);
+        assert_eq!(driver.received().unwrap().1, [1; 60]);
+
+        // Both buffers held, the third frame stays in the chip.
+        driver.receive().unwrap();
+        assert_eq!(driver.queue_usage(), QueueUsage::default());
+
+        driver.release_received();
+        assert_eq!(driver.received().unwrap().1, [2; 60]);
+        driver.receive().unwrap();
+        run_rx(&mut driver, 1, &mut buffer);
+
+        let mut frame = [0; 64];
+        assert_eq!(
+            driver.take_received(&mut frame).map(|(_, len)| len),
+            Some(60)
+        );
+        assert_eq!(frame[..60], [2; 60]);
+        assert_eq!(driver.received().unwrap().1, [3; 60]);
+        driver.release_received();
+        assert_eq!(driver.received(), None);
+    }
+
+    #[test]
+    fn estat_waits_for_the_reset_to_settle() {
+        let mut driver = ready_driver();
+        driver.reset().unwrap();
+
+        let src = driver.poll_pending_transaction().unwrap();
+        assert_eq!(
+            src.iter().next(),
+            Some((OperationKind::Write, &[OpCode::SRC as u8][..]))
+        );
+        driver.handle_transaction(src).unwrap();
+
+        // Init is queued, but nothing goes out before the deadline.
+        assert!(driver.is_settling());
+        assert!(driver.poll_pending_transaction().is_none());
+        driver.settle_reset(u32::MAX, 2);
+        assert!(driver.poll_pending_transaction().is_none());
+        driver.settle_reset(0, 2);
+        assert!(driver.poll_pending_transaction().is_none());
+        driver.settle_reset(1, 2);
+        assert!(!driver.is_settling());
+
+        // CLKRDY is polled, then init goes out.
+        let mut clkrdy = [0x00, 0x01].into_iter();
+        let registers = run(&mut driver, |register| {
+            if register == Register::ESTAT {
+                clkrdy.next().unwrap()
+            } else {
+                0
+            }
+        });
+        assert_eq!(registers[..2], [Register::ESTAT, Register::ESTAT]);
+        assert_eq!(driver.queue_usage(), QueueUsage::default());
+
+        // Only a reset settles.
+        driver.settle_reset(100, 2);
+        assert!(!driver.is_settling());
+    }
+
+    #[test]
+    fn transmit_status_vector_must_fit_after_the_frame() {
+        // Control byte at 0x1000, frame up to ETXND, then 7 bytes of status vector.
+        let start = 0x1000;
+        let fits = usize::from(Driver::BUFFER_END - start) - TX_STATUS_LEN;
+        assert_eq!(Driver::tx_end(start, fits), Ok(0x1FF8));
+        assert_eq!(
+            Driver::tx_end(start, fits + 1),
+            Err(TxFrameError::TxBufferTooSmall)
+        );
+        assert_eq!(
+            Driver::tx_end(0x1FFF, 0),
+            Err(TxFrameError::TxBufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn prepare_needs_room_for_the_padding() {
+        let mut buffer = [0; 59];
+        assert_eq!(
+            SOFTWARE_PADDING.prepare(&mut buffer, 59),
+            Err(TxFrameError::BufferTooSmall)
+        );
+        assert_eq!(CHIP_PADDING.prepare(&mut buffer, 59), Ok(59));
+        assert_eq!(
+            CHIP_PADDING.prepare(&mut buffer, 60),
+            Err(TxFrameError::BufferTooSmall)
+        );
+    }
 }
@@ -0,0 +1,297 @@
+//! Errors of every layer of the firmware.
+//!
+//! Each module keeps its own error enum, these group them by layer so failures can be
+//! propagated with `?` up to the main loop and logged in one place.
+
+use thiserror::Error;
+
+use crate::{
+    arp::ArpError,
+    auth::AuthError,
+    bootp::BootpError,
+    cli::{BatchError, CliError},
+    config::ConfigError,
+    conntrack::ConntrackError,
+    dhcp::DhcpError,
+    dns::DnsError,
+    enc28j60::{ProtocolViolation, TransactionError, TxFrameError},
+    events::EventBusError,
+    firewall::FirewallError,
+    frame::FrameBufError,
+    ftp::FtpAlgError,
+    http::HttpError,
+    igmp::IgmpError,
+    interface::InterfaceError,
+    json::JsonError,
+    lease::LeaseError,
+    nat::NatError,
+    persist::PersistError,
+    rawsock::RawSocketError,
+    routing::RoutingError,
+    rxhooks::HookError,
+    sip::SipAlgError,
+    tcp::TcpError,
+    timer::TimerError,
+};
+
+/// Any error of the firmware.
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    #[error("Driver: {0}")]
+    Driver(#[from] DriverError),
+    #[error("Network: {0}")]
+    Net(#[from] NetError),
+    #[error("Service: {0}")]
+    Service(#[from] ServiceError),
+}
+
+/// Errors talking to network hardware.
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DriverError {
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+    #[error(transparent)]
+    ProtocolViolation(#[from] ProtocolViolation),
+    #[error(transparent)]
+    TxFrame(#[from] TxFrameError),
+}
+
+/// Errors building or handling frames and packets.
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NetError {
+    #[error(transparent)]
+    Frame(#[from] FrameBufError),
+    #[error(transparent)]
+    Arp(#[from] ArpError),
+    #[error(transparent)]
+    Conntrack(#[from] ConntrackError),
+    #[error(transparent)]
+    Firewall(#[from] FirewallError),
+    #[error(transparent)]
+    Igmp(#[from] IgmpError),
+    #[error(transparent)]
+    Nat(#[from] NatError),
+    #[error(transparent)]
+    FtpAlg(#[from] FtpAlgError),
+    #[error(transparent)]
+    SipAlg(#[from] SipAlgError),
+    #[error(transparent)]
+    Tcp(#[from] TcpError),
+    #[error(transparent)]
+    Routing(#[from] RoutingError),
+    #[error(transparent)]
+    Interface(#[from] InterfaceError),
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    #[error(transparent)]
+    RawSocket(#[from] RawSocketError),
+}
+
+/// Errors of the services running on top of the network stack.
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ServiceError {
+    #[error(transparent)]
+    Events(#[from] EventBusError),
+    #[error(transparent)]
+    Dhcp(#[from] DhcpError),
+    #[error(transparent)]
+    Bootp(#[from] BootpError),
+    #[error(transparent)]
+    Dns(#[from] DnsError),
+    #[error(transparent)]
+    Cli(#[from] CliError),
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+    #[error(transparent)]
+    Http(#[from] HttpError),
+    #[error(transparent)]
+    Json(#[from] JsonError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Lease(#[from] LeaseError),
+    #[error(transparent)]
+    Persist(#[from] PersistError),
+    #[error(transparent)]
+    Timer(#[from] TimerError),
+}
+
+impl From<TransactionError> for Error {
+    fn from(value: TransactionError) -> Self {
+        DriverError::from(value).into()
+    }
+}
+
+impl From<ProtocolViolation> for Error {
+    fn from(value: ProtocolViolation) -> Self {
+        DriverError::from(value).into()
+    }
+}
+
+impl From<TxFrameError> for Error {
+    fn from(value: TxFrameError) -> Self {
+        DriverError::from(value).into()
+    }
+}
+
+impl From<FrameBufError> for Error {
+    fn from(value: FrameBufError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<ArpError> for Error {
+    fn from(value: ArpError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<ConntrackError> for Error {
+    fn from(value: ConntrackError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<FirewallError> for Error {
+    fn from(value: FirewallError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<IgmpError> for Error {
+    fn from(value: IgmpError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<NatError> for Error {
+    fn from(value: NatError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<TcpError> for Error {
+    fn from(value: TcpError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<FtpAlgError> for Error {
+    fn from(value: FtpAlgError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<SipAlgError> for Error {
+    fn from(value: SipAlgError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<RoutingError> for Error {
+    fn from(value: RoutingError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<InterfaceError> for Error {
+    fn from(value: InterfaceError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<HookError> for Error {
+    fn from(value: HookError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<RawSocketError> for Error {
+    fn from(value: RawSocketError) -> Self {
+        NetError::from(value).into()
+    }
+}
+
+impl From<EventBusError> for Error {
+    fn from(value: EventBusError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<DhcpError> for Error {
+    fn from(value: DhcpError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<BootpError> for Error {
+    fn from(value: BootpError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<DnsError> for Error {
+    fn from(value: DnsError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<CliError> for Error {
+    fn from(value: CliError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<BatchError> for Error {
+    fn from(value: BatchError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<HttpError> for Error {
+    fn from(value: HttpError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<JsonError> for Error {
+    fn from(value: JsonError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<AuthError> for Error {
+    fn from(value: AuthError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(value: ConfigError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<LeaseError> for Error {
+    fn from(value: LeaseError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<PersistError> for Error {
+    fn from(value: PersistError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
+
+impl From<TimerError> for Error {
+    fn from(value: TimerError) -> Self {
+        ServiceError::from(value).into()
+    }
+}
@@ -0,0 +1,164 @@
+//! Ethernet layer definitions.
+
+use core::{fmt, net::Ipv4Addr, str::FromStr};
+
+/// Hardware address of an Ethernet interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+
+    /// Group addresses have the least significant bit of the first octet set, broadcast included.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Address an IPv4 multicast group maps to (RFC 1112), `01:00:5e` and its low 23 bits.
+    pub fn ipv4_multicast(group: Ipv4Addr) -> Self {
+        let [_, b, c, d] = group.octets();
+        MacAddress([0x01, 0x00, 0x5e, b & 0x7f, c, d])
+    }
+}
+
+/// Formats as `aa:bb:cc:dd:ee:ff`.
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseMacError;
+
+/// Parses six hexadecimal octets separated by `:` or `-`.
+impl FromStr for MacAddress {
+    type Err = ParseMacError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for octet in &mut octets {
+            let part = parts.next().ok_or(ParseMacError)?;
+            if part.len() != 2 {
+                return Err(ParseMacError);
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| ParseMacError)?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(ParseMacError),
+            None => Ok(MacAddress(octets)),
+        }
+    }
+}
+
+/// EtherType values.
+pub mod ethertype {
+    pub const IPV4: u16 = 0x0800;
+    pub const ARP: u16 = 0x0806;
+    pub const VLAN: u16 = 0x8100;
+    pub const IPV6: u16 = 0x86DD;
+}
+
+/// Offset of the EtherType, or of the TPID in tagged frames.
+const ETHERTYPE_OFFSET: usize = 12;
+
+/// IEEE 802.1Q tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VlanTag {
+    /// 802.1p priority code point, 0 to 7.
+    pub pcp: u8,
+    /// Drop eligible indicator.
+    pub dei: bool,
+    /// VLAN identifier, 12 bits.
+    pub vid: u16,
+}
+
+impl VlanTag {
+    pub fn from_tci(tci: u16) -> Self {
+        Self {
+            pcp: (tci >> 13) as u8,
+            dei: tci & 0x1000 != 0,
+            vid: tci & 0x0FFF,
+        }
+    }
+
+    pub fn tci(&self) -> u16 {
+        ((self.pcp as u16 & 0b111) << 13) | ((self.dei as u16) << 12) | (self.vid & 0x0FFF)
+    }
+
+    /// Tag of `frame`, `None` for untagged frames.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let header = frame.get(ETHERTYPE_OFFSET..ETHERTYPE_OFFSET + 4)?;
+        if u16::from_be_bytes([header[0], header[1]]) != ethertype::VLAN {
+            return None;
+        }
+
+        Some(Self::from_tci(u16::from_be_bytes([header[2], header[3]])))
+    }
+
+    /// Overwrites the tag of a tagged `frame`.
+    pub fn write(&self, frame: &mut [u8]) -> Option<()> {
+        Self::parse(frame)?;
+        frame[ETHERTYPE_OFFSET + 2..ETHERTYPE_OFFSET + 4]
+            .copy_from_slice(&self.tci().to_be_bytes());
+        Some(())
+    }
+}
+
+/// Maps 802.1p priorities of bridged frames to egress queues, optionally remarking them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CosMap {
+    /// Queue for each PCP value, higher queues are served first.
+    pub queues: [u8; 8],
+    /// New PCP for each PCP value.
+    pub remark: Option<[u8; 8]>,
+}
+
+impl CosMap {
+    /// Recommended priority to traffic class mapping of IEEE 802.1Q (table 8-5) for
+    /// `queue_count` queues, clamped to 1 to 8.
+    pub fn recommended(queue_count: u8) -> Self {
+        const TABLE: [[u8; 8]; 8] = [
+            [0, 0, 0, 0, 0, 0, 0, 0],
+            [0, 0, 0, 0, 1, 1, 1, 1],
+            [0, 0, 0, 0, 1, 1, 2, 2],
+            [0, 0, 1, 1, 2, 2, 3, 3],
+            [0, 0, 1, 1, 2, 2, 3, 4],
+            [1, 0, 2, 2, 3, 3, 4, 5],
+            [1, 0, 2, 3, 4, 4, 5, 6],
+            [1, 0, 2, 3, 4, 5, 6, 7],
+        ];
+
+        Self {
+            queues: TABLE[queue_count.clamp(1, 8) as usize - 1],
+            remark: None,
+        }
+    }
+
+    /// Egress queue of `frame`, remarking its priority if configured.
+    ///
+    /// Untagged frames go to the queue of PCP 0, best effort.
+    pub fn classify(&self, frame: &mut [u8]) -> u8 {
+        let Some(mut tag) = VlanTag::parse(frame) else {
+            return self.queues[0];
+        };
+
+        let queue = self.queues[tag.pcp as usize];
+        if let Some(remark) = self.remark {
+            tag.pcp = remark[tag.pcp as usize] & 0b111;
+            tag.write(frame);
+        }
+
+        queue
+    }
+}
@@ -0,0 +1,170 @@
+//! Bounded publish/subscribe bus for notifications between subsystems.
+//!
+//! Published events are kept in a ring of `N` slots shared by every subscriber, each subscriber
+//! keeps its own read position. A subscriber that falls more than `N` events behind loses the
+//! oldest ones and is told how many were missed.
+
+use thiserror::Error;
+
+use core::net::Ipv4Addr;
+
+use crate::{
+    conntrack::FlowKey,
+    ethernet::MacAddress,
+    interface::{InterfaceId, InterfaceState},
+};
+
+/// Notifications subsystems react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    LinkUp,
+    LinkDown,
+    LeaseAcquired,
+    ConfigChanged,
+    WanDown,
+    /// The interface moved to a new state.
+    InterfaceState(InterfaceId, InterfaceState),
+    /// The DHCP server leased an address to a client.
+    LeaseGranted {
+        mac: MacAddress,
+        address: Ipv4Addr,
+    },
+    /// The firewall dropped a packet of a new flow.
+    FirewallDrop(FlowKey),
+}
+
+impl Event {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Event::LinkUp => "link_up",
+            Event::LinkDown => "link_down",
+            Event::LeaseAcquired => "lease_acquired",
+            Event::ConfigChanged => "config_changed",
+            Event::WanDown => "wan_down",
+            Event::InterfaceState(..) => "interface_state",
+            Event::LeaseGranted { .. } => "lease_granted",
+            Event::FirewallDrop(_) => "firewall_drop",
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EventBusError {
+    #[error("Bus ran out of memory for additional subscribers.")]
+    SubscribersOutOfMemory,
+    #[error("Subscriber fell behind and missed {0} events.")]
+    Lagged(u32),
+}
+
+/// Handle returned by [`EventBus::subscribe`] to read events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscriber(usize);
+
+/// Event bus with room for `N` events, a power of two, and `S` subscribers.
+pub struct EventBus<const N: usize, const S: usize> {
+    events: [Option<Event>; N],
+    /// Number of events ever published, the next one goes to `published % N`.
+    published: u32,
+    /// Sequence number of the next event each subscriber reads.
+    cursors: heapless::Vec<u32, S>,
+}
+
+impl<const N: usize, const S: usize> Default for EventBus<N, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const S: usize> EventBus<N, S> {
+    /// Sequence numbers wrap, an event keeps its slot across the wrap only if `N` divides 2^32.
+    const VALID_SIZE: () = assert!(
+        N.is_power_of_two(),
+        "N must be a power of two for events to keep their slot as sequence numbers wrap"
+    );
+
+    pub const fn new() -> Self {
+        let () = Self::VALID_SIZE;
+        Self {
+            events: [None; N],
+            published: 0,
+            cursors: heapless::Vec::new(),
+        }
+    }
+
+    /// Registers a new subscriber, it only sees events published from now on.
+    pub fn subscribe(&mut self) -> Result<Subscriber, EventBusError> {
+        self.cursors
+            .push(self.published)
+            .map_err(|_| EventBusError::SubscribersOutOfMemory)?;
+        Ok(Subscriber(self.cursors.len() - 1))
+    }
+
+    /// Skips the events `subscriber` hasn't read yet, for a new reader taking over the
+    /// subscription.
+    pub fn catch_up(&mut self, subscriber: Subscriber) {
+        self.cursors[subscriber.0] = self.published;
+    }
+
+    /// Publishes `event` to every subscriber, overwriting the oldest event if the ring is full.
+    pub fn publish(&mut self, event: Event) {
+        self.events[self.published as usize % N] = Some(event);
+        self.published = self.published.wrapping_add(1);
+    }
+
+    /// Next event for `subscriber`, if any.
+    ///
+    /// When events were overwritten before being read, [`EventBusError::Lagged`] is returned
+    /// once and the subscriber resumes from the oldest event still available.
+    pub fn poll(&mut self, subscriber: Subscriber) -> Result<Option<Event>, EventBusError> {
+        let cursor = &mut self.cursors[subscriber.0];
+        let pending = self.published.wrapping_sub(*cursor);
+
+        if pending as usize > N {
+            let missed = pending - N as u32;
+            *cursor = cursor.wrapping_add(missed);
+            return Err(EventBusError::Lagged(missed));
+        }
+
+        if pending == 0 {
+            return Ok(None);
+        }
+
+        let event = self.events[*cursor as usize % N];
+        *cursor = cursor.wrapping_add(1);
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_read_in_order_across_the_sequence_wrap() {
+        let mut bus = EventBus::<4, 1>::new();
+        bus.published = u32::MAX - 1;
+        let subscriber = bus.subscribe().unwrap();
+
+        let events = [
+            Event::LinkDown,
+            Event::LinkUp,
+            Event::WanDown,
+            Event::ConfigChanged,
+        ];
+        for event in events {
+            bus.publish(event);
+        }
+        for event in events {
+            assert_eq!(bus.poll(subscriber), Ok(Some(event)));
+        }
+        assert_eq!(bus.poll(subscriber), Ok(None));
+
+        bus.publish(Event::LinkDown);
+        for _ in 0..4 {
+            bus.publish(Event::LinkUp);
+        }
+        assert_eq!(bus.poll(subscriber), Err(EventBusError::Lagged(1)));
+        assert_eq!(bus.poll(subscriber), Ok(Some(Event::LinkUp)));
+    }
+}
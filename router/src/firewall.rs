@@ -0,0 +1,272 @@
+//! Stateless packet filter rules.
+//!
+//! Rule edits are staged on a copy of the rule set and made active in one step with
+//! [`Firewall::commit`], so the data plane never sees a half-edited rule set.
+//! The active rule set is never written to while it's active.
+
+use core::ops::RangeInclusive;
+
+use thiserror::Error;
+
+use crate::cidr::Ipv4Cidr;
+use crate::conntrack::{Conntrack, FlowKey, Protocol};
+use crate::trace::{self, Decision, Stage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    Accept,
+    Drop,
+}
+
+/// A rule applies its action to flows matching every field that is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub protocol: Option<Protocol>,
+    pub source: Option<Ipv4Cidr>,
+    pub destination: Option<Ipv4Cidr>,
+    pub destination_ports: Option<RangeInclusive<u16>>,
+}
+
+impl Rule {
+    /// A rule matching everything.
+    pub fn any(action: Action) -> Self {
+        Self {
+            action,
+            protocol: None,
+            source: None,
+            destination: None,
+            destination_ports: None,
+        }
+    }
+
+    pub fn matches(&self, flow: &FlowKey) -> bool {
+        self.protocol.is_none_or(|p| p == flow.protocol)
+            && self.source.is_none_or(|s| s.contains(*flow.source.ip()))
+            && self
+                .destination
+                .is_none_or(|d| d.contains(*flow.destination.ip()))
+            && self
+                .destination_ports
+                .as_ref()
+                .is_none_or(|ports| ports.contains(&flow.destination.port()))
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FirewallError {
+    #[error("Rule set ran out of memory for additional rules.")]
+    RulesOutOfMemory,
+}
+
+/// Ordered rules, the first matching rule wins.
+#[derive(Debug, Clone)]
+pub struct RuleSet<const N: usize> {
+    rules: heapless::Vec<Rule, N>,
+    /// Action for flows no rule matches.
+    pub default_action: Action,
+}
+
+impl<const N: usize> RuleSet<N> {
+    pub fn new(default_action: Action) -> Self {
+        Self {
+            rules: heapless::Vec::new(),
+            default_action,
+        }
+    }
+
+    pub fn push(&mut self, rule: Rule) -> Result<(), FirewallError> {
+        self.rules
+            .push(rule)
+            .map_err(|_| FirewallError::RulesOutOfMemory)
+    }
+
+    pub fn insert(&mut self, index: usize, rule: Rule) -> Result<(), FirewallError> {
+        self.rules
+            .insert(index, rule)
+            .map_err(|_| FirewallError::RulesOutOfMemory)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Rule {
+        self.rules.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn evaluate(&self, flow: &FlowKey) -> Action {
+        let (action, decision) = self.decide(flow);
+        trace::record(flow, Stage::Filter, decision);
+        action
+    }
+
+    fn decide(&self, flow: &FlowKey) -> (Action, Decision) {
+        match self.rules.iter().position(|rule| rule.matches(flow)) {
+            Some(index) => {
+                let action = self.rules[index].action;
+                (action, Decision::Rule { index, action })
+            }
+            None => (
+                self.default_action,
+                Decision::DefaultAction(self.default_action),
+            ),
+        }
+    }
+}
+
+/// Double-buffered rule sets of up to `N` rules each.
+pub struct Firewall<const N: usize> {
+    rule_sets: [RuleSet<N>; 2],
+    /// Index of the rule set applied to traffic, the other one is staged.
+    active: usize,
+}
+
+impl<const N: usize> Firewall<N> {
+    pub fn new(default_action: Action) -> Self {
+        Self {
+            rule_sets: [RuleSet::new(default_action), RuleSet::new(default_action)],
+            active: 0,
+        }
+    }
+
+    /// Rule set currently applied to traffic.
+    pub fn active(&self) -> &RuleSet<N> {
+        &self.rule_sets[self.active]
+    }
+
+    /// Rule set being edited, starting as a copy of the active one.
+    pub fn staged(&mut self) -> &mut RuleSet<N> {
+        &mut self.rule_sets[1 - self.active]
+    }
+
+    /// Throws away staged edits.
+    pub fn discard(&mut self) {
+        self.rule_sets[1 - self.active] = self.rule_sets[self.active].clone();
+    }
+
+    /// Makes the staged rule set active, the previously active one becomes the new staging copy.
+    pub fn commit(&mut self) {
+        self.active = 1 - self.active;
+        self.discard();
+    }
+
+    pub fn evaluate(&self, flow: &FlowKey) -> Action {
+        self.active().evaluate(flow)
+    }
+
+    /// Drops the tracked flows the active rule set no longer accepts, after a commit, returning
+    /// how many were dropped. Flows still accepted carry on undisturbed.
+    pub fn revalidate<T, const C: usize>(&self, conntrack: &mut Conntrack<T, C>) -> usize {
+        let before = conntrack.len();
+        // Not traced, these aren't packets going through the filter.
+        conntrack.retain(|flow| self.active().decide(&flow.key).0 == Action::Accept);
+        before - conntrack.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+    use crate::conntrack::{EvictionPolicy, FlowState, TcpState, TimeoutProfile};
+
+    fn flow(protocol: Protocol, host: u8, port: u16) -> FlowKey {
+        FlowKey {
+            protocol,
+            source: SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, host), 40000 + port),
+            destination: SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 7), port),
+        }
+    }
+
+    fn tracked(keys: &[FlowKey]) -> Conntrack<(), 8> {
+        let mut conntrack = Conntrack::new(TimeoutProfile::default(), EvictionPolicy::RefuseNew);
+        for key in keys {
+            conntrack
+                .insert(*key, FlowState::Tcp(TcpState::Established), (), 0)
+                .unwrap();
+        }
+        conntrack
+    }
+
+    fn keys(conntrack: &Conntrack<(), 8>) -> Vec<FlowKey> {
+        let mut keys: Vec<_> = conntrack.iter().map(|flow| flow.key).collect();
+        keys.sort_by_key(|key| (*key.source.ip(), key.destination.port()));
+        keys
+    }
+
+    #[test]
+    fn accept_rule_keeps_every_flow() {
+        let flows = [
+            flow(Protocol::Tcp, 10, 443),
+            flow(Protocol::Tcp, 11, 22),
+            flow(Protocol::Udp, 12, 53),
+        ];
+        let mut conntrack = tracked(&flows);
+        let mut firewall = Firewall::<4>::new(Action::Accept);
+        firewall
+            .staged()
+            .push(Rule {
+                protocol: Some(Protocol::Tcp),
+                destination_ports: Some(443..=443),
+                ..Rule::any(Action::Accept)
+            })
+            .unwrap();
+        firewall.commit();
+
+        assert_eq!(firewall.revalidate(&mut conntrack), 0);
+        assert_eq!(keys(&conntrack), flows);
+    }
+
+    #[test]
+    fn drop_rule_drops_exactly_the_flows_it_matches() {
+        let ssh = flow(Protocol::Tcp, 10, 22);
+        let https = flow(Protocol::Tcp, 10, 443);
+        let other_ssh = flow(Protocol::Tcp, 20, 22);
+        let dns = flow(Protocol::Udp, 10, 22);
+        let mut conntrack = tracked(&[ssh, https, other_ssh, dns]);
+        let mut firewall = Firewall::<4>::new(Action::Accept);
+        firewall
+            .staged()
+            .push(Rule {
+                protocol: Some(Protocol::Tcp),
+                source: Some(Ipv4Cidr::new(Ipv4Addr::new(192, 168, 1, 0), 28).unwrap()),
+                destination_ports: Some(22..=22),
+                ..Rule::any(Action::Drop)
+            })
+            .unwrap();
+
+        // Staged rules apply to nothing until committed.
+        assert_eq!(firewall.revalidate(&mut conntrack), 0);
+        firewall.commit();
+        assert_eq!(firewall.revalidate(&mut conntrack), 1);
+        assert_eq!(keys(&conntrack), [dns, https, other_ssh]);
+    }
+
+    #[test]
+    fn earlier_accept_rule_shields_flows_from_a_drop_rule() {
+        let ssh = flow(Protocol::Tcp, 10, 22);
+        let telnet = flow(Protocol::Tcp, 10, 23);
+        let mut conntrack = tracked(&[ssh, telnet]);
+        let mut firewall = Firewall::<4>::new(Action::Accept);
+        let staged = firewall.staged();
+        staged
+            .push(Rule {
+                destination_ports: Some(22..=22),
+                ..Rule::any(Action::Accept)
+            })
+            .unwrap();
+        staged.push(Rule::any(Action::Drop)).unwrap();
+        firewall.commit();
+
+        assert_eq!(firewall.revalidate(&mut conntrack), 1);
+        assert_eq!(keys(&conntrack), [ssh]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Heap-free formatting and parsing helpers shared by the CLI, logs and HTTP pages.
+
+use core::{
+    fmt::{self, Display, Write},
+    str::FromStr,
+};
+
+use crate::text;
+
+/// Formats `value` into a string of at most `N` bytes.
+pub fn to_string<const N: usize>(value: impl Display) -> Result<heapless::String<N>, fmt::Error> {
+    let mut string = heapless::String::new();
+    write!(string, "{value}")?;
+    Ok(string)
+}
+
+/// A duration in seconds, displayed like `2d 3h 4m 5s` and parsed from a number with an
+/// optional `s`, `m`, `h` or `d` suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Duration(pub u32);
+
+impl Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = [(86_400, 'd'), (3_600, 'h'), (60, 'm'), (1, 's')];
+        let mut rest = self.0;
+        let mut first = true;
+
+        for (seconds, unit) in units {
+            let count = rest / seconds;
+            rest %= seconds;
+            // Zero seconds still needs to show something.
+            if count == 0 && !(first && seconds == 1) {
+                continue;
+            }
+
+            if !first {
+                f.write_char(' ')?;
+            }
+            write!(f, "{count}{unit}")?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseDurationError;
+
+impl FromStr for Duration {
+    type Err = ParseDurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, multiplier) = match s.as_bytes().last() {
+            Some(b's') => (&s[..s.len() - 1], 1),
+            Some(b'm') => (&s[..s.len() - 1], 60),
+            Some(b'h') => (&s[..s.len() - 1], 3_600),
+            Some(b'd') => (&s[..s.len() - 1], 86_400),
+            _ => (s, 1),
+        };
+
+        text::parse_decimal(number.as_bytes(), u32::MAX)
+            .and_then(|number| number.checked_mul(multiplier))
+            .map(Duration)
+            .ok_or(ParseDurationError)
+    }
+}
+
+/// A byte count, displayed with binary units like `1.5 KiB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Bytes(pub u64);
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+
+        let mut unit = 0;
+        let mut scaled = self.0;
+        while scaled >= 1024 * 1024 && unit < UNITS.len() - 1 {
+            scaled /= 1024;
+            unit += 1;
+        }
+
+        // One decimal, truncated.
+        let tenths = scaled * 10 / 1024;
+        write!(f, "{}.{} {}", tenths / 10, tenths % 10, UNITS[unit])
+    }
+}
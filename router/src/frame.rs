@@ -0,0 +1,104 @@
+//! Frames made of non-contiguous segments.
+//!
+//! Lets headers be prepended, replaced or stripped without moving the payload around,
+//! which matters when the payload is a full 1500 bytes frame sitting in another buffer.
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameBufError {
+    #[error("Frame ran out of segments.")]
+    SegmentsOutOfMemory,
+    #[error("Destination buffer is smaller than the frame.")]
+    BufferTooSmall,
+}
+
+/// A frame split over up to `K` segments, in order.
+#[derive(Debug, Clone, Default)]
+pub struct FrameBuf<'a, const K: usize> {
+    segments: heapless::Vec<&'a [u8], K>,
+}
+
+impl<'a, const K: usize> FrameBuf<'a, K> {
+    pub fn new() -> Self {
+        Self {
+            segments: heapless::Vec::new(),
+        }
+    }
+
+    /// Adds a segment before the current start of the frame, e.g. a new header.
+    pub fn push_front(&mut self, segment: &'a [u8]) -> Result<(), FrameBufError> {
+        self.segments
+            .insert(0, segment)
+            .map_err(|_| FrameBufError::SegmentsOutOfMemory)
+    }
+
+    /// Adds a segment after the current end of the frame.
+    pub fn push_back(&mut self, segment: &'a [u8]) -> Result<(), FrameBufError> {
+        self.segments
+            .push(segment)
+            .map_err(|_| FrameBufError::SegmentsOutOfMemory)
+    }
+
+    /// Drops the first `len` bytes of the frame, e.g. to strip a header before replacing it.
+    ///
+    /// Dropping more bytes than the frame has leaves it empty.
+    pub fn strip_front(&mut self, mut len: usize) {
+        let consumed = self
+            .segments
+            .iter()
+            .take_while(|segment| {
+                if segment.len() > len {
+                    return false;
+                }
+
+                len -= segment.len();
+                true
+            })
+            .count();
+
+        self.segments.rotate_left(consumed);
+        self.segments.truncate(self.segments.len() - consumed);
+
+        if let Some(first) = self.segments.first_mut() {
+            *first = &first[len..];
+        }
+    }
+
+    /// Total length of the frame in bytes.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn segments(&self) -> &[&'a [u8]] {
+        &self.segments
+    }
+
+    /// Iterates over the bytes of the frame in order.
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.iter().copied())
+    }
+
+    /// Gathers the frame into `buffer` returning the number of bytes written.
+    pub fn copy_to(&self, buffer: &mut [u8]) -> Result<usize, FrameBufError> {
+        let len = self.len();
+        if buffer.len() < len {
+            return Err(FrameBufError::BufferTooSmall);
+        }
+
+        let mut offset = 0;
+        for segment in &self.segments {
+            buffer[offset..offset + segment.len()].copy_from_slice(segment);
+            offset += segment.len();
+        }
+
+        Ok(len)
+    }
+}
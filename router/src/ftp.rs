@@ -0,0 +1,135 @@
+//! FTP application level gateway.
+//!
+//! FTP sends the address of its data connections inside the control connection, in `PORT`
+//! commands (active mode) and `227` replies to `PASV` (passive mode). Behind NAPT those carry
+//! the private address, so they're rewritten to the translated one and an [`Expectation`] is
+//! opened for the data connection.
+//!
+//! A rewrite can change the length of the line, the caller has to keep track of the difference
+//! to adjust sequence and acknowledgment numbers of the rest of the control connection.
+
+use core::fmt::Write;
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::conntrack::{Expectation, Protocol};
+use crate::text;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FtpAlgError {
+    #[error("Destination buffer is too small for the rewritten line.")]
+    BufferTooSmall,
+}
+
+/// Line of the control connection announcing a data connection endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataEndpoint {
+    /// `PORT` command from the client, the server connects to it.
+    Port(SocketAddrV4),
+    /// `227` reply from the server, the client connects to it.
+    Passive(SocketAddrV4),
+}
+
+impl DataEndpoint {
+    pub fn address(&self) -> SocketAddrV4 {
+        match self {
+            DataEndpoint::Port(address) | DataEndpoint::Passive(address) => *address,
+        }
+    }
+}
+
+/// Finds the `h1,h2,h3,h4,p1,p2` part of a `PORT` command or `227` reply.
+fn endpoint_span(line: &[u8]) -> Option<(bool, core::ops::Range<usize>)> {
+    let (is_port, start) = if text::strip_prefix_ignore_case(line, b"PORT ").is_some() {
+        (true, 5)
+    } else if line.starts_with(b"227 ") {
+        // The RFC doesn't mandate the parentheses, the numbers start at the first digit.
+        (false, 4 + line[4..].iter().position(u8::is_ascii_digit)?)
+    } else {
+        return None;
+    };
+
+    let len = line[start..]
+        .iter()
+        .position(|b| !b.is_ascii_digit() && *b != b',')
+        .unwrap_or(line.len() - start);
+
+    Some((is_port, start..start + len))
+}
+
+fn parse_endpoint(numbers: &[u8]) -> Option<SocketAddrV4> {
+    let mut values = [0u8; 6];
+    let mut parts = numbers.split(|b| *b == b',');
+    for value in &mut values {
+        *value = text::parse_decimal(parts.next()?, u8::MAX)?;
+    }
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let [a, b, c, d, high, low] = values;
+    Some(SocketAddrV4::new(
+        Ipv4Addr::new(a, b, c, d),
+        u16::from_be_bytes([high, low]),
+    ))
+}
+
+/// Parses a control connection line, `None` if it doesn't announce a data endpoint.
+pub fn parse_line(line: &[u8]) -> Option<DataEndpoint> {
+    let (is_port, span) = endpoint_span(line)?;
+    let address = parse_endpoint(&line[span])?;
+
+    Some(if is_port {
+        DataEndpoint::Port(address)
+    } else {
+        DataEndpoint::Passive(address)
+    })
+}
+
+/// Writes `line` to `out` with its data endpoint replaced by `translated`,
+/// returning the length of the new line.
+///
+/// Lines without a data endpoint are copied as they are.
+pub fn rewrite_line(
+    line: &[u8],
+    translated: SocketAddrV4,
+    out: &mut [u8],
+) -> Result<usize, FtpAlgError> {
+    let Some((_, span)) =
+        endpoint_span(line).filter(|(_, span)| parse_endpoint(&line[span.clone()]).is_some())
+    else {
+        out.get_mut(..line.len())
+            .ok_or(FtpAlgError::BufferTooSmall)?
+            .copy_from_slice(line);
+        return Ok(line.len());
+    };
+
+    // At most "255,255,255,255,255,255".
+    let mut endpoint = heapless::String::<23>::new();
+    let [a, b, c, d] = translated.ip().octets();
+    let [high, low] = translated.port().to_be_bytes();
+    write!(endpoint, "{a},{b},{c},{d},{high},{low}").unwrap();
+
+    let len = line.len() - span.len() + endpoint.len();
+    let out = out.get_mut(..len).ok_or(FtpAlgError::BufferTooSmall)?;
+    let tail = span.start + endpoint.len();
+    out[..span.start].copy_from_slice(&line[..span.start]);
+    out[span.start..tail].copy_from_slice(endpoint.as_bytes());
+    out[tail..].copy_from_slice(&line[span.end..]);
+
+    Ok(len)
+}
+
+/// Expectation for the data connection announced by `endpoint`, `peer` being the other end of
+/// the control connection, which is who opens the data connection.
+pub fn expectation(endpoint: DataEndpoint, peer: Ipv4Addr, expires_at: u32) -> Expectation {
+    Expectation {
+        protocol: Protocol::Tcp,
+        source: peer,
+        destination: endpoint.address(),
+        expires_at,
+    }
+}
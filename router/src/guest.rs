@@ -0,0 +1,68 @@
+//! Guest network configuration.
+//!
+//! A secondary LAN subnet, either on its own VLAN or on dedicated ports, that gets WAN access
+//! but can't reach the primary LAN.
+
+use core::net::Ipv4Addr;
+
+use crate::bridge::MacTable;
+use crate::cidr::Ipv4Cidr;
+use crate::firewall::{Action, FirewallError, Rule, RuleSet};
+
+/// Where guest clients connect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestAttachment {
+    /// Frames tagged with this VLAN id.
+    Vlan(u16),
+    /// Every port whose bit is set.
+    Ports(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestNetwork {
+    pub attachment: GuestAttachment,
+    /// Router's address on the guest subnet, with the subnet's prefix length.
+    pub address: Ipv4Cidr,
+    /// First and last address handed out by DHCP.
+    pub pool_start: Ipv4Addr,
+    pub pool_end: Ipv4Addr,
+    /// DNS server announced to guests, the router itself when unset.
+    pub dns: Option<Ipv4Addr>,
+    /// Keep guest clients from reaching each other.
+    pub client_isolation: bool,
+}
+
+impl GuestNetwork {
+    pub fn subnet(&self) -> Ipv4Cidr {
+        self.address.network()
+    }
+
+    /// Rule keeping guests out of the `lan` subnet, the router's LAN address included.
+    pub fn firewall_rule(&self, lan: Ipv4Cidr) -> Rule {
+        Rule {
+            source: Some(self.subnet()),
+            destination: Some(lan),
+            ..Rule::any(Action::Drop)
+        }
+    }
+
+    /// Inserts [`Self::firewall_rule`] ahead of every other rule in `rules`.
+    pub fn install_firewall_rule<const N: usize>(
+        &self,
+        rules: &mut RuleSet<N>,
+        lan: Ipv4Cidr,
+    ) -> Result<(), FirewallError> {
+        rules.insert(0, self.firewall_rule(lan))
+    }
+
+    /// Marks the guest ports isolated in the bridge when client isolation is on.
+    pub fn apply_isolation<const N: usize>(&self, table: &mut MacTable<N>) {
+        let GuestAttachment::Ports(ports) = self.attachment else {
+            return;
+        };
+
+        for port in (0..32).filter(|port| ports & (1 << port) != 0) {
+            table.set_isolated(port, self.client_isolation);
+        }
+    }
+}
@@ -0,0 +1,1203 @@
+//! Minimal HTTP/1.1 for the management interface.
+//!
+//! Requests are parsed in place from the received bytes, responses are written into a caller
+//! provided buffer and delimited by closing the connection.
+//!
+//! Bodies too large for a single buffer, like table dumps, are sent with chunked transfer
+//! encoding: a [`ChunkedWriter`] fills one buffer at a time and remembers where it stopped.
+//!
+//! Endpoints under `/api` exchange [`json`](crate::json) for dashboards and scripts, `/events`
+//! pushes the [event bus](crate::events) to a browser as server-sent events.
+
+use core::{
+    fmt::{self, Write},
+    net::Ipv4Addr,
+};
+
+use thiserror::Error;
+
+use crate::{
+    arp::{ArpCache, ArpEntry, ArpError},
+    cidr::Ipv4Cidr,
+    cli,
+    config::{Config, ConfigError, SetError},
+    conntrack::{Conntrack, Flow},
+    ethernet::MacAddress,
+    events::{Event, EventBus, EventBusError, Subscriber},
+    json::{self, Json, JsonError, ObjectWriter, ToJson},
+    lease::{Lease, Leases},
+    metrics::{self, Metric},
+    sysinfo::{self, PoolUsage, SystemInfo},
+    text,
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HttpError {
+    #[error("Request head isn't complete yet.")]
+    Incomplete,
+    #[error("Request is malformed.")]
+    Malformed,
+    #[error("Response doesn't fit the buffer.")]
+    BufferTooSmall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: Method,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    /// Header lines, without the request line and the final empty line.
+    headers: &'a str,
+    /// Offset of the body in the received bytes.
+    pub body_offset: usize,
+}
+
+impl<'a> Request<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, HttpError> {
+        let head_len = text::find(bytes, b"\r\n\r\n").ok_or(HttpError::Incomplete)?;
+        let head = core::str::from_utf8(&bytes[..head_len]).map_err(|_| HttpError::Malformed)?;
+
+        let (request_line, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(HttpError::Malformed);
+        };
+
+        if !version.starts_with("HTTP/1.") {
+            return Err(HttpError::Malformed);
+        }
+
+        let method = match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            _ => return Err(HttpError::Malformed),
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        Ok(Self {
+            method,
+            path,
+            query,
+            headers,
+            body_offset: head_len + 4,
+        })
+    }
+
+    /// Value of the first header called `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        let value = text::header(self.headers.as_bytes(), name.as_bytes())?;
+        core::str::from_utf8(value).ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub u16, pub &'static str);
+
+impl Status {
+    pub const OK: Status = Status(200, "OK");
+    pub const FOUND: Status = Status(302, "Found");
+    pub const BAD_REQUEST: Status = Status(400, "Bad Request");
+    pub const NOT_FOUND: Status = Status(404, "Not Found");
+    pub const METHOD_NOT_ALLOWED: Status = Status(405, "Method Not Allowed");
+}
+
+/// Writes a response into a buffer.
+pub struct ResponseWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> ResponseWriter<'a> {
+    /// Starts a response with its status line.
+    pub fn new(buffer: &'a mut [u8], status: Status) -> Result<Self, HttpError> {
+        let mut writer = Self { buffer, len: 0 };
+        write!(writer, "HTTP/1.1 {} {}\r\n", status.0, status.1)
+            .map_err(|_| HttpError::BufferTooSmall)?;
+        Ok(writer)
+    }
+
+    pub fn header(&mut self, name: &str, value: &str) -> Result<&mut Self, HttpError> {
+        write!(self, "{name}: {value}\r\n").map_err(|_| HttpError::BufferTooSmall)?;
+        Ok(self)
+    }
+
+    /// Announces a body sent in chunks by a [`ChunkedWriter`], after the head.
+    pub fn chunked(&mut self) -> Result<&mut Self, HttpError> {
+        self.header("Transfer-Encoding", "chunked")
+    }
+
+    /// Ends the head, everything written afterwards is the body.
+    pub fn end_head(&mut self) -> Result<&mut Self, HttpError> {
+        self.write_str("Connection: close\r\n\r\n")
+            .map_err(|_| HttpError::BufferTooSmall)?;
+        Ok(self)
+    }
+
+    /// Length of the response written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Write for ResponseWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.buffer
+            .get_mut(self.len..self.len + s.len())
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// Writes a chunked body one buffer at a time.
+///
+/// The writer keeps a position rather than the source, so nothing stays borrowed between two
+/// chunks: each call is given the rows, or bytes, from [`Self::position`] on. A source changing
+/// in between, like a table gaining entries, shifts what the rest of the body contains but never
+/// breaks its framing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkedWriter {
+    position: usize,
+    finished: bool,
+}
+
+impl ChunkedWriter {
+    /// A chunk starts with its size in 4 hex digits and ends with a line break.
+    const SIZE_LINE_LEN: usize = 6;
+    const MAX_CHUNK_LEN: usize = 0xFFFF;
+    const LAST_CHUNK: &str = "0\r\n\r\n";
+
+    pub const fn new() -> Self {
+        Self {
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// Rows, or bytes, already sent.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Whether the last chunk was written.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Writes as many of `rows` as fit into `buffer` as a chunk, `rows` starting at
+    /// [`Self::position`]. The last chunk follows once `rows` runs out.
+    ///
+    /// Returns the length written, 0 once finished.
+    pub fn next_chunk<R: fmt::Display>(
+        &mut self,
+        rows: impl IntoIterator<Item = R>,
+        buffer: &mut [u8],
+    ) -> Result<usize, HttpError> {
+        self.fill(buffer, |data| {
+            let mut written = 0;
+            let mut rows_written = 0;
+            for row in rows {
+                let mut writer = ResponseWriter {
+                    buffer: &mut data[written..],
+                    len: 0,
+                };
+                if write!(writer, "{row}").is_err() {
+                    return (written, rows_written, false);
+                }
+                written += writer.len;
+                rows_written += 1;
+            }
+            (written, rows_written, true)
+        })
+    }
+
+    /// Writes as much of `bytes` as fits into `buffer` as a chunk, `bytes` starting at
+    /// [`Self::position`]. The last chunk follows once `bytes` is empty.
+    ///
+    /// Returns the length written, 0 once finished.
+    pub fn next_chunk_bytes(
+        &mut self,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<usize, HttpError> {
+        self.fill(buffer, |data| {
+            let len = bytes.len().min(data.len());
+            data[..len].copy_from_slice(&bytes[..len]);
+            (len, len, len == bytes.len())
+        })
+    }
+
+    /// Frames what `write` puts into the chunk's data, it returns the length written, how far
+    /// it moved the position and whether the source ran out.
+    fn fill(
+        &mut self,
+        buffer: &mut [u8],
+        write: impl FnOnce(&mut [u8]) -> (usize, usize, bool),
+    ) -> Result<usize, HttpError> {
+        if self.finished {
+            return Ok(0);
+        }
+
+        let end = buffer
+            .len()
+            .saturating_sub(2)
+            .min(Self::SIZE_LINE_LEN + Self::MAX_CHUNK_LEN);
+        let data = buffer.get_mut(Self::SIZE_LINE_LEN..end).unwrap_or_default();
+        let (len, advanced, exhausted) = write(data);
+        self.position += advanced;
+
+        let mut written = 0;
+        if len > 0 {
+            let mut size = ResponseWriter {
+                buffer: &mut buffer[..Self::SIZE_LINE_LEN],
+                len: 0,
+            };
+            write!(size, "{len:04x}\r\n").map_err(|_| HttpError::BufferTooSmall)?;
+            written = Self::SIZE_LINE_LEN + len;
+            buffer[written..written + 2].copy_from_slice(b"\r\n");
+            written += 2;
+        } else if !exhausted {
+            // Not even a single row fits.
+            return Err(HttpError::BufferTooSmall);
+        }
+
+        if exhausted {
+            let Some(last) = buffer.get_mut(written..written + Self::LAST_CHUNK.len()) else {
+                // Goes out with the next call.
+                return Ok(written);
+            };
+            last.copy_from_slice(Self::LAST_CHUNK.as_bytes());
+            written += Self::LAST_CHUNK.len();
+            self.finished = true;
+        }
+
+        Ok(written)
+    }
+}
+
+/// Serves `GET /metrics` in the Prometheus text format, returning the response length.
+///
+/// The body is chunked, with as many metrics as fit after the head; the rest follows with
+/// [`next_metrics_chunk`] until `stream` is finished.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_metrics(
+    request: &Request<'_>,
+    metrics: &[Metric<'_>],
+    stream: &mut ChunkedWriter,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/metrics" {
+        return Ok(None);
+    }
+
+    let (head_len, body) = start_stream(request, "text/plain; version=0.0.4", stream, response)?;
+    if !body {
+        return Ok(Some(head_len));
+    }
+
+    let chunk = next_metrics_chunk(metrics, stream, &mut response[head_len..])?;
+    Ok(Some(head_len + chunk))
+}
+
+/// Writes the head of a chunked `GET` or `HEAD` response and starts `stream`, returning the
+/// head's length and whether a body follows. Other methods are refused, the response being
+/// complete.
+fn start_stream(
+    request: &Request<'_>,
+    content_type: &str,
+    stream: &mut ChunkedWriter,
+    response: &mut [u8],
+) -> Result<(usize, bool), HttpError> {
+    *stream = ChunkedWriter::new();
+    stream.finished = true;
+
+    if !matches!(request.method, Method::Get | Method::Head) {
+        let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+        writer.header("Allow", "GET, HEAD")?.end_head()?;
+        return Ok((writer.len(), false));
+    }
+
+    let mut writer = ResponseWriter::new(response, Status::OK)?;
+    writer
+        .header("Content-Type", content_type)?
+        .chunked()?
+        .end_head()?;
+
+    stream.finished = request.method == Method::Head;
+    Ok((writer.len(), !stream.finished))
+}
+
+/// Writes the next chunk of a `/metrics` body, returning its length, 0 once finished.
+pub fn next_metrics_chunk(
+    metrics: &[Metric<'_>],
+    stream: &mut ChunkedWriter,
+    buffer: &mut [u8],
+) -> Result<usize, HttpError> {
+    let rows = metrics.get(stream.position()..).unwrap_or_default();
+    stream.next_chunk(rows.iter().map(metrics::Prometheus), buffer)
+}
+
+/// Serves `/config`: `GET` downloads the configuration in its text form, `PUT` replaces it
+/// with the text in the body of `received` once all of it is valid.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_config(
+    request: &Request<'_>,
+    received: &[u8],
+    config: &mut Config,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/config" {
+        return Ok(None);
+    }
+
+    match request.method {
+        Method::Get | Method::Head => {
+            let mut writer = ResponseWriter::new(response, Status::OK)?;
+            writer
+                .header("Content-Type", "text/plain")?
+                .header(
+                    "Content-Disposition",
+                    "attachment; filename=\"router.conf\"",
+                )?
+                .end_head()?;
+            if request.method == Method::Get {
+                config
+                    .export(&mut writer)
+                    .map_err(|_| HttpError::BufferTooSmall)?;
+            }
+            Ok(Some(writer.len()))
+        }
+        Method::Put => {
+            let imported = received
+                .get(request.body_offset..)
+                .and_then(|body| core::str::from_utf8(body).ok())
+                .ok_or(HttpError::Malformed)
+                .map(Config::import);
+
+            let writer = match imported {
+                Ok(Ok(imported)) => {
+                    *config = imported;
+                    let mut writer = ResponseWriter::new(response, Status::OK)?;
+                    writer.end_head()?;
+                    writer
+                }
+                Ok(Err(error)) => {
+                    let mut writer = ResponseWriter::new(response, Status::BAD_REQUEST)?;
+                    writer.header("Content-Type", "text/plain")?.end_head()?;
+                    writeln!(writer, "{error}").map_err(|_| HttpError::BufferTooSmall)?;
+                    writer
+                }
+                Err(_) => {
+                    let mut writer = ResponseWriter::new(response, Status::BAD_REQUEST)?;
+                    writer.end_head()?;
+                    writer
+                }
+            };
+            Ok(Some(writer.len()))
+        }
+        _ => {
+            let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+            writer.header("Allow", "GET, HEAD, PUT")?.end_head()?;
+            Ok(Some(writer.len()))
+        }
+    }
+}
+
+/// Serves `POST /batch`: runs the CLI commands in the body as a batch, see
+/// [`cli::run_batch`].
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_batch(
+    request: &Request<'_>,
+    received: &[u8],
+    config: &mut Config,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/batch" {
+        return Ok(None);
+    }
+
+    if request.method != Method::Post {
+        let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+        writer.header("Allow", "POST")?.end_head()?;
+        return Ok(Some(writer.len()));
+    }
+
+    let body = received
+        .get(request.body_offset..)
+        .and_then(|body| core::str::from_utf8(body).ok())
+        .ok_or(HttpError::Malformed)?;
+
+    let result = cli::run_batch(body, config);
+    let status = match result {
+        Ok(_) => Status::OK,
+        Err(_) => Status::BAD_REQUEST,
+    };
+
+    let mut writer = ResponseWriter::new(response, status)?;
+    writer.header("Content-Type", "text/plain")?.end_head()?;
+    match result {
+        Ok(count) => writeln!(writer, "Ran {count} commands."),
+        Err(error) => writeln!(writer, "{error}"),
+    }
+    .map_err(|_| HttpError::BufferTooSmall)?;
+    Ok(Some(writer.len()))
+}
+
+const JSON: &str = "application/json";
+
+/// Snapshot of the router served by `GET /api/status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouterStatus {
+    /// Seconds since boot.
+    pub uptime: u32,
+    pub lan_address: Ipv4Cidr,
+    /// Address on the active uplink, if it has one.
+    pub wan_address: Option<Ipv4Addr>,
+    pub leases: usize,
+    pub flows: usize,
+    pub flow_capacity: usize,
+}
+
+impl ToJson for RouterStatus {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        ObjectWriter::new(out)?
+            .member("uptime", self.uptime)?
+            .member("lan_address", self.lan_address)?
+            .member("wan_address", self.wan_address)?
+            .member("leases", self.leases)?
+            .member("flows", self.flows)?
+            .member("flow_capacity", self.flow_capacity)?
+            .end()
+    }
+}
+
+/// Serves `GET /api/status`.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_status(
+    request: &Request<'_>,
+    status: &RouterStatus,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/api/status" {
+        return Ok(None);
+    }
+
+    if !matches!(request.method, Method::Get | Method::Head) {
+        let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+        writer.header("Allow", "GET, HEAD")?.end_head()?;
+        return Ok(Some(writer.len()));
+    }
+
+    let mut writer = ResponseWriter::new(response, Status::OK)?;
+    writer.header("Content-Type", JSON)?.end_head()?;
+    if request.method == Method::Get {
+        writeln!(writer, "{}", Json(status)).map_err(|_| HttpError::BufferTooSmall)?;
+    }
+    Ok(Some(writer.len()))
+}
+
+impl ToJson for PoolUsage {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        ObjectWriter::new(out)?
+            .member("name", self.name)?
+            .member("used", self.used)?
+            .member("high_water", self.high_water)?
+            .member("capacity", self.capacity)?
+            .member("bytes", self.bytes)?
+            .end()
+    }
+}
+
+struct Pools<'a>(&'a [PoolUsage]);
+
+impl ToJson for Pools<'_> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        json::write_array(out, self.0)
+    }
+}
+
+impl ToJson for SystemInfo<'_> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        ObjectWriter::new(out)?
+            .member("name", sysinfo::NAME)?
+            .member("version", sysinfo::VERSION)?
+            .member("git_hash", sysinfo::GIT_HASH)?
+            .member("device_id", json::Text(self.unique_id))?
+            .member("uptime", self.uptime)?
+            .member("flash_used", self.flash_used)?
+            .member("flash_size", self.flash_size)?
+            .member("pool_bytes", self.pool_bytes())?
+            .member("pools", Pools(self.pools))?
+            .end()
+    }
+}
+
+/// Serves `GET /api/system`, the firmware version and device information.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_system(
+    request: &Request<'_>,
+    info: &SystemInfo<'_>,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/api/system" {
+        return Ok(None);
+    }
+
+    if !matches!(request.method, Method::Get | Method::Head) {
+        let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+        writer.header("Allow", "GET, HEAD")?.end_head()?;
+        return Ok(Some(writer.len()));
+    }
+
+    let mut writer = ResponseWriter::new(response, Status::OK)?;
+    writer.header("Content-Type", JSON)?.end_head()?;
+    if request.method == Method::Get {
+        writeln!(writer, "{}", Json(info)).map_err(|_| HttpError::BufferTooSmall)?;
+    }
+    Ok(Some(writer.len()))
+}
+
+struct LeaseRow<'a> {
+    lease: &'a Lease,
+    now: u32,
+}
+
+impl ToJson for LeaseRow<'_> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        ObjectWriter::new(out)?
+            .member("mac", self.lease.mac)?
+            .member("address", self.lease.address)?
+            .member("expires_in", self.lease.remaining(self.now))?
+            .end()
+    }
+}
+
+/// Serves `GET /api/leases`, an array of the DHCP leases streamed like
+/// [`serve_metrics`], the rest following with [`next_leases_chunk`].
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_leases<const N: usize>(
+    request: &Request<'_>,
+    leases: &Leases<N>,
+    now: u32,
+    stream: &mut ChunkedWriter,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/api/leases" {
+        return Ok(None);
+    }
+
+    let (head_len, body) = start_stream(request, JSON, stream, response)?;
+    if !body {
+        return Ok(Some(head_len));
+    }
+
+    let chunk = next_leases_chunk(leases, now, stream, &mut response[head_len..])?;
+    Ok(Some(head_len + chunk))
+}
+
+/// Writes the next chunk of a `/api/leases` body, returning its length, 0 once finished.
+pub fn next_leases_chunk<const N: usize>(
+    leases: &Leases<N>,
+    now: u32,
+    stream: &mut ChunkedWriter,
+    buffer: &mut [u8],
+) -> Result<usize, HttpError> {
+    let rows = json::array_rows(leases.iter().map(|lease| LeaseRow { lease, now }));
+    stream.next_chunk(rows.skip(stream.position()), buffer)
+}
+
+struct FlowRow<'a, T> {
+    flow: &'a Flow<T>,
+    now: u32,
+}
+
+impl<T> ToJson for FlowRow<'_, T> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        let key = &self.flow.key;
+        ObjectWriter::new(out)?
+            .member("protocol", key.protocol.name())?
+            .member("source", key.source)?
+            .member("destination", key.destination)?
+            .member("state", self.flow.state.name())?
+            .member("idle", self.now.wrapping_sub(self.flow.last_seen))?
+            .end()
+    }
+}
+
+/// Serves `GET /api/conntrack`, an array of the tracked flows streamed like
+/// [`serve_metrics`], the rest following with [`next_conntrack_chunk`].
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_conntrack<T, const N: usize>(
+    request: &Request<'_>,
+    conntrack: &Conntrack<T, N>,
+    now: u32,
+    stream: &mut ChunkedWriter,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/api/conntrack" {
+        return Ok(None);
+    }
+
+    let (head_len, body) = start_stream(request, JSON, stream, response)?;
+    if !body {
+        return Ok(Some(head_len));
+    }
+
+    let chunk = next_conntrack_chunk(conntrack, now, stream, &mut response[head_len..])?;
+    Ok(Some(head_len + chunk))
+}
+
+/// Writes the next chunk of a `/api/conntrack` body, returning its length, 0 once finished.
+pub fn next_conntrack_chunk<T, const N: usize>(
+    conntrack: &Conntrack<T, N>,
+    now: u32,
+    stream: &mut ChunkedWriter,
+    buffer: &mut [u8],
+) -> Result<usize, HttpError> {
+    let rows = json::array_rows(conntrack.iter().map(|flow| FlowRow { flow, now }));
+    stream.next_chunk(rows.skip(stream.position()), buffer)
+}
+
+struct ArpRow {
+    entry: ArpEntry,
+    now: u32,
+}
+
+impl ToJson for ArpRow {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        ObjectWriter::new(out)?
+            .member("address", self.entry.address)?
+            .member("mac", self.entry.mac)?
+            .member("type", self.entry.kind.name())?
+            .member("age", self.now.wrapping_sub(self.entry.updated_at))?
+            .end()
+    }
+}
+
+/// Serves `GET /api/arp`, an array of the ARP cache's entries streamed like
+/// [`serve_metrics`], the rest following with [`next_arp_chunk`].
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_arp<T, const N: usize, const Q: usize>(
+    request: &Request<'_>,
+    cache: &ArpCache<T, N, Q>,
+    now: u32,
+    stream: &mut ChunkedWriter,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/api/arp" {
+        return Ok(None);
+    }
+
+    let (head_len, body) = start_stream(request, JSON, stream, response)?;
+    if !body {
+        return Ok(Some(head_len));
+    }
+
+    let chunk = next_arp_chunk(cache, now, stream, &mut response[head_len..])?;
+    Ok(Some(head_len + chunk))
+}
+
+/// Writes the next chunk of a `/api/arp` body, returning its length, 0 once finished.
+pub fn next_arp_chunk<T, const N: usize, const Q: usize>(
+    cache: &ArpCache<T, N, Q>,
+    now: u32,
+    stream: &mut ChunkedWriter,
+    buffer: &mut [u8],
+) -> Result<usize, HttpError> {
+    let rows = json::array_rows(cache.entries().map(|entry| ArpRow { entry, now }));
+    stream.next_chunk(rows.skip(stream.position()), buffer)
+}
+
+/// Serves `/api/arp/<address>`: `PUT` pins the address to the MAC in the body, like
+/// `{"mac": "02:00:00:00:00:01"}`, `DELETE` removes its entry, static or not.
+///
+/// Packets that were waiting on the address are dropped, they'll be retransmitted.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_arp_entry<T, const N: usize, const Q: usize>(
+    request: &Request<'_>,
+    received: &[u8],
+    cache: &mut ArpCache<T, N, Q>,
+    now: u32,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    let Some(address) = request.path.strip_prefix("/api/arp/") else {
+        return Ok(None);
+    };
+    let Ok(address) = address.parse::<Ipv4Addr>() else {
+        let mut writer = ResponseWriter::new(response, Status::NOT_FOUND)?;
+        writer.end_head()?;
+        return Ok(Some(writer.len()));
+    };
+
+    let result = match request.method {
+        Method::Put => {
+            let body = received
+                .get(request.body_offset..)
+                .and_then(|body| core::str::from_utf8(body).ok())
+                .ok_or(HttpError::Malformed)?;
+
+            static_mac(body).and_then(|mac| {
+                cache
+                    .insert_static(address, mac, now)
+                    .map(|_| Status::OK)
+                    .map_err(|ArpError::TableFull| "cache is full of static entries")
+            })
+        }
+        Method::Delete if cache.remove(address) => Ok(Status::OK),
+        Method::Delete => Ok(Status::NOT_FOUND),
+        _ => {
+            let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+            writer.header("Allow", "PUT, DELETE")?.end_head()?;
+            return Ok(Some(writer.len()));
+        }
+    };
+
+    let error = match result {
+        Ok(status) => {
+            let mut writer = ResponseWriter::new(response, status)?;
+            writer.end_head()?;
+            return Ok(Some(writer.len()));
+        }
+        Err(error) => error,
+    };
+
+    let mut writer = ResponseWriter::new(response, Status::BAD_REQUEST)?;
+    writer.header("Content-Type", JSON)?.end_head()?;
+    ObjectWriter::new(&mut writer)
+        .and_then(|mut object| object.member("error", error)?.end())
+        .and_then(|()| writer.write_char('\n'))
+        .map_err(|_| HttpError::BufferTooSmall)?;
+    Ok(Some(writer.len()))
+}
+
+/// MAC of a static ARP entry in a request body.
+fn static_mac(body: &str) -> Result<MacAddress, &'static str> {
+    for member in json::members(body).map_err(|_| "malformed JSON")? {
+        match member.map_err(|_| "malformed JSON")? {
+            ("mac", json::Value::String(mac)) => return mac.parse().map_err(|_| "invalid MAC"),
+            ("mac", _) => return Err("invalid MAC"),
+            _ => {}
+        }
+    }
+    Err("missing MAC")
+}
+
+/// Why settings posted to `/api/config` were refused.
+enum ApiConfigError<'a> {
+    Json(JsonError),
+    Setting(&'a str, SetError),
+    Config(ConfigError),
+}
+
+impl ToJson for ApiConfigError<'_> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        let mut object = ObjectWriter::new(out)?;
+        match self {
+            ApiConfigError::Json(error) => object.member("error", json::Text(error))?,
+            ApiConfigError::Setting(key, SetError::UnknownKey) => object
+                .member("error", "unknown setting")?
+                .member("setting", *key)?,
+            ApiConfigError::Setting(key, SetError::InvalidValue) => object
+                .member("error", "invalid value")?
+                .member("setting", *key)?,
+            ApiConfigError::Config(error) => object.member("error", json::Text(error))?,
+        };
+        object.end()
+    }
+}
+
+/// Serves `POST /api/config`: the body is an object of settings keyed as in the text form,
+/// like `{"dhcp.lease_time": "12h", "wan.preempt": false}`. Booleans stand for `on` and `off`.
+///
+/// The settings are applied over the current configuration once all of them are valid,
+/// the rest of the configuration is kept.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_api_config(
+    request: &Request<'_>,
+    received: &[u8],
+    config: &mut Config,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/api/config" {
+        return Ok(None);
+    }
+
+    if request.method != Method::Post {
+        let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+        writer.header("Allow", "POST")?.end_head()?;
+        return Ok(Some(writer.len()));
+    }
+
+    let body = received
+        .get(request.body_offset..)
+        .and_then(|body| core::str::from_utf8(body).ok())
+        .ok_or(HttpError::Malformed)?;
+
+    let result = apply_settings(body, config);
+    let status = match result {
+        Ok(_) => Status::OK,
+        Err(_) => Status::BAD_REQUEST,
+    };
+
+    let mut writer = ResponseWriter::new(response, status)?;
+    writer.header("Content-Type", JSON)?.end_head()?;
+    match result {
+        Ok(applied) => ObjectWriter::new(&mut writer)
+            .and_then(|mut object| object.member("applied", applied)?.end()),
+        Err(error) => error.write_json(&mut writer),
+    }
+    .and_then(|()| writer.write_char('\n'))
+    .map_err(|_| HttpError::BufferTooSmall)?;
+    Ok(Some(writer.len()))
+}
+
+/// Applies the settings in `body` to a copy of `config`, replacing it once all of them are
+/// valid. Returns the number of settings applied.
+fn apply_settings<'a>(body: &'a str, config: &mut Config) -> Result<usize, ApiConfigError<'a>> {
+    let mut updated = config.clone();
+    let mut applied = 0;
+
+    for member in json::members(body).map_err(ApiConfigError::Json)? {
+        let (key, value) = member.map_err(ApiConfigError::Json)?;
+        let value = match value {
+            json::Value::String(value) | json::Value::Number(value) => value,
+            json::Value::Bool(true) => "on",
+            json::Value::Bool(false) => "off",
+            json::Value::Null | json::Value::Nested => {
+                return Err(ApiConfigError::Setting(key, SetError::InvalidValue));
+            }
+        };
+
+        updated
+            .set(key, value)
+            .map_err(|error| ApiConfigError::Setting(key, error))?;
+        applied += 1;
+    }
+
+    updated.validate().map_err(ApiConfigError::Config)?;
+    *config = updated;
+    Ok(applied)
+}
+
+/// Live feed of the event bus on `/events`, as server-sent events.
+///
+/// One browser at a time reads the feed through a subscription taken at boot, a new connection
+/// takes it over from the events published after it connected.
+pub struct EventStream {
+    subscriber: Subscriber,
+    /// Ticks without events after which a comment is sent, so idle connections aren't closed
+    /// by the browser or a proxy.
+    keepalive: u32,
+    /// Read from the bus but not written yet for lack of room, or the number of events missed.
+    pending: Option<Result<Event, u32>>,
+    last_write: u32,
+}
+
+impl EventStream {
+    pub fn new(subscriber: Subscriber, keepalive: u32) -> Self {
+        Self {
+            subscriber,
+            keepalive,
+            pending: None,
+            last_write: 0,
+        }
+    }
+}
+
+/// Serves `GET /events`, the events themselves following with [`next_events`] for as long as
+/// the connection stays open.
+///
+/// Returns `Ok(None)` for other paths so the caller can route them elsewhere.
+pub fn serve_events<const N: usize, const S: usize>(
+    request: &Request<'_>,
+    bus: &mut EventBus<N, S>,
+    stream: &mut EventStream,
+    now: u32,
+    response: &mut [u8],
+) -> Result<Option<usize>, HttpError> {
+    if request.path != "/events" {
+        return Ok(None);
+    }
+
+    if request.method != Method::Get {
+        let mut writer = ResponseWriter::new(response, Status::METHOD_NOT_ALLOWED)?;
+        writer.header("Allow", "GET")?.end_head()?;
+        return Ok(Some(writer.len()));
+    }
+
+    let mut writer = ResponseWriter::new(response, Status::OK)?;
+    writer
+        .header("Content-Type", "text/event-stream")?
+        .header("Cache-Control", "no-cache")?
+        .end_head()?;
+
+    bus.catch_up(stream.subscriber);
+    stream.pending = None;
+    stream.last_write = now;
+    Ok(Some(writer.len()))
+}
+
+/// Writes the events published since the last call, or a keepalive once the connection was
+/// idle for long enough, returning the length written.
+pub fn next_events<const N: usize, const S: usize>(
+    bus: &mut EventBus<N, S>,
+    stream: &mut EventStream,
+    now: u32,
+    buffer: &mut [u8],
+) -> Result<usize, HttpError> {
+    let mut written = 0;
+    loop {
+        let message = match stream.pending.take() {
+            Some(message) => message,
+            None => match bus.poll(stream.subscriber) {
+                Ok(Some(event)) => Ok(event),
+                Ok(None) => break,
+                Err(EventBusError::Lagged(missed)) => Err(missed),
+                Err(EventBusError::SubscribersOutOfMemory) => break,
+            },
+        };
+
+        let mut writer = ResponseWriter {
+            buffer: &mut buffer[written..],
+            len: 0,
+        };
+        let result = match message {
+            Ok(event) => write!(writer, "event: {}\ndata: {}\n\n", event.name(), Json(event)),
+            Err(missed) => {
+                write!(writer, "event: lagged\ndata: {{\"missed\":{missed}}}\n\n")
+            }
+        };
+        if result.is_err() {
+            stream.pending = Some(message);
+            if written == 0 {
+                // Not even a single event fits.
+                return Err(HttpError::BufferTooSmall);
+            }
+            break;
+        }
+        written += writer.len;
+    }
+
+    if written == 0 && now.wrapping_sub(stream.last_write) >= stream.keepalive {
+        let mut writer = ResponseWriter { buffer, len: 0 };
+        writer
+            .write_str(": keepalive\n\n")
+            .map_err(|_| HttpError::BufferTooSmall)?;
+        written = writer.len;
+    }
+
+    if written > 0 {
+        stream.last_write = now;
+    }
+    Ok(written)
+}
+
+/// An event's details as the data of a server-sent event.
+impl ToJson for Event {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        let mut object = ObjectWriter::new(out)?;
+        match self {
+            Event::InterfaceState(interface, state) => {
+                object
+                    .member("interface", interface.index())?
+                    .member("state", state.name())?;
+            }
+            Event::LeaseGranted { mac, address } => {
+                object.member("mac", mac)?.member("address", address)?;
+            }
+            Event::FirewallDrop(key) => {
+                object
+                    .member("protocol", key.protocol.name())?
+                    .member("source", key.source)?
+                    .member("destination", key.destination)?;
+            }
+            Event::LinkUp
+            | Event::LinkDown
+            | Event::LeaseAcquired
+            | Event::ConfigChanged
+            | Event::WanDown => {}
+        }
+        object.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::metrics::{Counter, Value};
+
+    #[test]
+    fn parses_the_request_line_and_headers() {
+        let bytes = b"PUT /config?dry=1 HTTP/1.1\r\nHost: router\r\ncontent-length: 5\r\n\r\nhello";
+        let request = Request::parse(bytes).unwrap();
+
+        assert_eq!(request.method, Method::Put);
+        assert_eq!(request.path, "/config");
+        assert_eq!(request.query, Some("dry=1"));
+        assert_eq!(request.header("Content-Length"), Some("5"));
+        assert_eq!(request.header("HOST"), Some("router"));
+        assert_eq!(request.header("Accept"), None);
+        assert_eq!(&bytes[request.body_offset..], b"hello");
+    }
+
+    #[test]
+    fn waits_for_the_end_of_the_head() {
+        assert_eq!(Request::parse(b""), Err(HttpError::Incomplete));
+        assert_eq!(
+            Request::parse(b"GET / HTTP/1.1\r\nHost: router\r\n"),
+            Err(HttpError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        for bytes in [
+            &b"GET /\r\n\r\n"[..],
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET / HTTP/2\r\n\r\n",
+            b"PATCH / HTTP/1.1\r\n\r\n",
+            b"get / HTTP/1.1\r\n\r\n",
+            b"GET /\xff HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(Request::parse(bytes), Err(HttpError::Malformed));
+        }
+    }
+
+    #[test]
+    fn response_must_fit_the_buffer() {
+        let mut buffer = [0; 128];
+        let mut writer = ResponseWriter::new(&mut buffer, Status::NOT_FOUND).unwrap();
+        writer.header("Content-Type", "text/plain").unwrap();
+        writer.end_head().unwrap();
+        let len = writer.len();
+        assert_eq!(
+            &buffer[..len],
+            b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n"
+        );
+
+        let mut buffer = [0; 16];
+        assert!(matches!(
+            ResponseWriter::new(&mut buffer, Status::OK),
+            Err(HttpError::BufferTooSmall)
+        ));
+
+        let mut buffer = [0; 24];
+        let mut writer = ResponseWriter::new(&mut buffer, Status::OK).unwrap();
+        assert!(matches!(
+            writer.header("Content-Type", "text/plain"),
+            Err(HttpError::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn chunks_rows_across_buffers() {
+        let rows = ["first\n", "second\n", "third\n"];
+        let mut stream = ChunkedWriter::new();
+        let mut body: heapless::Vec<u8, 128> = heapless::Vec::new();
+
+        // Room for the size line, two rows and the chunk's line break, but not the last chunk.
+        let mut buffer = [0; 6 + 13 + 2];
+        while !stream.is_finished() {
+            let rest = rows.get(stream.position()..).unwrap_or_default();
+            let len = stream.next_chunk(rest, &mut buffer).unwrap();
+            body.extend_from_slice(&buffer[..len]).unwrap();
+        }
+
+        assert_eq!(stream.position(), rows.len());
+        assert_eq!(
+            body.as_slice(),
+            b"000d\r\nfirst\nsecond\n\r\n0006\r\nthird\n\r\n0\r\n\r\n"
+        );
+        assert_eq!(stream.next_chunk(rows, &mut buffer), Ok(0));
+    }
+
+    #[test]
+    fn refuses_a_row_larger_than_the_buffer() {
+        let mut stream = ChunkedWriter::new();
+        let mut buffer = [0; 12];
+        assert_eq!(
+            stream.next_chunk(["too long for a chunk\n"], &mut buffer),
+            Err(HttpError::BufferTooSmall)
+        );
+        assert!(!stream.is_finished());
+    }
+
+    #[test]
+    fn metrics_are_only_served_to_get_and_head() {
+        let counter = Counter::new();
+        counter.add(3);
+        let metrics = [Metric {
+            name: "rx_frames",
+            help: "Frames received.",
+            value: Value::Counter(&counter),
+        }];
+        let mut stream = ChunkedWriter::new();
+        let mut response = [0; 256];
+
+        let request = Request::parse(b"GET /status HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            serve_metrics(&request, &metrics, &mut stream, &mut response),
+            Ok(None)
+        );
+
+        let request = Request::parse(b"POST /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let len = serve_metrics(&request, &metrics, &mut stream, &mut response)
+            .unwrap()
+            .unwrap();
+        assert!(response[..len].starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(stream.is_finished());
+
+        let request = Request::parse(b"HEAD /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let len = serve_metrics(&request, &metrics, &mut stream, &mut response)
+            .unwrap()
+            .unwrap();
+        assert!(response[..len].ends_with(b"\r\n\r\n"));
+        assert!(stream.is_finished());
+
+        let request = Request::parse(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let len = serve_metrics(&request, &metrics, &mut stream, &mut response)
+            .unwrap()
+            .unwrap();
+        let response = core::str::from_utf8(&response[..len]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.contains("rx_frames 3\n"));
+        assert!(response.ends_with("0\r\n\r\n"));
+        assert!(stream.is_finished());
+    }
+}
@@ -0,0 +1,223 @@
+//! ICMP timestamp (RFC 792) and address mask (RFC 950) requests addressed to the router.
+//!
+//! Both are obsolete, their only users nowadays are scanners: a timestamp reply gives away the
+//! router's clock, here its uptime, and an address mask reply the LAN's netmask. Rather than
+//! leaving them to whatever the stack does with unknown types, each gets an explicit action,
+//! reply or drop, and counters. Both are dropped by default.
+//!
+//! Replies to address mask requests are only correct where the router is authoritative for
+//! the mask (RFC 1122 3.2.2.9), the caller passes the mask of the interface the request came
+//! in on and should keep the WAN's to itself.
+
+use core::{fmt, net::Ipv4Addr, str::FromStr};
+
+use crate::checksum;
+
+pub mod icmp_type {
+    pub const TIMESTAMP: u8 = 13;
+    pub const TIMESTAMP_REPLY: u8 = 14;
+    pub const ADDRESS_MASK: u8 = 17;
+    pub const ADDRESS_MASK_REPLY: u8 = 18;
+}
+
+/// Type, code, checksum, identifier and sequence number.
+const HEADER_LEN: usize = 8;
+/// Originate, receive and transmit timestamps.
+const TIMESTAMP_LEN: usize = HEADER_LEN + 12;
+const ADDRESS_MASK_LEN: usize = HEADER_LEN + 4;
+
+/// Marks a timestamp that isn't milliseconds since midnight UT (RFC 792).
+const NON_STANDARD_TIME: u32 = 1 << 31;
+
+/// Kinds of requests with their own action and counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QueryKind {
+    Timestamp,
+    AddressMask,
+}
+
+impl QueryKind {
+    pub const COUNT: usize = 2;
+    pub const ALL: [QueryKind; Self::COUNT] = [QueryKind::Timestamp, QueryKind::AddressMask];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            QueryKind::Timestamp => "timestamp",
+            QueryKind::AddressMask => "address_mask",
+        }
+    }
+}
+
+impl fmt::Display for QueryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QueryAction {
+    Reply,
+    /// Drop the request silently, counting it.
+    Drop,
+}
+
+impl QueryAction {
+    const ALL: [QueryAction; 2] = [QueryAction::Reply, QueryAction::Drop];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            QueryAction::Reply => "reply",
+            QueryAction::Drop => "drop",
+        }
+    }
+}
+
+impl fmt::Display for QueryAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for QueryAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name() == s)
+            .ok_or(())
+    }
+}
+
+/// Action for each [`QueryKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryPolicy {
+    /// Indexed by [`QueryKind`].
+    pub actions: [QueryAction; QueryKind::COUNT],
+}
+
+impl Default for QueryPolicy {
+    fn default() -> Self {
+        Self::new(QueryAction::Drop, QueryAction::Drop)
+    }
+}
+
+impl QueryPolicy {
+    pub fn new(timestamp: QueryAction, address_mask: QueryAction) -> Self {
+        let mut actions = [QueryAction::Drop; QueryKind::COUNT];
+        actions[QueryKind::Timestamp as usize] = timestamp;
+        actions[QueryKind::AddressMask as usize] = address_mask;
+        Self { actions }
+    }
+
+    pub fn action(&self, kind: QueryKind) -> QueryAction {
+        self.actions[kind as usize]
+    }
+}
+
+/// Counters of the requests seen, each indexed by [`QueryKind`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QueryStats {
+    pub replied: [u32; QueryKind::COUNT],
+    pub dropped: [u32; QueryKind::COUNT],
+    /// Requests too short or with a bad checksum.
+    pub malformed: u32,
+}
+
+/// What to do with an ICMP message, see [`IcmpQueries::handle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QueryVerdict {
+    /// Not a timestamp or address mask request, left to the rest of the stack.
+    Other,
+    /// Send back the reply of this length.
+    Reply(usize),
+    /// Drop the request, the stats tell why.
+    Drop,
+}
+
+/// Applies a [`QueryPolicy`] to the requests addressed to the router.
+pub struct IcmpQueries {
+    policy: QueryPolicy,
+    stats: QueryStats,
+}
+
+impl IcmpQueries {
+    pub fn new(policy: QueryPolicy) -> Self {
+        Self {
+            policy,
+            stats: QueryStats::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &QueryPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: QueryPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        self.stats
+    }
+
+    /// Handles the ICMP message `request`, without its IP header, writing the reply to `reply`
+    /// if the policy says so.
+    ///
+    /// `uptime_ms` goes in the receive and transmit timestamps of timestamp replies, flagged as
+    /// not being UT as the router has no wall clock. `netmask` is the one of the interface the
+    /// request came in on, `None` where the router isn't authoritative for it.
+    pub fn handle(
+        &mut self,
+        request: &[u8],
+        uptime_ms: u32,
+        netmask: Option<Ipv4Addr>,
+        reply: &mut [u8],
+    ) -> QueryVerdict {
+        let (kind, len) = match request.first() {
+            Some(&icmp_type::TIMESTAMP) => (QueryKind::Timestamp, TIMESTAMP_LEN),
+            Some(&icmp_type::ADDRESS_MASK) => (QueryKind::AddressMask, ADDRESS_MASK_LEN),
+            _ => return QueryVerdict::Other,
+        };
+
+        let Some(request) = request
+            .get(..len)
+            .filter(|_| checksum::checksum(request) == 0)
+        else {
+            self.stats.malformed += 1;
+            return QueryVerdict::Drop;
+        };
+
+        let allowed = self.policy.action(kind) == QueryAction::Reply
+            && (kind != QueryKind::AddressMask || netmask.is_some());
+        let Some(reply) = reply.get_mut(..len).filter(|_| allowed) else {
+            self.stats.dropped[kind as usize] += 1;
+            return QueryVerdict::Drop;
+        };
+
+        reply.copy_from_slice(request);
+        match kind {
+            QueryKind::Timestamp => {
+                reply[0] = icmp_type::TIMESTAMP_REPLY;
+                let time = (uptime_ms | NON_STANDARD_TIME).to_be_bytes();
+                reply[12..16].copy_from_slice(&time);
+                reply[16..20].copy_from_slice(&time);
+            }
+            QueryKind::AddressMask => {
+                reply[0] = icmp_type::ADDRESS_MASK_REPLY;
+                reply[8..12].copy_from_slice(&netmask.unwrap_or(Ipv4Addr::UNSPECIFIED).octets());
+            }
+        }
+        reply[1] = 0;
+        reply[2..4].fill(0);
+        let sum = checksum::checksum(reply);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+
+        self.stats.replied[kind as usize] += 1;
+        QueryVerdict::Reply(len)
+    }
+}
@@ -0,0 +1,247 @@
+//! The router's identity on the LAN: its hostname, the LAN's domain and the search domains
+//! handed to clients.
+//!
+//! The router is known as `<hostname>.<domain>`, e.g. `router.lan`. The DNS forwarder answers
+//! that name from its local zone and doesn't forward names below the LAN domain upstream, and
+//! the DHCP server hands out the domain (option 15) and the search list (option 119, RFC 3397)
+//! so clients resolve bare names within it. Search domains provided by the WAN's DHCP server
+//! are decoded with [`SearchList::decode`] and handed out when none are configured.
+
+use core::{fmt, net::Ipv4Addr, str::FromStr};
+
+use crate::{
+    dhcp::{self, DhcpError, OptionsBuilder},
+    dns::{self, DnsError, LocalData, LocalRecords, Name},
+    text,
+};
+
+/// Longest label, and so hostname.
+pub const MAX_LABEL_LEN: usize = 63;
+/// Longest domain kept, plenty for a LAN's.
+pub const MAX_DOMAIN_LEN: usize = 96;
+/// Longest `<hostname>.<domain>`.
+pub const MAX_FQDN_LEN: usize = MAX_LABEL_LEN + 1 + MAX_DOMAIN_LEN;
+/// Search domains handed out besides the LAN domain.
+pub const MAX_SEARCH_DOMAINS: usize = 3;
+
+/// Whether `label` is a valid hostname label (RFC 1123): letters, digits and hyphens, not
+/// starting or ending with a hyphen.
+fn is_valid_label(label: &str) -> bool {
+    (1..=MAX_LABEL_LEN).contains(&label.len())
+        && label
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+}
+
+/// The router's name, a single label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hostname(heapless::String<MAX_LABEL_LEN>);
+
+impl Hostname {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Hostname {
+    fn default() -> Self {
+        "router".parse().unwrap()
+    }
+}
+
+impl fmt::Display for Hostname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Hostname {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !is_valid_label(s) {
+            return Err(());
+        }
+
+        heapless::String::try_from(s).map(Self).map_err(|_| ())
+    }
+}
+
+/// A domain name, without a trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Domain(heapless::String<MAX_DOMAIN_LEN>);
+
+impl Domain {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `name` is this domain or below it, ignoring ASCII case and a trailing dot.
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').as_bytes();
+        text::strip_suffix_ignore_case(name, self.0.as_bytes())
+            .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with(b"."))
+    }
+
+    /// Appends the domain in uncompressed wire format.
+    fn push_wire<const N: usize>(&self, out: &mut heapless::Vec<u8, N>) -> Result<(), ()> {
+        for label in self.0.split('.') {
+            out.push(label.len() as u8).map_err(|_| ())?;
+            out.extend_from_slice(label.as_bytes()).map_err(|_| ())?;
+        }
+        out.push(0).map_err(|_| ())
+    }
+}
+
+impl Default for Domain {
+    fn default() -> Self {
+        "lan".parse().unwrap()
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Domain {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_suffix('.').unwrap_or(s);
+        if !s.split('.').all(is_valid_label) {
+            return Err(());
+        }
+
+        heapless::String::try_from(s).map(Self).map_err(|_| ())
+    }
+}
+
+/// Search domains, in order. The text form separates them with commas, `none` if empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchList(heapless::Vec<Domain, MAX_SEARCH_DOMAINS>);
+
+impl SearchList {
+    pub const fn new() -> Self {
+        Self(heapless::Vec::new())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Domain> {
+        self.0.iter()
+    }
+
+    /// Decodes the payload of a received domain search option, whose names may be
+    /// compressed. Domains past [`MAX_SEARCH_DOMAINS`] or too long to keep are left out.
+    pub fn decode(data: &[u8]) -> Result<Self, DnsError> {
+        let mut list = Self::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let (name, next) = Name::parse(data, offset)?;
+            offset = next;
+
+            let mut text = heapless::String::<MAX_DOMAIN_LEN>::new();
+            let fits = name.labels().enumerate().all(|(index, label)| {
+                let label = core::str::from_utf8(label).unwrap_or("");
+                (index == 0 || text.push('.').is_ok()) && text.push_str(label).is_ok()
+            });
+            if let Some(domain) = fits.then(|| text.parse().ok()).flatten()
+                && !list.0.contains(&domain)
+            {
+                let _ = list.0.push(domain);
+            }
+        }
+
+        Ok(list)
+    }
+}
+
+impl fmt::Display for SearchList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none");
+        }
+
+        for (index, domain) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{domain}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SearchList {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut list = Self::new();
+        if s == "none" {
+            return Ok(list);
+        }
+
+        for domain in s.split(',') {
+            list.0.push(domain.trim().parse()?).map_err(|_| ())?;
+        }
+        Ok(list)
+    }
+}
+
+/// `<hostname>.<domain>`.
+pub fn fqdn(hostname: &Hostname, domain: &Domain) -> heapless::String<MAX_FQDN_LEN> {
+    let mut fqdn = heapless::String::new();
+    // Both fit by construction.
+    let _ = fqdn.push_str(hostname.as_str());
+    let _ = fqdn.push('.');
+    let _ = fqdn.push_str(domain.as_str());
+    fqdn
+}
+
+/// Adds the router's name and fully qualified name to the DNS local zone, at `address`.
+/// Call [`withdraw`] with the previous identity first when it changes.
+pub fn publish<const N: usize, const L: usize>(
+    records: &mut LocalRecords<N, L>,
+    hostname: &Hostname,
+    domain: &Domain,
+    address: Ipv4Addr,
+    ttl: u32,
+) -> Result<(), DnsError> {
+    records.insert(&fqdn(hostname, domain), LocalData::A(address), ttl)?;
+    records.insert(hostname.as_str(), LocalData::A(address), ttl)
+}
+
+/// Removes the router's addresses published by [`publish`] from the DNS local zone.
+pub fn withdraw<const N: usize, const L: usize>(
+    records: &mut LocalRecords<N, L>,
+    hostname: &Hostname,
+    domain: &Domain,
+) {
+    records.remove(&fqdn(hostname, domain), Some(dns::rtype::A));
+    records.remove(hostname.as_str(), Some(dns::rtype::A));
+}
+
+/// Appends the domain name option and the domain search option, the LAN domain first followed
+/// by the search domains, to a DHCP server's reply.
+pub fn append_dhcp_options(
+    builder: &mut OptionsBuilder<'_>,
+    domain: &Domain,
+    search: &SearchList,
+) -> Result<(), DhcpError> {
+    builder.string(dhcp::code::DOMAIN_NAME, domain.as_str())?;
+
+    let mut encoded = heapless::Vec::<u8, { u8::MAX as usize }>::new();
+    for domain in core::iter::once(domain).chain(search.iter()) {
+        domain
+            .push_wire(&mut encoded)
+            .map_err(|_| DhcpError::InvalidOption(dhcp::code::DOMAIN_SEARCH))?;
+    }
+    builder.raw(dhcp::code::DOMAIN_SEARCH, &encoded)?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! IGMPv2 (RFC 2236) querier.
+//!
+//! When no other router queries the LAN, snooping switches never learn which ports want which
+//! groups. The [`Querier`] takes the role until a querier with a lower address shows up, sending
+//! periodic general queries and group-specific queries when a member leaves.
+//!
+//! The default intervals assume one tick per second.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::{checksum, clock};
+
+const MEMBERSHIP_QUERY: u8 = 0x11;
+const V2_MEMBERSHIP_REPORT: u8 = 0x16;
+const LEAVE_GROUP: u8 = 0x17;
+
+/// Length of an IGMPv2 message.
+pub const MESSAGE_LEN: usize = 8;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IgmpError {
+    #[error("Message is too short.")]
+    Truncated,
+    #[error("Message checksum doesn't match.")]
+    BadChecksum,
+    #[error("Unknown message type {0}.")]
+    UnknownType(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// General query when `group` is unspecified, group-specific otherwise.
+    Query {
+        /// In tenths of a second.
+        max_response_time: u8,
+        group: Ipv4Addr,
+    },
+    Report {
+        group: Ipv4Addr,
+    },
+    Leave {
+        group: Ipv4Addr,
+    },
+}
+
+impl Message {
+    pub fn parse(data: &[u8]) -> Result<Self, IgmpError> {
+        let data = data.get(..MESSAGE_LEN).ok_or(IgmpError::Truncated)?;
+        if checksum::checksum(data) != 0 {
+            return Err(IgmpError::BadChecksum);
+        }
+
+        let group = Ipv4Addr::new(data[4], data[5], data[6], data[7]);
+        match data[0] {
+            MEMBERSHIP_QUERY => Ok(Message::Query {
+                max_response_time: data[1],
+                group,
+            }),
+            V2_MEMBERSHIP_REPORT => Ok(Message::Report { group }),
+            LEAVE_GROUP => Ok(Message::Leave { group }),
+            kind => Err(IgmpError::UnknownType(kind)),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; MESSAGE_LEN] {
+        let (kind, max_response_time, group) = match *self {
+            Message::Query {
+                max_response_time,
+                group,
+            } => (MEMBERSHIP_QUERY, max_response_time, group),
+            Message::Report { group } => (V2_MEMBERSHIP_REPORT, 0, group),
+            Message::Leave { group } => (LEAVE_GROUP, 0, group),
+        };
+
+        let [a, b, c, d] = group.octets();
+        let mut data = [kind, max_response_time, 0, 0, a, b, c, d];
+        let [high, low] = checksum::checksum(&data).to_be_bytes();
+        data[2] = high;
+        data[3] = low;
+        data
+    }
+}
+
+/// A query to send and where to send it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Query {
+    pub destination: Ipv4Addr,
+    pub message: Message,
+}
+
+/// Querier timing, see section 8 of RFC 2236.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuerierConfig {
+    pub query_interval: u32,
+    /// Max response time of general queries, in tenths of a second.
+    pub query_response_interval: u8,
+    /// Max response time of group-specific queries, in tenths of a second.
+    pub last_member_query_interval: u8,
+    /// Time without hearing a lower addressed querier before taking over again.
+    pub other_querier_present_interval: u32,
+    /// Queries sent at a shorter interval right after starting.
+    pub startup_query_count: u8,
+}
+
+impl Default for QuerierConfig {
+    fn default() -> Self {
+        Self {
+            query_interval: 125,
+            query_response_interval: 100,
+            last_member_query_interval: 10,
+            // Robustness variable * query interval + query response interval / 2.
+            other_querier_present_interval: 255,
+            startup_query_count: 2,
+        }
+    }
+}
+
+/// Querier election and query scheduling for one interface.
+pub struct Querier<const G: usize> {
+    config: QuerierConfig,
+    address: Ipv4Addr,
+    /// When another querier with a lower address was last heard from.
+    other_querier_seen_at: Option<u32>,
+    next_general_query_at: u32,
+    startup_queries_left: u8,
+    /// Groups a member left from, waiting for a group-specific query.
+    pending_leaves: heapless::Deque<Ipv4Addr, G>,
+}
+
+impl<const G: usize> Querier<G> {
+    /// Starts as querier for the interface with `address`, queueing up to `G` leaves.
+    pub fn new(config: QuerierConfig, address: Ipv4Addr, now: u32) -> Self {
+        Self {
+            config,
+            address,
+            other_querier_seen_at: None,
+            next_general_query_at: now,
+            startup_queries_left: config.startup_query_count,
+            pending_leaves: heapless::Deque::new(),
+        }
+    }
+
+    /// Whether this router is currently the querier.
+    pub fn is_querier(&self, now: u32) -> bool {
+        self.other_querier_seen_at.is_none_or(|seen_at| {
+            now.wrapping_sub(seen_at) >= self.config.other_querier_present_interval
+        })
+    }
+
+    /// Feeds a message received on the interface from `source`.
+    pub fn receive(&mut self, source: Ipv4Addr, message: Message, now: u32) {
+        match message {
+            // The querier with the lowest address wins the election.
+            Message::Query { .. } if source < self.address => {
+                self.other_querier_seen_at = Some(now);
+                self.pending_leaves.clear();
+            }
+            Message::Leave { group }
+                if self.is_querier(now) && !self.pending_leaves.iter().any(|g| *g == group) =>
+            {
+                // Worst case the members answer the next general query instead.
+                let _ = self.pending_leaves.push_back(group);
+            }
+            _ => {}
+        }
+    }
+
+    /// Next query to send, if any is due.
+    pub fn poll(&mut self, now: u32) -> Option<Query> {
+        if !self.is_querier(now) {
+            return None;
+        }
+
+        if self.other_querier_seen_at.take().is_some() {
+            // Taking over from a querier that went silent.
+            self.next_general_query_at = now;
+        }
+
+        if let Some(group) = self.pending_leaves.pop_front() {
+            return Some(Query {
+                destination: group,
+                message: Message::Query {
+                    max_response_time: self.config.last_member_query_interval,
+                    group,
+                },
+            });
+        }
+
+        if !clock::is_due(self.next_general_query_at, now) {
+            return None;
+        }
+
+        let interval = if self.startup_queries_left > 0 {
+            self.startup_queries_left -= 1;
+            self.config.query_interval / 4
+        } else {
+            self.config.query_interval
+        };
+        self.next_general_query_at = now.wrapping_add(interval);
+
+        Some(Query {
+            destination: Ipv4Addr::new(224, 0, 0, 1),
+            message: Message::Query {
+                max_response_time: self.config.query_response_interval,
+                group: Ipv4Addr::UNSPECIFIED,
+            },
+        })
+    }
+}
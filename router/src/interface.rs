@@ -0,0 +1,315 @@
+//! Registry of the network interfaces and their state.
+//!
+//! Interfaces are registered once at boot and referred to by their [`InterfaceId`] from then
+//! on, by the driver adapters, routes, services and events alike. The registry holds what the
+//! rest of the firmware needs to know about each: its MAC, address, MTU, role and counters.
+//!
+//! Each interface goes through admin down, no link, acquiring an address and up. Changes are
+//! published on the [`EventBus`] so services can follow the interfaces they run on instead of
+//! assuming they're always up.
+
+use core::fmt;
+
+use thiserror::Error;
+
+use crate::{
+    cidr::Ipv4Cidr,
+    ethernet::MacAddress,
+    events::{Event, EventBus},
+};
+
+/// Handle of a registered interface, its index in the [`Interfaces`] registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceId(u8);
+
+impl InterfaceId {
+    /// Id of the interface registered `index`th, for tables set up before the registry.
+    pub const fn new(index: u8) -> Self {
+        Self(index)
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for InterfaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What an interface is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InterfaceRole {
+    /// Uplink, with NAT and the firewall's inbound rules.
+    Wan,
+    Lan,
+    /// Port of the bridge, see [`crate::bridge`], without an address of its own.
+    BridgeMember {
+        port: u8,
+    },
+}
+
+/// Counters of the frames through an interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrafficStats {
+    pub rx_frames: u32,
+    pub rx_bytes: u32,
+    pub rx_dropped: u32,
+    pub tx_frames: u32,
+    pub tx_bytes: u32,
+    pub tx_dropped: u32,
+}
+
+impl TrafficStats {
+    pub fn received(&mut self, len: usize) {
+        self.rx_frames = self.rx_frames.wrapping_add(1);
+        self.rx_bytes = self.rx_bytes.wrapping_add(len as u32);
+    }
+
+    pub fn sent(&mut self, len: usize) {
+        self.tx_frames = self.tx_frames.wrapping_add(1);
+        self.tx_bytes = self.tx_bytes.wrapping_add(len as u32);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InterfaceState {
+    /// Disabled by the admin.
+    AdminDown,
+    /// Enabled but the cable is unplugged.
+    NoLink,
+    /// Link is up, waiting on DHCP, link-local or address conflict detection.
+    Acquiring,
+    Up,
+}
+
+impl InterfaceState {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            InterfaceState::AdminDown => "admin_down",
+            InterfaceState::NoLink => "no_link",
+            InterfaceState::Acquiring => "acquiring",
+            InterfaceState::Up => "up",
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InterfaceError {
+    #[error("Table ran out of memory for additional interfaces.")]
+    InterfacesOutOfMemory,
+    #[error("No interface is called that.")]
+    UnknownInterface,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interface {
+    pub name: &'static str,
+    pub role: InterfaceRole,
+    pub mac: MacAddress,
+    pub mtu: u16,
+    /// Address and subnet, once configured or acquired.
+    pub address: Option<Ipv4Cidr>,
+    pub stats: TrafficStats,
+    admin_up: bool,
+    link_up: bool,
+    addressed: bool,
+}
+
+impl Interface {
+    pub const DEFAULT_MTU: u16 = 1500;
+
+    /// Enabled interface waiting for its link.
+    pub const fn new(name: &'static str, role: InterfaceRole, mac: MacAddress) -> Self {
+        Self {
+            name,
+            role,
+            mac,
+            mtu: Self::DEFAULT_MTU,
+            address: None,
+            stats: TrafficStats {
+                rx_frames: 0,
+                rx_bytes: 0,
+                rx_dropped: 0,
+                tx_frames: 0,
+                tx_bytes: 0,
+                tx_dropped: 0,
+            },
+            admin_up: true,
+            link_up: false,
+            addressed: false,
+        }
+    }
+
+    pub fn state(&self) -> InterfaceState {
+        match (self.admin_up, self.link_up, self.addressed) {
+            (false, _, _) => InterfaceState::AdminDown,
+            (true, false, _) => InterfaceState::NoLink,
+            (true, true, false) => InterfaceState::Acquiring,
+            (true, true, true) => InterfaceState::Up,
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.state() == InterfaceState::Up
+    }
+}
+
+/// Up to `N` interfaces, identified by their [`InterfaceId`].
+pub struct Interfaces<const N: usize> {
+    interfaces: heapless::Vec<Interface, N>,
+}
+
+impl<const N: usize> Default for Interfaces<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Interfaces<N> {
+    pub const fn new() -> Self {
+        Self {
+            interfaces: heapless::Vec::new(),
+        }
+    }
+
+    /// Registers an interface, returning its id.
+    pub fn add(&mut self, interface: Interface) -> Result<InterfaceId, InterfaceError> {
+        self.interfaces
+            .push(interface)
+            .map_err(|_| InterfaceError::InterfacesOutOfMemory)?;
+        Ok(InterfaceId(self.interfaces.len() as u8 - 1))
+    }
+
+    pub fn get(&self, id: InterfaceId) -> Option<&Interface> {
+        self.interfaces.get(id.index())
+    }
+
+    /// Id of the interface called `name`.
+    pub fn find(&self, name: &str) -> Result<InterfaceId, InterfaceError> {
+        self.interfaces
+            .iter()
+            .position(|interface| interface.name == name)
+            .map(|index| InterfaceId(index as u8))
+            .ok_or(InterfaceError::UnknownInterface)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (InterfaceId, &Interface)> {
+        self.interfaces
+            .iter()
+            .enumerate()
+            .map(|(index, interface)| (InterfaceId(index as u8), interface))
+    }
+
+    /// Interface with `role`, like the single WAN interface.
+    pub fn with_role(&self, role: InterfaceRole) -> Option<InterfaceId> {
+        self.iter()
+            .find(|(_, interface)| interface.role == role)
+            .map(|(id, _)| id)
+    }
+
+    /// Counters of the interface, for its driver adapter to update.
+    pub fn stats_mut(&mut self, id: InterfaceId) -> Option<&mut TrafficStats> {
+        self.interfaces
+            .get_mut(id.index())
+            .map(|interface| &mut interface.stats)
+    }
+
+    /// Changes the MTU, e.g. from the configuration.
+    pub fn set_mtu(&mut self, id: InterfaceId, mtu: u16) -> Result<(), InterfaceError> {
+        let interface = self
+            .interfaces
+            .get_mut(id.index())
+            .ok_or(InterfaceError::UnknownInterface)?;
+        interface.mtu = mtu;
+        Ok(())
+    }
+
+    /// Binds an address to the interface or removes it. The interface is only up once the
+    /// address is confirmed with [`Self::set_addressed`].
+    pub fn set_address<const E: usize, const S: usize>(
+        &mut self,
+        id: InterfaceId,
+        address: Option<Ipv4Cidr>,
+        events: &mut EventBus<E, S>,
+    ) -> Result<(), InterfaceError> {
+        self.update(id, events, |interface| {
+            interface.address = address;
+            if address.is_none() {
+                interface.addressed = false;
+            }
+        })
+    }
+
+    /// Enables or disables an interface, e.g. for `interface wan down`.
+    pub fn set_admin<const E: usize, const S: usize>(
+        &mut self,
+        id: InterfaceId,
+        up: bool,
+        events: &mut EventBus<E, S>,
+    ) -> Result<(), InterfaceError> {
+        self.update(id, events, |interface| {
+            interface.admin_up = up;
+            if !up {
+                interface.addressed = false;
+            }
+        })
+    }
+
+    /// Records a link change reported by the PHY.
+    pub fn set_link<const E: usize, const S: usize>(
+        &mut self,
+        id: InterfaceId,
+        up: bool,
+        events: &mut EventBus<E, S>,
+    ) -> Result<(), InterfaceError> {
+        self.update(id, events, |interface| {
+            interface.link_up = up;
+            // The address has to be confirmed again on whatever network the cable now leads to.
+            if !up {
+                interface.addressed = false;
+            }
+        })
+    }
+
+    /// Records that an address got bound to, or removed from, the interface.
+    pub fn set_addressed<const E: usize, const S: usize>(
+        &mut self,
+        id: InterfaceId,
+        addressed: bool,
+        events: &mut EventBus<E, S>,
+    ) -> Result<(), InterfaceError> {
+        self.update(id, events, |interface| {
+            interface.addressed = addressed && interface.admin_up && interface.link_up;
+        })
+    }
+
+    /// Applies `f` to the interface, publishing its new state if it changed.
+    fn update<const E: usize, const S: usize>(
+        &mut self,
+        id: InterfaceId,
+        events: &mut EventBus<E, S>,
+        f: impl FnOnce(&mut Interface),
+    ) -> Result<(), InterfaceError> {
+        let interface = self
+            .interfaces
+            .get_mut(id.index())
+            .ok_or(InterfaceError::UnknownInterface)?;
+
+        let before = interface.state();
+        f(interface);
+        let after = interface.state();
+        if after != before {
+            events.publish(Event::InterfaceState(id, after));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! Port-scan and SYN-flood detection on the WAN.
+//!
+//! Every new connection attempt from a source is counted over a window, along with the distinct
+//! destination ports it touched. A source going over either threshold is blocked for a while.
+//!
+//! The default thresholds assume one tick per second.
+
+use core::net::Ipv4Addr;
+
+use crate::clock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectionConfig {
+    /// Length of the window connection attempts are counted over.
+    pub window: u32,
+    /// New connections a source may open per window, above this it's considered a flood.
+    pub max_new_connections: u32,
+    /// Distinct destination ports a source may touch per window, above this it's a scan.
+    pub max_distinct_ports: usize,
+    /// How long an offending source stays blocked.
+    pub block_duration: u32,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            max_new_connections: 100,
+            max_distinct_ports: 16,
+            block_duration: 600,
+        }
+    }
+}
+
+/// Why a source got blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlockReason {
+    Flood,
+    PortScan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// The source was already blocked.
+    Blocked,
+    /// The source crossed a threshold with this connection and is now blocked, worth logging.
+    NewlyBlocked(BlockReason),
+}
+
+struct Source<const P: usize> {
+    address: Ipv4Addr,
+    window_start: u32,
+    new_connections: u32,
+    ports: heapless::Vec<u16, P>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub address: Ipv4Addr,
+    pub reason: BlockReason,
+    pub expires_at: u32,
+}
+
+/// Tracks `S` sources, remembering up to `P` distinct ports each, and up to `B` blocked sources.
+pub struct ScanDetector<const S: usize, const P: usize, const B: usize> {
+    config: DetectionConfig,
+    sources: heapless::Vec<Source<P>, S>,
+    blocks: heapless::Vec<Block, B>,
+}
+
+impl<const S: usize, const P: usize, const B: usize> ScanDetector<S, P, B> {
+    pub fn new(config: DetectionConfig) -> Self {
+        Self {
+            config,
+            sources: heapless::Vec::new(),
+            blocks: heapless::Vec::new(),
+        }
+    }
+
+    pub fn is_blocked(&self, address: Ipv4Addr, now: u32) -> bool {
+        self.blocks
+            .iter()
+            .any(|block| block.address == address && !clock::is_due(block.expires_at, now))
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Accounts a new connection attempt from `source` to `port`.
+    pub fn observe_new_connection(&mut self, source: Ipv4Addr, port: u16, now: u32) -> Verdict {
+        if self.is_blocked(source, now) {
+            return Verdict::Blocked;
+        }
+
+        let config = self.config;
+        let index = match self.sources.iter().position(|s| s.address == source) {
+            Some(index) => index,
+            None => {
+                if self.sources.is_full() {
+                    // Forget the source whose window started the longest ago.
+                    let Some(oldest) = self
+                        .sources
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, s)| now.wrapping_sub(s.window_start))
+                        .map(|(i, _)| i)
+                    else {
+                        return Verdict::Allow;
+                    };
+                    self.sources.swap_remove(oldest);
+                }
+
+                let _ = self.sources.push(Source {
+                    address: source,
+                    window_start: now,
+                    new_connections: 0,
+                    ports: heapless::Vec::new(),
+                });
+                self.sources.len() - 1
+            }
+        };
+
+        let tracked = &mut self.sources[index];
+        if now.wrapping_sub(tracked.window_start) >= config.window {
+            tracked.window_start = now;
+            tracked.new_connections = 0;
+            tracked.ports.clear();
+        }
+
+        tracked.new_connections += 1;
+        if !tracked.ports.contains(&port) {
+            // Once P ports are remembered the source is over any sensible threshold anyway.
+            let _ = tracked.ports.push(port);
+        }
+
+        let reason = if tracked.ports.len() > config.max_distinct_ports {
+            BlockReason::PortScan
+        } else if tracked.new_connections > config.max_new_connections {
+            BlockReason::Flood
+        } else {
+            return Verdict::Allow;
+        };
+
+        self.sources.swap_remove(index);
+        self.block(source, reason, now);
+        Verdict::NewlyBlocked(reason)
+    }
+
+    /// Drops expired blocks.
+    pub fn expire(&mut self, now: u32) {
+        self.blocks
+            .retain(|block| !clock::is_due(block.expires_at, now));
+    }
+
+    fn block(&mut self, address: Ipv4Addr, reason: BlockReason, now: u32) {
+        self.expire(now);
+        let block = Block {
+            address,
+            reason,
+            expires_at: now.wrapping_add(self.config.block_duration),
+        };
+
+        if self.blocks.is_full() {
+            // Replace the block closest to expiring.
+            if let Some(soonest) = self
+                .blocks
+                .iter_mut()
+                .min_by_key(|block| block.expires_at.wrapping_sub(now))
+            {
+                *soonest = block;
+            }
+            return;
+        }
+
+        let _ = self.blocks.push(block);
+    }
+}
@@ -0,0 +1,275 @@
+//! IPv4 options on the forwarding path.
+//!
+//! Options are rare in legitimate traffic and some are dangerous to forward: source routing
+//! lets a sender pick the path its packets take, and the replies', around the firewall. Each
+//! kind of option gets an explicit action, by default source routed packets are dropped and
+//! anything else passes.
+//!
+//! Stripped options are overwritten with NOPs rather than removed, which keeps the header
+//! length and the rest of the packet in place; only the header checksum changes.
+
+use core::{fmt, str::FromStr};
+
+use crate::checksum;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const CHECKSUM_OFFSET: usize = 10;
+
+mod option_type {
+    pub const END: u8 = 0;
+    pub const NOP: u8 = 1;
+    pub const RECORD_ROUTE: u8 = 7;
+    pub const TIMESTAMP: u8 = 68;
+    pub const SECURITY: u8 = 130;
+    pub const LOOSE_SOURCE_ROUTE: u8 = 131;
+    pub const EXTENDED_SECURITY: u8 = 133;
+    pub const CIPSO: u8 = 134;
+    pub const STRICT_SOURCE_ROUTE: u8 = 137;
+    pub const ROUTER_ALERT: u8 = 148;
+}
+
+/// Kinds of options with their own action and counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OptionKind {
+    /// Loose and strict source routing.
+    SourceRoute,
+    RecordRoute,
+    Timestamp,
+    RouterAlert,
+    /// Basic, extended and CIPSO security labels.
+    Security,
+    /// Anything else, obsolete options like the stream identifier included.
+    Other,
+}
+
+impl OptionKind {
+    pub const COUNT: usize = 6;
+
+    fn of(option_type: u8) -> Self {
+        match option_type {
+            option_type::LOOSE_SOURCE_ROUTE | option_type::STRICT_SOURCE_ROUTE => {
+                OptionKind::SourceRoute
+            }
+            option_type::RECORD_ROUTE => OptionKind::RecordRoute,
+            option_type::TIMESTAMP => OptionKind::Timestamp,
+            option_type::ROUTER_ALERT => OptionKind::RouterAlert,
+            option_type::SECURITY | option_type::EXTENDED_SECURITY | option_type::CIPSO => {
+                OptionKind::Security
+            }
+            _ => OptionKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OptionAction {
+    /// Forward the option as is.
+    Pass,
+    /// Forward the packet without the option.
+    Strip,
+    /// Drop the packet.
+    Drop,
+}
+
+impl OptionAction {
+    const ALL: [OptionAction; 3] = [OptionAction::Pass, OptionAction::Strip, OptionAction::Drop];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            OptionAction::Pass => "pass",
+            OptionAction::Strip => "strip",
+            OptionAction::Drop => "drop",
+        }
+    }
+}
+
+impl fmt::Display for OptionAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OptionAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name() == s)
+            .ok_or(())
+    }
+}
+
+/// Action for each [`OptionKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionsPolicy {
+    /// Indexed by [`OptionKind`].
+    pub actions: [OptionAction; OptionKind::COUNT],
+}
+
+impl Default for OptionsPolicy {
+    fn default() -> Self {
+        Self::new(OptionAction::Drop, OptionAction::Pass)
+    }
+}
+
+impl OptionsPolicy {
+    /// Policy applying `source_route` to source routing options and `others` to the rest.
+    pub fn new(source_route: OptionAction, others: OptionAction) -> Self {
+        let mut actions = [others; OptionKind::COUNT];
+        actions[OptionKind::SourceRoute as usize] = source_route;
+        Self { actions }
+    }
+
+    pub fn action(&self, kind: OptionKind) -> OptionAction {
+        self.actions[kind as usize]
+    }
+}
+
+/// Why a packet was dropped for its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OptionsDrop {
+    /// The policy drops packets with this kind of option.
+    Policy(OptionKind),
+    /// An option's length runs past the header or is too short.
+    Malformed,
+}
+
+/// Counters of the options seen on forwarded packets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OptionsStats {
+    /// Options seen, indexed by [`OptionKind`].
+    pub seen: [u32; OptionKind::COUNT],
+    /// Options stripped, indexed by [`OptionKind`].
+    pub stripped: [u32; OptionKind::COUNT],
+    /// Packets dropped, indexed by the [`OptionKind`] that got them dropped.
+    pub dropped: [u32; OptionKind::COUNT],
+    pub malformed: u32,
+}
+
+/// Applies an [`OptionsPolicy`] to forwarded packets.
+pub struct OptionsFilter {
+    policy: OptionsPolicy,
+    stats: OptionsStats,
+}
+
+impl OptionsFilter {
+    pub fn new(policy: OptionsPolicy) -> Self {
+        Self {
+            policy,
+            stats: OptionsStats::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &OptionsPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: OptionsPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn stats(&self) -> OptionsStats {
+        self.stats
+    }
+
+    /// Applies the policy to the IPv4 packet starting at `packet`, stripping options in place.
+    ///
+    /// Packets without options are left alone without looking further than the IHL.
+    pub fn process(&mut self, packet: &mut [u8]) -> Result<(), OptionsDrop> {
+        let header_len = match packet.first() {
+            Some(first) => (first & 0x0f) as usize * 4,
+            None => return Ok(()),
+        };
+        if header_len <= IPV4_MIN_HEADER_LEN {
+            return Ok(());
+        }
+        let Some(header) = packet.get_mut(..header_len) else {
+            self.stats.malformed += 1;
+            return Err(OptionsDrop::Malformed);
+        };
+
+        // Decide on every option first, a packet that ends up dropped isn't modified.
+        let mut strip = false;
+        let mut drop = None;
+        for option in Options::new(&header[IPV4_MIN_HEADER_LEN..]) {
+            let Ok((offset, _)) = option else {
+                self.stats.malformed += 1;
+                return Err(OptionsDrop::Malformed);
+            };
+
+            let kind = OptionKind::of(header[IPV4_MIN_HEADER_LEN + offset]);
+            self.stats.seen[kind as usize] += 1;
+            match self.policy.action(kind) {
+                OptionAction::Pass => {}
+                OptionAction::Strip => strip = true,
+                OptionAction::Drop => drop = drop.or(Some(kind)),
+            }
+        }
+
+        if let Some(kind) = drop {
+            self.stats.dropped[kind as usize] += 1;
+            return Err(OptionsDrop::Policy(kind));
+        }
+        if !strip {
+            return Ok(());
+        }
+
+        let mut offset = IPV4_MIN_HEADER_LEN;
+        while let Some(Ok((start, len))) = Options::new(&header[offset..]).next() {
+            let start = offset + start;
+            let kind = OptionKind::of(header[start]);
+            if self.policy.action(kind) == OptionAction::Strip {
+                self.stats.stripped[kind as usize] += 1;
+                header[start..start + len].fill(option_type::NOP);
+            }
+            offset = start + len;
+        }
+
+        header[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 2].fill(0);
+        let sum = checksum::checksum(header);
+        header[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 2].copy_from_slice(&sum.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// Offset and length of each option in an options area, NOPs and the end marker skipped.
+struct Options<'a> {
+    options: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Options<'a> {
+    fn new(options: &'a [u8]) -> Self {
+        Self { options, offset: 0 }
+    }
+}
+
+impl Iterator for Options<'_> {
+    type Item = Result<(usize, usize), ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self.offset;
+            match *self.options.get(start)? {
+                option_type::END => return None,
+                option_type::NOP => self.offset += 1,
+                _ => {
+                    let len = self.options.get(start + 1).copied().unwrap_or(0) as usize;
+                    if len < 2 || start + len > self.options.len() {
+                        // Nothing after a malformed option can be trusted.
+                        self.offset = self.options.len();
+                        return Some(Err(()));
+                    }
+
+                    self.offset += len;
+                    return Some(Ok((start, len)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! JSON for the management APIs.
+//!
+//! Values are written straight into any [`Write`], an object or array at a time, without
+//! building a document first. Tables too large for a buffer are written a row at a time with
+//! [`array_rows`], which pairs with [`crate::http::ChunkedWriter`].
+//!
+//! The reader only handles what configuration uploads need: a flat object of settings. It's
+//! tolerant of whitespace and trailing commas, and leaves strings as they appear in the text,
+//! escapes included, since setting values never need any.
+
+use core::{
+    fmt::{self, Display, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+};
+
+use thiserror::Error;
+
+use crate::{cidr::Ipv4Cidr, ethernet::MacAddress};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JsonError {
+    #[error("Expected a JSON object.")]
+    NotAnObject,
+    #[error("JSON is malformed at byte {0}.")]
+    Malformed(usize),
+}
+
+/// A value that can be written as JSON.
+pub trait ToJson {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result;
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        (**self).write_json(out)
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        match self {
+            Some(value) => value.write_json(out),
+            None => out.write_str("null"),
+        }
+    }
+}
+
+impl ToJson for bool {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        out.write_str(if *self { "true" } else { "false" })
+    }
+}
+
+macro_rules! number_to_json {
+    ($($number:ty),*) => {
+        $(impl ToJson for $number {
+            fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+                write!(out, "{self}")
+            }
+        })*
+    };
+}
+
+number_to_json!(u8, u16, u32, u64, usize, i8, i16, i32, i64);
+
+impl ToJson for str {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        Text(self).write_json(out)
+    }
+}
+
+/// Addresses are written as strings in their usual notation.
+macro_rules! text_to_json {
+    ($($text:ty),*) => {
+        $(impl ToJson for $text {
+            fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+                Text(self).write_json(out)
+            }
+        })*
+    };
+}
+
+text_to_json!(Ipv4Addr, SocketAddrV4, Ipv4Cidr, MacAddress);
+
+/// Anything displayable, written as an escaped JSON string.
+pub struct Text<T>(pub T);
+
+impl<T: Display> ToJson for Text<T> {
+    fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        out.write_char('"')?;
+        write!(Escaper(out), "{}", self.0)?;
+        out.write_char('"')
+    }
+}
+
+struct Escaper<'w, W>(&'w mut W);
+
+impl<W: Write> Write for Escaper<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(index) = rest.find(|c: char| c == '"' || c == '\\' || c < ' ') {
+            self.0.write_str(&rest[..index])?;
+            match rest.as_bytes()[index] {
+                b'"' => self.0.write_str("\\\"")?,
+                b'\\' => self.0.write_str("\\\\")?,
+                b'\n' => self.0.write_str("\\n")?,
+                b'\r' => self.0.write_str("\\r")?,
+                b'\t' => self.0.write_str("\\t")?,
+                control => write!(self.0, "\\u{control:04x}")?,
+            }
+            rest = &rest[index + 1..];
+        }
+        self.0.write_str(rest)
+    }
+}
+
+/// Displays a value as JSON, e.g. to `write!` it.
+pub struct Json<T>(pub T);
+
+impl<T: ToJson> Display for Json<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write_json(f)
+    }
+}
+
+/// Writes an object's members, closing it on [`Self::end`].
+pub struct ObjectWriter<'w, W: Write> {
+    out: &'w mut W,
+    empty: bool,
+}
+
+impl<'w, W: Write> ObjectWriter<'w, W> {
+    pub fn new(out: &'w mut W) -> Result<Self, fmt::Error> {
+        out.write_char('{')?;
+        Ok(Self { out, empty: true })
+    }
+
+    pub fn member(&mut self, name: &str, value: impl ToJson) -> Result<&mut Self, fmt::Error> {
+        if !self.empty {
+            self.out.write_char(',')?;
+        }
+        self.empty = false;
+
+        name.write_json(self.out)?;
+        self.out.write_char(':')?;
+        value.write_json(self.out)?;
+        Ok(self)
+    }
+
+    pub fn end(&mut self) -> fmt::Result {
+        self.out.write_char('}')
+    }
+}
+
+/// Writes `items` as an array.
+pub fn write_array<W: Write>(
+    out: &mut W,
+    items: impl IntoIterator<Item = impl ToJson>,
+) -> fmt::Result {
+    out.write_char('[')?;
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            out.write_char(',')?;
+        }
+        item.write_json(out)?;
+    }
+    out.write_char(']')
+}
+
+/// A piece of an array written a row at a time.
+pub enum ArrayRow<T> {
+    Open,
+    Item { first: bool, value: T },
+    Close,
+}
+
+impl<T: ToJson> Display for ArrayRow<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrayRow::Open => f.write_char('['),
+            ArrayRow::Item { first, value } => {
+                if !first {
+                    f.write_char(',')?;
+                }
+                value.write_json(f)?;
+                f.write_char('\n')
+            }
+            ArrayRow::Close => f.write_str("]\n"),
+        }
+    }
+}
+
+/// Rows of an array of `items`: the opening bracket, one row per item and the closing bracket.
+pub fn array_rows<T: ToJson>(
+    items: impl IntoIterator<Item = T>,
+) -> impl Iterator<Item = ArrayRow<T>> {
+    let items = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| ArrayRow::Item {
+            first: index == 0,
+            value,
+        });
+
+    core::iter::once(ArrayRow::Open)
+        .chain(items)
+        .chain(core::iter::once(ArrayRow::Close))
+}
+
+/// A member's value in a parsed object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Value<'a> {
+    /// Contents between the quotes, escapes left as they are.
+    String(&'a str),
+    /// The number as written.
+    Number(&'a str),
+    Bool(bool),
+    Null,
+    /// An object or array, skipped over.
+    Nested,
+}
+
+/// Iterator over the members of an object, see [`members`].
+pub struct Members<'a> {
+    text: &'a str,
+    offset: usize,
+    done: bool,
+}
+
+/// Reads the members of the object in `text`, in order.
+pub fn members(text: &str) -> Result<Members<'_>, JsonError> {
+    let mut members = Members {
+        text,
+        offset: 0,
+        done: false,
+    };
+
+    members.skip_whitespace();
+    if !members.eat(b'{') {
+        return Err(JsonError::NotAnObject);
+    }
+    Ok(members)
+}
+
+impl<'a> Members<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.offset).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.offset += 1;
+        }
+        matched
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.offset += 1;
+        }
+    }
+
+    fn malformed(&mut self) -> JsonError {
+        self.done = true;
+        JsonError::Malformed(self.offset)
+    }
+
+    /// Contents of the string starting at the current offset.
+    fn string(&mut self) -> Result<&'a str, JsonError> {
+        if !self.eat(b'"') {
+            return Err(self.malformed());
+        }
+
+        let start = self.offset;
+        let mut escaped = false;
+        while let Some(byte) = self.peek() {
+            self.offset += 1;
+            match byte {
+                b'\\' if !escaped => escaped = true,
+                b'"' if !escaped => return Ok(&self.text[start..self.offset - 1]),
+                _ => escaped = false,
+            }
+        }
+        Err(self.malformed())
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, JsonError> {
+        match self.peek() {
+            Some(b'"') => return self.string().map(Value::String),
+            Some(b'{' | b'[') => return self.skip_nested().map(|()| Value::Nested),
+            _ => {}
+        }
+
+        let rest = &self.text[self.offset..];
+        for (name, value) in [
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("null", Value::Null),
+        ] {
+            if rest.starts_with(name) {
+                self.offset += name.len();
+                return Ok(value);
+            }
+        }
+
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.malformed());
+        }
+        self.offset += len;
+        Ok(Value::Number(&rest[..len]))
+    }
+
+    fn member(&mut self) -> Result<(&'a str, Value<'a>), JsonError> {
+        let name = self.string()?;
+        self.skip_whitespace();
+        if !self.eat(b':') {
+            return Err(self.malformed());
+        }
+        self.skip_whitespace();
+        let value = self.value()?;
+
+        self.skip_whitespace();
+        // A trailing comma before the closing brace is tolerated.
+        if !self.eat(b',') && self.peek() != Some(b'}') {
+            return Err(self.malformed());
+        }
+        Ok((name, value))
+    }
+
+    fn skip_nested(&mut self) -> Result<(), JsonError> {
+        let mut depth = 0usize;
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'"' => {
+                    self.string()?;
+                    continue;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.offset += 1;
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.offset += 1;
+        }
+        Err(self.malformed())
+    }
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = Result<(&'a str, Value<'a>), JsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            self.done = true;
+            return None;
+        }
+
+        Some(self.member())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(value: impl ToJson) -> heapless::String<128> {
+        let mut text = heapless::String::new();
+        write!(text, "{}", Json(value)).unwrap();
+        text
+    }
+
+    #[test]
+    fn escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(json("plain"), "\"plain\"");
+        assert_eq!(json("say \"hi\""), r#""say \"hi\"""#);
+        assert_eq!(json(r"C:\dir"), r#""C:\\dir""#);
+        assert_eq!(json("a\nb\r\tc"), r#""a\nb\r\tc""#);
+        assert_eq!(json("\0\u{1b}\u{1f}"), r#""\u0000\u001b\u001f""#);
+        assert_eq!(json("\"\""), r#""\"\"""#);
+        assert_eq!(json(""), "\"\"");
+    }
+
+    #[test]
+    fn leaves_other_characters_alone() {
+        assert_eq!(json("café / ☕ ~\u{7f}"), "\"café / ☕ ~\u{7f}\"");
+    }
+
+    #[test]
+    fn escapes_displayed_values() {
+        struct Pieces;
+
+        impl Display for Pieces {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                // An escape split across writes still comes out whole.
+                f.write_str("one\"")?;
+                f.write_str("\\two")?;
+                f.write_char('\n')
+            }
+        }
+
+        assert_eq!(json(Text(Pieces)), r#""one\"\\two\n""#);
+    }
+
+    #[test]
+    fn escapes_member_names_and_values() {
+        let mut text = heapless::String::<128>::new();
+        ObjectWriter::new(&mut text)
+            .unwrap()
+            .member("na\"me", "va\\lue")
+            .unwrap()
+            .member("count", 2u8)
+            .unwrap()
+            .end()
+            .unwrap();
+        assert_eq!(text, r#"{"na\"me":"va\\lue","count":2}"#);
+    }
+
+    #[test]
+    fn reader_keeps_escapes_as_written() {
+        let mut members = members(r#"{"name": "a \"quoted\" \\", "next": [1, "]"], }"#).unwrap();
+        assert_eq!(
+            members.next(),
+            Some(Ok(("name", Value::String(r#"a \"quoted\" \\"#))))
+        );
+        assert_eq!(members.next(), Some(Ok(("next", Value::Nested))));
+        assert_eq!(members.next(), None);
+    }
+
+    #[test]
+    fn reader_stops_at_an_unterminated_string() {
+        let mut members = members(r#"{"name": "open \""#).unwrap();
+        assert_eq!(members.next(), Some(Err(JsonError::Malformed(17))));
+        assert_eq!(members.next(), None);
+        assert!(matches!(super::members("[1]"), Err(JsonError::NotAnObject)));
+    }
+}
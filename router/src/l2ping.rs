@@ -0,0 +1,223 @@
+//! Link-layer ping between routers.
+//!
+//! Tests a link without any IP configuration: echo requests go to the target's MAC with the
+//! local experimental EtherType (IEEE 802 0x88B5), and a router running this firmware answers
+//! them, so a cable, a switch port or a VLAN can be checked before addresses are set up or
+//! when DHCP is what's broken.
+//!
+//! Echo messages start with a magic so other users of the experimental EtherType are ignored,
+//! followed by the message type, an identifier, a sequence number and the sender's timestamp,
+//! which the reply carries back so the turnaround is measured without keeping state per
+//! request.
+
+use crate::ethernet::MacAddress;
+
+/// IEEE 802 local experimental EtherType.
+pub const ETHERTYPE: u16 = 0x88B5;
+
+const MAGIC: [u8; 4] = *b"DIYR";
+const REQUEST: u8 = 1;
+const REPLY: u8 = 2;
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Magic, type, identifier, sequence number and timestamp.
+const MESSAGE_LEN: usize = 4 + 1 + 2 + 2 + 4;
+/// Frames are padded to the minimum size, without the CRC.
+pub const FRAME_LEN: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Echo {
+    reply: bool,
+    identifier: u16,
+    sequence: u16,
+    timestamp: u32,
+}
+
+impl Echo {
+    fn parse(frame: &[u8]) -> Option<(MacAddress, MacAddress, Self)> {
+        let header = frame.get(..ETHERNET_HEADER_LEN)?;
+        if header[12..14] != ETHERTYPE.to_be_bytes() {
+            return None;
+        }
+        let message = frame.get(ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + MESSAGE_LEN)?;
+        if message[..4] != MAGIC {
+            return None;
+        }
+
+        let reply = match message[4] {
+            REQUEST => false,
+            REPLY => true,
+            _ => return None,
+        };
+        let mut destination = [0; 6];
+        let mut source = [0; 6];
+        destination.copy_from_slice(&header[..6]);
+        source.copy_from_slice(&header[6..12]);
+
+        Some((
+            MacAddress(destination),
+            MacAddress(source),
+            Self {
+                reply,
+                identifier: u16::from_be_bytes([message[5], message[6]]),
+                sequence: u16::from_be_bytes([message[7], message[8]]),
+                timestamp: u32::from_be_bytes([message[9], message[10], message[11], message[12]]),
+            },
+        ))
+    }
+
+    fn write(
+        &self,
+        destination: MacAddress,
+        source: MacAddress,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        let frame = buffer.get_mut(..FRAME_LEN)?;
+        frame.fill(0);
+        frame[..6].copy_from_slice(&destination.0);
+        frame[6..12].copy_from_slice(&source.0);
+        frame[12..14].copy_from_slice(&ETHERTYPE.to_be_bytes());
+
+        let message = &mut frame[ETHERNET_HEADER_LEN..];
+        message[..4].copy_from_slice(&MAGIC);
+        message[4] = if self.reply { REPLY } else { REQUEST };
+        message[5..7].copy_from_slice(&self.identifier.to_be_bytes());
+        message[7..9].copy_from_slice(&self.sequence.to_be_bytes());
+        message[9..13].copy_from_slice(&self.timestamp.to_be_bytes());
+        Some(FRAME_LEN)
+    }
+}
+
+/// Writes the reply to an echo request received by `own_mac`, returning its length, or `None`
+/// if `frame` isn't an echo request for it or `buffer` is too small.
+pub fn answer(frame: &[u8], own_mac: MacAddress, buffer: &mut [u8]) -> Option<usize> {
+    let (destination, source, echo) = Echo::parse(frame)?;
+    if echo.reply || destination != own_mac {
+        return None;
+    }
+
+    Echo {
+        reply: true,
+        ..echo
+    }
+    .write(source, own_mac, buffer)
+}
+
+/// Outcome of a ping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PingResult {
+    Reply {
+        sequence: u16,
+        turnaround: u32,
+    },
+    /// No reply within the timeout.
+    Timeout {
+        sequence: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PingStats {
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    /// Shortest and longest turnarounds, 0 until a reply arrived.
+    pub min_turnaround: u32,
+    pub max_turnaround: u32,
+}
+
+/// Pings a single target, one request at a time.
+pub struct L2Pinger {
+    target: MacAddress,
+    own_mac: MacAddress,
+    identifier: u16,
+    timeout: u32,
+    sequence: u16,
+    /// Sequence number and send time of the request awaiting a reply.
+    outstanding: Option<(u16, u32)>,
+    stats: PingStats,
+}
+
+impl L2Pinger {
+    /// Pinger of `target` from `own_mac`. `identifier` tells apart concurrent pingers.
+    pub fn new(target: MacAddress, own_mac: MacAddress, identifier: u16, timeout: u32) -> Self {
+        Self {
+            target,
+            own_mac,
+            identifier,
+            timeout,
+            sequence: 0,
+            outstanding: None,
+            stats: PingStats::default(),
+        }
+    }
+
+    pub fn target(&self) -> MacAddress {
+        self.target
+    }
+
+    pub fn stats(&self) -> PingStats {
+        self.stats
+    }
+
+    /// Writes the next request into `buffer`, returning its length, or `None` while a reply
+    /// is still awaited or if `buffer` is too small.
+    pub fn request(&mut self, now: u32, buffer: &mut [u8]) -> Option<usize> {
+        if self.outstanding.is_some() {
+            return None;
+        }
+
+        let len = Echo {
+            reply: false,
+            identifier: self.identifier,
+            sequence: self.sequence,
+            timestamp: now,
+        }
+        .write(self.target, self.own_mac, buffer)?;
+
+        self.outstanding = Some((self.sequence, now));
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stats.sent += 1;
+        Some(len)
+    }
+
+    /// Takes a received frame, returning the result if it's the reply awaited.
+    pub fn receive(&mut self, frame: &[u8], now: u32) -> Option<PingResult> {
+        let (destination, source, echo) = Echo::parse(frame)?;
+        let (sequence, _) = self.outstanding?;
+        if !echo.reply
+            || destination != self.own_mac
+            || source != self.target
+            || echo.identifier != self.identifier
+            || echo.sequence != sequence
+        {
+            return None;
+        }
+
+        self.outstanding = None;
+        let turnaround = now.wrapping_sub(echo.timestamp);
+        if self.stats.received == 0 {
+            self.stats.min_turnaround = turnaround;
+        }
+        self.stats.received += 1;
+        self.stats.min_turnaround = self.stats.min_turnaround.min(turnaround);
+        self.stats.max_turnaround = self.stats.max_turnaround.max(turnaround);
+        Some(PingResult::Reply {
+            sequence,
+            turnaround,
+        })
+    }
+
+    /// Gives up on the awaited reply once the timeout passed.
+    pub fn poll(&mut self, now: u32) -> Option<PingResult> {
+        let (sequence, sent_at) = self.outstanding?;
+        if now.wrapping_sub(sent_at) < self.timeout {
+            return None;
+        }
+
+        self.outstanding = None;
+        self.stats.lost += 1;
+        Some(PingResult::Timeout { sequence })
+    }
+}
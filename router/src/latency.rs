@@ -0,0 +1,107 @@
+//! Forwarding latency of frames.
+//!
+//! A frame is stamped when its RX status vector is read, and the latency is recorded when its
+//! forwarded copy is handed to TX. Latencies go in a histogram with power of two buckets so the
+//! effect of changes to the packet path (interrupts, DMA) can be compared.
+//!
+//! Timestamps come from whatever tick source the caller uses, e.g. SysTick.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+
+/// Number of histogram buckets, the last one collecting everything above.
+pub const BUCKETS: usize = 16;
+
+/// Tick at which a frame's RX status vector was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxTimestamp(pub u32);
+
+/// Histogram of latencies, bucket `i` counting latencies below `2^i` ticks not counted in a
+/// lower bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub buckets: [u32; BUCKETS],
+    pub min: u32,
+    pub max: u32,
+    pub total: u64,
+    pub count: u32,
+}
+
+impl LatencyHistogram {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            min: u32::MAX,
+            max: 0,
+            total: 0,
+            count: 0,
+        }
+    }
+
+    pub fn record(&mut self, ticks: u32) {
+        let bucket = (u32::BITS - ticks.leading_zeros()) as usize;
+        let bucket = &mut self.buckets[bucket.min(BUCKETS - 1)];
+        *bucket = bucket.saturating_add(1);
+
+        self.min = self.min.min(ticks);
+        self.max = self.max.max(ticks);
+        self.total = self.total.saturating_add(ticks as u64);
+        self.count = self.count.saturating_add(1);
+    }
+
+    /// Average latency, `None` if nothing was recorded.
+    pub fn avg(&self) -> Option<u32> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some((self.total / self.count as u64) as u32)
+    }
+
+    /// Smallest latency at least `percent` of the frames were forwarded within, as the upper
+    /// bound of the bucket it falls in.
+    pub fn percentile(&self, percent: u8) -> Option<u32> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let wanted = (self.count as u64 * percent.min(100) as u64).div_ceil(100);
+        let mut seen = 0;
+        let index = self.buckets.iter().position(|count| {
+            seen += *count as u64;
+            seen >= wanted
+        })?;
+
+        Some(if index == BUCKETS - 1 {
+            self.max
+        } else {
+            (1u32 << index) - 1
+        })
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static HISTOGRAM: Mutex<RefCell<LatencyHistogram>> =
+    Mutex::new(RefCell::new(LatencyHistogram::new()));
+
+/// Records a frame stamped `rx` whose forwarded copy was handed to TX at `tx_at`.
+pub fn record_forwarded(rx: RxTimestamp, tx_at: u32) {
+    let ticks = tx_at.wrapping_sub(rx.0);
+    cortex_m::interrupt::free(|cs| HISTOGRAM.borrow(cs).borrow_mut().record(ticks));
+}
+
+/// Snapshot of the forwarding latencies recorded so far.
+pub fn histogram() -> LatencyHistogram {
+    cortex_m::interrupt::free(|cs| *HISTOGRAM.borrow(cs).borrow())
+}
+
+pub fn reset() {
+    cortex_m::interrupt::free(|cs| *HISTOGRAM.borrow(cs).borrow_mut() = LatencyHistogram::new());
+}
@@ -0,0 +1,290 @@
+//! DHCP server lease table.
+//!
+//! Binds client hardware addresses to pool addresses until the lease expires. Expired leases
+//! are kept until their slot is needed, so a returning client can get its previous address
+//! back.
+//!
+//! Lease times are usually given in seconds.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::{clock, ethernet::MacAddress};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LeaseError {
+    #[error("Lease table is full.")]
+    TableFull,
+    #[error("Address is leased to another client.")]
+    AddressInUse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub mac: MacAddress,
+    pub address: Ipv4Addr,
+    pub expires_at: u32,
+}
+
+impl Lease {
+    pub fn is_expired(&self, now: u32) -> bool {
+        clock::is_due(self.expires_at, now)
+    }
+
+    /// Ticks until the lease expires, zero once it has.
+    pub fn remaining(&self, now: u32) -> u32 {
+        if self.is_expired(now) {
+            0
+        } else {
+            self.expires_at.wrapping_sub(now)
+        }
+    }
+}
+
+/// Table of up to `N` leases.
+pub struct Leases<const N: usize> {
+    leases: heapless::Vec<Lease, N>,
+    high_water: usize,
+}
+
+impl<const N: usize> Default for Leases<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Leases<N> {
+    pub const fn new() -> Self {
+        Self {
+            leases: heapless::Vec::new(),
+            high_water: 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Lease> {
+        self.leases.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leases.is_empty()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Most leases held at once, expired ones included.
+    pub fn high_water(&self) -> usize {
+        self.high_water
+    }
+
+    /// Lease of `mac`, expired or not.
+    pub fn by_mac(&self, mac: MacAddress) -> Option<&Lease> {
+        self.leases.iter().find(|lease| lease.mac == mac)
+    }
+
+    /// Lease of `address`, expired or not.
+    pub fn by_address(&self, address: Ipv4Addr) -> Option<&Lease> {
+        self.leases.iter().find(|lease| lease.address == address)
+    }
+
+    /// Whether `address` is held by a lease that hasn't expired.
+    pub fn is_leased(&self, address: Ipv4Addr, now: u32) -> bool {
+        self.by_address(address)
+            .is_some_and(|lease| !lease.is_expired(now))
+    }
+
+    /// Leases `address` to `mac` for `duration` ticks, replacing the client's previous lease.
+    ///
+    /// When the table is full the lease that expired longest ago makes room.
+    pub fn insert(
+        &mut self,
+        mac: MacAddress,
+        address: Ipv4Addr,
+        duration: u32,
+        now: u32,
+    ) -> Result<(), LeaseError> {
+        if self
+            .by_address(address)
+            .is_some_and(|lease| lease.mac != mac && !lease.is_expired(now))
+        {
+            return Err(LeaseError::AddressInUse);
+        }
+
+        // Stale leases of the address or the client would shadow the new one.
+        self.leases
+            .retain(|lease| lease.mac != mac && lease.address != address);
+
+        if self.leases.is_full() {
+            let oldest = self
+                .leases
+                .iter()
+                .enumerate()
+                .filter(|(_, lease)| lease.is_expired(now))
+                .max_by_key(|(_, lease)| now.wrapping_sub(lease.expires_at))
+                .map(|(i, _)| i)
+                .ok_or(LeaseError::TableFull)?;
+            self.leases.swap_remove(oldest);
+        }
+
+        let _ = self.leases.push(Lease {
+            mac,
+            address,
+            expires_at: now.wrapping_add(duration),
+        });
+        self.high_water = self.high_water.max(self.leases.len());
+        Ok(())
+    }
+
+    /// Ends the lease of `mac` early, on a DHCPRELEASE.
+    pub fn release(&mut self, mac: MacAddress, now: u32) {
+        if let Some(lease) = self.leases.iter_mut().find(|lease| lease.mac == mac) {
+            lease.expires_at = now;
+        }
+    }
+
+    pub fn remove(&mut self, mac: MacAddress) {
+        self.leases.retain(|lease| lease.mac != mac);
+    }
+
+    /// Keeps only the leases for which `f` returns true.
+    pub fn retain(&mut self, f: impl FnMut(&Lease) -> bool) {
+        self.leases.retain(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    const LEASE_TIME: u32 = 3600;
+
+    fn mac(last: u8) -> MacAddress {
+        MacAddress([0x02, 0, 0, 0, 0, last])
+    }
+
+    fn address(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 168, 1, last)
+    }
+
+    #[test]
+    fn lease_expires_after_its_duration() {
+        let clock = MockClock::new(500);
+        let mut leases = Leases::<4>::new();
+        leases
+            .insert(mac(1), address(10), LEASE_TIME, clock.now())
+            .unwrap();
+
+        clock.advance(LEASE_TIME - 1);
+        let lease = *leases.by_mac(mac(1)).unwrap();
+        assert!(!lease.is_expired(clock.now()));
+        assert_eq!(lease.remaining(clock.now()), 1);
+        assert!(leases.is_leased(address(10), clock.now()));
+
+        clock.advance(1);
+        assert!(lease.is_expired(clock.now()));
+        assert_eq!(lease.remaining(clock.now()), 0);
+        assert!(!leases.is_leased(address(10), clock.now()));
+        // Kept for the client to come back to.
+        assert_eq!(leases.by_mac(mac(1)), Some(&lease));
+    }
+
+    #[test]
+    fn address_is_free_for_others_once_expired() {
+        let clock = MockClock::new(0);
+        let mut leases = Leases::<4>::new();
+        leases
+            .insert(mac(1), address(10), LEASE_TIME, clock.now())
+            .unwrap();
+
+        clock.advance(LEASE_TIME / 2);
+        assert_eq!(
+            leases.insert(mac(2), address(10), LEASE_TIME, clock.now()),
+            Err(LeaseError::AddressInUse)
+        );
+        // Renewing by the holder is fine.
+        leases
+            .insert(mac(1), address(10), LEASE_TIME, clock.now())
+            .unwrap();
+
+        clock.advance(LEASE_TIME);
+        leases
+            .insert(mac(2), address(10), LEASE_TIME, clock.now())
+            .unwrap();
+        assert_eq!(leases.by_address(address(10)).unwrap().mac, mac(2));
+        assert_eq!(leases.by_mac(mac(1)), None);
+    }
+
+    #[test]
+    fn release_expires_now() {
+        let clock = MockClock::new(0);
+        let mut leases = Leases::<4>::new();
+        leases
+            .insert(mac(1), address(10), LEASE_TIME, clock.now())
+            .unwrap();
+
+        clock.advance(10);
+        leases.release(mac(1), clock.now());
+        assert!(!leases.is_leased(address(10), clock.now()));
+    }
+
+    #[test]
+    fn full_table_makes_room_from_the_longest_expired() {
+        let clock = MockClock::new(0);
+        let mut leases = Leases::<2>::new();
+        leases
+            .insert(mac(1), address(10), 100, clock.now())
+            .unwrap();
+        leases
+            .insert(mac(2), address(11), 200, clock.now())
+            .unwrap();
+
+        clock.advance(150);
+        // Only the first one expired.
+        leases
+            .insert(mac(3), address(12), 100, clock.now())
+            .unwrap();
+        assert_eq!(leases.by_mac(mac(1)), None);
+        assert!(leases.by_mac(mac(2)).is_some());
+
+        assert_eq!(
+            leases.insert(mac(4), address(13), 100, clock.now()),
+            Err(LeaseError::TableFull)
+        );
+
+        clock.advance(200);
+        // Both expired, the second one longer ago.
+        leases
+            .insert(mac(4), address(13), 100, clock.now())
+            .unwrap();
+        assert_eq!(leases.by_mac(mac(2)), None);
+        assert!(leases.by_mac(mac(3)).is_some());
+        assert_eq!(leases.high_water(), 2);
+    }
+
+    #[test]
+    fn expiry_wraps_with_the_clock() {
+        let clock = MockClock::new(u32::MAX - 10);
+        let mut leases = Leases::<4>::new();
+        leases
+            .insert(mac(1), address(10), LEASE_TIME, clock.now())
+            .unwrap();
+
+        clock.advance(20);
+        assert!(leases.is_leased(address(10), clock.now()));
+        assert_eq!(
+            leases.by_mac(mac(1)).unwrap().remaining(clock.now()),
+            LEASE_TIME - 20
+        );
+
+        clock.advance(LEASE_TIME);
+        assert!(!leases.is_leased(address(10), clock.now()));
+    }
+}
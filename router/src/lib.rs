@@ -0,0 +1,73 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod announce;
+pub mod arp;
+pub mod auth;
+pub mod bootp;
+pub mod bridge;
+pub mod bringup;
+pub mod captive;
+pub mod checksum;
+pub mod cidr;
+pub mod cli;
+pub mod clock;
+pub mod coalesce;
+pub mod config;
+pub mod conntrack;
+pub mod dad;
+pub mod dhcp;
+pub mod dns;
+pub mod dnstcp;
+pub mod enc28j60;
+pub mod error;
+pub mod ethernet;
+pub mod events;
+pub mod firewall;
+pub mod format;
+pub mod frame;
+pub mod ftp;
+pub mod guest;
+pub mod http;
+pub mod icmp;
+pub mod identity;
+pub mod igmp;
+pub mod interface;
+pub mod intrusion;
+pub mod ipopts;
+pub mod json;
+pub mod l2ping;
+pub mod latency;
+pub mod lease;
+pub mod linklocal;
+pub mod log;
+pub mod metrics;
+pub mod nat;
+pub mod peek;
+pub mod persist;
+pub mod phypower;
+pub mod pressure;
+pub mod profile;
+pub mod profiling;
+pub mod ratelimit;
+pub mod rawsock;
+pub mod reconfig;
+pub mod reset;
+pub mod routing;
+pub mod rxcsum;
+pub mod rxhooks;
+pub mod rxstrict;
+pub mod sched;
+pub mod services;
+pub mod sha256;
+pub mod sip;
+pub mod starvation;
+pub mod storm;
+pub mod supervisor;
+pub mod sync;
+pub mod sysinfo;
+pub mod tcp;
+pub mod text;
+pub mod timer;
+pub mod trace;
+pub mod txqueue;
+pub mod wan;
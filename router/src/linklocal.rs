@@ -0,0 +1,134 @@
+//! IPv4 link-local address autoconfiguration (RFC 3927).
+//!
+//! Used when DHCP fails so the device stays reachable for management. A pseudo-random address
+//! in 169.254.1.0 - 169.254.254.255 is claimed through [`AddressClaim`]; on conflict another one
+//! is picked.
+//!
+//! The default timings assume one tick per second.
+
+use core::net::Ipv4Addr;
+
+use crate::{
+    arp::ArpPacket,
+    dad::{AddressClaim, ProbeConfig},
+    ethernet::MacAddress,
+};
+
+/// Protocol timings, RFC 3927 section 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkLocalConfig {
+    pub probe: ProbeConfig,
+    /// Conflicts after which probing slows down to one address every `rate_limit_interval`.
+    pub max_conflicts: u32,
+    pub rate_limit_interval: u32,
+}
+
+impl Default for LinkLocalConfig {
+    fn default() -> Self {
+        Self {
+            probe: ProbeConfig::default(),
+            max_conflicts: 10,
+            rate_limit_interval: 60,
+        }
+    }
+}
+
+pub struct LinkLocal {
+    config: LinkLocalConfig,
+    mac: MacAddress,
+    /// Claim on the current candidate, `None` while disabled.
+    claim: Option<AddressClaim>,
+    conflicts: u32,
+    /// xorshift32 state.
+    random: u32,
+}
+
+impl LinkLocal {
+    pub fn new(config: LinkLocalConfig, mac: MacAddress) -> Self {
+        // Seeded from the MAC so hosts pick different addresses yet each host tends to get
+        // the same one across reboots, as the RFC recommends.
+        let seed = mac.0.iter().fold(0x811c_9dc5u32, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+        });
+
+        Self {
+            config,
+            mac,
+            claim: None,
+            conflicts: 0,
+            random: seed.max(1),
+        }
+    }
+
+    /// Claim on the address being acquired or in use.
+    pub fn claim(&self) -> Option<&AddressClaim> {
+        self.claim.as_ref()
+    }
+
+    /// The address once it's safe to use.
+    pub fn address(&self) -> Option<Ipv4Addr> {
+        self.claim
+            .as_ref()
+            .filter(|claim| claim.is_usable())
+            .map(AddressClaim::address)
+    }
+
+    /// Starts acquiring an address, e.g. after DHCP failed.
+    pub fn start(&mut self, now: u32) {
+        if self.claim.is_none() {
+            self.conflicts = 0;
+            self.pick_address(now);
+        }
+    }
+
+    /// Stops using link-local addressing, e.g. once DHCP got a lease.
+    pub fn stop(&mut self) {
+        self.claim = None;
+    }
+
+    /// ARP packet to send now, if any.
+    pub fn poll(&mut self, now: u32) -> Option<ArpPacket> {
+        self.claim.as_mut()?.poll(now)
+    }
+
+    /// Checks a received ARP packet for conflicts, returning an announcement to send when the
+    /// address gets defended.
+    pub fn handle_arp(&mut self, packet: &ArpPacket, now: u32) -> Option<ArpPacket> {
+        let claim = self.claim.as_mut()?;
+        let defense = claim.handle_arp(packet, now);
+        if claim.is_conflict() {
+            self.conflicts = self.conflicts.saturating_add(1);
+            self.pick_address(now);
+        }
+
+        defense
+    }
+
+    fn pick_address(&mut self, now: u32) {
+        // 169.254.1.0 through 169.254.254.255.
+        let offset = self.next_random() % (254 * 256 - 256);
+        let address = Ipv4Addr::from(u32::from(Ipv4Addr::new(169, 254, 1, 0)) + offset);
+
+        let start_at = if self.conflicts >= self.config.max_conflicts {
+            now.wrapping_add(self.config.rate_limit_interval)
+        } else {
+            now
+        };
+
+        self.claim = Some(AddressClaim::new(
+            self.config.probe,
+            self.mac,
+            address,
+            start_at,
+        ));
+    }
+
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.random;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random = x;
+        x
+    }
+}
@@ -0,0 +1,161 @@
+//! Log levels adjustable at runtime per subsystem.
+//!
+//! Levels live in atomics so the [`log!`](crate::log!) macro can check them from anywhere,
+//! interrupt handlers included, before formatting anything. Messages go out over defmt, without
+//! the `defmt` feature they're dropped after the level check.
+
+use core::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Verbosity, each level including the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 6] = [
+        Level::Off,
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|level| level.name() == s)
+            .ok_or(())
+    }
+}
+
+/// Subsystems with their own log level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Module {
+    Driver,
+    Arp,
+    Dhcp,
+    Nat,
+    Firewall,
+}
+
+impl Module {
+    pub const COUNT: usize = 5;
+    pub const ALL: [Module; Self::COUNT] = [
+        Module::Driver,
+        Module::Arp,
+        Module::Dhcp,
+        Module::Nat,
+        Module::Firewall,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Module::Driver => "driver",
+            Module::Arp => "arp",
+            Module::Dhcp => "dhcp",
+            Module::Nat => "nat",
+            Module::Firewall => "firewall",
+        }
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Module {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|module| module.name() == s)
+            .ok_or(())
+    }
+}
+
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+static LEVELS: [AtomicU8; Module::COUNT] =
+    [const { AtomicU8::new(DEFAULT_LEVEL as u8) }; Module::COUNT];
+
+pub fn level(module: Module) -> Level {
+    Level::ALL[LEVELS[module as usize].load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_level(module: Module, level: Level) {
+    LEVELS[module as usize].store(level as u8, Ordering::Relaxed);
+}
+
+/// Sets every module's level, e.g. from the stored configuration on boot.
+pub fn set_levels(levels: &[Level; Module::COUNT]) {
+    for (module, level) in Module::ALL.into_iter().zip(levels) {
+        set_level(module, *level);
+    }
+}
+
+/// Whether messages of `level` from `module` are logged.
+pub fn enabled(module: Module, level: Level) -> bool {
+    level != Level::Off && level <= self::level(module)
+}
+
+/// Logs a defmt formatted message if `module` is at `level` or more verbose.
+///
+/// ```ignore
+/// log!(Module::Arp, Level::Debug, "resolved {}", address.octets());
+/// ```
+#[macro_export]
+macro_rules! log {
+    ($module:expr, $level:expr, $format:literal $(, $arg:expr)* $(,)?) => {{
+        let level = $level;
+        if $crate::log::enabled($module, level) {
+            #[cfg(feature = "defmt")]
+            match level {
+                $crate::log::Level::Off => {}
+                $crate::log::Level::Error => defmt::error!($format $(, $arg)*),
+                $crate::log::Level::Warn => defmt::warn!($format $(, $arg)*),
+                $crate::log::Level::Info => defmt::info!($format $(, $arg)*),
+                $crate::log::Level::Debug => defmt::debug!($format $(, $arg)*),
+                $crate::log::Level::Trace => defmt::trace!($format $(, $arg)*),
+            }
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($(&$arg,)*);
+        }
+    }};
+}
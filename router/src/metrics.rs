@@ -0,0 +1,300 @@
+//! Counters, gauges and fixed-bucket histograms shared by the driver, the stack and services.
+//!
+//! Metrics are plain atomics, so they can live in statics and be updated from interrupt
+//! handlers without a critical section. Subsystems list theirs as [`Metric`]s, which
+//! [`write_plain`] renders for the CLI and status page and [`write_prometheus`] for scraping.
+//!
+//! Raw counters say little about what's happening now, [`Rates`] keeps periodic samples of some
+//! of them to show their rates over the last few seconds instead.
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicI32, AtomicU32, Ordering},
+};
+
+/// Monotonically increasing count, wrapping on overflow.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU32);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u32) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value going up and down, like a queue depth.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI32);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Self(AtomicI32::new(0))
+    }
+
+    pub fn set(&self, value: i32) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Histogram with `B` buckets of ascending upper bounds plus one for larger values.
+///
+/// Observations update several atomics independently, a reader racing a writer may see a
+/// count one off from the buckets; fine for monitoring.
+#[derive(Debug)]
+pub struct Histogram<const B: usize> {
+    bounds: [u32; B],
+    buckets: [AtomicU32; B],
+    overflow: AtomicU32,
+    sum: AtomicU32,
+    count: AtomicU32,
+}
+
+impl<const B: usize> Histogram<B> {
+    /// `bounds` are the inclusive upper bounds of the buckets, in ascending order.
+    pub const fn new(bounds: [u32; B]) -> Self {
+        Self {
+            bounds,
+            buckets: [const { AtomicU32::new(0) }; B],
+            overflow: AtomicU32::new(0),
+            sum: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u32) {
+        match self.bounds.iter().position(|bound| value <= *bound) {
+            Some(index) => self.buckets[index].fetch_add(1, Ordering::Relaxed),
+            None => self.overflow.fetch_add(1, Ordering::Relaxed),
+        };
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for bucket in self
+            .buckets
+            .iter()
+            .chain([&self.overflow, &self.sum, &self.count])
+        {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Read access to a histogram independent of its bucket count.
+pub trait HistogramSource: Sync {
+    /// Upper bound and observation count of bucket `index`, `None` past the last one.
+    fn bucket(&self, index: usize) -> Option<(u32, u32)>;
+    /// Observations above the last bound.
+    fn overflow(&self) -> u32;
+    /// Sum of all observations, wrapping on overflow.
+    fn sum(&self) -> u32;
+    fn count(&self) -> u32;
+}
+
+impl<const B: usize> HistogramSource for Histogram<B> {
+    fn bucket(&self, index: usize) -> Option<(u32, u32)> {
+        Some((
+            *self.bounds.get(index)?,
+            self.buckets[index].load(Ordering::Relaxed),
+        ))
+    }
+
+    fn overflow(&self) -> u32 {
+        self.overflow.load(Ordering::Relaxed)
+    }
+
+    fn sum(&self) -> u32 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Value<'a> {
+    Counter(&'a Counter),
+    Gauge(&'a Gauge),
+    Histogram(&'a dyn HistogramSource),
+}
+
+/// A named metric to export.
+#[derive(Clone, Copy)]
+pub struct Metric<'a> {
+    /// Lowercase with underscores, e.g. `enc28j60_rx_frames`.
+    pub name: &'static str,
+    pub help: &'static str,
+    pub value: Value<'a>,
+}
+
+/// Writes one `name value` line per metric, histograms as `name{le=bound} count` lines with
+/// cumulative counts.
+pub fn write_plain(out: &mut impl Write, metrics: &[Metric<'_>]) -> fmt::Result {
+    for metric in metrics {
+        let name = metric.name;
+        match metric.value {
+            Value::Counter(counter) => writeln!(out, "{name} {}", counter.get())?,
+            Value::Gauge(gauge) => writeln!(out, "{name} {}", gauge.get())?,
+            Value::Histogram(histogram) => {
+                let mut cumulative = 0u32;
+                for (bound, count) in (0..).map_while(|i| histogram.bucket(i)) {
+                    cumulative = cumulative.wrapping_add(count);
+                    writeln!(out, "{name}{{le={bound}}} {cumulative}")?;
+                }
+                writeln!(out, "{name}{{le=inf}} {}", histogram.count())?;
+                writeln!(out, "{name}_sum {}", histogram.sum())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A metric in the Prometheus text format, as a row of a streamed body.
+pub struct Prometheus<'a, 'b>(pub &'b Metric<'a>);
+
+impl fmt::Display for Prometheus<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_prometheus(f, core::slice::from_ref(self.0))
+    }
+}
+
+/// Writes the metrics in the Prometheus text exposition format (version 0.0.4).
+pub fn write_prometheus(out: &mut impl Write, metrics: &[Metric<'_>]) -> fmt::Result {
+    for metric in metrics {
+        let name = metric.name;
+        let kind = match metric.value {
+            Value::Counter(_) => "counter",
+            Value::Gauge(_) => "gauge",
+            Value::Histogram(_) => "histogram",
+        };
+        writeln!(out, "# HELP {name} {}", metric.help)?;
+        writeln!(out, "# TYPE {name} {kind}")?;
+
+        match metric.value {
+            Value::Counter(counter) => writeln!(out, "{name} {}", counter.get())?,
+            Value::Gauge(gauge) => writeln!(out, "{name} {}", gauge.get())?,
+            Value::Histogram(histogram) => {
+                let mut cumulative = 0u32;
+                for (bound, count) in (0..).map_while(|i| histogram.bucket(i)) {
+                    cumulative = cumulative.wrapping_add(count);
+                    writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}")?;
+                }
+                writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", histogram.count())?;
+                writeln!(out, "{name}_sum {}", histogram.sum())?;
+                writeln!(out, "{name}_count {}", histogram.count())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Windows, in seconds, rates are shown over by [`Rates::write`].
+pub const RATE_WINDOWS: [u32; 3] = [1, 10, 60];
+
+#[derive(Debug, Clone, Copy)]
+struct Sample<const C: usize> {
+    taken_at: u32,
+    values: [u32; C],
+}
+
+/// Ring of the last `S` samples of `C` counters, taken about once per second, to compute their
+/// rates without floating point.
+///
+/// With one sample per second, `S` has to be at least one more than the longest window.
+pub struct Rates<'a, const C: usize, const S: usize> {
+    counters: [(&'static str, &'a Counter); C],
+    ticks_per_second: u32,
+    samples: [Sample<C>; S],
+    /// Samples taken, the next one goes to `taken % S`.
+    taken: usize,
+}
+
+impl<'a, const C: usize, const S: usize> Rates<'a, C, S> {
+    /// Tracks `counters` by name, like `enc28j60_rx_frames`, for a clock of `ticks_per_second`.
+    pub fn new(counters: [(&'static str, &'a Counter); C], ticks_per_second: u32) -> Self {
+        Self {
+            counters,
+            ticks_per_second,
+            samples: [Sample {
+                taken_at: 0,
+                values: [0; C],
+            }; S],
+            taken: 0,
+        }
+    }
+
+    /// Samples every counter, call it about once per second.
+    pub fn sample(&mut self, now: u32) {
+        self.samples[self.taken % S] = Sample {
+            taken_at: now,
+            values: self.counters.map(|(_, counter)| counter.get()),
+        };
+        self.taken += 1;
+    }
+
+    /// Rate per second of counter `index` over the last `seconds`, `None` until there are
+    /// samples that far apart.
+    pub fn rate(&self, index: usize, seconds: u32) -> Option<u32> {
+        let latest = self.sample_back(0)?;
+        let window = seconds.saturating_mul(self.ticks_per_second);
+
+        // Most recent sample at least a window older than the latest.
+        let earlier = (1..S.min(self.taken))
+            .filter_map(|back| self.sample_back(back))
+            .find(|sample| latest.taken_at.wrapping_sub(sample.taken_at) >= window)?;
+
+        let elapsed = latest.taken_at.wrapping_sub(earlier.taken_at);
+        let delta = latest.values[index].wrapping_sub(earlier.values[index]);
+        let rate = delta as u64 * self.ticks_per_second as u64 / (elapsed as u64).max(1);
+        Some(rate.min(u32::MAX as u64) as u32)
+    }
+
+    /// Writes one `name rate rate rate` line per counter with its rates per second over
+    /// [`RATE_WINDOWS`], `-` where there aren't enough samples yet.
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        for (index, (name, _)) in self.counters.iter().enumerate() {
+            write!(out, "{name}")?;
+            for seconds in RATE_WINDOWS {
+                match self.rate(index, seconds) {
+                    Some(rate) => write!(out, " {rate}/s")?,
+                    None => out.write_str(" -")?,
+                }
+            }
+            out.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// Sample taken `back` samples before the latest.
+    fn sample_back(&self, back: usize) -> Option<&Sample<C>> {
+        (back < self.taken.min(S)).then(|| &self.samples[(self.taken - 1 - back) % S])
+    }
+}
@@ -0,0 +1,439 @@
+//! NAT hairpinning.
+//!
+//! A LAN client reaching a port-forwarded service through the WAN address sends packets that
+//! never leave the router. Their destination is rewritten to the forwarded LAN host, as for
+//! packets from the WAN, and their source to the router's LAN address so the host answers
+//! through the router: answering the client directly, from an address the client never talked
+//! to, would get the reply dropped. Replies get both rewrites undone.
+//!
+//! Rewrites patch the IPv4 header and TCP or UDP checksums incrementally. Hairpinning is off
+//! unless the `nat.hairpin` setting is on.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::{
+    checksum,
+    cidr::Ipv4Cidr,
+    conntrack::{FlowKey, Protocol},
+    profiling::{self, Stage},
+};
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV4_CHECKSUM: usize = 10;
+const IPV4_SOURCE: usize = 12;
+const IPV4_DESTINATION: usize = 16;
+
+mod protocol_number {
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NatError {
+    #[error("Packet is too short for its headers.")]
+    Truncated,
+    #[error("Packet isn't the first fragment of a TCP or UDP over IPv4 packet.")]
+    Unsupported,
+}
+
+/// Service on the LAN reachable at a port of the WAN address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortForward {
+    pub protocol: Protocol,
+    pub wan_port: u16,
+    pub lan: SocketAddrV4,
+}
+
+/// Rewrite of the packets of a flow, in the direction they flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub from: FlowKey,
+    pub to: FlowKey,
+}
+
+impl Translation {
+    /// Rewrite of the packets flowing back, undoing this one.
+    pub fn reply(&self) -> Self {
+        Self {
+            from: self.to.reversed(),
+            to: self.from.reversed(),
+        }
+    }
+}
+
+/// Decides which packets are hairpinned.
+#[derive(Debug, Clone, Copy)]
+pub struct Hairpin<'a> {
+    pub enabled: bool,
+    /// Router's LAN address and subnet.
+    pub lan: Ipv4Cidr,
+    pub wan: Ipv4Addr,
+    pub forwards: &'a [PortForward],
+}
+
+impl Hairpin<'_> {
+    /// Translation of a packet from the LAN to a forwarded port of the WAN address, `None` for
+    /// any other packet or when hairpinning is off.
+    ///
+    /// The client's port is kept, the caller checks the translated flow isn't taken in its
+    /// connection tracking as for any other mapping.
+    pub fn translation(&self, packet: &[u8]) -> Option<Translation> {
+        profiling::measure(Stage::NatLookup, || self.find_translation(packet))
+    }
+
+    fn find_translation(&self, packet: &[u8]) -> Option<Translation> {
+        if !self.enabled {
+            return None;
+        }
+
+        let from = flow_key(packet).ok()?;
+        let client = *from.source.ip();
+        if !self.lan.contains(client)
+            || client == self.lan.address()
+            || *from.destination.ip() != self.wan
+        {
+            return None;
+        }
+
+        let forward = self.forwards.iter().find(|forward| {
+            forward.protocol == from.protocol && forward.wan_port == from.destination.port()
+        })?;
+
+        Some(Translation {
+            from,
+            to: FlowKey {
+                protocol: from.protocol,
+                source: SocketAddrV4::new(self.lan.address(), from.source.port()),
+                destination: forward.lan,
+            },
+        })
+    }
+}
+
+/// Transport header of an IPv4 packet, with its protocol and the offset of its checksum.
+fn transport(packet: &[u8]) -> Result<(Protocol, usize, usize), NatError> {
+    let first = *packet.first().ok_or(NatError::Truncated)?;
+    let header_len = usize::from(first & 0x0F) * 4;
+    if first >> 4 != 4 || header_len < IPV4_MIN_HEADER_LEN {
+        return Err(NatError::Unsupported);
+    }
+    if packet.len() < header_len {
+        return Err(NatError::Truncated);
+    }
+
+    // Later fragments carry no ports.
+    let fragment_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1FFF;
+    if fragment_offset != 0 {
+        return Err(NatError::Unsupported);
+    }
+
+    let (protocol, checksum, min_len) = match packet[9] {
+        protocol_number::TCP => (Protocol::Tcp, 16, 20),
+        protocol_number::UDP => (Protocol::Udp, 6, 8),
+        _ => return Err(NatError::Unsupported),
+    };
+    if packet.len() < header_len + min_len {
+        return Err(NatError::Truncated);
+    }
+
+    Ok((protocol, header_len, header_len + checksum))
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([packet[offset], packet[offset + 1]])
+}
+
+fn read_address(packet: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(
+        packet[offset],
+        packet[offset + 1],
+        packet[offset + 2],
+        packet[offset + 3],
+    )
+}
+
+/// Flow of a TCP or UDP over IPv4 packet.
+pub fn flow_key(packet: &[u8]) -> Result<FlowKey, NatError> {
+    let (protocol, header_len, _) = transport(packet)?;
+
+    Ok(FlowKey {
+        protocol,
+        source: SocketAddrV4::new(
+            read_address(packet, IPV4_SOURCE),
+            read_u16(packet, header_len),
+        ),
+        destination: SocketAddrV4::new(
+            read_address(packet, IPV4_DESTINATION),
+            read_u16(packet, header_len + 2),
+        ),
+    })
+}
+
+/// Rewrites the addresses and ports of `packet` to those of `to`, patching the checksums.
+pub fn translate(packet: &mut [u8], to: &FlowKey) -> Result<(), NatError> {
+    let (protocol, header_len, checksum_offset) = transport(packet)?;
+    if protocol != to.protocol {
+        return Err(NatError::Unsupported);
+    }
+
+    let fields = [
+        (IPV4_SOURCE, to.source.ip().to_bits()),
+        (IPV4_DESTINATION, to.destination.ip().to_bits()),
+    ];
+    let ports = [
+        (header_len, to.source.port()),
+        (header_len + 2, to.destination.port()),
+    ];
+
+    let mut header_checksum = read_u16(packet, IPV4_CHECKSUM);
+    let mut transport_checksum = read_u16(packet, checksum_offset);
+    for (offset, new) in fields {
+        let old = read_address(packet, offset).to_bits();
+        header_checksum = checksum::update_u32(header_checksum, old, new);
+        // Addresses are part of the pseudo header.
+        transport_checksum = checksum::update_u32(transport_checksum, old, new);
+        packet[offset..offset + 4].copy_from_slice(&new.to_be_bytes());
+    }
+    for (offset, new) in ports {
+        transport_checksum =
+            checksum::update_u16(transport_checksum, read_u16(packet, offset), new);
+        packet[offset..offset + 2].copy_from_slice(&new.to_be_bytes());
+    }
+
+    packet[IPV4_CHECKSUM..IPV4_CHECKSUM + 2].copy_from_slice(&header_checksum.to_be_bytes());
+    match protocol {
+        // A zero UDP checksum means the sender didn't compute one, and a computed zero is sent
+        // as all ones instead.
+        Protocol::Udp if read_u16(packet, checksum_offset) == 0 => return Ok(()),
+        Protocol::Udp if transport_checksum == 0 => transport_checksum = 0xFFFF,
+        _ => {}
+    }
+    packet[checksum_offset..checksum_offset + 2].copy_from_slice(&transport_checksum.to_be_bytes());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAN: Ipv4Cidr = Ipv4Cidr::new(Ipv4Addr::new(192, 168, 1, 1), 24).unwrap();
+    const WAN: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 80);
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 50), 40000);
+
+    const FORWARDS: [PortForward; 2] = [
+        PortForward {
+            protocol: Protocol::Tcp,
+            wan_port: 8080,
+            lan: SERVER,
+        },
+        PortForward {
+            protocol: Protocol::Udp,
+            wan_port: 5353,
+            lan: SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 53),
+        },
+    ];
+
+    fn hairpin() -> Hairpin<'static> {
+        Hairpin {
+            enabled: true,
+            lan: LAN,
+            wan: WAN,
+            forwards: &FORWARDS,
+        }
+    }
+
+    fn key(protocol: Protocol, source: SocketAddrV4, destination: SocketAddrV4) -> FlowKey {
+        FlowKey {
+            protocol,
+            source,
+            destination,
+        }
+    }
+
+    /// IPv4 packet of `flow` carrying `payload`, with correct checksums.
+    fn packet(flow: &FlowKey, payload: &[u8]) -> Vec<u8> {
+        let (number, transport_len) = match flow.protocol {
+            Protocol::Tcp => (protocol_number::TCP, 20),
+            Protocol::Udp => (protocol_number::UDP, 8),
+            Protocol::Icmp => unreachable!(),
+        };
+        let total_len = (IPV4_MIN_HEADER_LEN + transport_len + payload.len()) as u16;
+
+        let mut packet = vec![0x45, 0, 0, 0, 0x12, 0x34, 0x40, 0, 64, number, 0, 0];
+        packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&flow.source.ip().octets());
+        packet.extend_from_slice(&flow.destination.ip().octets());
+        let checksum = checksum::checksum(&packet);
+        packet[IPV4_CHECKSUM..IPV4_CHECKSUM + 2].copy_from_slice(&checksum.to_be_bytes());
+
+        packet.extend_from_slice(&flow.source.port().to_be_bytes());
+        packet.extend_from_slice(&flow.destination.port().to_be_bytes());
+        match flow.protocol {
+            // Sequence and acknowledgment numbers, data offset, flags and window, checksum and
+            // urgent pointer.
+            Protocol::Tcp => packet
+                .extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xFF, 0xFF, 0, 0, 0, 0]),
+            Protocol::Udp => {
+                let len = (transport_len + payload.len()) as u16;
+                packet.extend_from_slice(&len.to_be_bytes());
+                packet.extend_from_slice(&[0, 0]);
+            }
+            Protocol::Icmp => unreachable!(),
+        }
+        packet.extend_from_slice(payload);
+
+        let checksum = match transport_checksum(&packet) {
+            0 if flow.protocol == Protocol::Udp => 0xFFFF,
+            checksum => checksum,
+        };
+        let (_, _, offset) = transport(&packet).unwrap();
+        packet[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    /// Checksum of the transport header and payload with their pseudo header, zero when the
+    /// checksum field is right.
+    fn transport_checksum(packet: &[u8]) -> u16 {
+        let segment = &packet[IPV4_MIN_HEADER_LEN..];
+        let mut pseudo_header = [0; 12];
+        pseudo_header[..8].copy_from_slice(&packet[IPV4_SOURCE..IPV4_DESTINATION + 4]);
+        pseudo_header[9] = packet[9];
+        pseudo_header[10..].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+        checksum::checksum_with(&pseudo_header, segment)
+    }
+
+    fn assert_checksums_valid(packet: &[u8]) {
+        assert_eq!(checksum::checksum(&packet[..IPV4_MIN_HEADER_LEN]), 0);
+        assert_eq!(transport_checksum(packet), 0);
+    }
+
+    #[test]
+    fn lan_client_to_forwarded_port_is_hairpinned() {
+        let from = key(Protocol::Tcp, CLIENT, SocketAddrV4::new(WAN, 8080));
+        let translation = hairpin().translation(&packet(&from, b"GET /")).unwrap();
+
+        assert_eq!(translation.from, from);
+        assert_eq!(
+            translation.to,
+            key(
+                Protocol::Tcp,
+                SocketAddrV4::new(LAN.address(), CLIENT.port()),
+                SERVER
+            )
+        );
+    }
+
+    #[test]
+    fn other_packets_are_not_hairpinned() {
+        let to_wan = |source, port| key(Protocol::Tcp, source, SocketAddrV4::new(WAN, port));
+        let from_wan = SocketAddrV4::new(Ipv4Addr::new(198, 51, 100, 1), 40000);
+        let from_router = SocketAddrV4::new(LAN.address(), 40000);
+
+        for flow in [
+            to_wan(from_wan, 8080),
+            to_wan(from_router, 8080),
+            to_wan(CLIENT, 8081),
+            key(Protocol::Udp, CLIENT, SocketAddrV4::new(WAN, 8080)),
+            key(Protocol::Tcp, CLIENT, SERVER),
+        ] {
+            assert_eq!(hairpin().translation(&packet(&flow, &[])), None, "{flow:?}");
+        }
+
+        let disabled = Hairpin {
+            enabled: false,
+            ..hairpin()
+        };
+        let flow = to_wan(CLIENT, 8080);
+        assert_eq!(disabled.translation(&packet(&flow, &[])), None);
+    }
+
+    #[test]
+    fn tcp_gets_both_rewrites_with_valid_checksums() {
+        let from = key(Protocol::Tcp, CLIENT, SocketAddrV4::new(WAN, 8080));
+        let mut hairpinned = packet(&from, b"GET / HTTP/1.1\r\n");
+        let translation = hairpin().translation(&hairpinned).unwrap();
+
+        translate(&mut hairpinned, &translation.to).unwrap();
+        assert_eq!(flow_key(&hairpinned), Ok(translation.to));
+        assert_checksums_valid(&hairpinned);
+        assert_eq!(hairpinned, packet(&translation.to, b"GET / HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn reply_gets_both_rewrites_undone() {
+        let from = key(Protocol::Tcp, CLIENT, SocketAddrV4::new(WAN, 8080));
+        let translation = hairpin().translation(&packet(&from, &[])).unwrap();
+        let reply = translation.reply();
+        assert_eq!(reply.from, translation.to.reversed());
+
+        let mut answer = packet(&reply.from, b"HTTP/1.1 200 OK\r\n");
+        translate(&mut answer, &reply.to).unwrap();
+
+        // Comes back from the address the client talked to.
+        assert_eq!(flow_key(&answer), Ok(from.reversed()));
+        assert_checksums_valid(&answer);
+        assert_eq!(answer, packet(&from.reversed(), b"HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn udp_gets_both_rewrites_with_valid_checksums() {
+        let from = key(Protocol::Udp, CLIENT, SocketAddrV4::new(WAN, 5353));
+        let mut hairpinned = packet(&from, b"\x12\x34query");
+        let translation = hairpin().translation(&hairpinned).unwrap();
+
+        translate(&mut hairpinned, &translation.to).unwrap();
+        assert_checksums_valid(&hairpinned);
+
+        translate(&mut hairpinned, &from).unwrap();
+        assert_eq!(hairpinned, packet(&from, b"\x12\x34query"));
+    }
+
+    #[test]
+    fn udp_without_checksum_keeps_none() {
+        let from = key(Protocol::Udp, CLIENT, SocketAddrV4::new(WAN, 5353));
+        let mut hairpinned = packet(&from, b"query");
+        let offset = IPV4_MIN_HEADER_LEN + 6;
+        hairpinned[offset..offset + 2].copy_from_slice(&[0, 0]);
+        let translation = hairpin().translation(&hairpinned).unwrap();
+
+        translate(&mut hairpinned, &translation.to).unwrap();
+        assert_eq!(flow_key(&hairpinned), Ok(translation.to));
+        assert_eq!(checksum::checksum(&hairpinned[..IPV4_MIN_HEADER_LEN]), 0);
+        assert_eq!(read_u16(&hairpinned, offset), 0);
+    }
+
+    #[test]
+    fn udp_checksum_computed_as_zero_is_sent_as_all_ones() {
+        let from = key(Protocol::Udp, CLIENT, SocketAddrV4::new(WAN, 5353));
+        let translation = hairpin().translation(&packet(&from, &[0, 0])).unwrap();
+
+        // Adding the checksum of the translated packet as a payload word makes it sum to
+        // 0xFFFF, a checksum of zero.
+        let offset = IPV4_MIN_HEADER_LEN + 6;
+        let payload = read_u16(&packet(&translation.to, &[0, 0]), offset).to_be_bytes();
+
+        let mut hairpinned = packet(&from, &payload);
+        assert_ne!(read_u16(&hairpinned, offset), 0xFFFF);
+        translate(&mut hairpinned, &translation.to).unwrap();
+
+        assert_eq!(read_u16(&hairpinned, offset), 0xFFFF);
+        assert_checksums_valid(&hairpinned);
+    }
+
+    #[test]
+    fn later_fragments_and_short_packets_are_refused() {
+        let from = key(Protocol::Tcp, CLIENT, SocketAddrV4::new(WAN, 8080));
+        let mut fragment = packet(&from, &[]);
+        fragment[7] = 0x10;
+        assert_eq!(translate(&mut fragment, &from), Err(NatError::Unsupported));
+
+        let mut short = packet(&from, &[]);
+        short.truncate(IPV4_MIN_HEADER_LEN + 10);
+        assert_eq!(translate(&mut short, &from), Err(NatError::Truncated));
+    }
+}
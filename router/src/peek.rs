@@ -0,0 +1,111 @@
+//! Decisions on a received frame from its first bytes.
+//!
+//! Over SPI a frame's payload costs far more than its headers. The receive path reads the first
+//! [`PEEK_LEN`] bytes, lets the forwarding and filtering code decide from them, and only reads
+//! the rest of wanted frames; unwanted ones are skipped by moving the read pointer. A broadcast
+//! storm then costs little more than its headers.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use crate::{
+    conntrack::{FlowKey, Protocol},
+    ethernet::{MacAddress, VlanTag, ethertype},
+    profiling::{self, Stage},
+};
+
+/// Ethernet header, a VLAN tag, an IPv4 header without options and the ports.
+pub const PEEK_LEN: usize = 14 + 4 + 20 + 4;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+
+mod ip_protocol {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+}
+
+/// Headers found in the first bytes of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peek {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub vlan: Option<VlanTag>,
+    /// EtherType after the VLAN tag, if any.
+    pub ethertype: u16,
+    /// Flow of IPv4 TCP, UDP and ICMP query packets, `None` for other protocols, non-first
+    /// fragments and headers not within the prefix.
+    pub flow: Option<FlowKey>,
+}
+
+impl Peek {
+    /// Parses the headers in `prefix`, `None` if it's shorter than an Ethernet header.
+    pub fn parse(prefix: &[u8]) -> Option<Self> {
+        profiling::measure(Stage::FrameParse, || Self::parse_headers(prefix))
+    }
+
+    fn parse_headers(prefix: &[u8]) -> Option<Self> {
+        let ethernet = prefix.get(..14)?;
+        let vlan = VlanTag::parse(prefix);
+        let l3_offset = if vlan.is_some() { 18 } else { 14 };
+        let ethertype = prefix.get(l3_offset - 2..l3_offset)?;
+        let ethertype = u16::from_be_bytes([ethertype[0], ethertype[1]]);
+
+        let flow = if ethertype == ethertype::IPV4 {
+            prefix.get(l3_offset..).and_then(ipv4_flow)
+        } else {
+            None
+        };
+
+        Some(Self {
+            destination: MacAddress(ethernet[..6].try_into().unwrap()),
+            source: MacAddress(ethernet[6..12].try_into().unwrap()),
+            vlan,
+            ethertype,
+            flow,
+        })
+    }
+}
+
+fn ipv4_flow(packet: &[u8]) -> Option<FlowKey> {
+    let header = packet.get(..IPV4_MIN_HEADER_LEN)?;
+    let header_len = (header[0] & 0x0f) as usize * 4;
+    let fragment_offset = u16::from_be_bytes([header[6], header[7]]) & 0x1fff;
+    if header[0] >> 4 != 4 || header_len < IPV4_MIN_HEADER_LEN || fragment_offset != 0 {
+        return None;
+    }
+
+    let source = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+    let destination = Ipv4Addr::new(header[16], header[17], header[18], header[19]);
+    let l4 = packet.get(header_len..)?;
+    let (protocol, source_port, destination_port) = match header[9] {
+        ip_protocol::TCP | ip_protocol::UDP => {
+            let ports = l4.get(..4)?;
+            let protocol = if header[9] == ip_protocol::TCP {
+                Protocol::Tcp
+            } else {
+                Protocol::Udp
+            };
+            (
+                protocol,
+                u16::from_be_bytes([ports[0], ports[1]]),
+                u16::from_be_bytes([ports[2], ports[3]]),
+            )
+        }
+        ip_protocol::ICMP => {
+            let icmp = l4.get(..8)?;
+            // Echo, timestamp and information request/reply carry an identifier.
+            if !matches!(icmp[0], 0 | 8 | 13 | 14 | 15 | 16) {
+                return None;
+            }
+            let identifier = u16::from_be_bytes([icmp[4], icmp[5]]);
+            (Protocol::Icmp, identifier, identifier)
+        }
+        _ => return None,
+    };
+
+    Some(FlowKey {
+        protocol,
+        source: SocketAddrV4::new(source, source_port),
+        destination: SocketAddrV4::new(destination, destination_port),
+    })
+}
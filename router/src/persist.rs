@@ -0,0 +1,607 @@
+//! Snapshots of the hot state tables, kept across soft reboots.
+//!
+//! On a configuration save, a clean reboot or on a [`SnapshotSchedule`] the caller writes the
+//! DHCP leases, resolved ARP entries and tracked flows to flash or battery-backed RAM, and
+//! restores them on boot. Without them a restarted router would hand out addresses that are
+//! still in use and drop every NAT binding.
+//!
+//! Ticks restart from zero on boot, so entries are stored by their remaining lifetime or idle
+//! time rather than by absolute ticks; the time spent rebooting isn't accounted for. Snapshots
+//! end with a CRC-32, one torn by a power loss is rejected as a whole.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::{
+    arp::ArpCache,
+    conntrack::{Conntrack, FlowKey, FlowState, Protocol, TcpState},
+    ethernet::MacAddress,
+    lease::Leases,
+};
+
+const MAGIC: [u8; 4] = *b"DRS1";
+/// Magic and body length.
+const HEADER_LEN: usize = 8;
+/// Tag, record length and record count.
+const SECTION_HEADER_LEN: usize = 5;
+const CRC_LEN: usize = 4;
+
+/// Section tags.
+mod tag {
+    pub const LEASES: u8 = 1;
+    pub const ARP: u8 = 2;
+    pub const FLOWS: u8 = 3;
+}
+
+const LEASE_LEN: usize = 6 + 4 + 4;
+const ARP_LEN: usize = 4 + 6 + 4;
+/// Flow record without its data.
+const FLOW_LEN: usize = 1 + 6 + 6 + 1 + 4;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PersistError {
+    #[error("Snapshot doesn't fit the buffer.")]
+    BufferTooSmall,
+    #[error("Snapshot is missing or corrupted.")]
+    Corrupted,
+}
+
+/// Per-flow data that can be stored in a snapshot, like a NAT binding.
+pub trait FlowData: Sized {
+    /// Length of the encoded data.
+    const LEN: usize;
+
+    /// Encodes into exactly [`FlowData::LEN`] bytes.
+    fn encode(&self, buffer: &mut [u8]);
+    fn decode(buffer: &[u8]) -> Option<Self>;
+}
+
+impl FlowData for () {
+    const LEN: usize = 0;
+
+    fn encode(&self, _buffer: &mut [u8]) {}
+
+    fn decode(_buffer: &[u8]) -> Option<Self> {
+        Some(())
+    }
+}
+
+/// Writes a snapshot into a buffer.
+pub struct SnapshotWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SnapshotWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Result<Self, PersistError> {
+        let mut writer = Self { buffer, len: 0 };
+        writer.push(&MAGIC)?;
+        writer.push(&[0; 4])?;
+        Ok(writer)
+    }
+
+    /// Adds the leases that haven't expired.
+    pub fn leases<const N: usize>(
+        &mut self,
+        leases: &Leases<N>,
+        now: u32,
+    ) -> Result<&mut Self, PersistError> {
+        let section = self.start_section(tag::LEASES, LEASE_LEN)?;
+        for lease in leases.iter().filter(|lease| !lease.is_expired(now)) {
+            self.push(&lease.mac.0)?;
+            self.push(&lease.address.octets())?;
+            self.push(&lease.remaining(now).to_be_bytes())?;
+            self.count(section);
+        }
+        Ok(self)
+    }
+
+    /// Adds the resolved ARP entries, packets waiting on a resolution are lost anyway.
+    pub fn arp<T, const N: usize, const Q: usize>(
+        &mut self,
+        cache: &ArpCache<T, N, Q>,
+        now: u32,
+    ) -> Result<&mut Self, PersistError> {
+        let section = self.start_section(tag::ARP, ARP_LEN)?;
+        for (address, mac, updated_at) in cache.resolved() {
+            self.push(&address.octets())?;
+            self.push(&mac.0)?;
+            self.push(&now.wrapping_sub(updated_at).to_be_bytes())?;
+            self.count(section);
+        }
+        Ok(self)
+    }
+
+    /// Adds the tracked flows with their data.
+    pub fn flows<T: FlowData, const N: usize>(
+        &mut self,
+        conntrack: &Conntrack<T, N>,
+        now: u32,
+    ) -> Result<&mut Self, PersistError> {
+        let section = self.start_section(tag::FLOWS, FLOW_LEN + T::LEN)?;
+        for flow in conntrack.iter() {
+            self.push(&[encode_protocol(flow.key.protocol)])?;
+            self.push_socket(flow.key.source)?;
+            self.push_socket(flow.key.destination)?;
+            self.push(&[encode_state(flow.state)])?;
+            self.push(&now.wrapping_sub(flow.last_seen).to_be_bytes())?;
+
+            let data = self
+                .buffer
+                .get_mut(self.len..self.len + T::LEN)
+                .ok_or(PersistError::BufferTooSmall)?;
+            flow.data.encode(data);
+            self.len += T::LEN;
+            self.count(section);
+        }
+        Ok(self)
+    }
+
+    /// Fills in the length and checksum, returning the snapshot to store.
+    pub fn finish(mut self) -> Result<&'a [u8], PersistError> {
+        let body_len = (self.len - HEADER_LEN) as u32;
+        self.buffer[4..HEADER_LEN].copy_from_slice(&body_len.to_be_bytes());
+
+        let crc = crc32(&self.buffer[..self.len]);
+        self.push(&crc.to_be_bytes())?;
+        Ok(&self.buffer[..self.len])
+    }
+
+    /// Writes a section header with no records yet, returning its offset.
+    fn start_section(&mut self, tag: u8, record_len: usize) -> Result<usize, PersistError> {
+        let offset = self.len;
+        self.push(&[tag])?;
+        self.push(&(record_len as u16).to_be_bytes())?;
+        self.push(&[0; 2])?;
+        Ok(offset)
+    }
+
+    /// Counts a record written to the section at `offset`.
+    fn count(&mut self, offset: usize) {
+        let count = &mut self.buffer[offset + 3..offset + SECTION_HEADER_LEN];
+        let incremented = u16::from_be_bytes([count[0], count[1]]) + 1;
+        count.copy_from_slice(&incremented.to_be_bytes());
+    }
+
+    fn push_socket(&mut self, socket: SocketAddrV4) -> Result<(), PersistError> {
+        self.push(&socket.ip().octets())?;
+        self.push(&socket.port().to_be_bytes())
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), PersistError> {
+        self.buffer
+            .get_mut(self.len..self.len + bytes.len())
+            .ok_or(PersistError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+/// A validated snapshot to restore from.
+pub struct Snapshot<'a> {
+    body: &'a [u8],
+}
+
+impl<'a> Snapshot<'a> {
+    /// Checks the snapshot at the start of `bytes`, anything after it like erased flash is
+    /// ignored.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, PersistError> {
+        let header = bytes.get(..HEADER_LEN).ok_or(PersistError::Corrupted)?;
+        if header[..4] != MAGIC {
+            return Err(PersistError::Corrupted);
+        }
+
+        let body_len = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+        let checked = HEADER_LEN
+            .checked_add(body_len)
+            .and_then(|len| bytes.get(..len))
+            .ok_or(PersistError::Corrupted)?;
+        let crc = bytes
+            .get(checked.len()..checked.len() + CRC_LEN)
+            .ok_or(PersistError::Corrupted)?;
+        if crc32(checked).to_be_bytes() != crc {
+            return Err(PersistError::Corrupted);
+        }
+
+        let snapshot = Self {
+            body: &checked[HEADER_LEN..],
+        };
+        let mut rest = snapshot.body;
+        while !rest.is_empty() {
+            let (.., next) = split_section(rest).ok_or(PersistError::Corrupted)?;
+            rest = next;
+        }
+        Ok(snapshot)
+    }
+
+    /// Restores the leases, returning how many were restored.
+    pub fn restore_leases<const N: usize>(&self, leases: &mut Leases<N>, now: u32) -> usize {
+        self.records(tag::LEASES, LEASE_LEN)
+            .filter(|record| {
+                let mac = MacAddress(record[..6].try_into().unwrap());
+                let address = ipv4(&record[6..10]);
+                let remaining = u32::from_be_bytes(record[10..14].try_into().unwrap());
+                leases.insert(mac, address, remaining, now).is_ok()
+            })
+            .count()
+    }
+
+    /// Restores the resolved ARP entries, returning how many were restored.
+    pub fn restore_arp<T, const N: usize, const Q: usize>(
+        &self,
+        cache: &mut ArpCache<T, N, Q>,
+        now: u32,
+    ) -> usize {
+        let mut restored = 0;
+        for record in self.records(tag::ARP, ARP_LEN) {
+            let address = ipv4(&record[..4]);
+            let mac = MacAddress(record[4..10].try_into().unwrap());
+            let age = u32::from_be_bytes(record[10..14].try_into().unwrap());
+            // Entries were made from packets for us, they're made again.
+            cache.insert(address, mac, true, now.wrapping_sub(age));
+            restored += 1;
+        }
+        restored
+    }
+
+    /// Restores the flows, returning how many were restored.
+    ///
+    /// Flows are skipped if stored with data of another length or if the data doesn't decode.
+    pub fn restore_flows<T: FlowData, const N: usize>(
+        &self,
+        conntrack: &mut Conntrack<T, N>,
+        now: u32,
+    ) -> usize {
+        self.records(tag::FLOWS, FLOW_LEN + T::LEN)
+            .filter(|record| {
+                let (Some(protocol), Some(state), Some(data)) = (
+                    decode_protocol(record[0]),
+                    decode_state(record[13]),
+                    T::decode(&record[FLOW_LEN..]),
+                ) else {
+                    return false;
+                };
+
+                let key = FlowKey {
+                    protocol,
+                    source: socket(&record[1..7]),
+                    destination: socket(&record[7..13]),
+                };
+                let idle = u32::from_be_bytes(record[14..18].try_into().unwrap());
+                conntrack
+                    .insert(key, state, data, now.wrapping_sub(idle))
+                    .is_ok()
+            })
+            .count()
+    }
+
+    /// Records of the section `tag`, none if it's missing or has records of another length.
+    fn records(&self, tag: u8, record_len: usize) -> impl Iterator<Item = &'a [u8]> + use<'a> {
+        let mut rest = self.body;
+        let mut records: &[u8] = &[];
+        while let Some((section_tag, section_record_len, section_records, next)) =
+            split_section(rest)
+        {
+            if section_tag == tag && section_record_len == record_len {
+                records = section_records;
+                break;
+            }
+            rest = next;
+        }
+
+        records.chunks_exact(record_len.max(1))
+    }
+}
+
+/// Splits off the first section as its tag, record length, records and the remaining bytes.
+fn split_section(bytes: &[u8]) -> Option<(u8, usize, &[u8], &[u8])> {
+    let header = bytes.get(..SECTION_HEADER_LEN)?;
+    let record_len = u16::from_be_bytes([header[1], header[2]]) as usize;
+    let count = u16::from_be_bytes([header[3], header[4]]) as usize;
+
+    let records_len = record_len * count;
+    let records = bytes.get(SECTION_HEADER_LEN..SECTION_HEADER_LEN + records_len)?;
+    Some((
+        header[0],
+        record_len,
+        records,
+        &bytes[SECTION_HEADER_LEN + records_len..],
+    ))
+}
+
+/// Decides when to write a snapshot on a timer, only if anything changed since the last one, to
+/// spare the flash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotSchedule {
+    interval: u32,
+    saved_at: u32,
+    dirty: bool,
+}
+
+impl SnapshotSchedule {
+    pub const fn new(interval: u32, now: u32) -> Self {
+        Self {
+            interval,
+            saved_at: now,
+            dirty: false,
+        }
+    }
+
+    /// Records a change worth saving, like a new lease.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_due(&self, now: u32) -> bool {
+        self.dirty && now.wrapping_sub(self.saved_at) >= self.interval
+    }
+
+    pub fn saved(&mut self, now: u32) {
+        self.saved_at = now;
+        self.dirty = false;
+    }
+}
+
+fn encode_protocol(protocol: Protocol) -> u8 {
+    match protocol {
+        Protocol::Tcp => 6,
+        Protocol::Udp => 17,
+        Protocol::Icmp => 1,
+    }
+}
+
+fn decode_protocol(byte: u8) -> Option<Protocol> {
+    match byte {
+        6 => Some(Protocol::Tcp),
+        17 => Some(Protocol::Udp),
+        1 => Some(Protocol::Icmp),
+        _ => None,
+    }
+}
+
+fn encode_state(state: FlowState) -> u8 {
+    match state {
+        FlowState::Tcp(TcpState::Opening) => 0,
+        FlowState::Tcp(TcpState::Established) => 1,
+        FlowState::Tcp(TcpState::Closing) => 2,
+        FlowState::Tcp(TcpState::TimeWait) => 3,
+        FlowState::Udp => 4,
+        FlowState::Icmp => 5,
+    }
+}
+
+fn decode_state(byte: u8) -> Option<FlowState> {
+    match byte {
+        0 => Some(FlowState::Tcp(TcpState::Opening)),
+        1 => Some(FlowState::Tcp(TcpState::Established)),
+        2 => Some(FlowState::Tcp(TcpState::Closing)),
+        3 => Some(FlowState::Tcp(TcpState::TimeWait)),
+        4 => Some(FlowState::Udp),
+        5 => Some(FlowState::Icmp),
+        _ => None,
+    }
+}
+
+fn ipv4(bytes: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])
+}
+
+fn socket(bytes: &[u8]) -> SocketAddrV4 {
+    SocketAddrV4::new(ipv4(bytes), u16::from_be_bytes([bytes[4], bytes[5]]))
+}
+
+/// CRC-32 (IEEE 802.3), bitwise as snapshots are small and rare.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(u32::MAX, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::conntrack::{EvictionPolicy, TimeoutProfile};
+
+    /// A NAT port, say.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Port(u16);
+
+    impl FlowData for Port {
+        const LEN: usize = 2;
+
+        fn encode(&self, buffer: &mut [u8]) {
+            buffer.copy_from_slice(&self.0.to_be_bytes());
+        }
+
+        fn decode(buffer: &[u8]) -> Option<Self> {
+            Some(Self(u16::from_be_bytes(buffer.try_into().ok()?)))
+        }
+    }
+
+    const BEFORE_REBOOT: u32 = 10_000;
+    const AFTER_REBOOT: u32 = 100;
+
+    fn mac(last: u8) -> MacAddress {
+        MacAddress([0x02, 0, 0, 0, 0, last])
+    }
+
+    fn flow_key() -> FlowKey {
+        FlowKey {
+            protocol: Protocol::Tcp,
+            source: SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 40_000),
+            destination: SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 443),
+        }
+    }
+
+    /// A snapshot of a lease, an ARP entry and a flow, taken at [`BEFORE_REBOOT`].
+    fn snapshot(buffer: &mut [u8]) -> &[u8] {
+        let mut leases = Leases::<4>::new();
+        leases
+            .insert(
+                mac(1),
+                Ipv4Addr::new(192, 168, 1, 20),
+                3_600,
+                BEFORE_REBOOT - 600,
+            )
+            .unwrap();
+        // Expired, left out.
+        leases
+            .insert(
+                mac(2),
+                Ipv4Addr::new(192, 168, 1, 21),
+                100,
+                BEFORE_REBOOT - 600,
+            )
+            .unwrap();
+
+        let mut arp = ArpCache::<(), 4, 2>::new(1_200, 3);
+        arp.insert(
+            Ipv4Addr::new(192, 168, 1, 20),
+            mac(1),
+            true,
+            BEFORE_REBOOT - 30,
+        );
+
+        let mut conntrack =
+            Conntrack::<Port, 4>::new(TimeoutProfile::default(), EvictionPolicy::RefuseNew);
+        conntrack
+            .insert(
+                flow_key(),
+                FlowState::Tcp(TcpState::Established),
+                Port(61_000),
+                BEFORE_REBOOT - 5,
+            )
+            .unwrap();
+
+        let mut writer = SnapshotWriter::new(buffer).unwrap();
+        writer
+            .leases(&leases, BEFORE_REBOOT)
+            .unwrap()
+            .arp(&arp, BEFORE_REBOOT)
+            .unwrap()
+            .flows(&conntrack, BEFORE_REBOOT)
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn restores_entries_with_their_remaining_time() {
+        let mut buffer = [0; 128];
+        let snapshot = Snapshot::parse(snapshot(&mut buffer)).unwrap();
+
+        let mut leases = Leases::<4>::new();
+        assert_eq!(snapshot.restore_leases(&mut leases, AFTER_REBOOT), 1);
+        let lease = leases.iter().next().unwrap();
+        assert_eq!(lease.mac, mac(1));
+        assert_eq!(lease.address, Ipv4Addr::new(192, 168, 1, 20));
+        assert_eq!(lease.remaining(AFTER_REBOOT), 3_000);
+
+        let mut arp = ArpCache::<(), 4, 2>::new(1_200, 3);
+        assert_eq!(snapshot.restore_arp(&mut arp, AFTER_REBOOT), 1);
+        assert_eq!(
+            arp.resolved().next(),
+            Some((Ipv4Addr::new(192, 168, 1, 20), mac(1), AFTER_REBOOT - 30))
+        );
+
+        let mut conntrack =
+            Conntrack::<Port, 4>::new(TimeoutProfile::default(), EvictionPolicy::RefuseNew);
+        assert_eq!(snapshot.restore_flows(&mut conntrack, AFTER_REBOOT), 1);
+        let flow = conntrack.get(&flow_key()).unwrap();
+        assert_eq!(flow.state, FlowState::Tcp(TcpState::Established));
+        assert_eq!(flow.data, Port(61_000));
+        assert_eq!(flow.last_seen, AFTER_REBOOT - 5);
+    }
+
+    #[test]
+    fn skips_flows_stored_with_other_data() {
+        let mut buffer = [0; 128];
+        let snapshot = Snapshot::parse(snapshot(&mut buffer)).unwrap();
+
+        let mut conntrack =
+            Conntrack::<(), 4>::new(TimeoutProfile::default(), EvictionPolicy::RefuseNew);
+        assert_eq!(snapshot.restore_flows(&mut conntrack, AFTER_REBOOT), 0);
+        assert_eq!(conntrack.len(), 0);
+    }
+
+    #[test]
+    fn ignores_what_follows_the_snapshot() {
+        let mut buffer = [0xFF; 128];
+        let len = snapshot(&mut buffer).len();
+        assert!(len < buffer.len());
+        assert!(Snapshot::parse(&buffer).is_ok());
+    }
+
+    #[test]
+    fn rejects_any_corruption() {
+        let mut buffer = [0; 128];
+        let len = snapshot(&mut buffer).len();
+
+        for index in 0..len {
+            let mut corrupted = buffer;
+            corrupted[index] ^= 0x10;
+            assert!(
+                matches!(
+                    Snapshot::parse(&corrupted[..len]),
+                    Err(PersistError::Corrupted)
+                ),
+                "byte {index} flipped"
+            );
+        }
+
+        // Torn by a power loss.
+        for torn in [0, HEADER_LEN - 1, HEADER_LEN, len - 1] {
+            assert!(matches!(
+                Snapshot::parse(&buffer[..torn]),
+                Err(PersistError::Corrupted)
+            ));
+        }
+
+        // Erased flash.
+        assert!(matches!(
+            Snapshot::parse(&[0xFF; 64]),
+            Err(PersistError::Corrupted)
+        ));
+    }
+
+    #[test]
+    fn rejects_sections_overrunning_the_body() {
+        let mut buffer = [0; 64];
+        let mut writer = SnapshotWriter::new(&mut buffer).unwrap();
+        // A section claiming a record it doesn't have, checksummed as if valid.
+        writer.start_section(tag::ARP, ARP_LEN).unwrap();
+        writer.count(HEADER_LEN);
+        let snapshot = writer.finish().unwrap();
+
+        assert!(matches!(
+            Snapshot::parse(snapshot),
+            Err(PersistError::Corrupted)
+        ));
+    }
+
+    #[test]
+    fn refuses_a_buffer_too_small() {
+        let mut buffer = [0; 32];
+        let mut leases = Leases::<4>::new();
+        for last in 1..=3 {
+            leases
+                .insert(mac(last), Ipv4Addr::new(192, 168, 1, last), 3_600, 0)
+                .unwrap();
+        }
+
+        let mut writer = SnapshotWriter::new(&mut buffer).unwrap();
+        assert!(matches!(
+            writer.leases(&leases, 0),
+            Err(PersistError::BufferTooSmall)
+        ));
+        assert!(matches!(
+            SnapshotWriter::new(&mut [0; 4]),
+            Err(PersistError::BufferTooSmall)
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+//! Powering the PHY down while no cable is plugged.
+//!
+//! The PHY draws most of the ENC28J60's current, even with no link, and a port left unplugged
+//! for good is common on a small router. Once an interface has been without link for a while,
+//! its PHY is powered down with [`Enc28j60::set_phy_power_down`].
+//!
+//! The ENC28J60 has no energy detect to wake on: a powered down PHY sees nothing, link pulses
+//! included. So the PHY is powered up every so often and listens for a link for a moment; if
+//! the driver reports one while listening, it goes through [`Interfaces::set_link`] as usual
+//! and the PHY stays up. Forcing the link up with PHCON2.FRCLNK would keep the MAC sending
+//! into an empty port instead, so it isn't used.
+//!
+//! An interface disabled by the admin has its PHY powered down right away, and up again once
+//! it's enabled.
+//!
+//! The default timings assume one tick per millisecond.
+//!
+//! [`Enc28j60::set_phy_power_down`]: crate::enc28j60::Enc28j60::set_phy_power_down
+//! [`Interfaces::set_link`]: crate::interface::Interfaces::set_link
+
+use crate::{clock, interface::InterfaceState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerSaveConfig {
+    /// Ticks without link before the PHY is powered down, 0 to keep it always up.
+    pub idle_after: u32,
+    /// Ticks the PHY stays down between two checks for a link.
+    pub sleep_for: u32,
+    /// Ticks the PHY stays up on each check, long enough for the link to come up: link pulses
+    /// are 16 ms apart and the PHY needs a few of them.
+    pub listen_for: u32,
+}
+
+impl Default for PowerSaveConfig {
+    fn default() -> Self {
+        Self {
+            idle_after: 60_000,
+            sleep_for: 5_000,
+            listen_for: 500,
+        }
+    }
+}
+
+/// What to do with the PHY, see [`PhyPowerSave::poll`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerAction {
+    PowerDown,
+    PowerUp,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerSaveStats {
+    /// Times the PHY was powered down.
+    pub sleeps: u32,
+    /// Times it was powered up to listen for a link.
+    pub checks: u32,
+    /// Links found while listening.
+    pub woken: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum PowerState {
+    /// PHY up, without link since the given tick if any.
+    Awake { no_link_since: Option<u32> },
+    /// PHY down until the given tick.
+    Asleep { until: u32 },
+    /// PHY up to check for a link until the given tick.
+    Listening { until: u32 },
+    /// PHY down with the interface disabled.
+    Disabled,
+}
+
+/// Power saving of a single interface's PHY.
+pub struct PhyPowerSave {
+    config: PowerSaveConfig,
+    state: PowerState,
+    stats: PowerSaveStats,
+}
+
+impl PhyPowerSave {
+    pub fn new(config: PowerSaveConfig) -> Self {
+        Self {
+            config,
+            state: PowerState::Awake {
+                no_link_since: None,
+            },
+            stats: PowerSaveStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &PowerSaveConfig {
+        &self.config
+    }
+
+    /// Changes the timings, taking effect from the next sleep.
+    pub fn set_config(&mut self, config: PowerSaveConfig) {
+        self.config = config;
+    }
+
+    pub fn stats(&self) -> PowerSaveStats {
+        self.stats
+    }
+
+    /// Whether the PHY is powered down, its link reading meaningless.
+    pub fn is_asleep(&self) -> bool {
+        matches!(self.state, PowerState::Asleep { .. } | PowerState::Disabled)
+    }
+
+    /// Follows the interface's `state`, returning what to do with the PHY if anything. Call
+    /// it periodically, after feeding the driver's link reading to the interface unless
+    /// [`Self::is_asleep`].
+    pub fn poll(&mut self, state: InterfaceState, now: u32) -> Option<PowerAction> {
+        let due = |at: u32| clock::is_due(at, now);
+
+        match (self.state, state) {
+            (PowerState::Disabled, InterfaceState::AdminDown) => None,
+            (_, InterfaceState::AdminDown) => {
+                let asleep = self.is_asleep();
+                self.state = PowerState::Disabled;
+                (!asleep).then_some(PowerAction::PowerDown)
+            }
+            (PowerState::Disabled | PowerState::Asleep { .. }, InterfaceState::NoLink)
+                if self.config.idle_after == 0 =>
+            {
+                self.wake()
+            }
+            (PowerState::Disabled, _) => self.wake(),
+            (PowerState::Asleep { until }, InterfaceState::NoLink) => {
+                if !due(until) {
+                    return None;
+                }
+                self.stats.checks += 1;
+                self.state = PowerState::Listening {
+                    until: now.wrapping_add(self.config.listen_for),
+                };
+                Some(PowerAction::PowerUp)
+            }
+            // The link was set up regardless, e.g. by a driver reset.
+            (PowerState::Asleep { .. }, _) => self.wake(),
+            (PowerState::Listening { until }, InterfaceState::NoLink) => {
+                due(until).then(|| self.sleep(now))
+            }
+            (PowerState::Listening { .. }, _) => {
+                self.stats.woken += 1;
+                self.state = PowerState::Awake {
+                    no_link_since: None,
+                };
+                None
+            }
+            (PowerState::Awake { no_link_since }, InterfaceState::NoLink) => {
+                let since = no_link_since.unwrap_or(now);
+                self.state = PowerState::Awake {
+                    no_link_since: Some(since),
+                };
+                let idle =
+                    self.config.idle_after > 0 && due(since.wrapping_add(self.config.idle_after));
+                idle.then(|| self.sleep(now))
+            }
+            (PowerState::Awake { .. }, _) => {
+                self.state = PowerState::Awake {
+                    no_link_since: None,
+                };
+                None
+            }
+        }
+    }
+
+    fn sleep(&mut self, now: u32) -> PowerAction {
+        self.stats.sleeps += 1;
+        self.state = PowerState::Asleep {
+            until: now.wrapping_add(self.config.sleep_for),
+        };
+        PowerAction::PowerDown
+    }
+
+    fn wake(&mut self) -> Option<PowerAction> {
+        self.state = PowerState::Awake {
+            no_link_since: None,
+        };
+        Some(PowerAction::PowerUp)
+    }
+}
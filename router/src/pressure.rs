@@ -0,0 +1,178 @@
+//! Behavior when the fixed size pools run out.
+//!
+//! Everything is statically sized, so under load frame buffers, the connection tracking table
+//! and sockets do run out. Rather than failing wherever a pool happens to be full, new work is
+//! admitted here first: data-plane work stops at a reserve short of capacity, which is kept for
+//! management traffic so the router can still be reached while forwarding is saturated.
+//!
+//! Refusals are counted per pool and logged at most once per interval, a flood would otherwise
+//! turn into a flood of log messages.
+
+use core::net::Ipv4Addr;
+
+use crate::{
+    conntrack::{FlowKey, Protocol},
+    log::{Level, Module},
+};
+
+/// TCP ports of the management services: SSH, telnet and the web interface.
+pub const MANAGEMENT_PORTS: [u16; 4] = [22, 23, 80, 443];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pool {
+    Frames,
+    Conntrack,
+    Sockets,
+}
+
+impl Pool {
+    pub const COUNT: usize = 3;
+
+    const fn module(&self) -> Module {
+        match self {
+            Pool::Frames | Pool::Sockets => Module::Driver,
+            Pool::Conntrack => Module::Nat,
+        }
+    }
+}
+
+/// Who the work is for, deciding whether it may use the reserve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Traffic {
+    /// Forwarded traffic.
+    DataPlane,
+    /// Traffic to or from the router's own management services.
+    Management,
+}
+
+impl Traffic {
+    /// Classifies a packet of `key` seen by a router owning `local`.
+    pub fn classify(key: &FlowKey, local: Ipv4Addr) -> Self {
+        let to_service =
+            *key.destination.ip() == local && MANAGEMENT_PORTS.contains(&key.destination.port());
+        let from_service =
+            *key.source.ip() == local && MANAGEMENT_PORTS.contains(&key.source.port());
+
+        if key.protocol == Protocol::Tcp && (to_service || from_service) {
+            Traffic::Management
+        } else {
+            Traffic::DataPlane
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PressureConfig {
+    /// Entries of each pool, indexed by [`Pool`], only management traffic may use.
+    pub reserve: [usize; Pool::COUNT],
+    /// Ticks between two log messages about the same pool.
+    pub log_interval: u32,
+}
+
+impl Default for PressureConfig {
+    fn default() -> Self {
+        Self {
+            reserve: [2, 4, 1],
+            log_interval: 10_000,
+        }
+    }
+}
+
+/// Counters of a pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PoolStats {
+    /// Data-plane work refused on reaching the reserve.
+    pub data_refused: u32,
+    /// Management work refused with the reserve used up as well.
+    pub management_refused: u32,
+    /// Times the pool went from available to exhausted.
+    pub exhaustions: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PoolState {
+    stats: PoolStats,
+    exhausted: bool,
+    last_log: Option<u32>,
+    /// Refusals since the last log message.
+    unlogged: u32,
+}
+
+/// Admission control over the pools.
+pub struct Pressure {
+    config: PressureConfig,
+    pools: [PoolState; Pool::COUNT],
+}
+
+impl Pressure {
+    pub fn new(config: PressureConfig) -> Self {
+        Self {
+            config,
+            pools: [PoolState::default(); Pool::COUNT],
+        }
+    }
+
+    pub fn stats(&self, pool: Pool) -> PoolStats {
+        self.pools[pool as usize].stats
+    }
+
+    /// Whether `pool` is refusing data-plane work since the last admission check.
+    pub fn is_exhausted(&self, pool: Pool) -> bool {
+        self.pools[pool as usize].exhausted
+    }
+
+    /// Whether new work for `traffic` may take an entry of `pool`, which has `used` out of
+    /// `capacity` entries in use.
+    pub fn admit(
+        &mut self,
+        pool: Pool,
+        traffic: Traffic,
+        used: usize,
+        capacity: usize,
+        now: u32,
+    ) -> bool {
+        let limit = match traffic {
+            Traffic::DataPlane => capacity.saturating_sub(self.config.reserve[pool as usize]),
+            Traffic::Management => capacity,
+        };
+
+        let state = &mut self.pools[pool as usize];
+        if used < limit {
+            if traffic == Traffic::DataPlane {
+                state.exhausted = false;
+            }
+            return true;
+        }
+
+        match traffic {
+            Traffic::DataPlane => state.stats.data_refused += 1,
+            Traffic::Management => state.stats.management_refused += 1,
+        }
+        if !state.exhausted {
+            state.exhausted = true;
+            state.stats.exhaustions += 1;
+        }
+        state.unlogged += 1;
+
+        let due = state
+            .last_log
+            .is_none_or(|last| now.wrapping_sub(last) >= self.config.log_interval);
+        if due {
+            let refused = state.unlogged;
+            state.last_log = Some(now);
+            state.unlogged = 0;
+            crate::log!(
+                pool.module(),
+                Level::Warn,
+                "{} pool exhausted, {} refused",
+                pool,
+                refused
+            );
+        }
+
+        false
+    }
+}
@@ -0,0 +1,149 @@
+//! Sizes of the firmware's tables and queues, bundled per RAM budget.
+//!
+//! Every bounded structure takes its capacity as a const generic, which leaves picking dozens
+//! of numbers to whoever builds the firmware. The profiles pick them for a RAM budget and
+//! alias the structures with their sizes filled in:
+//!
+//! - [`small`] for parts with 64 KiB of RAM, a handful of LAN hosts;
+//! - [`medium`] for the STM32F407's 128 KiB of main SRAM, a home network;
+//! - [`large`] for 192 KiB parts, or the F407 with its CCM, a busy small office.
+//!
+//! [`active`] is the profile of the build, medium unless the `profile-small` or
+//! `profile-large` feature selects another. With both enabled, as `--all-features` does, the
+//! large one wins. Its sizes are defaults: a structure can still be declared with its own,
+//! e.g. after `show memory` told the profile's was off.
+
+/// Defines a profile module with its sizes and the structures aliased with them.
+macro_rules! profile {
+    ($(#[$doc:meta])* $name:ident { $($(#[$size_doc:meta])* $size:ident = $value:expr;)* }) => {
+        $(#[$doc])*
+        pub mod $name {
+            $($(#[$size_doc])* pub const $size: usize = $value;)*
+
+            pub type Enc28j60 =
+                crate::enc28j60::Enc28j60<SPI_OPERATIONS, SPI_TRANSACTIONS, SPI_BYTES>;
+            pub type ArpCache<T> = crate::arp::ArpCache<T, ARP_ENTRIES, ARP_QUEUE>;
+            pub type Conntrack<T> = crate::conntrack::Conntrack<T, FLOWS>;
+            pub type Expectations = crate::conntrack::Expectations<EXPECTATIONS>;
+            pub type Leases = crate::lease::Leases<LEASES>;
+            pub type MacTable = crate::bridge::MacTable<BRIDGED_MACS>;
+            pub type Firewall = crate::firewall::Firewall<RULES>;
+            pub type RoutingTable = crate::routing::RoutingTable<ROUTES, POLICY_RULES>;
+            pub type LocalRecords = crate::dns::LocalRecords<DNS_RECORDS, DNS_NAME_LEN>;
+            pub type DnsForwarder = crate::dns::Forwarder<DNS_CLIENTS, DNS_OUTSTANDING>;
+            pub type EventBus = crate::events::EventBus<EVENTS, EVENT_SUBSCRIBERS>;
+            pub type TxQueue<T> = crate::txqueue::TxQueue<T, TX_QUEUE_DEPTH>;
+            pub type RawSocket = crate::rawsock::RawSocket<RAW_SOCKET_QUEUE, RAW_SOCKET_FRAME>;
+            pub type RxHooks = crate::rxhooks::RxHooks<RX_HOOKS>;
+            pub type Timers<T> = crate::timer::Timers<T, TIMERS>;
+        }
+    };
+}
+
+profile! {
+    /// 64 KiB of RAM.
+    small {
+        /// Transaction queue of the ENC28J60, room for a full frame to transmit.
+        SPI_OPERATIONS = 48;
+        SPI_TRANSACTIONS = 40;
+        SPI_BYTES = 1600;
+        ARP_ENTRIES = 16;
+        /// Packets waiting on each ARP resolution.
+        ARP_QUEUE = 1;
+        FLOWS = 128;
+        EXPECTATIONS = 4;
+        LEASES = 32;
+        BRIDGED_MACS = 64;
+        RULES = 16;
+        ROUTES = 8;
+        POLICY_RULES = 2;
+        DNS_RECORDS = 8;
+        /// Clients rate limited by the DNS forwarder at once.
+        DNS_CLIENTS = 16;
+        /// Queries waiting on upstream at once, each taking a UDP port.
+        DNS_OUTSTANDING = 8;
+        DNS_NAME_LEN = 64;
+        EVENTS = 16;
+        EVENT_SUBSCRIBERS = 2;
+        TX_QUEUE_DEPTH = 8;
+        RAW_SOCKET_QUEUE = 2;
+        RAW_SOCKET_FRAME = 1518;
+        RX_HOOKS = 2;
+        TIMERS = 16;
+    }
+}
+
+profile! {
+    /// 128 KiB of RAM.
+    medium {
+        /// Transaction queue of the ENC28J60, room for a full frame to transmit.
+        SPI_OPERATIONS = 64;
+        SPI_TRANSACTIONS = 50;
+        SPI_BYTES = 1700;
+        ARP_ENTRIES = 64;
+        /// Packets waiting on each ARP resolution.
+        ARP_QUEUE = 2;
+        FLOWS = 512;
+        EXPECTATIONS = 16;
+        LEASES = 128;
+        BRIDGED_MACS = 256;
+        RULES = 64;
+        ROUTES = 32;
+        POLICY_RULES = 8;
+        DNS_RECORDS = 32;
+        /// Clients rate limited by the DNS forwarder at once.
+        DNS_CLIENTS = 64;
+        /// Queries waiting on upstream at once, each taking a UDP port.
+        DNS_OUTSTANDING = 32;
+        DNS_NAME_LEN = 96;
+        EVENTS = 64;
+        EVENT_SUBSCRIBERS = 4;
+        TX_QUEUE_DEPTH = 16;
+        RAW_SOCKET_QUEUE = 4;
+        RAW_SOCKET_FRAME = 1518;
+        RX_HOOKS = 4;
+        TIMERS = 32;
+    }
+}
+
+profile! {
+    /// 192 KiB of RAM.
+    large {
+        /// Transaction queue of the ENC28J60, room for two full frames to transmit.
+        SPI_OPERATIONS = 96;
+        SPI_TRANSACTIONS = 80;
+        SPI_BYTES = 3300;
+        ARP_ENTRIES = 128;
+        /// Packets waiting on each ARP resolution.
+        ARP_QUEUE = 4;
+        FLOWS = 1024;
+        EXPECTATIONS = 32;
+        LEASES = 253;
+        BRIDGED_MACS = 512;
+        RULES = 128;
+        ROUTES = 64;
+        POLICY_RULES = 16;
+        DNS_RECORDS = 64;
+        /// Clients rate limited by the DNS forwarder at once.
+        DNS_CLIENTS = 128;
+        /// Queries waiting on upstream at once, each taking a UDP port.
+        DNS_OUTSTANDING = 64;
+        DNS_NAME_LEN = 128;
+        EVENTS = 128;
+        EVENT_SUBSCRIBERS = 8;
+        TX_QUEUE_DEPTH = 32;
+        RAW_SOCKET_QUEUE = 8;
+        RAW_SOCKET_FRAME = 1518;
+        RX_HOOKS = 8;
+        TIMERS = 64;
+    }
+}
+
+#[cfg(all(feature = "profile-small", not(feature = "profile-large")))]
+pub use small as active;
+
+#[cfg(feature = "profile-large")]
+pub use large as active;
+
+#[cfg(not(any(feature = "profile-small", feature = "profile-large")))]
+pub use medium as active;
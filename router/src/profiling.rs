@@ -0,0 +1,229 @@
+//! Cycle accurate timing of the packet path stages.
+//!
+//! Uses the DWT cycle counter, which needs to be enabled by the firmware before any
+//! measurement is meaningful (see [`enable`]). `show profiling` prints the accumulated counts,
+//! see [`Report`].
+//! Without the `profiling` feature [`measure`] is a plain call to the measured closure, as it is
+//! off target, e.g. in the host tests, where there's no cycle counter and nothing is recorded.
+
+use core::fmt::{self, Display};
+
+#[cfg(all(feature = "profiling", target_arch = "arm"))]
+use core::cell::RefCell;
+
+#[cfg(all(feature = "profiling", target_arch = "arm"))]
+use cortex_m::interrupt::Mutex;
+#[cfg(feature = "profiling")]
+use cortex_m::peripheral::{DCB, DWT};
+
+/// Stages of the packet path that are measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// A complete SPI transaction with the ENC28J60.
+    SpiTransfer,
+    /// Feeding a completed transaction back to the driver.
+    HandleTransaction,
+    /// Parsing the headers of a received frame.
+    FrameParse,
+    /// Looking up the route of a packet.
+    Forwarding,
+    /// Looking up or adding a flow in connection tracking, or a hairpin translation.
+    NatLookup,
+}
+
+impl Stage {
+    pub const COUNT: usize = 5;
+
+    pub const ALL: [Self; Self::COUNT] = [
+        Self::SpiTransfer,
+        Self::HandleTransaction,
+        Self::FrameParse,
+        Self::Forwarding,
+        Self::NatLookup,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SpiTransfer => "spi_transfer",
+            Self::HandleTransaction => "handle_transaction",
+            Self::FrameParse => "frame_parse",
+            Self::Forwarding => "forwarding",
+            Self::NatLookup => "nat_lookup",
+        }
+    }
+}
+
+/// Accumulated cycle counts for a single [`Stage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageStats {
+    pub min: u32,
+    pub max: u32,
+    pub total: u64,
+    pub count: u32,
+}
+
+impl StageStats {
+    const fn new() -> Self {
+        Self {
+            min: u32::MAX,
+            max: 0,
+            total: 0,
+            count: 0,
+        }
+    }
+
+    /// Average cycles per measurement, `None` if the stage never ran.
+    pub fn avg(&self) -> Option<u32> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some((self.total / self.count as u64) as u32)
+    }
+
+    #[cfg(all(feature = "profiling", target_arch = "arm"))]
+    fn record(&mut self, cycles: u32) {
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.total = self.total.saturating_add(cycles as u64);
+        self.count = self.count.saturating_add(1);
+    }
+}
+
+impl Default for StageStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(feature = "profiling", target_arch = "arm"))]
+static STATS: Mutex<RefCell<[StageStats; Stage::COUNT]>> =
+    Mutex::new(RefCell::new([StageStats::new(); Stage::COUNT]));
+
+/// Starts the DWT cycle counter.
+#[cfg(feature = "profiling")]
+pub fn enable(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+}
+
+/// Runs `f` accounting the cycles it took to `stage`.
+#[cfg(all(feature = "profiling", target_arch = "arm"))]
+pub fn measure<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    let start = DWT::cycle_count();
+    let result = f();
+    // The counter wraps every few tens of seconds, a single stage never takes that long.
+    let cycles = DWT::cycle_count().wrapping_sub(start);
+
+    cortex_m::interrupt::free(|cs| STATS.borrow(cs).borrow_mut()[stage as usize].record(cycles));
+
+    result
+}
+
+#[cfg(not(all(feature = "profiling", target_arch = "arm")))]
+#[inline(always)]
+pub fn measure<T>(_: Stage, f: impl FnOnce() -> T) -> T {
+    f()
+}
+
+/// Snapshot of the cycles accumulated for `stage`.
+#[cfg(all(feature = "profiling", target_arch = "arm"))]
+pub fn stats(stage: Stage) -> StageStats {
+    cortex_m::interrupt::free(|cs| STATS.borrow(cs).borrow()[stage as usize])
+}
+
+#[cfg(all(feature = "profiling", not(target_arch = "arm")))]
+pub fn stats(_: Stage) -> StageStats {
+    StageStats::new()
+}
+
+/// Clears every accumulated measurement.
+#[cfg(all(feature = "profiling", target_arch = "arm"))]
+pub fn reset() {
+    cortex_m::interrupt::free(|cs| {
+        *STATS.borrow(cs).borrow_mut() = [StageStats::new(); Stage::COUNT]
+    });
+}
+
+#[cfg(all(feature = "profiling", not(target_arch = "arm")))]
+pub fn reset() {}
+
+/// Snapshot of every stage, for `show profiling`.
+#[cfg(feature = "profiling")]
+pub fn report() -> Report {
+    Report(Stage::ALL.map(stats))
+}
+
+/// The output of `show profiling`, a line per stage with the number of measurements and their
+/// minimum, average and maximum cycles. Stages that never ran show dashes.
+pub struct Report(pub [StageStats; Stage::COUNT]);
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>8} {:>10} {:>10} {:>10}",
+            "Stage", "Count", "Min", "Avg", "Max"
+        )?;
+        for (stage, stats) in Stage::ALL.iter().zip(&self.0) {
+            match stats.avg() {
+                Some(avg) => writeln!(
+                    f,
+                    "{:<20} {:>8} {:>10} {:>10} {:>10}",
+                    stage.name(),
+                    stats.count,
+                    stats.min,
+                    avg,
+                    stats.max
+                )?,
+                None => writeln!(
+                    f,
+                    "{:<20} {:>8} {:>10} {:>10} {:>10}",
+                    stage.name(),
+                    0,
+                    "-",
+                    "-",
+                    "-"
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use super::*;
+
+    #[test]
+    fn report_has_a_line_per_stage() {
+        let mut stats = [StageStats::new(); Stage::COUNT];
+        stats[Stage::Forwarding as usize] = StageStats {
+            min: 100,
+            max: 300,
+            total: 400,
+            count: 2,
+        };
+
+        let mut out = heapless::String::<512>::new();
+        write!(out, "{}", Report(stats)).unwrap();
+        let mut lines = out.lines().skip(1);
+        assert_eq!(lines.clone().count(), Stage::COUNT);
+        assert!(
+            lines
+                .nth(Stage::Forwarding as usize)
+                .unwrap()
+                .split_whitespace()
+                .eq(["forwarding", "2", "100", "200", "300"])
+        );
+        assert!(out.lines().nth(1).unwrap().split_whitespace().eq([
+            "spi_transfer",
+            "0",
+            "-",
+            "-",
+            "-"
+        ]));
+    }
+}
@@ -0,0 +1,111 @@
+//! Per-key token bucket rate limiting.
+//!
+//! Meant to keep a single misbehaving source (a LAN client flooding DNS queries, a DHCP
+//! starvation attempt, ...) from exhausting shared resources.
+
+/// A bucket refilling one token every `ticks_per_token` up to `capacity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBucket {
+    tokens: u32,
+    updated_at: u32,
+}
+
+impl TokenBucket {
+    pub(crate) fn full(capacity: u32, now: u32) -> Self {
+        Self {
+            tokens: capacity,
+            updated_at: now,
+        }
+    }
+
+    pub(crate) fn refill(&mut self, capacity: u32, ticks_per_token: u32, now: u32) {
+        let ticks_per_token = ticks_per_token.max(1);
+        let new_tokens = now.wrapping_sub(self.updated_at) / ticks_per_token;
+        if new_tokens == 0 {
+            return;
+        }
+
+        if self.tokens.saturating_add(new_tokens) >= capacity {
+            self.tokens = capacity;
+            self.updated_at = now;
+        } else {
+            self.tokens += new_tokens;
+            // Keep the remainder so slow, steady callers aren't starved by rounding.
+            self.updated_at = self.updated_at.wrapping_add(new_tokens * ticks_per_token);
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> bool {
+        if self.tokens == 0 {
+            return false;
+        }
+
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// Token buckets for up to `N` keys, every key allowed bursts of `capacity` events
+/// and a sustained rate of one event per `ticks_per_token`.
+pub struct RateLimiter<K, const N: usize> {
+    buckets: heapless::Vec<(K, TokenBucket), N>,
+    capacity: u32,
+    ticks_per_token: u32,
+    /// Events refused since creation.
+    limited: u32,
+}
+
+impl<K: Copy + Eq, const N: usize> RateLimiter<K, N> {
+    pub fn new(capacity: u32, ticks_per_token: u32) -> Self {
+        Self {
+            buckets: heapless::Vec::new(),
+            capacity,
+            ticks_per_token,
+            limited: 0,
+        }
+    }
+
+    /// Takes a token for `key`, returning whether the event is allowed.
+    ///
+    /// When all `N` slots are in use the key idle for longest is forgotten to make room,
+    /// so it starts over with a full bucket next time it shows up.
+    pub fn check(&mut self, key: K, now: u32) -> bool {
+        let (capacity, ticks_per_token) = (self.capacity, self.ticks_per_token);
+
+        let index = match self.buckets.iter().position(|(k, _)| *k == key) {
+            Some(index) => index,
+            None => {
+                if self.buckets.is_full() {
+                    let Some(idle) = self
+                        .buckets
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, (_, bucket))| now.wrapping_sub(bucket.updated_at))
+                        .map(|(i, _)| i)
+                    else {
+                        // Only with N == 0, nothing to track so nothing to limit.
+                        return true;
+                    };
+                    self.buckets.swap_remove(idle);
+                }
+
+                let _ = self.buckets.push((key, TokenBucket::full(capacity, now)));
+                self.buckets.len() - 1
+            }
+        };
+
+        let bucket = &mut self.buckets[index].1;
+        bucket.refill(capacity, ticks_per_token, now);
+        let allowed = bucket.take();
+        if !allowed {
+            self.limited = self.limited.saturating_add(1);
+        }
+
+        allowed
+    }
+
+    /// Number of events refused so far.
+    pub fn limited(&self) -> u32 {
+        self.limited
+    }
+}
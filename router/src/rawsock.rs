@@ -0,0 +1,138 @@
+//! Raw sockets: whole Ethernet frames to and from an application.
+//!
+//! Lets an application prototype a protocol the stack doesn't implement yet by sending and
+//! receiving frames on an interface as they are on the wire. Received frames are copied to the
+//! socket, the stack still handles them; frames sent are queued for the stack to hand to the
+//! interface's transmit queue. Unlike [`crate::rxhooks`], the socket is drained by the
+//! application in its own time, at the cost of a copy.
+//!
+//! A raw socket bypasses the firewall both ways, so sockets only work while the
+//! `eth.raw_sockets` setting is on.
+
+use thiserror::Error;
+
+use crate::interface::InterfaceId;
+
+/// Destination, source and EtherType, the least a frame sent on a socket must have.
+const ETHERNET_HEADER_LEN: usize = 14;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RawSocketError {
+    #[error("Raw sockets are disabled.")]
+    Disabled,
+    #[error("Frame is too short or too long for the socket.")]
+    InvalidLength,
+    #[error("Socket's transmit queue is full.")]
+    QueueFull,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RawSocketStats {
+    pub received: u32,
+    pub sent: u32,
+    /// Received frames dropped because the application didn't keep up, or they were too long.
+    pub rx_dropped: u32,
+    /// Most frames queued at once each way.
+    pub rx_high_water: usize,
+    pub tx_high_water: usize,
+}
+
+/// Raw socket bound to an interface, queueing up to `Q` frames of up to `F` bytes each way.
+pub struct RawSocket<const Q: usize, const F: usize> {
+    interface: InterfaceId,
+    /// Only frames of this EtherType are received, all of them if `None`.
+    ethertype: Option<u16>,
+    rx: heapless::Deque<heapless::Vec<u8, F>, Q>,
+    tx: heapless::Deque<heapless::Vec<u8, F>, Q>,
+    stats: RawSocketStats,
+}
+
+impl<const Q: usize, const F: usize> RawSocket<Q, F> {
+    pub fn new(interface: InterfaceId, ethertype: Option<u16>) -> Self {
+        Self {
+            interface,
+            ethertype,
+            rx: heapless::Deque::new(),
+            tx: heapless::Deque::new(),
+            stats: RawSocketStats::default(),
+        }
+    }
+
+    pub fn interface(&self) -> InterfaceId {
+        self.interface
+    }
+
+    pub fn stats(&self) -> RawSocketStats {
+        self.stats
+    }
+
+    /// Copies a frame received on `interface` to the socket if it's bound there and the frame
+    /// matches its EtherType. Call it on every received frame with `enabled` from the config.
+    pub fn deliver(&mut self, enabled: bool, interface: InterfaceId, frame: &[u8]) {
+        if !enabled || interface != self.interface {
+            return;
+        }
+        if let Some(ethertype) = self.ethertype
+            && frame.get(12..14) != Some(&ethertype.to_be_bytes())
+        {
+            return;
+        }
+
+        let Ok(copy) = heapless::Vec::from_slice(frame) else {
+            self.stats.rx_dropped += 1;
+            return;
+        };
+        if self.rx.push_back(copy).is_err() {
+            self.stats.rx_dropped += 1;
+            return;
+        }
+        self.stats.received += 1;
+        self.stats.rx_high_water = self.stats.rx_high_water.max(self.rx.len());
+    }
+
+    /// Takes the oldest received frame, copying it into `buffer` and returning its length.
+    /// Frames longer than `buffer` are truncated.
+    pub fn recv(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let frame = self.rx.pop_front()?;
+        let len = frame.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&frame[..len]);
+        Some(len)
+    }
+
+    /// Queues a whole frame, Ethernet header included, for transmission on the interface.
+    pub fn send(&mut self, enabled: bool, frame: &[u8]) -> Result<(), RawSocketError> {
+        if !enabled {
+            return Err(RawSocketError::Disabled);
+        }
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return Err(RawSocketError::InvalidLength);
+        }
+
+        let copy = heapless::Vec::from_slice(frame).map_err(|_| RawSocketError::InvalidLength)?;
+        self.tx
+            .push_back(copy)
+            .map_err(|_| RawSocketError::QueueFull)?;
+        self.stats.tx_high_water = self.stats.tx_high_water.max(self.tx.len());
+        Ok(())
+    }
+
+    pub const fn capacity(&self) -> usize {
+        Q
+    }
+
+    /// Takes the oldest frame sent by the application, for the stack to transmit on
+    /// [`Self::interface`]. Frames queued before the socket was disabled are dropped.
+    pub fn poll_transmit(&mut self, enabled: bool) -> Option<heapless::Vec<u8, F>> {
+        if !enabled {
+            self.tx.clear();
+            self.rx.clear();
+            return None;
+        }
+
+        let frame = self.tx.pop_front()?;
+        self.stats.sent += 1;
+        Some(frame)
+    }
+}
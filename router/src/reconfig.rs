@@ -0,0 +1,278 @@
+//! Applying a changed configuration to the running subsystems.
+//!
+//! A new configuration, from the CLI or an upload, is compared with the running one and only
+//! what changed is applied. Subsystems aren't reinitialized: state the change doesn't affect,
+//! like connections still allowed by the firewall or leases still within the DHCP pool, is
+//! kept, so editing a rule doesn't cut every open session.
+
+use crate::{config::Config, conntrack::Conntrack, firewall::Firewall, lease::Leases, log};
+
+/// Groups of settings that differ between two configurations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Changes {
+    pub lan_address: bool,
+    /// Pool range, the lease time only applies to new leases.
+    pub dhcp_pool: bool,
+    pub firewall: bool,
+    pub log_levels: bool,
+    /// IPv4 options policy, to pass to [`crate::ipopts::OptionsFilter::set_policy`].
+    pub ip_options: bool,
+    /// ICMP query policy, to pass to [`crate::icmp::IcmpQueries::set_policy`].
+    pub icmp_queries: bool,
+    /// Hostname or LAN domain, the router's name in the DNS local zone is to be republished
+    /// with [`crate::identity::withdraw`] and [`crate::identity::publish`].
+    pub identity: bool,
+    /// Settings read where they're used, needing no action.
+    pub other: bool,
+}
+
+impl Changes {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let lan_address = old.lan_address != new.lan_address;
+        let dhcp_pool =
+            old.dhcp_pool_start != new.dhcp_pool_start || old.dhcp_pool_end != new.dhcp_pool_end;
+        let firewall = old.firewall_default != new.firewall_default;
+        let log_levels = old.log_levels != new.log_levels;
+        let ip_options = old.options_policy() != new.options_policy();
+        let icmp_queries = old.icmp_policy() != new.icmp_policy();
+        let identity = old.hostname != new.hostname || old.domain != new.domain;
+
+        // The new configuration with the groups above left as they were.
+        let rest = Config {
+            lan_address: old.lan_address,
+            dhcp_pool_start: old.dhcp_pool_start,
+            dhcp_pool_end: old.dhcp_pool_end,
+            firewall_default: old.firewall_default,
+            log_levels: old.log_levels,
+            ip_source_route: old.ip_source_route,
+            ip_options: old.ip_options,
+            icmp_timestamp: old.icmp_timestamp,
+            icmp_address_mask: old.icmp_address_mask,
+            hostname: old.hostname.clone(),
+            domain: old.domain.clone(),
+            ..new.clone()
+        };
+        let other = rest != *old;
+
+        Self {
+            lan_address,
+            dhcp_pool,
+            firewall,
+            log_levels,
+            ip_options,
+            icmp_queries,
+            identity,
+            other,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What applying a configuration dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Applied {
+    pub changes: Changes,
+    pub flows_dropped: usize,
+    pub leases_dropped: usize,
+}
+
+/// Applies `new` over `old` to the running subsystems, keeping what the changes don't affect.
+///
+/// Leases outside of the new pool and flows of the old LAN address or refused by the new
+/// firewall default are dropped, the rest is kept as is. A new firewall default is committed
+/// along with any staged rule edits.
+pub fn apply<const R: usize, T, const C: usize, const L: usize>(
+    old: &Config,
+    new: &Config,
+    firewall: &mut Firewall<R>,
+    conntrack: &mut Conntrack<T, C>,
+    leases: &mut Leases<L>,
+) -> Applied {
+    let changes = Changes::between(old, new);
+    let mut applied = Applied {
+        changes,
+        ..Applied::default()
+    };
+
+    if changes.lan_address {
+        let old_address = old.lan_address.address();
+        let before = conntrack.len();
+        conntrack.retain(|flow| {
+            *flow.key.source.ip() != old_address && *flow.key.destination.ip() != old_address
+        });
+        applied.flows_dropped += before - conntrack.len();
+    }
+
+    if changes.dhcp_pool || changes.lan_address {
+        let pool = new.dhcp_pool_start..=new.dhcp_pool_end;
+        let before = leases.len();
+        leases.retain(|lease| pool.contains(&lease.address));
+        applied.leases_dropped += before - leases.len();
+    }
+
+    if changes.firewall {
+        firewall.staged().default_action = new.firewall_default;
+        firewall.commit();
+        applied.flows_dropped += firewall.revalidate(conntrack);
+    }
+
+    if changes.log_levels {
+        log::set_levels(&new.log_levels);
+    }
+
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+    use crate::{
+        cidr::Ipv4Cidr,
+        conntrack::{EvictionPolicy, FlowKey, FlowState, Protocol, TcpState, TimeoutProfile},
+        ethernet::MacAddress,
+        firewall::{Action, Rule},
+    };
+
+    struct Router {
+        firewall: Firewall<4>,
+        conntrack: Conntrack<(), 8>,
+        leases: Leases<4>,
+    }
+
+    impl Router {
+        fn new(config: &Config, flows: &[FlowKey]) -> Self {
+            let mut conntrack =
+                Conntrack::new(TimeoutProfile::default(), EvictionPolicy::RefuseNew);
+            for key in flows {
+                conntrack
+                    .insert(*key, FlowState::Tcp(TcpState::Established), (), 0)
+                    .unwrap();
+            }
+            Self {
+                firewall: Firewall::new(config.firewall_default),
+                conntrack,
+                leases: Leases::new(),
+            }
+        }
+
+        fn apply(&mut self, old: &Config, new: &Config) -> Applied {
+            apply(
+                old,
+                new,
+                &mut self.firewall,
+                &mut self.conntrack,
+                &mut self.leases,
+            )
+        }
+
+        fn tracks(&self, key: &FlowKey) -> bool {
+            self.conntrack.get(key).is_some()
+        }
+    }
+
+    fn flow(source: Ipv4Addr, port: u16) -> FlowKey {
+        FlowKey {
+            protocol: Protocol::Tcp,
+            source: SocketAddrV4::new(source, 40000),
+            destination: SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 7), port),
+        }
+    }
+
+    fn config(firewall_default: Action) -> Config {
+        Config {
+            firewall_default,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn connection_survives_a_rule_addition() {
+        let old = config(Action::Accept);
+        let new = config(Action::Drop);
+        let https = flow(Ipv4Addr::new(192, 168, 1, 10), 443);
+        let ssh = flow(Ipv4Addr::new(192, 168, 1, 10), 22);
+        let mut router = Router::new(&old, &[https, ssh]);
+        router
+            .firewall
+            .staged()
+            .push(Rule {
+                destination_ports: Some(443..=443),
+                ..Rule::any(Action::Accept)
+            })
+            .unwrap();
+
+        let applied = router.apply(&old, &new);
+        assert!(applied.changes.firewall);
+        assert_eq!(applied.flows_dropped, 1);
+        assert!(router.tracks(&https));
+        assert!(!router.tracks(&ssh));
+        assert_eq!(router.firewall.active().rules().len(), 1);
+    }
+
+    #[test]
+    fn unrelated_change_keeps_flows_and_leases() {
+        let old = config(Action::Drop);
+        let new = Config {
+            wan_preempt: !old.wan_preempt,
+            ..old.clone()
+        };
+        let https = flow(Ipv4Addr::new(192, 168, 1, 10), 443);
+        let mut router = Router::new(&old, &[https]);
+        router
+            .leases
+            .insert(MacAddress([2, 0, 0, 0, 0, 1]), old.dhcp_pool_start, 100, 0)
+            .unwrap();
+        // Staged edits wait for a firewall change or an explicit commit.
+        router
+            .firewall
+            .staged()
+            .push(Rule::any(Action::Drop))
+            .unwrap();
+
+        let applied = router.apply(&old, &new);
+        assert_eq!(
+            applied.changes,
+            Changes {
+                other: true,
+                ..Changes::default()
+            }
+        );
+        assert_eq!((applied.flows_dropped, applied.leases_dropped), (0, 0));
+        assert!(router.tracks(&https));
+        assert_eq!(router.leases.len(), 1);
+        assert!(router.firewall.active().rules().is_empty());
+    }
+
+    #[test]
+    fn lan_address_change_drops_the_old_address_flows_and_leases() {
+        let old = config(Action::Accept);
+        let new = Config {
+            lan_address: Ipv4Cidr::new(Ipv4Addr::new(10, 0, 0, 1), 24).unwrap(),
+            dhcp_pool_start: Ipv4Addr::new(10, 0, 0, 100),
+            dhcp_pool_end: Ipv4Addr::new(10, 0, 0, 199),
+            ..old.clone()
+        };
+        let to_router = FlowKey {
+            destination: SocketAddrV4::new(old.lan_address.address(), 53),
+            ..flow(Ipv4Addr::new(192, 168, 1, 10), 53)
+        };
+        let forwarded = flow(Ipv4Addr::new(192, 168, 1, 10), 443);
+        let mut router = Router::new(&old, &[to_router, forwarded]);
+        router
+            .leases
+            .insert(MacAddress([2, 0, 0, 0, 0, 1]), old.dhcp_pool_start, 100, 0)
+            .unwrap();
+
+        let applied = router.apply(&old, &new);
+        assert_eq!((applied.flows_dropped, applied.leases_dropped), (1, 1));
+        assert!(router.tracks(&forwarded));
+        assert!(router.leases.is_empty());
+    }
+}
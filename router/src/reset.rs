@@ -0,0 +1,94 @@
+//! Factory reset by holding a button while booting.
+//!
+//! Holding the button for [`ResetConfig::hold_time`] right after power up confirms a reset, upon
+//! which the caller erases the stored configuration and reboots into defaults. This keeps a bad
+//! firewall rule or a forgotten password from locking the user out for good. The LED blinks
+//! while the button is held and stays lit once the reset is confirmed.
+//!
+//! The default timings assume one tick per millisecond.
+
+use embedded_hal::digital::{InputPin, OutputPin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ResetConfig {
+    /// How long the button has to be held to confirm the reset.
+    pub hold_time: u32,
+    /// Half period of the LED blinking while the button is held.
+    pub blink_interval: u32,
+    /// Whether the button pulls its pin low when pressed.
+    pub active_low: bool,
+}
+
+impl Default for ResetConfig {
+    fn default() -> Self {
+        Self {
+            hold_time: 5000,
+            blink_interval: 250,
+            active_low: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetState {
+    /// The button isn't pressed, or was let go before the reset was confirmed; boot normally.
+    Released,
+    /// The button is pressed but not for long enough yet.
+    Holding,
+    /// The button was held for long enough, erase the configuration.
+    Confirmed,
+}
+
+pub struct ResetButton<B, L> {
+    button: B,
+    led: L,
+    config: ResetConfig,
+    pressed_at: Option<u32>,
+}
+
+impl<B: InputPin, L: OutputPin> ResetButton<B, L> {
+    pub fn new(button: B, led: L, config: ResetConfig) -> Self {
+        Self {
+            button,
+            led,
+            config,
+            pressed_at: None,
+        }
+    }
+
+    /// Samples the button and updates the LED, to be called every few ticks until it returns
+    /// anything but [`ResetState::Holding`].
+    ///
+    /// A button that can't be read counts as released, so a faulty pin never wipes the
+    /// configuration.
+    pub fn poll(&mut self, now: u32) -> ResetState {
+        let pressed = if self.config.active_low {
+            self.button.is_low()
+        } else {
+            self.button.is_high()
+        };
+        if !pressed.unwrap_or(false) {
+            self.pressed_at = None;
+            let _ = self.led.set_low();
+            return ResetState::Released;
+        }
+
+        let pressed_at = *self.pressed_at.get_or_insert(now);
+        let held = now.wrapping_sub(pressed_at);
+        if held >= self.config.hold_time {
+            let _ = self.led.set_high();
+            return ResetState::Confirmed;
+        }
+
+        let lit = (held / self.config.blink_interval.max(1)).is_multiple_of(2);
+        let _ = self.led.set_state(lit.into());
+        ResetState::Holding
+    }
+
+    /// Gives back the pins, e.g. to reuse the LED for status afterwards.
+    pub fn release(self) -> (B, L) {
+        (self.button, self.led)
+    }
+}
@@ -0,0 +1,126 @@
+//! IPv4 routing table with source based policy rules.
+//!
+//! Routes live in numbered tables, [`MAIN_TABLE`] being the default one. Policy rules pick
+//! another table by source prefix, e.g. to send the guest subnet out of a secondary WAN. Within
+//! a table the longest matching prefix wins, ties broken by the lowest metric.
+
+use core::net::Ipv4Addr;
+
+use thiserror::Error;
+
+use crate::{
+    cidr::Ipv4Cidr,
+    interface::InterfaceId,
+    profiling::{self, Stage},
+};
+
+pub const MAIN_TABLE: u8 = 0;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RoutingError {
+    #[error("Routing table ran out of memory for additional routes.")]
+    RoutesOutOfMemory,
+    #[error("Routing table ran out of memory for additional rules.")]
+    RulesOutOfMemory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub table: u8,
+    pub destination: Ipv4Cidr,
+    /// Next hop, `None` for directly connected subnets.
+    pub gateway: Option<Ipv4Addr>,
+    pub interface: InterfaceId,
+    pub metric: u32,
+}
+
+/// Sends traffic from `source` to `table`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub source: Ipv4Cidr,
+    pub table: u8,
+    /// DNS server handed to matching clients instead of the default one.
+    pub dns: Option<Ipv4Addr>,
+}
+
+/// Up to `N` routes across all tables and `R` policy rules.
+pub struct RoutingTable<const N: usize, const R: usize> {
+    routes: heapless::Vec<Route, N>,
+    rules: heapless::Vec<PolicyRule, R>,
+}
+
+impl<const N: usize, const R: usize> Default for RoutingTable<N, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const R: usize> RoutingTable<N, R> {
+    pub const fn new() -> Self {
+        Self {
+            routes: heapless::Vec::new(),
+            rules: heapless::Vec::new(),
+        }
+    }
+
+    pub fn add_route(&mut self, route: Route) -> Result<(), RoutingError> {
+        self.routes
+            .push(route)
+            .map_err(|_| RoutingError::RoutesOutOfMemory)
+    }
+
+    /// Removes every route matching `f`.
+    pub fn remove_routes(&mut self, f: impl Fn(&Route) -> bool) {
+        self.routes.retain(|route| !f(route));
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Appends a rule, rules are evaluated in insertion order.
+    pub fn add_rule(&mut self, rule: PolicyRule) -> Result<(), RoutingError> {
+        self.rules
+            .push(rule)
+            .map_err(|_| RoutingError::RulesOutOfMemory)
+    }
+
+    pub fn remove_rules(&mut self, f: impl Fn(&PolicyRule) -> bool) {
+        self.rules.retain(|rule| !f(rule));
+    }
+
+    pub fn rules(&self) -> &[PolicyRule] {
+        &self.rules
+    }
+
+    /// Route for a packet from `source` to `destination`.
+    ///
+    /// Tables selected by matching policy rules are tried in order, then the main table.
+    pub fn lookup(&self, source: Ipv4Addr, destination: Ipv4Addr) -> Option<&Route> {
+        profiling::measure(Stage::Forwarding, || {
+            self.rules
+                .iter()
+                .filter(|rule| rule.source.contains(source))
+                .map(|rule| rule.table)
+                .chain([MAIN_TABLE])
+                .find_map(|table| self.lookup_in(table, destination))
+        })
+    }
+
+    /// DNS override of the first matching rule that has one.
+    pub fn dns_for(&self, source: Ipv4Addr) -> Option<Ipv4Addr> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.source.contains(source))
+            .find_map(|rule| rule.dns)
+    }
+
+    /// Longest prefix match for `destination` in `table`.
+    pub fn lookup_in(&self, table: u8, destination: Ipv4Addr) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.table == table && route.destination.contains(destination))
+            .min_by_key(|route| (u8::MAX - route.destination.prefix_len(), route.metric))
+    }
+}
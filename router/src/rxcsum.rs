@@ -0,0 +1,283 @@
+//! Checksum verification of received packets.
+//!
+//! Summing every received byte costs this MCU dearly, so each protocol's checksum gets its own
+//! mode: verified in software, offloaded to the ENC28J60's DMA checksum unit, which sums the
+//! packet where it sits in the chip's buffer, or skipped for bridged traffic. Bridged packets
+//! aren't for the router and their end hosts verify them anyway; packets for the router or
+//! routed by it are always verified, in software when their protocol is set to skip.
+//!
+//! Packets failing verification are dropped and counted per protocol.
+
+use core::{fmt, ops::Range, str::FromStr};
+
+use crate::checksum;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+
+mod protocol_number {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+}
+
+/// Protocols whose checksums are verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChecksumProtocol {
+    /// The IPv4 header's.
+    Ipv4,
+    Icmp,
+    Tcp,
+    Udp,
+}
+
+impl ChecksumProtocol {
+    pub const COUNT: usize = 4;
+    pub const ALL: [ChecksumProtocol; Self::COUNT] = [
+        ChecksumProtocol::Ipv4,
+        ChecksumProtocol::Icmp,
+        ChecksumProtocol::Tcp,
+        ChecksumProtocol::Udp,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            ChecksumProtocol::Ipv4 => "ipv4",
+            ChecksumProtocol::Icmp => "icmp",
+            ChecksumProtocol::Tcp => "tcp",
+            ChecksumProtocol::Udp => "udp",
+        }
+    }
+
+    /// Protocol of an IPv4 packet's payload, if its checksum is verified.
+    pub fn of_payload(packet: &[u8]) -> Option<Self> {
+        match *packet.get(9)? {
+            protocol_number::ICMP => Some(ChecksumProtocol::Icmp),
+            protocol_number::TCP => Some(ChecksumProtocol::Tcp),
+            protocol_number::UDP => Some(ChecksumProtocol::Udp),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ChecksumProtocol {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|protocol| protocol.name() == s)
+            .ok_or(())
+    }
+}
+
+/// How a protocol's checksums are verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VerifyMode {
+    Software,
+    /// By the chip's DMA checksum unit.
+    Hardware,
+    /// Not verified when bridged, in software otherwise.
+    Skip,
+}
+
+impl VerifyMode {
+    const ALL: [VerifyMode; 3] = [VerifyMode::Software, VerifyMode::Hardware, VerifyMode::Skip];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            VerifyMode::Software => "software",
+            VerifyMode::Hardware => "hardware",
+            VerifyMode::Skip => "skip",
+        }
+    }
+}
+
+impl fmt::Display for VerifyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for VerifyMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or(())
+    }
+}
+
+/// Mode for each [`ChecksumProtocol`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChecksumPolicy {
+    /// Indexed by [`ChecksumProtocol`].
+    pub modes: [VerifyMode; ChecksumProtocol::COUNT],
+}
+
+impl Default for ChecksumPolicy {
+    fn default() -> Self {
+        Self {
+            modes: [VerifyMode::Software; ChecksumProtocol::COUNT],
+        }
+    }
+}
+
+impl ChecksumPolicy {
+    pub fn mode(&self, protocol: ChecksumProtocol) -> VerifyMode {
+        self.modes[protocol as usize]
+    }
+}
+
+/// Counters of the checksums verified, indexed by [`ChecksumProtocol`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChecksumStats {
+    pub verified: [u32; ChecksumProtocol::COUNT],
+    /// Packets dropped for a bad checksum.
+    pub dropped: [u32; ChecksumProtocol::COUNT],
+    pub skipped: [u32; ChecksumProtocol::COUNT],
+    /// Verifications handed to the chip.
+    pub offloaded: [u32; ChecksumProtocol::COUNT],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Verification {
+    Valid,
+    /// The packet must be dropped.
+    Invalid,
+    /// The chip has to sum this range of the packet, see [`ChecksumVerifier::offloaded`].
+    Offload(Range<usize>),
+}
+
+/// Verifies checksums of received IPv4 packets according to a [`ChecksumPolicy`].
+pub struct ChecksumVerifier {
+    policy: ChecksumPolicy,
+    stats: ChecksumStats,
+}
+
+impl ChecksumVerifier {
+    pub fn new(policy: ChecksumPolicy) -> Self {
+        Self {
+            policy,
+            stats: ChecksumStats::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &ChecksumPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ChecksumPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn stats(&self) -> ChecksumStats {
+        self.stats
+    }
+
+    /// Verifies the `protocol` checksum of the IPv4 `packet`, the header's or the payload's.
+    ///
+    /// A truncated packet is invalid. A UDP datagram without a checksum is valid.
+    pub fn verify(
+        &mut self,
+        protocol: ChecksumProtocol,
+        packet: &[u8],
+        bridged: bool,
+    ) -> Verification {
+        let Some(range) = checksummed(protocol, packet) else {
+            return self.record(protocol, false);
+        };
+
+        match self.policy.mode(protocol) {
+            VerifyMode::Skip if bridged => {
+                self.stats.skipped[protocol as usize] += 1;
+                Verification::Valid
+            }
+            _ if protocol == ChecksumProtocol::Udp && packet[range.start + 6..][..2] == [0, 0] => {
+                self.stats.skipped[protocol as usize] += 1;
+                Verification::Valid
+            }
+            VerifyMode::Hardware => {
+                self.stats.offloaded[protocol as usize] += 1;
+                Verification::Offload(range)
+            }
+            VerifyMode::Software | VerifyMode::Skip => {
+                let sum = match pseudo_header(protocol, packet) {
+                    Some(pseudo_header) => checksum::checksum_with(&pseudo_header, &packet[range]),
+                    None => checksum::checksum(&packet[range]),
+                };
+                self.record(protocol, sum == 0)
+            }
+        }
+    }
+
+    /// Completes a verification handed to the chip with the `dma_checksum` it computed over
+    /// the range of `packet` it was given.
+    pub fn offloaded(
+        &mut self,
+        protocol: ChecksumProtocol,
+        packet: &[u8],
+        dma_checksum: u16,
+    ) -> Verification {
+        let sum = match pseudo_header(protocol, packet) {
+            Some(pseudo_header) => checksum::extend(dma_checksum, &pseudo_header),
+            None => dma_checksum,
+        };
+        self.record(protocol, sum == 0)
+    }
+
+    fn record(&mut self, protocol: ChecksumProtocol, valid: bool) -> Verification {
+        if valid {
+            self.stats.verified[protocol as usize] += 1;
+            Verification::Valid
+        } else {
+            self.stats.dropped[protocol as usize] += 1;
+            Verification::Invalid
+        }
+    }
+}
+
+/// Range of `packet` covered by the `protocol` checksum, `None` if truncated.
+fn checksummed(protocol: ChecksumProtocol, packet: &[u8]) -> Option<Range<usize>> {
+    let header_len = (*packet.first()? & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+    if header_len < IPV4_MIN_HEADER_LEN || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+
+    let range = match protocol {
+        ChecksumProtocol::Ipv4 => 0..header_len,
+        ChecksumProtocol::Icmp => header_len..total_len,
+        ChecksumProtocol::Tcp => (total_len - header_len >= 20).then_some(header_len..total_len)?,
+        ChecksumProtocol::Udp => (total_len - header_len >= 8).then_some(header_len..total_len)?,
+    };
+    Some(range)
+}
+
+/// Pseudo-header TCP and UDP checksum along with their segment: addresses, protocol and
+/// segment length.
+fn pseudo_header(protocol: ChecksumProtocol, packet: &[u8]) -> Option<[u8; 12]> {
+    if !matches!(protocol, ChecksumProtocol::Tcp | ChecksumProtocol::Udp) {
+        return None;
+    }
+
+    let range = checksummed(protocol, packet)?;
+    let segment_len = (range.end - range.start) as u16;
+    let mut pseudo_header = [0; 12];
+    pseudo_header[..8].copy_from_slice(&packet[12..20]);
+    pseudo_header[9] = packet[9];
+    pseudo_header[10..].copy_from_slice(&segment_len.to_be_bytes());
+    Some(pseudo_header)
+}
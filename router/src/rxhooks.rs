@@ -0,0 +1,194 @@
+//! Receive hooks for protocols the stack doesn't implement.
+//!
+//! User code registers a handler for an EtherType or a UDP port, and matching frames are handed
+//! to it before the stack sees them, straight from the receive buffer. The handler either
+//! consumes the frame, e.g. a hobby industrial protocol over its own EtherType, or passes it on
+//! to the stack, e.g. to only watch it.
+//!
+//! Handlers run in the receive path and must return quickly. UDP hooks only see unfragmented
+//! datagrams, and take precedence over the router's own services on the same port.
+
+use thiserror::Error;
+
+use crate::{ethernet::ethertype, interface::InterfaceId};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const UDP: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HookError {
+    #[error("No room for another receive hook.")]
+    Full,
+    #[error("A receive hook is already registered for this key.")]
+    AlreadyRegistered,
+}
+
+/// Frames a hook is registered for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HookKey {
+    /// Frames of the EtherType, after any VLAN tag.
+    EtherType(u16),
+    /// IPv4 UDP datagrams to the port.
+    UdpPort(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HookVerdict {
+    /// The frame was handled, the stack doesn't see it.
+    Consumed,
+    /// The stack handles the frame as usual.
+    Pass,
+}
+
+/// A received frame matching a hook.
+#[derive(Debug, Clone, Copy)]
+pub struct RxFrame<'a> {
+    pub interface: InterfaceId,
+    /// The whole frame, Ethernet header included.
+    pub frame: &'a [u8],
+    /// What follows the EtherType, or the UDP header for [`HookKey::UdpPort`].
+    pub payload: &'a [u8],
+}
+
+pub type RxHandler = fn(&RxFrame<'_>) -> HookVerdict;
+
+/// Handle of a registered hook, to unregister it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HookId(u8);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HookStats {
+    /// Frames handed to a hook.
+    pub matched: u32,
+    /// Frames the hooks consumed.
+    pub consumed: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Hook {
+    id: HookId,
+    key: HookKey,
+    handler: RxHandler,
+}
+
+/// Up to `N` receive hooks.
+pub struct RxHooks<const N: usize> {
+    hooks: heapless::Vec<Hook, N>,
+    next_id: u8,
+    stats: HookStats,
+}
+
+impl<const N: usize> Default for RxHooks<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RxHooks<N> {
+    pub const fn new() -> Self {
+        Self {
+            hooks: heapless::Vec::new(),
+            next_id: 0,
+            stats: HookStats {
+                matched: 0,
+                consumed: 0,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> HookStats {
+        self.stats
+    }
+
+    pub fn register(&mut self, key: HookKey, handler: RxHandler) -> Result<HookId, HookError> {
+        if self.hooks.iter().any(|hook| hook.key == key) {
+            return Err(HookError::AlreadyRegistered);
+        }
+
+        let id = HookId(self.next_id);
+        self.hooks
+            .push(Hook { id, key, handler })
+            .map_err(|_| HookError::Full)?;
+        self.next_id = self.next_id.wrapping_add(1);
+        Ok(id)
+    }
+
+    /// Removes a hook, returning whether it was registered.
+    pub fn unregister(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() != len
+    }
+
+    /// Hands `frame` to the hook matching it, if any. Call it on every received frame before
+    /// the stack, which handles the frame unless it was consumed.
+    pub fn dispatch(&mut self, interface: InterfaceId, frame: &[u8]) -> HookVerdict {
+        if self.hooks.is_empty() {
+            return HookVerdict::Pass;
+        }
+        let Some((frame_ethertype, offset)) = ethertype_of(frame) else {
+            return HookVerdict::Pass;
+        };
+
+        let matching = |key: HookKey| self.hooks.iter().find(|hook| hook.key == key);
+        let (hook, payload) = if let Some(hook) = matching(HookKey::EtherType(frame_ethertype)) {
+            (hook, &frame[offset..])
+        } else if frame_ethertype == ethertype::IPV4
+            && let Some((port, payload)) = udp_datagram(&frame[offset..])
+            && let Some(hook) = matching(HookKey::UdpPort(port))
+        {
+            (hook, payload)
+        } else {
+            return HookVerdict::Pass;
+        };
+
+        let verdict = (hook.handler)(&RxFrame {
+            interface,
+            frame,
+            payload,
+        });
+        self.stats.matched += 1;
+        if verdict == HookVerdict::Consumed {
+            self.stats.consumed += 1;
+        }
+        verdict
+    }
+}
+
+/// EtherType of `frame` and the offset of what follows it, skipping a VLAN tag.
+fn ethertype_of(frame: &[u8]) -> Option<(u16, usize)> {
+    let read = |offset: usize| {
+        let bytes = frame.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    match read(ETHERNET_HEADER_LEN - 2)? {
+        ethertype::VLAN => Some((
+            read(ETHERNET_HEADER_LEN - 2 + VLAN_TAG_LEN)?,
+            ETHERNET_HEADER_LEN + VLAN_TAG_LEN,
+        )),
+        frame_ethertype => Some((frame_ethertype, ETHERNET_HEADER_LEN)),
+    }
+}
+
+/// Destination port and payload of the UDP datagram in the IPv4 `packet`, if unfragmented.
+fn udp_datagram(packet: &[u8]) -> Option<(u16, &[u8])> {
+    let header_len = (*packet.first()? & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+    // More fragments flag and fragment offset.
+    let fragmented = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x3fff != 0;
+    if header_len < 20 || *packet.get(9)? != UDP || fragmented {
+        return None;
+    }
+
+    let datagram = packet.get(header_len..total_len)?;
+    let port = u16::from_be_bytes([*datagram.get(2)?, *datagram.get(3)?]);
+    Some((port, datagram.get(UDP_HEADER_LEN..)?))
+}
@@ -0,0 +1,412 @@
+//! Strict validation of received frames, for conformance test suites and fuzz corpora.
+//!
+//! The packet path is lenient where being strict buys nothing in normal operation: it reads the
+//! fields it needs and ignores reserved bits, trailing bytes or a length it can do without.
+//! Validating the stack against a test suite calls for the opposite, every frame with a header
+//! anomaly is rejected and the exact reason logged, so a test expecting a drop tells which
+//! check caught it and a fuzzer finds frames that slip through.
+//!
+//! The checks cover the headers the router parses: Ethernet, the VLAN tag, ARP, IPv4 and the
+//! TCP, UDP and ICMP headers of first fragments. Checksums are left to [`crate::rxcsum`] and IP
+//! options to [`crate::ipopts`]. Strict mode is off unless the `eth.strict_rx` setting is on.
+
+use core::{fmt, net::Ipv4Addr};
+
+use crate::{
+    ethernet::{MacAddress, VlanTag, ethertype},
+    log::{Level, Module},
+};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+/// Shortest frame without its CRC, shorter payloads are padded up to it.
+const MIN_FRAME_LEN: usize = 60;
+/// Largest 802.3 length field, values from 0x0600 on are EtherTypes.
+const MAX_LENGTH_FIELD: u16 = 1500;
+const MIN_ETHERTYPE: u16 = 0x0600;
+/// VID reserved by 802.1Q.
+const RESERVED_VID: u16 = 0x0FFF;
+
+const ARP_LEN: usize = 28;
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const TCP_MIN_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const ICMP_HEADER_LEN: usize = 8;
+
+mod ip_protocol {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+}
+
+mod tcp_flag {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+}
+
+/// Header anomalies a frame is rejected for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Anomaly {
+    /// Shorter than its Ethernet header and VLAN tag.
+    EthernetTruncated,
+    /// Group bit set in the source address.
+    SourceMulticast,
+    /// 802.3 length field past 1500, in the gap below the EtherTypes, or longer than the
+    /// payload.
+    LengthField,
+    /// VID 0xFFF.
+    VlanReserved,
+    ArpTruncated,
+    /// Hardware or protocol type or address length other than Ethernet and IPv4.
+    ArpFormat,
+    /// Neither a request nor a reply.
+    ArpOperation,
+    /// Shorter than the IPv4 header.
+    Ipv4Truncated,
+    Ipv4Version,
+    /// IHL under 5 or past the total length.
+    Ipv4HeaderLength,
+    /// Total length past the frame, or bytes after it in a frame longer than the minimum.
+    Ipv4TotalLength,
+    /// The flag bit reserved by RFC 791.
+    Ipv4ReservedFlag,
+    /// Fragment with the don't fragment flag set.
+    Ipv4Fragment,
+    /// Multicast or limited broadcast source.
+    Ipv4Source,
+    TcpTruncated,
+    /// Data offset under 5 or past the segment.
+    TcpDataOffset,
+    /// Reserved bits of the data offset byte set.
+    TcpReserved,
+    /// No flags, or SYN with FIN or RST.
+    TcpFlags,
+    UdpTruncated,
+    /// Length field under 8 or not matching the IP payload.
+    UdpLength,
+    IcmpTruncated,
+}
+
+impl Anomaly {
+    pub const COUNT: usize = 21;
+    pub const ALL: [Anomaly; Self::COUNT] = [
+        Anomaly::EthernetTruncated,
+        Anomaly::SourceMulticast,
+        Anomaly::LengthField,
+        Anomaly::VlanReserved,
+        Anomaly::ArpTruncated,
+        Anomaly::ArpFormat,
+        Anomaly::ArpOperation,
+        Anomaly::Ipv4Truncated,
+        Anomaly::Ipv4Version,
+        Anomaly::Ipv4HeaderLength,
+        Anomaly::Ipv4TotalLength,
+        Anomaly::Ipv4ReservedFlag,
+        Anomaly::Ipv4Fragment,
+        Anomaly::Ipv4Source,
+        Anomaly::TcpTruncated,
+        Anomaly::TcpDataOffset,
+        Anomaly::TcpReserved,
+        Anomaly::TcpFlags,
+        Anomaly::UdpTruncated,
+        Anomaly::UdpLength,
+        Anomaly::IcmpTruncated,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Anomaly::EthernetTruncated => "ethernet_truncated",
+            Anomaly::SourceMulticast => "source_multicast",
+            Anomaly::LengthField => "length_field",
+            Anomaly::VlanReserved => "vlan_reserved",
+            Anomaly::ArpTruncated => "arp_truncated",
+            Anomaly::ArpFormat => "arp_format",
+            Anomaly::ArpOperation => "arp_operation",
+            Anomaly::Ipv4Truncated => "ipv4_truncated",
+            Anomaly::Ipv4Version => "ipv4_version",
+            Anomaly::Ipv4HeaderLength => "ipv4_header_length",
+            Anomaly::Ipv4TotalLength => "ipv4_total_length",
+            Anomaly::Ipv4ReservedFlag => "ipv4_reserved_flag",
+            Anomaly::Ipv4Fragment => "ipv4_fragment",
+            Anomaly::Ipv4Source => "ipv4_source",
+            Anomaly::TcpTruncated => "tcp_truncated",
+            Anomaly::TcpDataOffset => "tcp_data_offset",
+            Anomaly::TcpReserved => "tcp_reserved",
+            Anomaly::TcpFlags => "tcp_flags",
+            Anomaly::UdpTruncated => "udp_truncated",
+            Anomaly::UdpLength => "udp_length",
+            Anomaly::IcmpTruncated => "icmp_truncated",
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Why a frame was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Violation {
+    pub anomaly: Anomaly,
+    /// Offset in the frame of the field at fault.
+    pub offset: usize,
+}
+
+impl Violation {
+    /// The violation with its offset moved past `header`, for the one of an inner header.
+    fn at(self, header: usize) -> Self {
+        Self {
+            offset: self.offset + header,
+            ..self
+        }
+    }
+}
+
+/// Counters of the frames validated, rejections indexed by [`Anomaly`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StrictStats {
+    pub validated: u32,
+    pub rejected: [u32; Anomaly::COUNT],
+}
+
+/// Rejects received frames with header anomalies while strict mode is on.
+#[derive(Debug, Default)]
+pub struct StrictValidator {
+    stats: StrictStats,
+}
+
+impl StrictValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> StrictStats {
+        self.stats
+    }
+
+    /// Validates a received `frame`, without its CRC, logging and counting the reason it's
+    /// rejected for. Call it on every received frame with `enabled` from the config, frames
+    /// all pass while it's off.
+    pub fn validate(&mut self, enabled: bool, frame: &[u8]) -> Result<(), Violation> {
+        if !enabled {
+            return Ok(());
+        }
+
+        self.stats.validated += 1;
+        check(frame).inspect_err(|violation| {
+            self.stats.rejected[violation.anomaly as usize] += 1;
+            crate::log!(
+                Module::Driver,
+                Level::Warn,
+                "Strict mode rejected a frame of {} bytes: {} at byte {}",
+                frame.len(),
+                violation.anomaly,
+                violation.offset
+            );
+        })
+    }
+}
+
+fn violation(anomaly: Anomaly, offset: usize) -> Violation {
+    Violation { anomaly, offset }
+}
+
+/// Checks the headers of `frame`, without its CRC, for the first anomaly.
+pub fn check(frame: &[u8]) -> Result<(), Violation> {
+    if frame.len() < ETHERNET_HEADER_LEN {
+        return Err(violation(Anomaly::EthernetTruncated, frame.len()));
+    }
+    if MacAddress(frame[6..12].try_into().unwrap()).is_multicast() {
+        return Err(violation(Anomaly::SourceMulticast, 6));
+    }
+
+    let mut type_offset = 12;
+    if read_u16(frame, type_offset) == ethertype::VLAN {
+        // The tag and the EtherType after it.
+        if frame.len() < ETHERNET_HEADER_LEN + VLAN_TAG_LEN {
+            return Err(violation(Anomaly::EthernetTruncated, frame.len()));
+        }
+        type_offset += VLAN_TAG_LEN;
+        if VlanTag::parse(frame).is_some_and(|tag| tag.vid == RESERVED_VID) {
+            return Err(violation(Anomaly::VlanReserved, 14));
+        }
+    }
+
+    let ethertype = read_u16(frame, type_offset);
+    let payload_offset = type_offset + 2;
+    let payload = &frame[payload_offset..];
+    // Padding of frames at the minimum length isn't part of the payload.
+    let padded = frame.len() <= MIN_FRAME_LEN + (type_offset - 12);
+    match ethertype {
+        // 802.3 frames carry their payload length instead, the payload being LLC.
+        ..=MAX_LENGTH_FIELD if ethertype as usize <= payload.len() => Ok(()),
+        ..MIN_ETHERTYPE => Err(violation(Anomaly::LengthField, type_offset)),
+        ethertype::ARP => check_arp(payload).map_err(|violation| violation.at(payload_offset)),
+        ethertype::IPV4 => {
+            check_ipv4(payload, padded).map_err(|violation| violation.at(payload_offset))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_arp(packet: &[u8]) -> Result<(), Violation> {
+    if packet.len() < ARP_LEN {
+        return Err(violation(Anomaly::ArpTruncated, packet.len()));
+    }
+    // Ethernet and IPv4, with their address lengths.
+    if packet[..6] != [0, 1, 0x08, 0x00, 6, 4] {
+        return Err(violation(Anomaly::ArpFormat, 0));
+    }
+    if !matches!(read_u16(packet, 6), 1 | 2) {
+        return Err(violation(Anomaly::ArpOperation, 6));
+    }
+
+    Ok(())
+}
+
+/// Checks the IPv4 `packet`, which may be followed by padding if `padded`.
+fn check_ipv4(packet: &[u8], padded: bool) -> Result<(), Violation> {
+    if packet.len() < IPV4_MIN_HEADER_LEN {
+        return Err(violation(Anomaly::Ipv4Truncated, packet.len()));
+    }
+    if packet[0] >> 4 != 4 {
+        return Err(violation(Anomaly::Ipv4Version, 0));
+    }
+
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let total_len = read_u16(packet, 2) as usize;
+    if total_len > packet.len() || (total_len < packet.len() && !padded) {
+        return Err(violation(Anomaly::Ipv4TotalLength, 2));
+    }
+    if header_len < IPV4_MIN_HEADER_LEN || header_len > total_len {
+        return Err(violation(Anomaly::Ipv4HeaderLength, 0));
+    }
+
+    let flags = read_u16(packet, 6);
+    let reserved = flags & 0x8000 != 0;
+    let dont_fragment = flags & 0x4000 != 0;
+    let more_fragments = flags & 0x2000 != 0;
+    let fragment_offset = flags & 0x1fff;
+    if reserved {
+        return Err(violation(Anomaly::Ipv4ReservedFlag, 6));
+    }
+    if dont_fragment && (more_fragments || fragment_offset != 0) {
+        return Err(violation(Anomaly::Ipv4Fragment, 6));
+    }
+
+    let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+    if source.is_multicast() || source.is_broadcast() {
+        return Err(violation(Anomaly::Ipv4Source, 12));
+    }
+
+    // Later fragments carry no header, the first one only part of the payload.
+    if fragment_offset != 0 {
+        return Ok(());
+    }
+    let payload = &packet[header_len..total_len];
+    let whole = !more_fragments;
+    match packet[9] {
+        ip_protocol::TCP => check_tcp(payload),
+        ip_protocol::UDP => check_udp(payload, whole),
+        ip_protocol::ICMP if payload.len() < ICMP_HEADER_LEN => {
+            Err(violation(Anomaly::IcmpTruncated, payload.len()))
+        }
+        _ => Ok(()),
+    }
+    .map_err(|violation| violation.at(header_len))
+}
+
+fn check_tcp(segment: &[u8]) -> Result<(), Violation> {
+    if segment.len() < TCP_MIN_HEADER_LEN {
+        return Err(violation(Anomaly::TcpTruncated, segment.len()));
+    }
+
+    let header_len = (segment[12] >> 4) as usize * 4;
+    if header_len < TCP_MIN_HEADER_LEN || header_len > segment.len() {
+        return Err(violation(Anomaly::TcpDataOffset, 12));
+    }
+    if segment[12] & 0x0f != 0 {
+        return Err(violation(Anomaly::TcpReserved, 12));
+    }
+
+    let flags = segment[13];
+    let syn = flags & tcp_flag::SYN != 0;
+    if flags == 0 || (syn && flags & (tcp_flag::FIN | tcp_flag::RST) != 0) {
+        return Err(violation(Anomaly::TcpFlags, 13));
+    }
+
+    Ok(())
+}
+
+/// Checks the UDP `datagram`, whose length is only known if it's `whole`, not fragmented.
+fn check_udp(datagram: &[u8], whole: bool) -> Result<(), Violation> {
+    if datagram.len() < UDP_HEADER_LEN {
+        return Err(violation(Anomaly::UdpTruncated, datagram.len()));
+    }
+
+    let len = read_u16(datagram, 4) as usize;
+    if len < UDP_HEADER_LEN || (whole && len != datagram.len()) {
+        return Err(violation(Anomaly::UdpLength, 4));
+    }
+
+    Ok(())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of a frame from a unicast source with `ethertype`, followed by `rest`.
+    fn frame(ethertype: u16, rest: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xFF; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(rest);
+        frame
+    }
+
+    #[test]
+    fn vlan_tag_must_fit_with_its_ethertype() {
+        for rest in [&[][..], &[0x00], &[0x00, 0x05], &[0x00, 0x05, 0x08]] {
+            let frame = frame(ethertype::VLAN, rest);
+            assert_eq!(
+                check(&frame),
+                Err(violation(Anomaly::EthernetTruncated, frame.len())),
+                "{} bytes",
+                frame.len()
+            );
+        }
+
+        // VID 5, then an EtherType with nothing to check.
+        assert_eq!(
+            check(&frame(ethertype::VLAN, &[0x00, 0x05, 0x86, 0xDD])),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reserved_vid_is_rejected() {
+        let frame = frame(ethertype::VLAN, &[0x0F, 0xFF, 0x86, 0xDD]);
+        assert_eq!(check(&frame), Err(violation(Anomaly::VlanReserved, 14)));
+    }
+
+    #[test]
+    fn untagged_headers_must_be_complete() {
+        let frame = frame(0x86DD, &[]);
+        assert_eq!(
+            check(&frame[..13]),
+            Err(violation(Anomaly::EthernetTruncated, 13))
+        );
+        assert_eq!(check(&frame), Ok(()));
+    }
+}
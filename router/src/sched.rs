@@ -0,0 +1,149 @@
+//! Fair sharing of the main loop between interfaces and services.
+//!
+//! Each pass of the main loop serves every interface in turn, taking at most a budget of frames
+//! from each with an [`RxBatch`], then runs the services (CLI, timers). An interface under load
+//! yields with frames still waiting instead of holding the loop, so the other interface and the
+//! services keep running.
+//!
+//! The counters show whether the budget fits the load: interfaces yielding pass after pass need
+//! a larger one, a long gap between service runs a smaller one.
+
+use crate::{enc28j60::RxBatch, interface::InterfaceId};
+
+/// Counters of an interface's share of the loop.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceStats {
+    pub frames: u32,
+    /// Passes that ended with frames left over.
+    pub yields: u32,
+    /// Most consecutive passes that ended with frames left over.
+    pub longest_backlog: u32,
+    backlog: u32,
+}
+
+/// Counters of the loop as a whole.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SchedulerStats {
+    pub passes: u32,
+    /// Longest time between two service runs, in ticks.
+    pub longest_service_gap: u32,
+}
+
+/// Scheduler of a main loop serving `I` interfaces.
+pub struct Scheduler<const I: usize> {
+    budget: u8,
+    interfaces: [InterfaceStats; I],
+    stats: SchedulerStats,
+    last_service: Option<u32>,
+}
+
+impl<const I: usize> Scheduler<I> {
+    /// Scheduler taking at most `budget` frames per interface and pass, at least 1.
+    pub fn new(budget: u8) -> Self {
+        Self {
+            budget: budget.max(1),
+            interfaces: [InterfaceStats::default(); I],
+            stats: SchedulerStats::default(),
+            last_service: None,
+        }
+    }
+
+    pub fn budget(&self) -> u8 {
+        self.budget
+    }
+
+    /// Changes the budget, e.g. after the configuration changed, from the next pass on.
+    pub fn set_budget(&mut self, budget: u8) {
+        self.budget = budget.max(1);
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        self.stats
+    }
+
+    /// Counters of `interface`, `None` if out of range.
+    pub fn interface_stats(&self, interface: InterfaceId) -> Option<InterfaceStats> {
+        self.interfaces.get(interface.index()).copied()
+    }
+
+    /// Starts serving an interface with `packet_count` frames waiting.
+    pub fn serve(&self, packet_count: u8) -> RxBatch {
+        RxBatch::start(packet_count, self.budget)
+    }
+
+    /// Records that `interface` was served, `frames` being how many were actually taken.
+    pub fn served(&mut self, interface: InterfaceId, frames: u8, batch: &RxBatch) {
+        let Some(stats) = self.interfaces.get_mut(interface.index()) else {
+            return;
+        };
+
+        stats.frames = stats.frames.wrapping_add(frames as u32);
+        if batch.left_over() > 0 {
+            stats.yields = stats.yields.wrapping_add(1);
+            stats.backlog += 1;
+            stats.longest_backlog = stats.longest_backlog.max(stats.backlog);
+        } else {
+            stats.backlog = 0;
+        }
+    }
+
+    /// Records that the services ran at `now`, ending the pass.
+    pub fn services_ran(&mut self, now: u32) {
+        if let Some(last) = self.last_service {
+            let gap = now.wrapping_sub(last);
+            self.stats.longest_service_gap = self.stats.longest_service_gap.max(gap);
+        }
+
+        self.last_service = Some(now);
+        self.stats.passes = self.stats.passes.wrapping_add(1);
+    }
+
+    /// Whether an interface still has frames from an earlier pass, in which case the loop
+    /// shouldn't sleep until the next interrupt.
+    pub fn backlogged(&self) -> bool {
+        self.interfaces.iter().any(|stats| stats.backlog > 0)
+    }
+
+    /// Clears the counters, keeping the budget.
+    pub fn reset_stats(&mut self) {
+        self.interfaces = [InterfaceStats::default(); I];
+        self.stats = SchedulerStats::default();
+        self.last_service = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[test]
+    fn longest_service_gap_spans_the_clock_wrapping() {
+        let clock = MockClock::new(u32::MAX - 10);
+        let mut scheduler = Scheduler::<2>::new(4);
+
+        scheduler.services_ran(clock.now());
+        clock.advance(5);
+        scheduler.services_ran(clock.now());
+        // Stalled across the wrap.
+        clock.advance(30);
+        scheduler.services_ran(clock.now());
+        clock.advance(1);
+        scheduler.services_ran(clock.now());
+
+        assert_eq!(
+            scheduler.stats(),
+            SchedulerStats {
+                passes: 4,
+                longest_service_gap: 30,
+            }
+        );
+
+        scheduler.reset_stats();
+        clock.advance(1000);
+        scheduler.services_ran(clock.now());
+        assert_eq!(scheduler.stats().longest_service_gap, 0);
+    }
+}
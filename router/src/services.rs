@@ -0,0 +1,85 @@
+//! Which interfaces management and infrastructure services can be reached on.
+//!
+//! Checked before a packet is handed to a service's socket, so a service bound to the LAN is
+//! never reachable from the WAN regardless of firewall rules.
+
+use core::net::Ipv4Addr;
+
+use crate::interface::InterfaceId;
+
+/// Services running on the router.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Service {
+    Cli,
+    Http,
+    Dns,
+    Snmp,
+    Mqtt,
+}
+
+impl Service {
+    const COUNT: usize = 5;
+}
+
+/// Where a service accepts packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    /// Bit `i` set allows the interface of index `i`, for up to 32 interfaces.
+    pub interfaces: u32,
+    /// Only accept packets sent to this address, any of the router's addresses otherwise.
+    pub address: Option<Ipv4Addr>,
+}
+
+impl Binding {
+    /// Not reachable anywhere.
+    pub const NONE: Binding = Binding {
+        interfaces: 0,
+        address: None,
+    };
+
+    /// Only `interface`, whose index must be below 32.
+    pub const fn interface(interface: InterfaceId) -> Self {
+        Self {
+            interfaces: 1 << interface.index(),
+            address: None,
+        }
+    }
+
+    pub fn allows(&self, interface: InterfaceId, destination: Ipv4Addr) -> bool {
+        let allowed = 1u32
+            .checked_shl(interface.index() as u32)
+            .is_some_and(|bit| self.interfaces & bit != 0);
+
+        allowed && self.address.is_none_or(|a| a == destination)
+    }
+}
+
+/// Bindings of every [`Service`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceBindings {
+    bindings: [Binding; Service::COUNT],
+}
+
+impl ServiceBindings {
+    /// Every service bound to the LAN interface only.
+    pub const fn lan_only(lan: InterfaceId) -> Self {
+        Self {
+            bindings: [Binding::interface(lan); Service::COUNT],
+        }
+    }
+
+    pub fn get(&self, service: Service) -> Binding {
+        self.bindings[service as usize]
+    }
+
+    pub fn set(&mut self, service: Service, binding: Binding) {
+        self.bindings[service as usize] = binding;
+    }
+
+    /// Whether a packet for `service` arriving on `interface` addressed to `destination`
+    /// may be delivered.
+    pub fn allows(&self, service: Service, interface: InterfaceId, destination: Ipv4Addr) -> bool {
+        self.get(service).allows(interface, destination)
+    }
+}
@@ -0,0 +1,123 @@
+//! SIP application level gateway.
+//!
+//! SIP carries the media endpoints in the SDP body of its messages, behind NAPT those hold the
+//! private address. The SDP connection (`c=`) and origin (`o=`) addresses are rewritten to the
+//! translated one and [`Expectation`]s are opened for the RTP and RTCP ports of each media (`m=`)
+//! line. Ports are kept as they are, which assumes port preserving NAT for media.
+//!
+//! SIP ALGs are known to break as many setups as they fix, so this one is off unless the
+//! `alg.sip` setting turns it on in [`AlgConfig`](crate::conntrack::AlgConfig).
+//!
+//! Rewriting addresses in the SIP headers (`Via`, `Contact`) and updating `Content-Length` after
+//! the body changed size is left to the caller.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::{
+    conntrack::{Expectation, Protocol},
+    format, text,
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SipAlgError {
+    #[error("Destination buffer is too small for the rewritten body.")]
+    BufferTooSmall,
+    #[error("Body has more media lines than can be tracked.")]
+    TooManyMedia,
+}
+
+/// Result of rewriting an SDP body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewrittenSdp<const M: usize> {
+    /// Length of the rewritten body.
+    pub len: usize,
+    /// RTP port of every media line.
+    pub media_ports: heapless::Vec<u16, M>,
+}
+
+/// Address token of an `o=` or `c=` line, its network and address type must be `IN IP4`.
+fn address_span(line: &[u8]) -> Option<core::ops::Range<usize>> {
+    if !line.starts_with(b"c=") && !line.starts_with(b"o=") {
+        return None;
+    }
+
+    const MARKER: &[u8] = b"IN IP4 ";
+    let start = text::find(line, MARKER)? + MARKER.len();
+    let len = line[start..]
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .unwrap_or(line.len() - start);
+
+    Some(start..start + len)
+}
+
+/// RTP port of an `m=` line.
+fn media_port(line: &[u8]) -> Option<u16> {
+    let rest = line.strip_prefix(b"m=")?;
+    let port = rest.split(|b| *b == b' ').nth(1)?;
+    // `port/count` announces consecutive ports, only the first one is tracked.
+    let port = port.split(|b| *b == b'/').next()?;
+    text::parse_decimal(port, u16::MAX)
+}
+
+/// Writes `body` to `out` with every IPv4 connection and origin address replaced by
+/// `translated`, collecting the media ports.
+pub fn rewrite_sdp<const M: usize>(
+    body: &[u8],
+    translated: Ipv4Addr,
+    out: &mut [u8],
+) -> Result<RewrittenSdp<M>, SipAlgError> {
+    let address = format::to_string::<15>(translated).unwrap();
+
+    let mut result = RewrittenSdp {
+        len: 0,
+        media_ports: heapless::Vec::new(),
+    };
+    let mut push = |bytes: &[u8], len: &mut usize| -> Result<(), SipAlgError> {
+        out.get_mut(*len..*len + bytes.len())
+            .ok_or(SipAlgError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        *len += bytes.len();
+        Ok(())
+    };
+
+    // Lines keep their terminator so the body is reproduced byte for byte.
+    for line in body.split_inclusive(|b| *b == b'\n') {
+        if let Some(port) = media_port(line) {
+            result
+                .media_ports
+                .push(port)
+                .map_err(|_| SipAlgError::TooManyMedia)?;
+        }
+
+        match address_span(line) {
+            Some(span) => {
+                push(&line[..span.start], &mut result.len)?;
+                push(address.as_bytes(), &mut result.len)?;
+                push(&line[span.end..], &mut result.len)?;
+            }
+            None => push(line, &mut result.len)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Expectations for the RTP and RTCP flows of a media port announced by the endpoint at
+/// `address`, coming from `peer`.
+pub fn media_expectations(
+    address: Ipv4Addr,
+    port: u16,
+    peer: Ipv4Addr,
+    expires_at: u32,
+) -> [Expectation; 2] {
+    [port, port.wrapping_add(1)].map(|port| Expectation {
+        protocol: Protocol::Udp,
+        source: peer,
+        destination: SocketAddrV4::new(address, port),
+        expires_at,
+    })
+}
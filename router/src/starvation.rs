@@ -0,0 +1,197 @@
+//! DHCP starvation protection.
+//!
+//! A starvation attack requests leases under ever changing client identifiers until the pool is
+//! exhausted. DISCOVERs are rate limited per MAC and per port, and the distinct clients seen
+//! behind a MAC or a port within a window are capped, so a single device can't hold more than
+//! its share of the lease table.
+//!
+//! The default thresholds assume one tick per second.
+
+use crate::{ethernet::MacAddress, ratelimit::RateLimiter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StarvationConfig {
+    /// DISCOVERs a MAC may send in a burst, and the sustained rate of one every
+    /// `ticks_per_discover`.
+    pub discover_burst: u32,
+    pub ticks_per_discover: u32,
+    /// Same for all DISCOVERs received on a port.
+    pub port_discover_burst: u32,
+    pub port_ticks_per_discover: u32,
+    /// Distinct client identifiers a single MAC may use within `client_window`.
+    pub max_clients_per_mac: usize,
+    /// Distinct client identifiers seen on a single port within `client_window`.
+    pub max_clients_per_port: usize,
+    pub client_window: u32,
+}
+
+impl Default for StarvationConfig {
+    fn default() -> Self {
+        Self {
+            discover_burst: 4,
+            ticks_per_discover: 5,
+            port_discover_burst: 16,
+            port_ticks_per_discover: 1,
+            max_clients_per_mac: 2,
+            max_clients_per_port: 32,
+            client_window: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StarvationReason {
+    /// Too many DISCOVERs from the MAC.
+    MacRate,
+    /// Too many DISCOVERs on the port.
+    PortRate,
+    /// The MAC keeps changing its client identifier.
+    MacChurn,
+    /// Too many distinct clients behind the port.
+    PortChurn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// The message must be ignored, a suspected starvation attempt worth logging.
+    Suspected(StarvationReason),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StarvationStats {
+    pub rate_limited: u32,
+    pub churn_refused: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Client {
+    port: u8,
+    mac: MacAddress,
+    /// FNV-1a hash of the client identifier, collisions only make the guard more lenient.
+    id: u32,
+    last_seen: u32,
+}
+
+/// Guards a DHCP server tracking up to `C` clients, `M` MACs and `P` ports.
+pub struct StarvationGuard<const C: usize, const M: usize, const P: usize> {
+    config: StarvationConfig,
+    clients: heapless::Vec<Client, C>,
+    mac_limiter: RateLimiter<MacAddress, M>,
+    port_limiter: RateLimiter<u8, P>,
+    stats: StarvationStats,
+}
+
+impl<const C: usize, const M: usize, const P: usize> StarvationGuard<C, M, P> {
+    pub fn new(config: StarvationConfig) -> Self {
+        Self {
+            config,
+            clients: heapless::Vec::new(),
+            mac_limiter: RateLimiter::new(config.discover_burst, config.ticks_per_discover),
+            port_limiter: RateLimiter::new(
+                config.port_discover_burst,
+                config.port_ticks_per_discover,
+            ),
+            stats: StarvationStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> StarvationStats {
+        self.stats
+    }
+
+    /// Checks a DISCOVER received on `port` from `mac`.
+    pub fn check_discover(
+        &mut self,
+        port: u8,
+        mac: MacAddress,
+        client_id: &[u8],
+        now: u32,
+    ) -> Verdict {
+        if !self.port_limiter.check(port, now) {
+            self.stats.rate_limited += 1;
+            return Verdict::Suspected(StarvationReason::PortRate);
+        }
+        if !self.mac_limiter.check(mac, now) {
+            self.stats.rate_limited += 1;
+            return Verdict::Suspected(StarvationReason::MacRate);
+        }
+
+        self.check_client(port, mac, client_id, now)
+    }
+
+    /// Checks a REQUEST, only clients over their share are refused, not the retransmissions
+    /// of a client already known.
+    pub fn check_request(
+        &mut self,
+        port: u8,
+        mac: MacAddress,
+        client_id: &[u8],
+        now: u32,
+    ) -> Verdict {
+        self.check_client(port, mac, client_id, now)
+    }
+
+    /// Forgets clients idle for longer than the window.
+    pub fn expire(&mut self, now: u32) {
+        let window = self.config.client_window;
+        self.clients
+            .retain(|client| now.wrapping_sub(client.last_seen) < window);
+    }
+
+    fn check_client(&mut self, port: u8, mac: MacAddress, client_id: &[u8], now: u32) -> Verdict {
+        self.expire(now);
+
+        let id = client_id.iter().fold(0x811c_9dc5u32, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+        });
+
+        if let Some(client) = self
+            .clients
+            .iter_mut()
+            .find(|client| client.mac == mac && client.id == id)
+        {
+            client.port = port;
+            client.last_seen = now;
+            return Verdict::Allow;
+        }
+
+        let reason = if self.clients.iter().filter(|c| c.mac == mac).count()
+            >= self.config.max_clients_per_mac
+        {
+            Some(StarvationReason::MacChurn)
+        } else if self.clients.iter().filter(|c| c.port == port).count()
+            >= self.config.max_clients_per_port
+        {
+            Some(StarvationReason::PortChurn)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            self.stats.churn_refused += 1;
+            return Verdict::Suspected(reason);
+        }
+
+        if self.clients.is_full()
+            && let Some(idle) = self
+                .clients
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, client)| now.wrapping_sub(client.last_seen))
+                .map(|(i, _)| i)
+        {
+            self.clients.swap_remove(idle);
+        }
+
+        let _ = self.clients.push(Client {
+            port,
+            mac,
+            id,
+            last_seen: now,
+        });
+        Verdict::Allow
+    }
+}
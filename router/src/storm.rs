@@ -0,0 +1,117 @@
+//! Broadcast and unknown multicast storm control for bridge ports.
+//!
+//! Flooded traffic goes out of every port, so a single looping or misbehaving device can saturate
+//! every 10 Mbps link. Each port gets a flooding budget per traffic class, when a port keeps
+//! overrunning it flooding from that port is muted for a while.
+
+use crate::{clock, ratelimit::TokenBucket};
+
+/// Traffic that gets flooded to every port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FloodClass {
+    Broadcast,
+    /// Multicast to groups no port is known to be a member of.
+    UnknownMulticast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StormConfig {
+    /// Frames a port may flood in a burst.
+    pub burst: u32,
+    /// Sustained flooding rate, one frame every `ticks_per_frame`.
+    pub ticks_per_frame: u32,
+    /// Frames over budget within `storm_window` ticks that make a storm.
+    pub storm_threshold: u32,
+    pub storm_window: u32,
+    /// How long flooding from a port stays muted after a storm.
+    pub mute_duration: u32,
+}
+
+/// Counters of a single port.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PortStormStats {
+    pub broadcast_dropped: u32,
+    pub multicast_dropped: u32,
+    pub mutes: u32,
+}
+
+struct PortState {
+    buckets: [TokenBucket; 2],
+    window_start: u32,
+    over_budget: u32,
+    muted_until: Option<u32>,
+    stats: PortStormStats,
+}
+
+/// Storm control for `P` ports.
+pub struct StormControl<const P: usize> {
+    config: StormConfig,
+    ports: [PortState; P],
+}
+
+impl<const P: usize> StormControl<P> {
+    pub fn new(config: StormConfig, now: u32) -> Self {
+        Self {
+            config,
+            ports: core::array::from_fn(|_| PortState {
+                buckets: [TokenBucket::full(config.burst, now); 2],
+                window_start: now,
+                over_budget: 0,
+                muted_until: None,
+                stats: PortStormStats::default(),
+            }),
+        }
+    }
+
+    pub fn stats(&self, port: usize) -> PortStormStats {
+        self.ports[port].stats
+    }
+
+    pub fn is_muted(&self, port: usize, now: u32) -> bool {
+        self.ports[port]
+            .muted_until
+            .is_some_and(|until| !clock::is_due(until, now))
+    }
+
+    /// Whether a frame of `class` received on `port` may be flooded.
+    pub fn allow(&mut self, port: usize, class: FloodClass, now: u32) -> bool {
+        let config = self.config;
+        let muted = self.is_muted(port, now);
+        let state = &mut self.ports[port];
+
+        let allowed = !muted && {
+            let bucket = &mut state.buckets[class as usize];
+            bucket.refill(config.burst, config.ticks_per_frame, now);
+            bucket.take()
+        };
+
+        if allowed {
+            return true;
+        }
+
+        match class {
+            FloodClass::Broadcast => state.stats.broadcast_dropped += 1,
+            FloodClass::UnknownMulticast => state.stats.multicast_dropped += 1,
+        }
+
+        if muted {
+            return false;
+        }
+
+        if now.wrapping_sub(state.window_start) >= config.storm_window {
+            state.window_start = now;
+            state.over_budget = 0;
+        }
+
+        state.over_budget += 1;
+        if state.over_budget >= config.storm_threshold {
+            state.muted_until = Some(now.wrapping_add(config.mute_duration));
+            state.over_budget = 0;
+            state.stats.mutes += 1;
+        }
+
+        false
+    }
+}
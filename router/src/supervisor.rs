@@ -0,0 +1,282 @@
+//! Recovery from a failing NIC without rebooting.
+//!
+//! Errors reported by the driver are counted by the supervisor, and once enough of them come in
+//! a row the NIC is reset and initialized again, dropping the state that referred to it: ARP
+//! entries and tracked connections. The rest of the firmware, DHCP leases and the CLI session
+//! included, keeps running.
+//!
+//! A NIC failing again right after a restart would otherwise be restarted in a tight loop, so
+//! each restart waits twice as long as the previous one, up to a maximum. The delay goes back
+//! to its initial value once the NIC has run without errors for a while.
+//!
+//! The default durations assume one tick per millisecond.
+
+use crate::{
+    arp::ArpCache,
+    clock,
+    conntrack::Conntrack,
+    enc28j60::{Enc28j60, Interrupts, RxFilter, TransactionError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupervisorConfig {
+    /// Consecutive errors that trigger a restart.
+    pub error_threshold: u8,
+    /// Delay before the first restart.
+    pub initial_backoff: u32,
+    /// Longest delay between restarts.
+    pub max_backoff: u32,
+    /// Time without errors after a restart for the delay to go back to its initial value.
+    pub stable_after: u32,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            error_threshold: 3,
+            initial_backoff: 100,
+            max_backoff: 60_000,
+            stable_after: 30_000,
+        }
+    }
+}
+
+/// Counters of the supervisor's lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SupervisorStats {
+    pub errors: u32,
+    pub restarts: u32,
+    /// Restarts that couldn't queue the initialization, they're tried again after the delay.
+    pub failed_restarts: u32,
+}
+
+pub struct Supervisor {
+    config: SupervisorConfig,
+    stats: SupervisorStats,
+    consecutive_errors: u8,
+    backoff: u32,
+    restart_at: Option<u32>,
+    last_restart: Option<u32>,
+    /// Interrupts the last restart still has to enable, see [`Self::restore_nic`].
+    restore_interrupts: Option<Interrupts>,
+    /// Receive filter the last restart still has to program.
+    restore_rx_filter: Option<RxFilter>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self {
+            config,
+            stats: SupervisorStats::default(),
+            consecutive_errors: 0,
+            backoff: config.initial_backoff,
+            restart_at: None,
+            last_restart: None,
+            restore_interrupts: None,
+            restore_rx_filter: None,
+        }
+    }
+
+    pub fn stats(&self) -> SupervisorStats {
+        self.stats
+    }
+
+    /// Delay the next restart will wait for.
+    pub fn backoff(&self) -> u32 {
+        self.backoff
+    }
+
+    /// Records a driver error, scheduling a restart once there are enough in a row.
+    pub fn report_error(&mut self, now: u32) {
+        self.stats.errors += 1;
+        self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+        if self.consecutive_errors >= self.config.error_threshold && self.restart_at.is_none() {
+            self.restart_at = Some(now.wrapping_add(self.backoff));
+        }
+    }
+
+    /// Records a successful exchange with the NIC.
+    pub fn report_ok(&mut self, now: u32) {
+        self.consecutive_errors = 0;
+        if self
+            .last_restart
+            .is_some_and(|last| now.wrapping_sub(last) >= self.config.stable_after)
+        {
+            self.backoff = self.config.initial_backoff;
+            self.last_restart = None;
+        }
+    }
+
+    /// Whether a scheduled restart is due, see [`Self::restart_nic`].
+    pub fn restart_due(&self, now: u32) -> bool {
+        self.restart_at.is_some_and(|at| clock::is_due(at, now))
+    }
+
+    /// Resets the NIC and drops the state depending on it, doubling the delay of the next
+    /// restart.
+    ///
+    /// The reset loses what the stack programmed into the NIC, `interrupts` and `rx_filter`
+    /// are queued after the initialization to restore it. What doesn't fit in the queue along
+    /// with the initialization is queued by [`Self::restore_nic`] once there's room.
+    pub fn restart_nic<
+        const N: usize,
+        const M: usize,
+        const B: usize,
+        P,
+        const A: usize,
+        const Q: usize,
+        T,
+        const C: usize,
+    >(
+        &mut self,
+        nic: &mut Enc28j60<N, M, B>,
+        arp: &mut ArpCache<P, A, Q>,
+        conntrack: &mut Conntrack<T, C>,
+        interrupts: Interrupts,
+        rx_filter: RxFilter,
+        now: u32,
+    ) -> Result<(), TransactionError> {
+        self.restart_at = None;
+        self.consecutive_errors = 0;
+        self.last_restart = Some(now);
+        self.backoff = self.backoff.saturating_mul(2).min(self.config.max_backoff);
+
+        arp.flush();
+        conntrack.flush();
+        self.stats.restarts += 1;
+        nic.reset().inspect_err(|_| {
+            self.stats.failed_restarts += 1;
+            self.restart_at = Some(now.wrapping_add(self.backoff));
+        })?;
+
+        self.restore_interrupts = Some(interrupts);
+        self.restore_rx_filter = Some(rx_filter);
+        self.restore_nic(nic);
+        Ok(())
+    }
+
+    /// Queues what the last restart still has to restore, interrupts then receive filter, as
+    /// far as the queue has room. Call it from the main loop after running the transactions.
+    pub fn restore_nic<const N: usize, const M: usize, const B: usize>(
+        &mut self,
+        nic: &mut Enc28j60<N, M, B>,
+    ) {
+        if let Some(interrupts) = self.restore_interrupts {
+            if nic.enable_interrupts(interrupts).is_err() {
+                return;
+            }
+            self.restore_interrupts = None;
+        }
+
+        if let Some(rx_filter) = self.restore_rx_filter
+            && nic.reconcile_rx_filter(rx_filter).is_ok()
+        {
+            self.restore_rx_filter = None;
+        }
+    }
+
+    /// Whether the last restart still has something to restore.
+    pub fn is_restoring(&self) -> bool {
+        self.restore_interrupts.is_some() || self.restore_rx_filter.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        conntrack::{EvictionPolicy, TimeoutProfile},
+        enc28j60::{OperationKind, Register, RxFilterConfig},
+        ethernet::MacAddress,
+        profile,
+    };
+
+    // Write Control Register opcode.
+    const WCR: u8 = 0b010_00000;
+
+    /// Runs the NIC's queue as the main loop would, restoring what the supervisor has left
+    /// after each transaction, and returns the register writes made, opcode first. Every read
+    /// comes back with CLKRDY set and BUSY clear.
+    fn run<const N: usize, const M: usize, const B: usize>(
+        supervisor: &mut Supervisor,
+        nic: &mut Enc28j60<N, M, B>,
+    ) -> Vec<[u8; 2]> {
+        let mut writes = Vec::new();
+        let mut now = 0;
+        loop {
+            nic.settle_reset(now, 2);
+            now += 1;
+            let Some(mut transaction) = nic.poll_pending_transaction() else {
+                if nic.is_settling() {
+                    continue;
+                }
+                return writes;
+            };
+            if let Some((OperationKind::Write, &[opcode, value])) = transaction.iter().next() {
+                writes.push([opcode, value]);
+            }
+            for operation in transaction.spi_operations() {
+                if let embedded_hal::spi::Operation::Read(bytes) = operation {
+                    // ESTAT.CLKRDY, MISTAT.BUSY is bit 0 too but in MII reads only, after a
+                    // dummy byte.
+                    bytes.fill(0);
+                    bytes[0] = 0x01;
+                }
+            }
+            nic.handle_transaction(transaction).unwrap();
+            supervisor.restore_nic(nic);
+        }
+    }
+
+    #[test]
+    fn restart_restores_interrupts_and_rx_filter() {
+        let mut supervisor = Supervisor::new(SupervisorConfig::default());
+        // The smallest queue, init and the restore don't fit in it together.
+        let mut nic = profile::small::Enc28j60::with_erx_length(0x1f0u16.try_into().unwrap());
+        let mut arp = ArpCache::<(), 4, 2>::new(1200, 3);
+        let mut conntrack =
+            Conntrack::<(), 4>::new(TimeoutProfile::default(), EvictionPolicy::LeastRecentlyUsed);
+        let filter = RxFilterConfig::new(MacAddress([0x02, 0, 0, 0, 0, 0x42])).filter();
+        let interrupts = Interrupts {
+            packet: true,
+            ..Interrupts::default()
+        };
+
+        supervisor
+            .restart_nic(&mut nic, &mut arp, &mut conntrack, interrupts, filter, 0)
+            .unwrap();
+        assert!(supervisor.is_restoring());
+        let writes = run(&mut supervisor, &mut nic);
+        assert!(!supervisor.is_restoring());
+
+        // INTIE along with PKTIE, after init left ERXFCON promiscuous.
+        let eie = writes
+            .iter()
+            .position(|write| *write == [WCR | Register::EIE.address(), 0b1100_0000])
+            .unwrap();
+        let init_erxfcon = writes
+            .iter()
+            .position(|write| *write == [WCR | Register::ERXFCON.address(), 0])
+            .unwrap();
+        let erxfcon = writes
+            .iter()
+            .rposition(|write| *write == [WCR | Register::ERXFCON.address(), filter.erxfcon])
+            .unwrap();
+        assert!(init_erxfcon < eie && eie < erxfcon);
+        assert!(writes.contains(&[WCR | Register::MAADR6.address(), 0x42]));
+        assert_eq!(supervisor.stats().restarts, 1);
+        assert_eq!(supervisor.backoff(), 200);
+    }
+
+    #[test]
+    fn restart_is_scheduled_after_enough_errors() {
+        let mut supervisor = Supervisor::new(SupervisorConfig::default());
+        supervisor.report_error(0);
+        supervisor.report_error(1);
+        assert!(!supervisor.restart_due(1000));
+        supervisor.report_error(2);
+        assert!(!supervisor.restart_due(101));
+        assert!(supervisor.restart_due(102));
+    }
+}
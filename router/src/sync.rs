@@ -0,0 +1,98 @@
+//! State shared between interrupt handlers and the main loop.
+//!
+//! A handler hands work to the main loop by raising an [`InterruptFlag`], and state both sides
+//! touch lives in a [`Shared`] cell, only reached within a critical section. Built with
+//! `--cfg loom` both sit on loom's primitives instead, so the tests in `tests/loom.rs` can run
+//! a handler and the main loop as threads through every interleaving:
+//!
+//! ```text
+//! cargo test-loom
+//! ```
+
+#[cfg(not(loom))]
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(loom)]
+use loom::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Interrupt that fired since the main loop last looked.
+#[derive(Debug)]
+pub struct InterruptFlag(AtomicBool);
+
+impl Default for InterruptFlag {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptFlag {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Called by the handler, after whatever the main loop is to see.
+    pub fn raise(&self) {
+        // A swap rather than a store: loom lets the swap of `take` read past a plain store.
+        self.0.swap(true, Ordering::Release);
+    }
+
+    /// Whether the interrupt fired, clearing the flag. What the handler did before raising it
+    /// is visible once this returns true.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Acquire)
+    }
+
+    /// Whether the interrupt fired, leaving the flag raised, e.g. to decide to sleep with
+    /// interrupts masked.
+    pub fn is_raised(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value shared with interrupt handlers.
+pub struct Shared<T> {
+    #[cfg(not(loom))]
+    value: cortex_m::interrupt::Mutex<RefCell<T>>,
+    #[cfg(loom)]
+    value: Mutex<T>,
+}
+
+impl<T> Shared<T> {
+    #[cfg(not(loom))]
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: cortex_m::interrupt::Mutex::new(RefCell::new(value)),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(value: T) -> Self {
+        Self {
+            value: Mutex::new(value),
+        }
+    }
+
+    /// Runs `f` on the value with interrupts disabled.
+    #[cfg(not(loom))]
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        cortex_m::interrupt::free(|cs| f(&mut self.value.borrow(cs).borrow_mut()))
+    }
+
+    /// Runs `f` on the value with no other thread in it.
+    #[cfg(loom)]
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.value.lock().unwrap())
+    }
+}
@@ -0,0 +1,131 @@
+//! Firmware and device information: the startup banner, `show version`, `show memory` and
+//! `GET /api/system`.
+//!
+//! The version comes from the package, the git hash from the `ROUTER_GIT_HASH` environment
+//! variable at build time, e.g. `ROUTER_GIT_HASH=$(git rev-parse --short HEAD) cargo build`.
+//! The rest is read at runtime by the caller: the MCU's unique ID and flash size from its
+//! system memory, the image size from the linker and the fill of the static pools from the
+//! subsystems owning them.
+
+use core::fmt::{self, Display, Write};
+
+use crate::format::Duration;
+
+pub const NAME: &str = "diy-router";
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Commit the firmware was built from, if given at build time.
+pub const GIT_HASH: Option<&str> = option_env!("ROUTER_GIT_HASH");
+
+/// The MCU's 96-bit unique device ID.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UniqueId(pub [u8; 12]);
+
+impl Display for UniqueId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// Fill and footprint of a bounded structure, e.g. the conntrack table.
+///
+/// The high-water mark is the most entries it held at once since boot: one that never gets
+/// close to the capacity wastes RAM, one that reached it may have refused or evicted entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PoolUsage {
+    pub name: &'static str,
+    pub used: usize,
+    pub high_water: usize,
+    pub capacity: usize,
+    /// RAM taken by the structure, whatever its fill.
+    pub bytes: usize,
+}
+
+impl PoolUsage {
+    /// Usage of `pool`, its footprint taken from its size.
+    pub fn of<T>(
+        name: &'static str,
+        pool: &T,
+        used: usize,
+        high_water: usize,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            name,
+            used,
+            high_water,
+            capacity,
+            bytes: core::mem::size_of_val(pool),
+        }
+    }
+}
+
+/// Snapshot of the firmware and the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemInfo<'a> {
+    pub unique_id: UniqueId,
+    /// Seconds since boot.
+    pub uptime: u32,
+    /// Bytes of flash taken by the firmware image, and the device's flash size.
+    pub flash_used: usize,
+    pub flash_size: usize,
+    pub pools: &'a [PoolUsage],
+}
+
+impl SystemInfo<'_> {
+    /// RAM taken by the pools.
+    pub fn pool_bytes(&self) -> usize {
+        self.pools.iter().map(|pool| pool.bytes).sum()
+    }
+}
+
+/// `<name> <version>`, with the git hash if known, as printed at startup.
+pub struct Banner;
+
+impl Display for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{NAME} {VERSION}")?;
+        if let Some(hash) = GIT_HASH {
+            write!(f, " ({hash})")?;
+        }
+        Ok(())
+    }
+}
+
+/// The output of `show version`, one item per line.
+impl Display for SystemInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{Banner}")?;
+        writeln!(f, "Device ID {}", self.unique_id)?;
+        writeln!(f, "Uptime {}", Duration(self.uptime))?;
+        writeln!(f, "Flash {} of {} bytes", self.flash_used, self.flash_size)?;
+        writeln!(f, "Pools {} bytes", self.pool_bytes())
+    }
+}
+
+/// The output of `show memory`, a line per pool with its fill, high-water mark, capacity and
+/// footprint. Pools whose high-water mark reached their capacity are flagged.
+pub struct MemoryReport<'a>(pub &'a [PoolUsage]);
+
+impl Display for MemoryReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {:>6} {:>6} {:>6} {:>7}",
+            "Pool", "Used", "Peak", "Max", "Bytes"
+        )?;
+        for pool in self.0 {
+            write!(
+                f,
+                "{:<16} {:>6} {:>6} {:>6} {:>7}",
+                pool.name, pool.used, pool.high_water, pool.capacity, pool.bytes
+            )?;
+            if pool.high_water >= pool.capacity {
+                f.write_str(" full")?;
+            }
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,459 @@
+//! Listening TCP sockets of the management services: listen backlog, connection caps and resets.
+//!
+//! A SYN to a listening port takes a slot of its backlog until the server accepts the
+//! connection, which it can only do while the listener is under its connection cap. A SYN that
+//! finds the backlog full, one to a port nothing listens on, and a connection left in the
+//! backlog past its timeout are answered with a reset (RFC 9293, 3.10.7.1), so clients of a
+//! flooded HTTP or CLI server fail fast instead of retransmitting into it.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::{checksum, clock};
+
+/// Length of a TCP header without options.
+pub const HEADER_LEN: usize = 20;
+
+const PROTOCOL: u8 = 6;
+
+pub mod flag {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const ACK: u8 = 0x10;
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TcpError {
+    #[error("Segment is shorter than its header.")]
+    Truncated,
+    #[error("Segment header is malformed.")]
+    Malformed,
+    #[error("Segment doesn't fit the buffer.")]
+    BufferTooSmall,
+    #[error("Port already has a listener.")]
+    AlreadyListening,
+    #[error("No room for another listener.")]
+    TooManyListeners,
+}
+
+/// Fields of a TCP segment that connection setup and resets look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub data_len: usize,
+}
+
+impl Segment {
+    /// Parses the segment from its TCP header on.
+    pub fn parse(segment: &[u8]) -> Result<Self, TcpError> {
+        if segment.len() < HEADER_LEN {
+            return Err(TcpError::Truncated);
+        }
+        let header_len = usize::from(segment[12] >> 4) * 4;
+        if header_len < HEADER_LEN {
+            return Err(TcpError::Malformed);
+        }
+        let data_len = segment
+            .len()
+            .checked_sub(header_len)
+            .ok_or(TcpError::Truncated)?;
+
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                segment[offset],
+                segment[offset + 1],
+                segment[offset + 2],
+                segment[offset + 3],
+            ])
+        };
+        Ok(Self {
+            source_port: u16::from_be_bytes([segment[0], segment[1]]),
+            destination_port: u16::from_be_bytes([segment[2], segment[3]]),
+            seq: read_u32(4),
+            ack: read_u32(8),
+            flags: segment[13],
+            data_len,
+        })
+    }
+
+    /// Sequence space the segment takes, SYN and FIN counting for one each.
+    pub fn seq_len(&self) -> u32 {
+        let control = [flag::SYN, flag::FIN]
+            .iter()
+            .filter(|&&control| self.flags & control != 0)
+            .count();
+        (self.data_len + control) as u32
+    }
+
+    /// Reset answering this segment, or `None` for a reset, which is never answered.
+    pub fn reset(&self) -> Option<Self> {
+        if self.flags & flag::RST != 0 {
+            return None;
+        }
+
+        let (seq, ack, flags) = if self.flags & flag::ACK != 0 {
+            (self.ack, 0, flag::RST)
+        } else {
+            (
+                0,
+                self.seq.wrapping_add(self.seq_len()),
+                flag::RST | flag::ACK,
+            )
+        };
+        Some(Self {
+            source_port: self.destination_port,
+            destination_port: self.source_port,
+            seq,
+            ack,
+            flags,
+            data_len: 0,
+        })
+    }
+
+    /// Writes the header, without options or data and with a zero window, checksummed for a
+    /// segment from `source` to `destination`. Returns its length.
+    pub fn write(
+        &self,
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        out: &mut [u8],
+    ) -> Result<usize, TcpError> {
+        let header = out.get_mut(..HEADER_LEN).ok_or(TcpError::BufferTooSmall)?;
+        header.fill(0);
+        header[0..2].copy_from_slice(&self.source_port.to_be_bytes());
+        header[2..4].copy_from_slice(&self.destination_port.to_be_bytes());
+        header[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        header[8..12].copy_from_slice(&self.ack.to_be_bytes());
+        header[12] = ((HEADER_LEN / 4) as u8) << 4;
+        header[13] = self.flags;
+
+        let mut pseudo_header = [0; 12];
+        pseudo_header[0..4].copy_from_slice(&source.octets());
+        pseudo_header[4..8].copy_from_slice(&destination.octets());
+        pseudo_header[9] = PROTOCOL;
+        pseudo_header[10..12].copy_from_slice(&(HEADER_LEN as u16).to_be_bytes());
+        let checksum = checksum::checksum_with(&pseudo_header, header);
+        header[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+        Ok(HEADER_LEN)
+    }
+}
+
+/// Why a SYN was refused, to be answered with a reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Refusal {
+    /// Nothing listens on the port.
+    NotListening,
+    /// The listener's backlog is full.
+    BacklogFull,
+}
+
+/// Connection waiting in a backlog for the server to accept it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pending {
+    pub peer: SocketAddrV4,
+    /// Sequence number of the peer's SYN.
+    pub seq: u32,
+    deadline: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListenerStats {
+    pub accepted: u32,
+    /// SYNs refused with the backlog full.
+    pub refused: u32,
+    /// Connections reset after waiting in the backlog for too long.
+    pub expired: u32,
+}
+
+/// Listening socket queueing up to `B` connections for the server, which holds at most
+/// `max_connections` accepted ones at a time.
+#[derive(Debug)]
+pub struct Listener<const B: usize> {
+    port: u16,
+    max_connections: usize,
+    /// Ticks a connection waits in the backlog before it's reset.
+    timeout: u32,
+    backlog: heapless::Vec<Pending, B>,
+    connections: usize,
+    stats: ListenerStats,
+}
+
+impl<const B: usize> Listener<B> {
+    pub fn new(port: u16, max_connections: usize, timeout: u32) -> Self {
+        Self {
+            port,
+            max_connections,
+            timeout,
+            backlog: heapless::Vec::new(),
+            connections: 0,
+            stats: ListenerStats::default(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Queues a connection from `peer`, whose SYN carried `seq`. A retransmitted SYN keeps
+    /// the connection's place.
+    pub fn syn(&mut self, peer: SocketAddrV4, seq: u32, now: u32) -> Result<(), Refusal> {
+        if self.backlog.iter().any(|pending| pending.peer == peer) {
+            return Ok(());
+        }
+
+        let pending = Pending {
+            peer,
+            seq,
+            deadline: now.wrapping_add(self.timeout),
+        };
+        self.backlog.push(pending).map_err(|_| {
+            self.stats.refused = self.stats.refused.saturating_add(1);
+            Refusal::BacklogFull
+        })
+    }
+
+    /// Oldest connection in the backlog, unless the server is at its connection cap.
+    pub fn accept(&mut self) -> Option<Pending> {
+        if self.connections >= self.max_connections || self.backlog.is_empty() {
+            return None;
+        }
+
+        self.connections += 1;
+        self.stats.accepted = self.stats.accepted.saturating_add(1);
+        Some(self.backlog.remove(0))
+    }
+
+    /// Forgets the queued connection from `peer`, which the peer reset.
+    pub fn abort(&mut self, peer: SocketAddrV4) {
+        self.backlog.retain(|pending| pending.peer != peer);
+    }
+
+    /// Frees the slot of an accepted connection that closed.
+    pub fn close(&mut self) {
+        self.connections = self.connections.saturating_sub(1);
+    }
+
+    /// Takes a connection that waited past its timeout, to be reset. Call until `None`.
+    pub fn expire(&mut self, now: u32) -> Option<Pending> {
+        // Every connection waits for the same timeout, the oldest expires first.
+        let oldest = self.backlog.first()?;
+        if !clock::is_due(oldest.deadline, now) {
+            return None;
+        }
+
+        self.stats.expired = self.stats.expired.saturating_add(1);
+        Some(self.backlog.remove(0))
+    }
+
+    /// Connections waiting to be accepted.
+    pub fn queued(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// Accepted connections still open.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    pub fn stats(&self) -> ListenerStats {
+        self.stats
+    }
+}
+
+/// Up to `N` listeners, with backlogs of `B` connections.
+#[derive(Debug, Default)]
+pub struct Listeners<const N: usize, const B: usize> {
+    listeners: heapless::Vec<Listener<B>, N>,
+}
+
+impl<const N: usize, const B: usize> Listeners<N, B> {
+    pub const fn new() -> Self {
+        Self {
+            listeners: heapless::Vec::new(),
+        }
+    }
+
+    pub fn listen(&mut self, listener: Listener<B>) -> Result<(), TcpError> {
+        if self.get(listener.port).is_some() {
+            return Err(TcpError::AlreadyListening);
+        }
+
+        self.listeners
+            .push(listener)
+            .map_err(|_| TcpError::TooManyListeners)
+    }
+
+    pub fn get(&self, port: u16) -> Option<&Listener<B>> {
+        self.listeners.iter().find(|listener| listener.port == port)
+    }
+
+    pub fn get_mut(&mut self, port: u16) -> Option<&mut Listener<B>> {
+        self.listeners
+            .iter_mut()
+            .find(|listener| listener.port == port)
+    }
+
+    /// Queues the connection `syn` opens from `peer`, or says why it's to be reset.
+    pub fn syn(&mut self, peer: SocketAddrV4, syn: &Segment, now: u32) -> Result<(), Refusal> {
+        self.get_mut(syn.destination_port)
+            .ok_or(Refusal::NotListening)?
+            .syn(peer, syn.seq, now)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Listener<B>> {
+        self.listeners.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+
+    fn peer(port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(CLIENT, port)
+    }
+
+    fn syn(destination_port: u16, seq: u32) -> Segment {
+        Segment {
+            source_port: 40000,
+            destination_port,
+            seq,
+            ack: 0,
+            flags: flag::SYN,
+            data_len: 0,
+        }
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let segment = Segment {
+            source_port: 80,
+            destination_port: 40000,
+            seq: 0x0102_0304,
+            ack: 0x0506_0708,
+            flags: flag::RST | flag::ACK,
+            data_len: 0,
+        };
+        let mut out = [0; HEADER_LEN];
+
+        assert_eq!(segment.write(ROUTER, CLIENT, &mut out), Ok(HEADER_LEN));
+        assert_eq!(Segment::parse(&out), Ok(segment));
+
+        let mut pseudo_header = [0; 12];
+        pseudo_header[0..4].copy_from_slice(&ROUTER.octets());
+        pseudo_header[4..8].copy_from_slice(&CLIENT.octets());
+        pseudo_header[9] = PROTOCOL;
+        pseudo_header[11] = HEADER_LEN as u8;
+        assert_eq!(checksum::checksum_with(&pseudo_header, &out), 0);
+    }
+
+    #[test]
+    fn parse_rejects_short_and_malformed_headers() {
+        assert_eq!(Segment::parse(&[0; 19]), Err(TcpError::Truncated));
+
+        let mut header = [0; HEADER_LEN];
+        header[12] = 4 << 4;
+        assert_eq!(Segment::parse(&header), Err(TcpError::Malformed));
+        header[12] = 6 << 4;
+        assert_eq!(Segment::parse(&header), Err(TcpError::Truncated));
+    }
+
+    #[test]
+    fn resets_follow_rfc_9293() {
+        let reset = syn(80, 1000).reset().unwrap();
+        assert_eq!((reset.source_port, reset.destination_port), (80, 40000));
+        assert_eq!((reset.seq, reset.ack), (0, 1001));
+        assert_eq!(reset.flags, flag::RST | flag::ACK);
+
+        let data = Segment {
+            ack: 7000,
+            flags: flag::ACK,
+            data_len: 10,
+            ..syn(80, 1000)
+        };
+        let reset = data.reset().unwrap();
+        assert_eq!((reset.seq, reset.flags), (7000, flag::RST));
+
+        assert_eq!(reset.reset(), None);
+    }
+
+    #[test]
+    fn full_backlog_refuses_and_retransmitted_syns_keep_their_place() {
+        let mut listener = Listener::<2>::new(80, 4, 100);
+
+        assert_eq!(listener.syn(peer(1), 10, 0), Ok(()));
+        assert_eq!(listener.syn(peer(2), 20, 0), Ok(()));
+        assert_eq!(listener.syn(peer(1), 10, 5), Ok(()));
+        assert_eq!(listener.syn(peer(3), 30, 5), Err(Refusal::BacklogFull));
+        assert_eq!(listener.stats().refused, 1);
+
+        assert_eq!(listener.accept().map(|pending| pending.peer), Some(peer(1)));
+        assert_eq!(listener.syn(peer(3), 30, 6), Ok(()));
+    }
+
+    #[test]
+    fn accepts_up_to_the_connection_cap() {
+        let mut listener = Listener::<4>::new(80, 2, 100);
+        for port in 1..=3 {
+            listener.syn(peer(port), 0, 0).unwrap();
+        }
+
+        assert!(listener.accept().is_some());
+        assert!(listener.accept().is_some());
+        assert_eq!(listener.accept(), None);
+        assert_eq!(listener.queued(), 1);
+
+        listener.close();
+        assert_eq!(listener.accept().map(|pending| pending.peer), Some(peer(3)));
+        assert_eq!(listener.connections(), 2);
+        assert_eq!(listener.stats().accepted, 3);
+    }
+
+    #[test]
+    fn connections_waiting_too_long_expire_across_the_tick_wrap() {
+        let start = u32::MAX - 10;
+        let mut listener = Listener::<4>::new(80, 0, 20);
+        listener.syn(peer(1), 0, start).unwrap();
+        listener.syn(peer(2), 0, start.wrapping_add(5)).unwrap();
+
+        assert_eq!(listener.expire(start.wrapping_add(19)), None);
+        assert_eq!(
+            listener
+                .expire(start.wrapping_add(20))
+                .map(|pending| pending.peer),
+            Some(peer(1))
+        );
+        assert_eq!(listener.expire(start.wrapping_add(20)), None);
+        assert_eq!(listener.stats().expired, 1);
+    }
+
+    #[test]
+    fn syns_to_other_ports_are_refused() {
+        let mut listeners = Listeners::<2, 2>::new();
+        listeners.listen(Listener::new(80, 1, 100)).unwrap();
+        assert_eq!(
+            listeners.listen(Listener::new(80, 1, 100)),
+            Err(TcpError::AlreadyListening)
+        );
+
+        assert_eq!(listeners.syn(peer(1), &syn(80, 0), 0), Ok(()));
+        assert_eq!(
+            listeners.syn(peer(1), &syn(23, 0), 0),
+            Err(Refusal::NotListening)
+        );
+        listeners.get_mut(80).unwrap().abort(peer(1));
+        assert_eq!(listeners.get(80).unwrap().queued(), 0);
+    }
+}
@@ -0,0 +1,88 @@
+//! Bounded, panic-free helpers for the text protocols: HTTP headers, FTP commands, SIP
+//! bodies, DNS names and configuration values.
+//!
+//! The parsers run on whatever arrived from the network, which may be truncated, not UTF-8 or
+//! crafted to trip them up, so these work on bytes, never index out of bounds and never
+//! overflow. Numbers are refused past the number of digits of their limit, so a long run of
+//! zeros can't keep a parser busy.
+
+/// Whether `a` and `b` are equal, ignoring ASCII case.
+pub fn eq_ignore_case(a: &[u8], b: &[u8]) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+/// `text` without `prefix`, compared ignoring ASCII case.
+pub fn strip_prefix_ignore_case<'a>(text: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+    let (head, rest) = text.split_at_checked(prefix.len())?;
+    eq_ignore_case(head, prefix).then_some(rest)
+}
+
+/// `text` without `suffix`, compared ignoring ASCII case.
+pub fn strip_suffix_ignore_case<'a>(text: &'a [u8], suffix: &[u8]) -> Option<&'a [u8]> {
+    let (rest, tail) = text.split_at_checked(text.len().checked_sub(suffix.len())?)?;
+    eq_ignore_case(tail, suffix).then_some(rest)
+}
+
+/// Offset of the first occurrence of `needle` in `haystack`.
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `text` split around the first `separator`, which is in neither part.
+pub fn split_once(text: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = text.iter().position(|&byte| byte == separator)?;
+    Some((&text[..index], &text[index + 1..]))
+}
+
+/// Lines of `text`, ending with LF or CRLF, without their line ending.
+pub fn lines(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    text.split(|&byte| byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+/// Value of the first `name: value` header line called `name`, compared ignoring ASCII case,
+/// with surrounding whitespace trimmed. Lines past the first empty one, the start of a body,
+/// aren't looked at.
+pub fn header<'a>(head: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    lines(head)
+        .take_while(|line| !line.trim_ascii().is_empty())
+        .find_map(|line| {
+            let (header, value) = split_once(line, b':')?;
+            eq_ignore_case(header.trim_ascii(), name).then_some(value.trim_ascii())
+        })
+}
+
+/// Unsigned integer [`parse_decimal`] can parse.
+pub trait Decimal: Copy + PartialOrd + TryFrom<u32> + Into<u32> {}
+
+impl Decimal for u8 {}
+impl Decimal for u16 {}
+impl Decimal for u32 {}
+
+/// Parses the decimal digits `digits`, which must be at most `max` and have no more digits
+/// than `max` has. Signs and whitespace aren't accepted.
+pub fn parse_decimal<T: Decimal>(digits: &[u8], max: T) -> Option<T> {
+    let mut max_digits = 1;
+    let mut rest = max.into() / 10;
+    while rest > 0 {
+        max_digits += 1;
+        rest /= 10;
+    }
+
+    if digits.is_empty() || digits.len() > max_digits {
+        return None;
+    }
+
+    let value = digits.iter().try_fold(0u32, |value, &digit| {
+        digit
+            .is_ascii_digit()
+            .then(|| value.checked_mul(10)?.checked_add((digit - b'0') as u32))?
+    })?;
+
+    T::try_from(value).ok().filter(|value| *value <= max)
+}
@@ -0,0 +1,401 @@
+//! Timers of the services: DHCP renewal, conntrack expiry, periodic housekeeping.
+//!
+//! [`Timers`] holds up to `N` timers, each carrying a token telling its owner what expired,
+//! e.g. an enum of the services. Starting one returns a [`TimerHandle`] to cancel or restart
+//! it. A handle names its slot and the slot's generation, which changes whenever the slot is
+//! freed, so a handle kept past its timer's end never touches the timer reusing the slot.
+//! One-shot timers free their slot as they fire, periodic ones once cancelled.
+//!
+//! A periodic timer's next deadline is its previous one plus the period, not the time it was
+//! polled plus the period, so a late main loop doesn't make it drift. Periods missed entirely
+//! while the loop stalled are skipped and counted rather than fired in a burst.
+//!
+//! Deadlines may lie up to half the tick range ahead.
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimerError {
+    #[error("Table ran out of memory for additional timers.")]
+    TimersOutOfMemory,
+}
+
+/// Refers to a started timer, see [`Timers::start`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimerHandle {
+    index: u16,
+    generation: u16,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimerStats {
+    pub fired: u32,
+    /// Periods of periodic timers skipped because they were polled too late.
+    pub skipped: u32,
+    pub cancelled: u32,
+    /// Starts refused with every slot taken.
+    pub refused: u32,
+    /// Most timers running at once.
+    pub high_water: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Timer<T> {
+    token: T,
+    deadline: u32,
+    /// 0 for one-shot timers.
+    period: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot<T> {
+    generation: u16,
+    timer: Option<Timer<T>>,
+}
+
+/// Up to `N` one-shot or periodic timers carrying a token `T`.
+pub struct Timers<T, const N: usize> {
+    slots: [Slot<T>; N],
+    stats: TimerStats,
+}
+
+impl<T: Copy, const N: usize> Default for Timers<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Timers<T, N> {
+    const VALID_SIZE: () = assert!(N <= u16::MAX as usize, "N doesn't fit a handle's index");
+
+    pub const fn new() -> Self {
+        let () = Self::VALID_SIZE;
+        Self {
+            slots: [const {
+                Slot {
+                    generation: 0,
+                    timer: None,
+                }
+            }; N],
+            stats: TimerStats {
+                fired: 0,
+                skipped: 0,
+                cancelled: 0,
+                refused: 0,
+                high_water: 0,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> TimerStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.timer.is_some())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Starts a timer firing once, `delay` ticks from `now`.
+    pub fn start(&mut self, token: T, delay: u32, now: u32) -> Result<TimerHandle, TimerError> {
+        self.insert(Timer {
+            token,
+            deadline: now.wrapping_add(delay),
+            period: 0,
+        })
+    }
+
+    /// Starts a timer firing every `period` ticks, at least 1, the first time one period
+    /// from `now`.
+    pub fn start_periodic(
+        &mut self,
+        token: T,
+        period: u32,
+        now: u32,
+    ) -> Result<TimerHandle, TimerError> {
+        let period = period.max(1);
+        self.insert(Timer {
+            token,
+            deadline: now.wrapping_add(period),
+            period,
+        })
+    }
+
+    /// Stops the timer, returning its token if it was still running.
+    pub fn cancel(&mut self, handle: TimerHandle) -> Option<T> {
+        let timer = self.slot_mut(handle)?.timer.take()?;
+        self.free(handle.index as usize);
+        self.stats.cancelled += 1;
+        Some(timer.token)
+    }
+
+    /// Moves the timer's next deadline to `delay` ticks from `now`, a periodic timer then
+    /// keeping its period from there. Returns false if the timer already ended.
+    pub fn restart(&mut self, handle: TimerHandle, delay: u32, now: u32) -> bool {
+        let Some(timer) = self.slot_mut(handle).and_then(|slot| slot.timer.as_mut()) else {
+            return false;
+        };
+
+        timer.deadline = now.wrapping_add(delay);
+        true
+    }
+
+    /// Whether the timer is still running.
+    pub fn is_running(&self, handle: TimerHandle) -> bool {
+        self.slots
+            .get(handle.index as usize)
+            .is_some_and(|slot| slot.generation == handle.generation && slot.timer.is_some())
+    }
+
+    /// Ticks from `now` to the next deadline, 0 if one is due, `None` without timers. The
+    /// main loop can sleep that long.
+    pub fn next_in(&self, now: u32) -> Option<u32> {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.timer.as_ref())
+            .map(|timer| (timer.deadline.wrapping_sub(now) as i32).max(0) as u32)
+            .min()
+    }
+
+    /// Token of a timer due at `now`, the most overdue first, if any. Call it until it returns
+    /// `None`.
+    pub fn poll(&mut self, now: u32) -> Option<T> {
+        let (index, _) = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let late = now.wrapping_sub(slot.timer.as_ref()?.deadline) as i32;
+                (late >= 0).then_some((index, late))
+            })
+            .max_by_key(|(_, late)| *late)?;
+
+        self.stats.fired += 1;
+        let slot = &mut self.slots[index];
+        let timer = slot.timer.as_mut()?;
+        let token = timer.token;
+        if timer.period == 0 {
+            slot.timer = None;
+            self.free(index);
+            return Some(token);
+        }
+
+        timer.deadline = timer.deadline.wrapping_add(timer.period);
+        let late = now.wrapping_sub(timer.deadline) as i32;
+        if late >= 0 {
+            let missed = late as u32 / timer.period + 1;
+            timer.deadline = timer
+                .deadline
+                .wrapping_add(missed.wrapping_mul(timer.period));
+            self.stats.skipped += missed;
+        }
+
+        Some(token)
+    }
+
+    fn insert(&mut self, timer: Timer<T>) -> Result<TimerHandle, TimerError> {
+        let Some((index, slot)) = self
+            .slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.timer.is_none())
+        else {
+            self.stats.refused += 1;
+            return Err(TimerError::TimersOutOfMemory);
+        };
+
+        slot.timer = Some(timer);
+        let handle = TimerHandle {
+            index: index as u16,
+            generation: slot.generation,
+        };
+        self.stats.high_water = self.stats.high_water.max(self.len());
+        Ok(handle)
+    }
+
+    /// Retires the handles of the slot at `index`, once its timer is gone.
+    fn free(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        slot.generation = slot.generation.wrapping_add(1);
+    }
+
+    fn slot_mut(&mut self, handle: TimerHandle) -> Option<&mut Slot<T>> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Token {
+        A,
+        B,
+    }
+
+    fn drain(timers: &mut Timers<Token, 4>, clock: &MockClock) -> Vec<Token> {
+        core::iter::from_fn(|| timers.poll(clock.now())).collect()
+    }
+
+    #[test]
+    fn one_shot_fires_once() {
+        let clock = MockClock::new(1000);
+        let mut timers = Timers::<Token, 4>::new();
+        let handle = timers.start(Token::A, 50, clock.now()).unwrap();
+
+        clock.advance(49);
+        assert_eq!(timers.poll(clock.now()), None);
+        assert_eq!(timers.next_in(clock.now()), Some(1));
+        clock.advance(1);
+        assert_eq!(drain(&mut timers, &clock), [Token::A]);
+        assert!(!timers.is_running(handle));
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn stale_handle_leaves_the_slot_reuser_alone() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 1>::new();
+        let stale = timers.start(Token::A, 10, clock.now()).unwrap();
+        clock.advance(10);
+        assert_eq!(timers.poll(clock.now()), Some(Token::A));
+
+        // Same slot, next generation.
+        let fresh = timers.start(Token::B, 10, clock.now()).unwrap();
+        assert_ne!(stale, fresh);
+        assert!(!timers.is_running(stale));
+        assert_eq!(timers.cancel(stale), None);
+        assert!(!timers.restart(stale, 1000, clock.now()));
+
+        assert!(timers.is_running(fresh));
+        clock.advance(10);
+        assert_eq!(timers.poll(clock.now()), Some(Token::B));
+        assert_eq!(timers.stats().cancelled, 0);
+    }
+
+    #[test]
+    fn cancel_retires_the_handle() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 1>::new();
+        let cancelled = timers.start(Token::A, 10, clock.now()).unwrap();
+        assert_eq!(timers.cancel(cancelled), Some(Token::A));
+        assert_eq!(timers.cancel(cancelled), None);
+
+        let fresh = timers.start(Token::B, 10, clock.now()).unwrap();
+        assert_eq!(timers.cancel(cancelled), None);
+        assert!(!timers.restart(cancelled, 0, clock.now()));
+        assert!(timers.is_running(fresh));
+        assert_eq!(timers.stats().cancelled, 1);
+    }
+
+    #[test]
+    fn restart_moves_the_deadline() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 4>::new();
+        let handle = timers.start(Token::A, 10, clock.now()).unwrap();
+        clock.advance(8);
+        assert!(timers.restart(handle, 10, clock.now()));
+
+        clock.advance(9);
+        assert_eq!(timers.poll(clock.now()), None);
+        clock.advance(1);
+        assert_eq!(timers.poll(clock.now()), Some(Token::A));
+    }
+
+    #[test]
+    fn periodic_keeps_its_schedule_when_polled_late() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 4>::new();
+        timers.start_periodic(Token::A, 100, clock.now()).unwrap();
+
+        // Polled 30 ticks late, the next deadline is still at 200, not 230.
+        clock.set(130);
+        assert_eq!(drain(&mut timers, &clock), [Token::A]);
+        assert_eq!(timers.next_in(clock.now()), Some(70));
+        clock.set(199);
+        assert_eq!(timers.poll(clock.now()), None);
+        clock.set(200);
+        assert_eq!(drain(&mut timers, &clock), [Token::A]);
+
+        let stats = timers.stats();
+        assert_eq!((stats.fired, stats.skipped), (2, 0));
+    }
+
+    #[test]
+    fn periodic_skips_the_periods_missed_in_a_stall() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 4>::new();
+        timers.start_periodic(Token::A, 100, clock.now()).unwrap();
+
+        // Due at 100, then 200, 300 and 400 go by: one fires, three are skipped.
+        clock.set(450);
+        assert_eq!(drain(&mut timers, &clock), [Token::A]);
+        let stats = timers.stats();
+        assert_eq!((stats.fired, stats.skipped), (1, 3));
+
+        // Back on schedule.
+        assert_eq!(timers.next_in(clock.now()), Some(50));
+        clock.set(500);
+        assert_eq!(drain(&mut timers, &clock), [Token::A]);
+        assert_eq!(timers.stats().skipped, 3);
+    }
+
+    #[test]
+    fn deadlines_wrap_with_the_clock() {
+        let clock = MockClock::new(u32::MAX - 5);
+        let mut timers = Timers::<Token, 4>::new();
+        timers.start(Token::A, 10, clock.now()).unwrap();
+        timers.start_periodic(Token::B, 4, clock.now()).unwrap();
+
+        clock.advance(4);
+        assert_eq!(clock.now(), u32::MAX - 1);
+        assert_eq!(drain(&mut timers, &clock), [Token::B]);
+        assert_eq!(timers.next_in(clock.now()), Some(4));
+
+        // Past the wrap, A is due at 4 and B at 2.
+        clock.advance(6);
+        assert_eq!(clock.now(), 4);
+        assert_eq!(drain(&mut timers, &clock), [Token::B, Token::A]);
+        assert_eq!(timers.next_in(clock.now()), Some(2));
+    }
+
+    #[test]
+    fn most_overdue_fires_first() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 4>::new();
+        timers.start(Token::B, 20, clock.now()).unwrap();
+        timers.start(Token::A, 10, clock.now()).unwrap();
+
+        clock.advance(30);
+        assert_eq!(drain(&mut timers, &clock), [Token::A, Token::B]);
+    }
+
+    #[test]
+    fn full_table_refuses() {
+        let clock = MockClock::new(0);
+        let mut timers = Timers::<Token, 1>::new();
+        timers.start(Token::A, 10, clock.now()).unwrap();
+        assert_eq!(
+            timers.start(Token::B, 10, clock.now()),
+            Err(TimerError::TimersOutOfMemory)
+        );
+        assert_eq!(timers.stats().refused, 1);
+    }
+}
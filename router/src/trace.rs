@@ -0,0 +1,184 @@
+//! Tracing of a single flow through the packet pipeline.
+//!
+//! Once a flow is marked with [`start`], every stage (RX, filter, NAT, route, TX) records its
+//! decision for the flow's packets in either direction. Events are logged over defmt as they
+//! happen and the latest ones kept for the CLI, which is how a port forward that doesn't work
+//! gets debugged.
+//!
+//! While nothing is traced [`record`] costs a single atomic load, so stages call it for every
+//! packet.
+
+use core::{
+    cell::RefCell,
+    fmt,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cortex_m::interrupt::Mutex;
+
+use crate::{conntrack::FlowKey, firewall::Action, interface::InterfaceId};
+
+/// Events kept for [`events`], older ones are dropped.
+pub const HISTORY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Stage {
+    Rx,
+    Filter,
+    Nat,
+    Route,
+    Tx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Accepted,
+    Dropped,
+    /// Rule at this index of the active rule set matched.
+    Rule {
+        index: usize,
+        action: Action,
+    },
+    /// No rule matched.
+    DefaultAction(Action),
+    /// Rewritten to this key.
+    Translated(FlowKey),
+    Routed {
+        interface: InterfaceId,
+        gateway: Option<Ipv4Addr>,
+    },
+    NoRoute,
+}
+
+/// A stage's decision on a packet of the traced flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// Key of the packet as the stage saw it.
+    pub key: FlowKey,
+    pub stage: Stage,
+    pub decision: Decision,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self.stage {
+            Stage::Rx => "rx",
+            Stage::Filter => "filter",
+            Stage::Nat => "nat",
+            Stage::Route => "route",
+            Stage::Tx => "tx",
+        };
+        write!(f, "{stage} {}: ", Key(&self.key))?;
+
+        match self.decision {
+            Decision::Accepted => f.write_str("accepted"),
+            Decision::Dropped => f.write_str("dropped"),
+            Decision::Rule { index, action } => write!(f, "rule {index}, {}", name(action)),
+            Decision::DefaultAction(action) => write!(f, "no rule, {}", name(action)),
+            Decision::Translated(key) => write!(f, "translated to {}", Key(&key)),
+            Decision::Routed {
+                interface,
+                gateway: Some(gateway),
+            } => write!(f, "via {gateway} on interface {interface}"),
+            Decision::Routed {
+                interface,
+                gateway: None,
+            } => write!(f, "direct on interface {interface}"),
+            Decision::NoRoute => f.write_str("no route"),
+        }
+    }
+}
+
+struct Key<'a>(&'a FlowKey);
+
+impl fmt::Display for Key<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let protocol = self.0.protocol.name();
+        write!(f, "{protocol} {} > {}", self.0.source, self.0.destination)
+    }
+}
+
+fn name(action: Action) -> &'static str {
+    match action {
+        Action::Accept => "accept",
+        Action::Drop => "drop",
+    }
+}
+
+struct Tracer {
+    key: Option<FlowKey>,
+    /// What NAT translated the flow to, so later stages still match.
+    translated: Option<FlowKey>,
+    history: heapless::HistoryBuffer<Event, HISTORY_LEN>,
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static TRACER: Mutex<RefCell<Tracer>> = Mutex::new(RefCell::new(Tracer {
+    key: None,
+    translated: None,
+    history: heapless::HistoryBuffer::new(),
+}));
+
+/// Traces `key`, in both directions, instead of any previously traced flow.
+pub fn start(key: FlowKey) {
+    cortex_m::interrupt::free(|cs| {
+        let mut tracer = TRACER.borrow(cs).borrow_mut();
+        tracer.key = Some(key);
+        tracer.translated = None;
+        tracer.history.clear();
+    });
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Stops tracing, the recorded events are kept.
+pub fn stop() {
+    ACTIVE.store(false, Ordering::Relaxed);
+    cortex_m::interrupt::free(|cs| TRACER.borrow(cs).borrow_mut().key = None);
+}
+
+/// The flow being traced.
+pub fn traced() -> Option<FlowKey> {
+    cortex_m::interrupt::free(|cs| TRACER.borrow(cs).borrow().key)
+}
+
+/// Records the decision of `stage` on a packet with `key`, if its flow is traced.
+pub fn record(key: &FlowKey, stage: Stage, decision: Decision) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    cortex_m::interrupt::free(|cs| {
+        let mut tracer = TRACER.borrow(cs).borrow_mut();
+        let matches = |traced: FlowKey| traced == *key || traced == key.reversed();
+        if !tracer.key.is_some_and(matches) && !tracer.translated.is_some_and(matches) {
+            return;
+        }
+        if let Decision::Translated(translated) = decision {
+            tracer.translated = Some(translated);
+        }
+
+        let event = Event {
+            key: *key,
+            stage,
+            decision,
+        };
+        #[cfg(feature = "defmt")]
+        defmt::info!("trace: {}", defmt::Display2Format(&event));
+        tracer.history.write(event);
+    });
+}
+
+/// Latest events, oldest first.
+pub fn events() -> heapless::Vec<Event, HISTORY_LEN> {
+    cortex_m::interrupt::free(|cs| {
+        TRACER
+            .borrow(cs)
+            .borrow()
+            .history
+            .oldest_ordered()
+            .copied()
+            .collect()
+    })
+}
@@ -0,0 +1,266 @@
+//! Transmit queues between the stack and the drivers.
+//!
+//! The ENC28J60 holds a single frame in flight, so bursts from the forwarding plane wait in a
+//! bounded queue per interface. Frames carry a priority band, e.g. from
+//! [`CosMap::classify`](crate::ethernet::CosMap::classify), and the highest queued band goes
+//! out first; callers that don't care put everything in band 0 and get a plain FIFO.
+//!
+//! A full queue drops the new frame, unless a frame of a lower band is queued, in which case
+//! the newest of those is dropped instead. Every drop is counted.
+//!
+//! Strict priority lets a busy high band starve the others. A [`TxPacer`] hands frames to the
+//! driver instead, taking turns between bands by weight, one frame at a time as the chip
+//! reports each transmission done, and optionally keeping a minimum gap between frames to
+//! shape the interface's rate.
+
+use crate::clock;
+
+/// Highest band a [`TxPacer`] tells apart, there are as many as a
+/// [`CosMap`](crate::ethernet::CosMap) has queues.
+pub const MAX_BAND: u8 = 7;
+
+/// Counters of the queue's lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxQueueStats {
+    pub enqueued: u32,
+    pub dequeued: u32,
+    /// New frames dropped because the queue was full.
+    pub tail_drops: u32,
+    /// Queued frames dropped to make room for a frame of a higher band.
+    pub displaced: u32,
+    /// Most frames queued at once.
+    pub high_water: usize,
+}
+
+/// Transmit queue of up to `D` frames of type `T`.
+pub struct TxQueue<T, const D: usize> {
+    /// Frames with their band, in arrival order.
+    frames: heapless::Vec<(u8, T), D>,
+    depth: usize,
+    stats: TxQueueStats,
+}
+
+impl<T, const D: usize> Default for TxQueue<T, D> {
+    fn default() -> Self {
+        Self::new(D)
+    }
+}
+
+impl<T, const D: usize> TxQueue<T, D> {
+    /// Queue holding at most `depth` frames, clamped to `D`.
+    pub fn new(depth: usize) -> Self {
+        Self {
+            frames: heapless::Vec::new(),
+            depth: depth.min(D),
+            stats: TxQueueStats::default(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Changes the depth, clamped to `D`. Frames over a reduced depth stay queued.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth.min(D);
+    }
+
+    pub fn stats(&self) -> TxQueueStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Queues `frame` in `band`, returning the frame dropped to respect the depth, if any, so
+    /// its buffer can be reclaimed.
+    pub fn enqueue(&mut self, frame: T, band: u8) -> Option<T> {
+        let mut dropped = None;
+        if self.frames.len() >= self.depth {
+            let lowest = self
+                .frames
+                .iter()
+                .enumerate()
+                .rev()
+                .min_by_key(|(_, (queued, _))| *queued)
+                .filter(|(_, (queued, _))| *queued < band)
+                .map(|(i, _)| i);
+
+            let Some(lowest) = lowest else {
+                self.stats.tail_drops += 1;
+                return Some(frame);
+            };
+
+            self.stats.displaced += 1;
+            dropped = Some(self.frames.remove(lowest).1);
+        }
+
+        if let Err((_, frame)) = self.frames.push((band, frame)) {
+            // Only with a depth of 0.
+            self.stats.tail_drops += 1;
+            return Some(frame);
+        }
+
+        self.stats.enqueued += 1;
+        self.stats.high_water = self.stats.high_water.max(self.frames.len());
+        dropped
+    }
+
+    /// Takes the oldest frame of the highest band, to be handed to the driver once it's free.
+    pub fn dequeue(&mut self) -> Option<T> {
+        let (index, _) = self
+            .frames
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, (band, _))| *band)?;
+
+        self.stats.dequeued += 1;
+        Some(self.frames.remove(index).1)
+    }
+
+    /// Takes the oldest frame of `band`.
+    pub fn dequeue_band(&mut self, band: u8) -> Option<T> {
+        let index = self.frames.iter().position(|(queued, _)| *queued == band)?;
+
+        self.stats.dequeued += 1;
+        Some(self.frames.remove(index).1)
+    }
+
+    /// Bit set of the bands with queued frames, bands over 7 counting as 7.
+    pub fn queued_bands(&self) -> u8 {
+        self.frames
+            .iter()
+            .fold(0, |bands, (band, _)| bands | 1 << (*band).min(MAX_BAND))
+    }
+
+    /// Drops every queued frame, e.g. when the link goes down.
+    pub fn flush(&mut self) -> impl Iterator<Item = T> + use<T, D> {
+        core::mem::take(&mut self.frames)
+            .into_iter()
+            .map(|(_, frame)| frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacerConfig {
+    /// Frames each band sends in a row when others are waiting, indexed by band.
+    pub weights: [u8; MAX_BAND as usize + 1],
+    /// Ticks from the start of a frame to the start of the next one, 0 to send them back to
+    /// back.
+    pub gap: u32,
+}
+
+impl Default for PacerConfig {
+    fn default() -> Self {
+        Self {
+            weights: [1, 1, 2, 2, 4, 4, 8, 8],
+            gap: 0,
+        }
+    }
+}
+
+/// Counters of the pacer's lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacerStats {
+    /// Frames handed to the driver, indexed by band.
+    pub sent: [u32; MAX_BAND as usize + 1],
+    /// Times a frame was held back to keep the gap.
+    pub held: u32,
+}
+
+/// Hands frames from a [`TxQueue`] to the driver one at a time, by weighted turns between
+/// bands.
+pub struct TxPacer {
+    config: PacerConfig,
+    stats: PacerStats,
+    /// A frame was handed to the driver and its transmission isn't done yet.
+    busy: bool,
+    last_start: Option<u32>,
+    /// Band whose turn it is and the frames it has left in it.
+    band: u8,
+    credit: u8,
+}
+
+impl TxPacer {
+    pub fn new(config: PacerConfig) -> Self {
+        Self {
+            config,
+            stats: PacerStats::default(),
+            busy: false,
+            last_start: None,
+            band: 0,
+            credit: 0,
+        }
+    }
+
+    pub fn config(&self) -> &PacerConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: PacerConfig) {
+        self.config = config;
+    }
+
+    pub fn stats(&self) -> PacerStats {
+        self.stats
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+
+    /// Records the transmission of the last frame as done, call it from the driver's
+    /// transmit interrupt rather than polling for it.
+    pub fn tx_done(&mut self) {
+        self.busy = false;
+    }
+
+    /// When the gap after the last frame ends, for the caller to wake up then.
+    pub fn next_at(&self) -> Option<u32> {
+        let last_start = self.last_start.filter(|_| self.config.gap > 0)?;
+        Some(last_start.wrapping_add(self.config.gap))
+    }
+
+    /// Next frame to hand to the driver, if the driver is free, the gap is over and a frame
+    /// is queued.
+    pub fn next<T, const D: usize>(&mut self, queue: &mut TxQueue<T, D>, now: u32) -> Option<T> {
+        let queued = queue.queued_bands();
+        if self.busy || queued == 0 {
+            return None;
+        }
+        if let Some(at) = self.next_at()
+            && !clock::is_due(at, now)
+        {
+            self.stats.held += 1;
+            return None;
+        }
+
+        if self.credit == 0 || queued & 1 << self.band == 0 {
+            // Next lower band with frames, wrapping around to the highest.
+            self.band = (1..=MAX_BAND + 1)
+                .map(|step| (self.band + MAX_BAND + 1 - step) % (MAX_BAND + 1))
+                .find(|band| queued & 1 << band != 0)?;
+            self.credit = self.config.weights[self.band as usize].max(1);
+        }
+
+        // Frames of bands over the highest are taken by the highest's turns.
+        let frame = match self.band {
+            MAX_BAND => queue.dequeue(),
+            band => queue.dequeue_band(band),
+        }?;
+        self.credit -= 1;
+        self.stats.sent[self.band as usize] += 1;
+        self.busy = true;
+        self.last_start = Some(now);
+        Some(frame)
+    }
+}
@@ -0,0 +1,309 @@
+//! Failover between two WAN uplinks.
+//!
+//! Each uplink is health checked by the caller (gateway ARP/ping probes, DHCP lease state, ...)
+//! and reports the outcome through [`Failover::report`]. An uplink is declared down after
+//! `down_after` consecutive failures and up again after `up_after` consecutive successes. When
+//! the active uplink goes down traffic moves to the other one, the default route of the main
+//! table is rewritten and tracked flows are handled according to the [`SwitchoverPolicy`].
+//!
+//! With both uplinks up, [`LoadBalancer`] can spread new flows over them instead.
+
+use core::{
+    hash::{Hash, Hasher},
+    net::Ipv4Addr,
+};
+
+use crate::{
+    cidr::Ipv4Cidr,
+    conntrack::{Conntrack, FlowKey},
+    interface::InterfaceId,
+    routing::{MAIN_TABLE, Route, RoutingError, RoutingTable},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Uplink {
+    Primary,
+    Secondary,
+}
+
+impl Uplink {
+    pub fn other(self) -> Self {
+        match self {
+            Uplink::Primary => Uplink::Secondary,
+            Uplink::Secondary => Uplink::Primary,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What happens to tracked flows when traffic moves to another uplink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchoverPolicy {
+    /// Drops the flows bound to an uplink that is down, their NAT bindings use an address that
+    /// is no longer reachable. Flows on an uplink left while still up, like on preemption,
+    /// keep using it.
+    Flush,
+    /// Keeps every flow, for uplinks sharing the public address or flows pinned to their uplink.
+    Preserve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailoverConfig {
+    /// Consecutive failed probes before an uplink is declared down.
+    pub down_after: u8,
+    /// Consecutive successful probes before an uplink is declared up again.
+    pub up_after: u8,
+    /// Moves back to the primary uplink as soon as it recovers.
+    pub preempt: bool,
+    pub policy: SwitchoverPolicy,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            down_after: 3,
+            up_after: 5,
+            preempt: true,
+            policy: SwitchoverPolicy::Flush,
+        }
+    }
+}
+
+/// Static description of an uplink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WanLink {
+    pub gateway: Ipv4Addr,
+    pub interface: InterfaceId,
+    pub metric: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Health {
+    up: bool,
+    /// Consecutive probes disagreeing with `up`.
+    streak: u8,
+}
+
+/// Traffic moved from `from` to `to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Switchover {
+    pub from: Uplink,
+    pub to: Uplink,
+}
+
+pub struct Failover {
+    links: [WanLink; 2],
+    health: [Health; 2],
+    active: Uplink,
+    config: FailoverConfig,
+}
+
+impl Failover {
+    /// Both uplinks start healthy with traffic on the primary one.
+    pub fn new(primary: WanLink, secondary: WanLink, config: FailoverConfig) -> Self {
+        let health = Health {
+            up: true,
+            streak: 0,
+        };
+
+        Self {
+            links: [primary, secondary],
+            health: [health; 2],
+            active: Uplink::Primary,
+            config,
+        }
+    }
+
+    pub fn active(&self) -> Uplink {
+        self.active
+    }
+
+    pub fn link(&self, uplink: Uplink) -> &WanLink {
+        &self.links[uplink.index()]
+    }
+
+    pub fn is_up(&self, uplink: Uplink) -> bool {
+        self.health[uplink.index()].up
+    }
+
+    pub fn config(&self) -> &FailoverConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: FailoverConfig) {
+        self.config = config;
+    }
+
+    /// Records the outcome of a health probe on `uplink`.
+    ///
+    /// Returns the switchover to apply with [`Failover::apply`] if the active uplink changed.
+    pub fn report(&mut self, uplink: Uplink, success: bool) -> Option<Switchover> {
+        let health = &mut self.health[uplink.index()];
+        if success == health.up {
+            health.streak = 0;
+        } else {
+            health.streak = health.streak.saturating_add(1);
+            let threshold = if health.up {
+                self.config.down_after
+            } else {
+                self.config.up_after
+            };
+
+            if health.streak >= threshold {
+                health.up = success;
+                health.streak = 0;
+            }
+        }
+
+        let wanted = self.preferred();
+        if wanted == self.active {
+            return None;
+        }
+
+        let switchover = Switchover {
+            from: self.active,
+            to: wanted,
+        };
+        self.active = wanted;
+        Some(switchover)
+    }
+
+    /// Installs the default route of the active uplink and applies the switchover policy to
+    /// `conntrack`, `uplink_of` telling which uplink a flow's NAT binding uses.
+    pub fn apply<T, const N: usize, const R: usize, const F: usize>(
+        &self,
+        switchover: Switchover,
+        routes: &mut RoutingTable<N, R>,
+        conntrack: &mut Conntrack<T, F>,
+        uplink_of: impl Fn(&T) -> Uplink,
+    ) -> Result<(), RoutingError> {
+        self.install_default_route(routes)?;
+
+        if self.config.policy == SwitchoverPolicy::Flush {
+            let from_up = self.is_up(switchover.from);
+            conntrack.retain(|flow| from_up || uplink_of(&flow.data) != switchover.from);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the default route of the main table with the active uplink's.
+    pub fn install_default_route<const N: usize, const R: usize>(
+        &self,
+        routes: &mut RoutingTable<N, R>,
+    ) -> Result<(), RoutingError> {
+        routes.remove_routes(|route| {
+            route.table == MAIN_TABLE && route.destination.prefix_len() == 0
+        });
+
+        let link = self.link(self.active);
+        routes.add_route(Route {
+            table: MAIN_TABLE,
+            destination: Ipv4Cidr::ANY,
+            gateway: Some(link.gateway),
+            interface: link.interface,
+            metric: link.metric,
+        })
+    }
+
+    fn preferred(&self) -> Uplink {
+        let primary_up = self.is_up(Uplink::Primary);
+        let secondary_up = self.is_up(Uplink::Secondary);
+
+        match self.active {
+            // Stays put when both are down, nothing better to go to.
+            Uplink::Primary if !primary_up && secondary_up => Uplink::Secondary,
+            Uplink::Secondary if primary_up && (self.config.preempt || !secondary_up) => {
+                Uplink::Primary
+            }
+            active => active,
+        }
+    }
+}
+
+/// Per-flow balancing of new flows over both uplinks.
+///
+/// The uplink is picked from a hash of the flow key so a flow always maps to the same uplink
+/// while weights and health don't change. The choice must still be stored with the flow's NAT
+/// binding and reused for its lifetime, see [`LoadBalancer::uplink_for`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadBalancer {
+    /// Relative share of new flows for the primary and secondary uplink.
+    pub weights: [u8; 2],
+}
+
+impl Default for LoadBalancer {
+    fn default() -> Self {
+        Self { weights: [1, 1] }
+    }
+}
+
+impl LoadBalancer {
+    /// Uplink for a new flow, only considering uplinks `failover` sees as up.
+    ///
+    /// Falls back to the active uplink when at most one uplink is usable.
+    pub fn select(&self, key: &FlowKey, failover: &Failover) -> Uplink {
+        let weight = |uplink: Uplink| {
+            if failover.is_up(uplink) {
+                self.weights[uplink.index()] as u32
+            } else {
+                0
+            }
+        };
+
+        let primary = weight(Uplink::Primary);
+        let total = primary + weight(Uplink::Secondary);
+        if primary == 0 || primary == total {
+            return failover.active();
+        }
+
+        let mut hasher = Fnv1a::default();
+        key.hash(&mut hasher);
+        if (hasher.finish() % total as u64) < primary as u64 {
+            Uplink::Primary
+        } else {
+            Uplink::Secondary
+        }
+    }
+
+    /// Uplink of the tracked flow `key`, or a newly selected one for untracked flows.
+    pub fn uplink_for<T, const N: usize>(
+        &self,
+        key: &FlowKey,
+        failover: &Failover,
+        conntrack: &Conntrack<T, N>,
+        uplink_of: impl Fn(&T) -> Uplink,
+    ) -> Uplink {
+        conntrack
+            .get(key)
+            .map(|flow| uplink_of(&flow.data))
+            .unwrap_or_else(|| self.select(key, failover))
+    }
+}
+
+/// 64 bit FNV-1a, cheap and good enough to spread flows.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
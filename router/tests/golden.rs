@@ -0,0 +1,224 @@
+//! Golden traces of the SPI transactions the ENC28J60 driver emits for scripted scenarios.
+//!
+//! A mock chip answers the driver's reads with canned register values and receive buffer
+//! bytes. Every transaction, with the bytes written and those read back, is recorded one per
+//! line and compared with the scenario's file in `tests/golden`, so a change to the SPI
+//! sequences shows up as a diff. After an intended change, run the tests with `UPDATE_GOLDEN=1`
+//! to record the files again, and review their diff.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    fs,
+    path::PathBuf,
+};
+
+use embedded_hal::spi::Operation;
+use router::enc28j60::{Enc28j60, OperationKind, Register, Transaction};
+
+type Driver = Enc28j60<40, 32, 128>;
+
+const RCR: u8 = 0b000_00000;
+const RBM: u8 = 0b001_11010;
+const BFS: u8 = 0b100_00000;
+const BFC: u8 = 0b101_00000;
+const SRC: u8 = 0b111_11111;
+const ECON1_BSEL: u8 = 0b0000_0011;
+
+/// Registers at addresses from this one on are in every bank.
+const COMMON_ADDRESS: u8 = 0x1B;
+
+/// Chip answering reads with canned values, tracking the bank selected in ECON1.
+#[derive(Default)]
+struct Chip {
+    bank: u8,
+    /// Register values by bank, `None` for those in every bank, and address.
+    registers: HashMap<(Option<u8>, u8), u8>,
+    /// Buffer memory from ERDPT on, handed out by RBM reads in order.
+    buffer: VecDeque<u8>,
+}
+
+impl Chip {
+    fn with(mut self, register: Register, value: u8) -> Self {
+        let bank = register.bank().map(|bank| bank as u8);
+        self.registers.insert((bank, register.address()), value);
+        self
+    }
+
+    fn with_buffer(mut self, bytes: &[u8]) -> Self {
+        self.buffer.extend(bytes);
+        self
+    }
+
+    fn register(&self, address: u8) -> u8 {
+        let bank = (address < COMMON_ADDRESS).then_some(self.bank);
+        self.registers
+            .get(&(bank, address))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Runs `transaction` as the chip would, filling its reads.
+    fn run<const N: usize, const B: usize>(&mut self, transaction: &mut Transaction<N, B>) {
+        let mut opcode = None;
+        for operation in transaction.spi_operations() {
+            match operation {
+                Operation::Write(bytes) => {
+                    opcode = bytes.first().copied();
+                    match bytes {
+                        [SRC] => self.bank = 0,
+                        [command, bits] if *command == BFS | 0x1F => {
+                            self.bank |= bits & ECON1_BSEL;
+                        }
+                        [command, bits] if *command == BFC | 0x1F => {
+                            self.bank &= !(bits & ECON1_BSEL);
+                        }
+                        _ => {}
+                    }
+                }
+                Operation::Read(bytes) => match opcode {
+                    Some(RBM) => bytes.fill_with(|| self.buffer.pop_front().unwrap_or_default()),
+                    Some(opcode) if opcode >> 5 == RCR >> 5 => {
+                        // MAC and MII registers clock out a dummy byte first.
+                        bytes.fill(0);
+                        if let Some(last) = bytes.last_mut() {
+                            *last = self.register(opcode & 0x1F);
+                        }
+                    }
+                    _ => panic!("read after {opcode:02x?}"),
+                },
+                _ => unreachable!("the driver only queues reads and writes"),
+            }
+        }
+    }
+}
+
+/// Runs every transaction the driver hands out against `chip`, writing each to `trace` when
+/// given.
+fn drain(driver: &mut Driver, chip: &mut Chip, mut trace: Option<&mut String>) {
+    while let Some(mut transaction) = driver.poll_pending_transaction() {
+        chip.run(&mut transaction);
+        if let Some(trace) = trace.as_deref_mut() {
+            record(&transaction, trace);
+        }
+        driver.handle_transaction(transaction).unwrap();
+    }
+}
+
+fn record<const N: usize, const B: usize>(transaction: &Transaction<N, B>, trace: &mut String) {
+    for (index, (kind, bytes)) in transaction.iter().enumerate() {
+        if index > 0 {
+            trace.push_str(" | ");
+        }
+        trace.push_str(match kind {
+            OperationKind::Write => "w",
+            OperationKind::Read => "r",
+        });
+        for byte in bytes {
+            write!(trace, " {byte:02x}").unwrap();
+        }
+    }
+    trace.push('\n');
+}
+
+/// Driver past init, and the chip it ran against.
+fn initialized(chip: Chip) -> (Driver, Chip) {
+    let mut chip = chip.with(Register::ESTAT, 0x01);
+    let mut driver = Driver::with_erx_length(0x1f0u16.try_into().unwrap());
+    driver.init().unwrap();
+    drain(&mut driver, &mut chip, None);
+    (driver, chip)
+}
+
+/// Compares `trace` with the golden file of `scenario`, or records it with `UPDATE_GOLDEN` set.
+fn check(scenario: &str, trace: &str) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", scenario]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("trace");
+    let header = format!(
+        "# SPI transactions of the {scenario} scenario, one per line: `w` bytes written, `r` bytes read back.\n"
+    );
+    let trace = header + trace;
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, trace).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!(
+            "{}: {error}, record it with UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    for (line, (expected, actual)) in golden.lines().zip(trace.lines()).enumerate() {
+        assert_eq!(
+            actual,
+            expected,
+            "{}:{} differs, record it again with UPDATE_GOLDEN=1 if intended",
+            path.display(),
+            line + 1
+        );
+    }
+    assert_eq!(
+        trace.lines().count(),
+        golden.lines().count(),
+        "{} has a different number of transactions",
+        path.display()
+    );
+}
+
+fn frame() -> Vec<u8> {
+    let mut frame = vec![0xFF; 6];
+    frame.extend_from_slice(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    frame.extend_from_slice(&[0x08, 0x06]);
+    frame.extend((0..46).map(|byte| byte as u8));
+    frame
+}
+
+#[test]
+fn init() {
+    let mut chip = Chip::default().with(Register::ESTAT, 0x01);
+    let mut driver = Driver::with_erx_length(0x1f0u16.try_into().unwrap());
+    let mut trace = String::new();
+
+    driver.init().unwrap();
+    drain(&mut driver, &mut chip, Some(&mut trace));
+
+    check("init", &trace);
+}
+
+#[test]
+fn transmit_one_frame() {
+    let (mut driver, mut chip) = initialized(Chip::default());
+    let mut trace = String::new();
+
+    driver.transmit(&frame()).unwrap();
+    drain(&mut driver, &mut chip, Some(&mut trace));
+
+    check("transmit", &trace);
+}
+
+#[test]
+fn receive_one_frame() {
+    let frame = frame();
+    // Next packet at 0x0042, 60 bytes and the CRC, received OK.
+    let mut buffer = vec![0x42, 0x00, 64, 0x00, 0x80, 0x00];
+    buffer.extend_from_slice(&frame);
+    let (mut driver, mut chip) = initialized(
+        Chip::default()
+            .with(Register::EPKTCNT, 1)
+            .with_buffer(&buffer),
+    );
+    let mut trace = String::new();
+
+    driver.receive().unwrap();
+    drain(&mut driver, &mut chip, Some(&mut trace));
+
+    let mut received = [0; 64];
+    let (header, len) = driver.take_received(&mut received).unwrap();
+    assert_eq!(header.next_packet, 0x0042);
+    assert_eq!(&received[..len], frame.as_slice());
+    check("receive", &trace);
+}
@@ -0,0 +1,136 @@
+//! Interleavings of an interrupt handler and the main loop over the state they share, run with
+//! `cargo test-loom`.
+//!
+//! Each side is a thread, loom runs them through every interleaving its model allows, which
+//! covers the handler preempting the main loop anywhere. The handler runs to completion on the
+//! target, but modelling it as a thread only adds interleavings.
+
+#![cfg(loom)]
+
+use loom::{cell::UnsafeCell, sync::Arc, thread};
+use router::{
+    events::{Event, EventBus, EventBusError, Subscriber},
+    sync::{InterruptFlag, Shared},
+};
+
+/// State the handler writes before raising the flag, like a latched status register.
+struct Latched(UnsafeCell<u8>);
+
+// SAFETY: the tests only read it after taking the flag raised after the write, which is what
+// they check.
+unsafe impl Sync for Latched {}
+
+#[test]
+fn handler_writes_are_seen_once_the_flag_is_taken() {
+    loom::model(|| {
+        let flag = Arc::new(InterruptFlag::new());
+        let latched = Arc::new(Latched(UnsafeCell::new(0)));
+
+        let handler = {
+            let (flag, latched) = (flag.clone(), latched.clone());
+            thread::spawn(move || {
+                // SAFETY: the main loop doesn't read it before the flag is raised.
+                latched.0.with_mut(|value| unsafe { *value = 0x40 });
+                flag.raise();
+            })
+        };
+
+        if flag.take() {
+            // SAFETY: loom reports a data race if raising and taking don't order the write
+            // before this read.
+            assert_eq!(latched.0.with(|value| unsafe { *value }), 0x40);
+        }
+        handler.join().unwrap();
+    });
+}
+
+#[test]
+fn flag_raised_while_the_main_loop_looks_is_not_lost() {
+    loom::model(|| {
+        let flag = Arc::new(InterruptFlag::new());
+
+        let handler = {
+            let flag = flag.clone();
+            thread::spawn(move || flag.raise())
+        };
+
+        // The main loop checks before sleeping and again on waking.
+        let before_sleep = flag.take();
+        handler.join().unwrap();
+        let after_wake = flag.is_raised() && flag.take();
+        assert!(before_sleep != after_wake);
+    });
+}
+
+fn drain<const N: usize>(
+    bus: &Shared<EventBus<N, 1>>,
+    subscriber: Subscriber,
+    seen: &mut Vec<Event>,
+    missed: &mut u32,
+) {
+    loop {
+        match bus.lock(|bus| bus.poll(subscriber)) {
+            Ok(Some(event)) => seen.push(event),
+            Ok(None) => return,
+            Err(EventBusError::Lagged(count)) => *missed += count,
+            Err(error) => panic!("{error}"),
+        }
+    }
+}
+
+#[test]
+fn events_published_by_the_handler_are_seen_in_order_with_the_flag() {
+    loom::model(|| {
+        let bus = Arc::new(Shared::new(EventBus::<4, 1>::new()));
+        let subscriber = bus.lock(|bus| bus.subscribe()).unwrap();
+        let flag = Arc::new(InterruptFlag::new());
+
+        let handler = {
+            let (bus, flag) = (bus.clone(), flag.clone());
+            thread::spawn(move || {
+                bus.lock(|bus| bus.publish(Event::LinkDown));
+                bus.lock(|bus| bus.publish(Event::LinkUp));
+                flag.raise();
+            })
+        };
+
+        let (mut seen, mut missed) = (Vec::new(), 0);
+        if flag.take() {
+            drain(&bus, subscriber, &mut seen, &mut missed);
+            assert_eq!(seen, [Event::LinkDown, Event::LinkUp]);
+        }
+        handler.join().unwrap();
+
+        drain(&bus, subscriber, &mut seen, &mut missed);
+        assert_eq!(seen, [Event::LinkDown, Event::LinkUp]);
+        assert_eq!(missed, 0);
+    });
+}
+
+#[test]
+fn events_overrun_while_polling_are_counted_as_missed() {
+    const EVENTS: [Event; 3] = [Event::LinkDown, Event::LinkUp, Event::WanDown];
+
+    loom::model(|| {
+        let bus = Arc::new(Shared::new(EventBus::<2, 1>::new()));
+        let subscriber = bus.lock(|bus| bus.subscribe()).unwrap();
+
+        let handler = {
+            let bus = bus.clone();
+            thread::spawn(move || {
+                for event in EVENTS {
+                    bus.lock(|bus| bus.publish(event));
+                }
+            })
+        };
+
+        let (mut seen, mut missed) = (Vec::new(), 0);
+        drain(&bus, subscriber, &mut seen, &mut missed);
+        handler.join().unwrap();
+        drain(&bus, subscriber, &mut seen, &mut missed);
+
+        // Whatever was read is the tail of what was published, in order.
+        assert_eq!(seen.len() as u32 + missed, EVENTS.len() as u32);
+        assert_eq!(seen, EVENTS[missed as usize..]);
+    });
+}
@@ -0,0 +1,721 @@
+//! End to end scenarios of a router and a client on a simulated link.
+//!
+//! Both nodes are assembled from the router's building blocks and exchange real Ethernet
+//! frames over a point-to-point link that takes a few ticks to cross. A [`MockClock`] drives
+//! the whole simulation: each step moves it one tick, delivers the frames due by then and runs
+//! the timers of both nodes, so lease renewals, ARP aging and timeouts happen in simulated time
+//! and the same way on every run.
+
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use router::{
+    arp::{ArpCache, ArpPacket, Enqueued, Operation},
+    bootp::{self, Header, MessageKind, ReplyDestination},
+    checksum,
+    cidr::Ipv4Cidr,
+    clock::{self, Clock, MockClock},
+    dhcp::{self, OptionsBuilder, code},
+    ethernet::{MacAddress, ethertype},
+    lease::Leases,
+};
+
+/// Ticks of a millisecond clock.
+const TICKS_PER_SECOND: u32 = 1000;
+/// Ticks for a frame to cross the link.
+const LATENCY: u32 = 2;
+
+const ROUTER_MAC: MacAddress = MacAddress([0x02, 0, 0, 0, 0, 0x01]);
+const CLIENT_MAC: MacAddress = MacAddress([0x02, 0, 0, 0, 0, 0x02]);
+const LAN: Ipv4Cidr = Ipv4Cidr::new(Ipv4Addr::new(192, 168, 1, 1), 24).unwrap();
+const POOL_START: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 100);
+const POOL_END: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 101);
+
+const LEASE_SECONDS: u32 = 60;
+const LEASE_TICKS: u32 = LEASE_SECONDS * TICKS_PER_SECOND;
+const ARP_MAX_AGE: u32 = 20 * TICKS_PER_SECOND;
+const ARP_RESOLVE_TIMEOUT: u32 = TICKS_PER_SECOND;
+/// Ticks before the client sends an unanswered DHCP message again.
+const DHCP_RETRANSMIT: u32 = 4 * TICKS_PER_SECOND;
+
+/// Port the router's own datagrams are sent from and to.
+const ECHO_PORT: u16 = 7;
+
+mod message_type {
+    pub const DISCOVER: u8 = 1;
+    pub const OFFER: u8 = 2;
+    pub const REQUEST: u8 = 3;
+    pub const ACK: u8 = 5;
+}
+
+fn ethernet(
+    destination: MacAddress,
+    source: MacAddress,
+    ethertype: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    frame.extend_from_slice(&destination.0);
+    frame.extend_from_slice(&source.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Destination, source, EtherType and payload of `frame`.
+fn parse_ethernet(frame: &[u8]) -> Option<(MacAddress, MacAddress, u16, &[u8])> {
+    let header = frame.get(..14)?;
+    Some((
+        MacAddress(header[..6].try_into().unwrap()),
+        MacAddress(header[6..12].try_into().unwrap()),
+        u16::from_be_bytes([header[12], header[13]]),
+        &frame[14..],
+    ))
+}
+
+fn arp(packet: ArpPacket, destination: MacAddress) -> Vec<u8> {
+    let mut payload = [0; router::arp::PACKET_LEN];
+    packet.write(&mut payload).unwrap();
+    ethernet(destination, packet.sender_mac, ethertype::ARP, &payload)
+}
+
+fn arp_request(mac: MacAddress, address: Ipv4Addr, target: Ipv4Addr) -> Vec<u8> {
+    let request = ArpPacket {
+        operation: Operation::Request,
+        sender_mac: mac,
+        sender_ip: address,
+        target_mac: MacAddress([0; 6]),
+        target_ip: target,
+    };
+    arp(request, MacAddress::BROADCAST)
+}
+
+fn arp_reply(request: &ArpPacket, mac: MacAddress) -> Vec<u8> {
+    let reply = ArpPacket {
+        operation: Operation::Reply,
+        sender_mac: mac,
+        sender_ip: request.target_ip,
+        target_mac: request.sender_mac,
+        target_ip: request.sender_ip,
+    };
+    arp(reply, request.sender_mac)
+}
+
+/// IPv4 packet carrying a UDP datagram.
+fn udp(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + payload.len() as u16;
+    let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 17, 0, 0];
+    packet[2..4].copy_from_slice(&(20 + udp_len).to_be_bytes());
+    packet.extend_from_slice(&source.ip().octets());
+    packet.extend_from_slice(&destination.ip().octets());
+    let header_checksum = checksum::checksum(&packet);
+    packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+    let mut segment = Vec::with_capacity(udp_len as usize);
+    segment.extend_from_slice(&source.port().to_be_bytes());
+    segment.extend_from_slice(&destination.port().to_be_bytes());
+    segment.extend_from_slice(&udp_len.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(payload);
+
+    let mut pseudo_header = [0; 12];
+    pseudo_header[..8].copy_from_slice(&packet[12..20]);
+    pseudo_header[9] = 17;
+    pseudo_header[10..].copy_from_slice(&udp_len.to_be_bytes());
+    let segment_checksum = match checksum::checksum_with(&pseudo_header, &segment) {
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    segment[6..8].copy_from_slice(&segment_checksum.to_be_bytes());
+
+    packet.extend_from_slice(&segment);
+    packet
+}
+
+/// Source, destination and payload of a UDP over IPv4 packet with a valid header checksum.
+fn parse_udp(packet: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])> {
+    let header = packet.get(..20)?;
+    if header[0] != 0x45 || header[9] != 17 || checksum::checksum(header) != 0 {
+        return None;
+    }
+
+    let segment = &packet[20..];
+    let port = |at: usize| u16::from_be_bytes([segment[at], segment[at + 1]]);
+    let address =
+        |at: usize| Ipv4Addr::new(header[at], header[at + 1], header[at + 2], header[at + 3]);
+    let len = usize::from(port(4));
+    Some((
+        SocketAddrV4::new(address(12), port(0)),
+        SocketAddrV4::new(address(16), port(2)),
+        segment.get(8..len)?,
+    ))
+}
+
+fn address_option(message: &[u8], code: u8) -> Option<Ipv4Addr> {
+    let option = dhcp::find(bootp::options(message)?, code)?;
+    <[u8; 4]>::try_from(option.data).ok().map(Ipv4Addr::from)
+}
+
+fn u32_option(message: &[u8], code: u8) -> Option<u32> {
+    let option = dhcp::find(bootp::options(message)?, code)?;
+    <[u8; 4]>::try_from(option.data)
+        .ok()
+        .map(u32::from_be_bytes)
+}
+
+/// DHCP message with `header`, of type `kind` and with `options`.
+fn dhcp_message(
+    header: &Header,
+    kind: u8,
+    options: impl FnOnce(&mut OptionsBuilder<'_>) -> Result<(), dhcp::DhcpError>,
+) -> Vec<u8> {
+    let mut message = vec![0; 576];
+    let len = header.write(&mut message).unwrap();
+    let mut builder = OptionsBuilder::new(&mut message[len..]);
+    builder.u8(code::MESSAGE_TYPE, kind).unwrap();
+    options(&mut builder).unwrap();
+    let options_len = builder.finish().unwrap().len();
+    message.truncate(len + options_len);
+    message
+}
+
+/// A node on the link.
+trait Node {
+    /// Handles `frame`, received at `now`, sending any answer to `out`.
+    fn receive(&mut self, frame: &[u8], now: u32, out: &mut Vec<Vec<u8>>);
+
+    /// Runs the node's timers at `now`.
+    fn poll(&mut self, now: u32, out: &mut Vec<Vec<u8>>);
+}
+
+/// Two nodes on a point-to-point link, driven by a mock clock.
+struct Sim<A, B> {
+    clock: MockClock,
+    a: A,
+    b: B,
+    /// Whether the cable is plugged in, frames sent while it isn't are lost.
+    connected: bool,
+    /// Frames on the wire, with the tick they arrive at and whether they go to `b`.
+    in_flight: VecDeque<(u32, bool, Vec<u8>)>,
+}
+
+impl<A: Node, B: Node> Sim<A, B> {
+    fn new(a: A, b: B) -> Self {
+        Self {
+            // Every timer of the scenarios crosses the wrap of the tick counter.
+            clock: MockClock::new(u32::MAX - LEASE_TICKS / 4),
+            a,
+            b,
+            connected: true,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    fn now(&self) -> u32 {
+        self.clock.now()
+    }
+
+    fn send(&mut self, to_b: bool, frames: Vec<Vec<u8>>) {
+        if !self.connected {
+            return;
+        }
+
+        let arrival = self.clock.now().wrapping_add(LATENCY);
+        self.in_flight
+            .extend(frames.into_iter().map(|frame| (arrival, to_b, frame)));
+    }
+
+    /// Lets `a` act at the current tick, e.g. send something, its frames going out on the link.
+    fn act_a(&mut self, act: impl FnOnce(&mut A, u32, &mut Vec<Vec<u8>>)) {
+        let mut out = Vec::new();
+        act(&mut self.a, self.clock.now(), &mut out);
+        self.send(true, out);
+    }
+
+    /// Moves the clock one tick, delivers the frames arriving by then and polls both nodes.
+    fn step(&mut self) {
+        self.clock.advance(1);
+        let now = self.clock.now();
+
+        while let Some((arrival, ..)) = self.in_flight.front()
+            && clock::is_due(*arrival, now)
+        {
+            let (_, to_b, frame) = self.in_flight.pop_front().unwrap();
+            let mut out = Vec::new();
+            if to_b {
+                self.b.receive(&frame, now, &mut out);
+            } else {
+                self.a.receive(&frame, now, &mut out);
+            }
+            self.send(!to_b, out);
+        }
+
+        let mut out = Vec::new();
+        self.a.poll(now, &mut out);
+        self.send(true, out);
+        let mut out = Vec::new();
+        self.b.poll(now, &mut out);
+        self.send(false, out);
+    }
+
+    fn run_for(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    /// Steps until `done` holds, at most `limit` ticks, returning whether it did.
+    fn run_until(&mut self, limit: u32, done: impl Fn(&Self) -> bool) -> bool {
+        for _ in 0..limit {
+            if done(self) {
+                return true;
+            }
+            self.step();
+        }
+        done(self)
+    }
+}
+
+/// Router side: DHCP server, ARP and a UDP endpoint.
+struct RouterNode {
+    leases: Leases<4>,
+    arp: ArpCache<Vec<u8>, 4, 2>,
+    arp_requests: u32,
+}
+
+impl RouterNode {
+    const ADDRESS: Ipv4Addr = LAN.address();
+
+    fn new() -> Self {
+        Self {
+            leases: Leases::new(),
+            arp: ArpCache::new(ARP_MAX_AGE, ARP_RESOLVE_TIMEOUT),
+            arp_requests: 0,
+        }
+    }
+
+    /// Sends `payload` to the echo port of `destination`.
+    fn send_to(&mut self, destination: Ipv4Addr, payload: &[u8], now: u32, out: &mut Vec<Vec<u8>>) {
+        let packet = udp(
+            SocketAddrV4::new(Self::ADDRESS, ECHO_PORT),
+            SocketAddrV4::new(destination, ECHO_PORT),
+            payload,
+        );
+        self.send_ip(destination, packet, now, out);
+    }
+
+    /// Sends `packet` to `destination` on the LAN, resolving it first if needed.
+    fn send_ip(
+        &mut self,
+        destination: Ipv4Addr,
+        packet: Vec<u8>,
+        now: u32,
+        out: &mut Vec<Vec<u8>>,
+    ) {
+        match self.arp.enqueue(destination, packet, now) {
+            Enqueued::Resolved(mac, packet) => {
+                out.push(ethernet(mac, ROUTER_MAC, ethertype::IPV4, &packet));
+            }
+            Enqueued::NeedsRequest => {
+                self.arp_requests += 1;
+                out.push(arp_request(ROUTER_MAC, Self::ADDRESS, destination));
+            }
+            Enqueued::Pending | Enqueued::DroppedOldest(_) => {}
+        }
+    }
+
+    fn handle_arp(&mut self, packet: ArpPacket, now: u32, out: &mut Vec<Vec<u8>>) {
+        let for_us = packet.target_ip == Self::ADDRESS;
+        let waiting = self
+            .arp
+            .insert(packet.sender_ip, packet.sender_mac, for_us, now);
+        for packet_out in waiting {
+            out.push(ethernet(
+                packet.sender_mac,
+                ROUTER_MAC,
+                ethertype::IPV4,
+                &packet_out,
+            ));
+        }
+        if for_us && packet.operation == Operation::Request {
+            out.push(arp_reply(&packet, ROUTER_MAC));
+        }
+    }
+
+    /// The client's previous address, else the first free one of the pool.
+    fn free_address(&self, mac: MacAddress, now: u32) -> Option<Ipv4Addr> {
+        let previous = self.leases.by_mac(mac).map(|lease| lease.address);
+        previous.or_else(|| {
+            (POOL_START.to_bits()..=POOL_END.to_bits())
+                .map(Ipv4Addr::from_bits)
+                .find(|address| !self.leases.is_leased(*address, now))
+        })
+    }
+
+    fn handle_dhcp(&mut self, message: &[u8], now: u32, out: &mut Vec<Vec<u8>>) {
+        let Ok(request) = Header::parse(message) else {
+            return;
+        };
+        let (kind, address) = match MessageKind::of(message) {
+            MessageKind::Dhcp(message_type::DISCOVER) => {
+                (message_type::OFFER, self.free_address(request.chaddr, now))
+            }
+            MessageKind::Dhcp(message_type::REQUEST) => {
+                let requested =
+                    address_option(message, code::REQUESTED_ADDRESS).unwrap_or(request.ciaddr);
+                let leased = self
+                    .leases
+                    .insert(request.chaddr, requested, LEASE_TICKS, now)
+                    .is_ok();
+                (message_type::ACK, leased.then_some(requested))
+            }
+            _ => return,
+        };
+        let Some(address) = address else {
+            return;
+        };
+
+        let mut reply = request.reply();
+        reply.yiaddr = address;
+        let message = dhcp_message(&reply, kind, |options| {
+            options
+                .address(code::SERVER_IDENTIFIER, Self::ADDRESS)?
+                .u32(code::LEASE_TIME, LEASE_SECONDS)?
+                .address(code::SUBNET_MASK, LAN.netmask())?;
+            Ok(())
+        });
+
+        let source = SocketAddrV4::new(Self::ADDRESS, bootp::SERVER_PORT);
+        let to = |address| SocketAddrV4::new(address, bootp::CLIENT_PORT);
+        match request.reply_destination(&reply, false) {
+            ReplyDestination::Broadcast => {
+                let packet = udp(source, to(Ipv4Addr::BROADCAST), &message);
+                out.push(ethernet(
+                    MacAddress::BROADCAST,
+                    ROUTER_MAC,
+                    ethertype::IPV4,
+                    &packet,
+                ));
+            }
+            ReplyDestination::Unicast {
+                address,
+                mac: Some(mac),
+            } => {
+                let packet = udp(source, to(address), &message);
+                out.push(ethernet(mac, ROUTER_MAC, ethertype::IPV4, &packet));
+            }
+            ReplyDestination::Unicast { address, mac: None } => {
+                let packet = udp(source, to(address), &message);
+                self.send_ip(address, packet, now, out);
+            }
+            ReplyDestination::Relay(_) => {}
+        }
+    }
+}
+
+impl Node for RouterNode {
+    fn receive(&mut self, frame: &[u8], now: u32, out: &mut Vec<Vec<u8>>) {
+        let Some((_, _, ethertype, payload)) = parse_ethernet(frame) else {
+            return;
+        };
+
+        match ethertype {
+            ethertype::ARP => {
+                if let Some(packet) = ArpPacket::parse(payload) {
+                    self.handle_arp(packet, now, out);
+                }
+            }
+            ethertype::IPV4 => {
+                if let Some((_, destination, message)) = parse_udp(payload)
+                    && destination.port() == bootp::SERVER_PORT
+                {
+                    self.handle_dhcp(message, now, out);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(&mut self, now: u32, _out: &mut Vec<Vec<u8>>) {
+        self.arp.age(now);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DhcpState {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+}
+
+/// Lease the client is bound to.
+#[derive(Debug, Clone, Copy)]
+struct ClientLease {
+    address: Ipv4Addr,
+    server: (MacAddress, Ipv4Addr),
+    bound_at: u32,
+    duration: u32,
+}
+
+/// Client side: a DHCP client answering ARP and collecting the datagrams sent to it.
+struct ClientNode {
+    state: DhcpState,
+    xid: u32,
+    sent_at: u32,
+    lease: Option<ClientLease>,
+    renewals: u32,
+    received: Vec<Vec<u8>>,
+}
+
+impl ClientNode {
+    fn new() -> Self {
+        Self {
+            state: DhcpState::Init,
+            xid: 0x1234_0000,
+            sent_at: 0,
+            lease: None,
+            renewals: 0,
+            received: Vec::new(),
+        }
+    }
+
+    fn address(&self) -> Option<Ipv4Addr> {
+        self.lease.map(|lease| lease.address)
+    }
+
+    fn request_header(&self) -> Header {
+        Header {
+            op: bootp::Op::Request,
+            hops: 0,
+            xid: self.xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr: CLIENT_MAC,
+        }
+    }
+
+    fn broadcast(message: &[u8]) -> Vec<u8> {
+        let packet = udp(
+            SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, bootp::CLIENT_PORT),
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, bootp::SERVER_PORT),
+            message,
+        );
+        ethernet(MacAddress::BROADCAST, CLIENT_MAC, ethertype::IPV4, &packet)
+    }
+
+    fn discover(&mut self, now: u32, out: &mut Vec<Vec<u8>>) {
+        self.xid = self.xid.wrapping_add(1);
+        self.lease = None;
+        let message = dhcp_message(&self.request_header(), message_type::DISCOVER, |_| Ok(()));
+        out.push(Self::broadcast(&message));
+        self.state = DhcpState::Selecting;
+        self.sent_at = now;
+    }
+
+    /// Renews the lease with the server that granted it, unicast from the leased address.
+    fn renew(&mut self, lease: ClientLease, now: u32, out: &mut Vec<Vec<u8>>) {
+        let header = Header {
+            ciaddr: lease.address,
+            ..self.request_header()
+        };
+        let message = dhcp_message(&header, message_type::REQUEST, |_| Ok(()));
+        let (server_mac, server) = lease.server;
+        let packet = udp(
+            SocketAddrV4::new(lease.address, bootp::CLIENT_PORT),
+            SocketAddrV4::new(server, bootp::SERVER_PORT),
+            &message,
+        );
+        out.push(ethernet(server_mac, CLIENT_MAC, ethertype::IPV4, &packet));
+        self.state = DhcpState::Renewing;
+        self.sent_at = now;
+    }
+
+    fn handle_dhcp(
+        &mut self,
+        source_mac: MacAddress,
+        destination: Ipv4Addr,
+        message: &[u8],
+        now: u32,
+        out: &mut Vec<Vec<u8>>,
+    ) {
+        let Ok(reply) = Header::parse(message) else {
+            return;
+        };
+        if !reply.is_reply_for(CLIENT_MAC, self.xid, destination, self.address()) {
+            return;
+        }
+        let Some(server) = address_option(message, code::SERVER_IDENTIFIER) else {
+            return;
+        };
+
+        match (self.state, MessageKind::of(message)) {
+            (DhcpState::Selecting, MessageKind::Dhcp(message_type::OFFER)) => {
+                let message =
+                    dhcp_message(&self.request_header(), message_type::REQUEST, |options| {
+                        options
+                            .address(code::REQUESTED_ADDRESS, reply.yiaddr)?
+                            .address(code::SERVER_IDENTIFIER, server)?;
+                        Ok(())
+                    });
+                out.push(Self::broadcast(&message));
+                self.state = DhcpState::Requesting;
+                self.sent_at = now;
+            }
+            (DhcpState::Requesting | DhcpState::Renewing, MessageKind::Dhcp(message_type::ACK)) => {
+                if self.state == DhcpState::Renewing {
+                    self.renewals += 1;
+                }
+                let seconds = u32_option(message, code::LEASE_TIME).unwrap_or(LEASE_SECONDS);
+                self.lease = Some(ClientLease {
+                    address: reply.yiaddr,
+                    server: (source_mac, server),
+                    bound_at: now,
+                    duration: seconds * TICKS_PER_SECOND,
+                });
+                self.state = DhcpState::Bound;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Node for ClientNode {
+    fn receive(&mut self, frame: &[u8], now: u32, out: &mut Vec<Vec<u8>>) {
+        let Some((destination_mac, source_mac, ethertype, payload)) = parse_ethernet(frame) else {
+            return;
+        };
+        if destination_mac != CLIENT_MAC && !destination_mac.is_broadcast() {
+            return;
+        }
+
+        match ethertype {
+            ethertype::ARP => {
+                if let Some(packet) = ArpPacket::parse(payload)
+                    && packet.operation == Operation::Request
+                    && Some(packet.target_ip) == self.address()
+                {
+                    out.push(arp_reply(&packet, CLIENT_MAC));
+                }
+            }
+            ethertype::IPV4 => {
+                let Some((_, destination, message)) = parse_udp(payload) else {
+                    return;
+                };
+                match destination.port() {
+                    bootp::CLIENT_PORT => {
+                        self.handle_dhcp(source_mac, *destination.ip(), message, now, out);
+                    }
+                    ECHO_PORT if Some(*destination.ip()) == self.address() => {
+                        self.received.push(message.to_vec());
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(&mut self, now: u32, out: &mut Vec<Vec<u8>>) {
+        let since_sent = now.wrapping_sub(self.sent_at);
+        match (self.state, self.lease) {
+            (DhcpState::Init, _) => self.discover(now, out),
+            (DhcpState::Selecting | DhcpState::Requesting, _) if since_sent >= DHCP_RETRANSMIT => {
+                self.discover(now, out);
+            }
+            (DhcpState::Bound | DhcpState::Renewing, Some(lease)) => {
+                let since_bound = now.wrapping_sub(lease.bound_at);
+                if since_bound >= lease.duration {
+                    // Lost the lease, starting over.
+                    self.discover(now, out);
+                } else if since_bound >= lease.duration / 2
+                    && (self.state == DhcpState::Bound || since_sent >= DHCP_RETRANSMIT)
+                {
+                    self.renew(lease, now, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A router and a client bound to a lease.
+fn bound() -> Sim<RouterNode, ClientNode> {
+    let mut sim = Sim::new(RouterNode::new(), ClientNode::new());
+    assert!(sim.run_until(100, |sim| sim.b.state == DhcpState::Bound));
+    sim
+}
+
+#[test]
+fn client_gets_a_lease_and_renews_it() {
+    let mut sim = bound();
+    let address = sim.b.address().unwrap();
+    let bound_at = sim.b.lease.unwrap().bound_at;
+    assert_eq!(address, POOL_START);
+    assert!(sim.a.leases.is_leased(address, sim.now()));
+
+    // Renewed halfway through, the lease outlives its first term.
+    sim.run_for(LEASE_TICKS / 2 + 100);
+    assert_eq!(sim.b.renewals, 1);
+    assert_eq!(sim.b.state, DhcpState::Bound);
+    sim.run_for(LEASE_TICKS / 2);
+    assert!(sim.now().wrapping_sub(bound_at) > LEASE_TICKS);
+    assert!(sim.a.leases.is_leased(address, sim.now()));
+    assert_eq!(sim.b.address(), Some(address));
+}
+
+#[test]
+fn lease_expires_once_the_client_is_gone_and_comes_back_to_it() {
+    let mut sim = bound();
+    let address = sim.b.address().unwrap();
+
+    sim.connected = false;
+    sim.run_for(LEASE_TICKS + 1);
+    assert!(!sim.a.leases.is_leased(address, sim.now()));
+    assert_eq!(sim.b.address(), None);
+
+    sim.connected = true;
+    assert!(sim.run_until(2 * DHCP_RETRANSMIT, |sim| sim.b.state == DhcpState::Bound));
+    assert_eq!(sim.b.address(), Some(address));
+}
+
+#[test]
+fn router_resolves_the_client_and_forgets_it_after_max_age() {
+    let mut sim = bound();
+    let address = sim.b.address().unwrap();
+    assert_eq!(sim.a.arp.lookup(address), None);
+
+    sim.act_a(|router, now, out| router.send_to(address, b"first", now, out));
+    sim.run_for(4 * LATENCY);
+    assert_eq!(sim.b.received, [b"first".to_vec()]);
+    assert_eq!(sim.a.arp.lookup(address), Some(CLIENT_MAC));
+    assert_eq!(sim.a.arp_requests, 1);
+
+    sim.run_for(ARP_MAX_AGE);
+    assert_eq!(sim.a.arp.lookup(address), None);
+
+    sim.act_a(|router, now, out| router.send_to(address, b"second", now, out));
+    sim.run_for(4 * LATENCY);
+    assert_eq!(sim.b.received, [b"first".to_vec(), b"second".to_vec()]);
+    assert_eq!(sim.a.arp_requests, 2);
+}
+
+#[test]
+fn unanswered_resolution_drops_the_waiting_datagram() {
+    let mut sim = bound();
+    let address = sim.b.address().unwrap();
+
+    sim.connected = false;
+    sim.act_a(|router, now, out| router.send_to(address, b"lost", now, out));
+    sim.run_for(ARP_RESOLVE_TIMEOUT);
+
+    let stats = sim.a.arp.stats();
+    assert_eq!(stats.resolution_failures, 1);
+    assert_eq!(stats.dropped_packets, 1);
+    assert!(sim.b.received.is_empty());
+}
@@ -0,0 +1,203 @@
+//! Invariants of the ENC28J60 driver's transaction queue, driven through its public API.
+//!
+//! Reads are what the queue is checked against: each one is a transaction of its own, the RCR
+//! opcode followed by the read, which comes back out as it was queued.
+
+use std::collections::VecDeque;
+
+use embedded_hal::spi::Operation;
+use proptest::prelude::*;
+use router::enc28j60::{Enc28j60, OperationKind, PhyRegister, QueueUsage, Register, Transaction};
+
+/// Small enough for the queue to fill up often, large enough for init.
+type Driver = Enc28j60<40, 32, 128>;
+
+/// Registers of every bank, including MAC ones whose reads take a dummy byte.
+const REGISTERS: [Register; 12] = [
+    Register::ERDPTL,
+    Register::EDMACSH,
+    Register::EHT3,
+    Register::EPKTCNT,
+    Register::MACON1,
+    Register::MIRDL,
+    Register::MAADR1,
+    Register::MISTAT,
+    Register::EREVID,
+    Register::EIE,
+    Register::ESTAT,
+    Register::ECON1,
+];
+
+#[derive(Debug, Clone)]
+enum Step {
+    Read(Register),
+    /// Frame of that many bytes.
+    Transmit(usize),
+    WritePhy(u16),
+    Poll,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        4 => (0..REGISTERS.len()).prop_map(|index| Step::Read(REGISTERS[index])),
+        1 => (14usize..300).prop_map(Step::Transmit),
+        1 => any::<u16>().prop_map(Step::WritePhy),
+        3 => Just(Step::Poll),
+    ]
+}
+
+/// Driver past its oscillator wait, so polls hand out what was queued.
+fn ready_driver() -> Driver {
+    let mut driver = Driver::with_erx_length(0x1f0u16.try_into().unwrap());
+    let mut estat = driver.poll_pending_transaction().unwrap();
+    for operation in estat.spi_operations() {
+        if let Operation::Read(bytes) = operation {
+            // CLKRDY.
+            bytes.fill(0x01);
+        }
+    }
+    driver.handle_transaction(estat).unwrap();
+    assert_eq!(driver.queue_usage(), QueueUsage::default());
+    driver
+}
+
+/// The register read `transaction` is made of, its address and read length, if it's one.
+fn read_of<const N: usize, const B: usize>(transaction: &Transaction<N, B>) -> Option<(u8, usize)> {
+    let mut operations = transaction.iter();
+    match (operations.next(), operations.next(), operations.next()) {
+        (Some((OperationKind::Write, &[opcode])), Some((OperationKind::Read, read)), None)
+            if opcode >> 5 == 0 =>
+        {
+            Some((opcode & 0x1F, read.len()))
+        }
+        _ => None,
+    }
+}
+
+fn expected_read(register: Register) -> (u8, usize) {
+    let len = if register.is_mac() { 2 } else { 1 };
+    (register.address(), len)
+}
+
+/// Room `transaction` took in the queue.
+fn usage_of<const N: usize, const B: usize>(transaction: &Transaction<N, B>) -> QueueUsage {
+    QueueUsage {
+        operations: transaction.iter().count(),
+        transactions: 1,
+        bytes: transaction.iter().map(|(_, payload)| payload.len()).sum(),
+    }
+}
+
+fn minus(usage: QueueUsage, taken: QueueUsage) -> QueueUsage {
+    QueueUsage {
+        operations: usage.operations - taken.operations,
+        transactions: usage.transactions - taken.transactions,
+        bytes: usage.bytes - taken.bytes,
+    }
+}
+
+/// Polls one transaction, checking it took exactly its own room in the queue, and checks it
+/// against the next expected read if it's a read.
+fn poll(driver: &mut Driver, reads: &mut VecDeque<(u8, usize)>) -> Option<()> {
+    let before = driver.queue_usage();
+    let transaction = driver.poll_pending_transaction()?;
+    assert_eq!(driver.queue_usage(), minus(before, usage_of(&transaction)));
+    assert!(driver.queue_usage().operations >= driver.queue_usage().transactions);
+
+    if let Some(read) = read_of(&transaction) {
+        assert_eq!(Some(read), reads.pop_front());
+    }
+    Some(())
+}
+
+/// Queues with `queue`, checking that a failure leaves the queue as it was.
+fn queue<E>(driver: &mut Driver, queue: impl FnOnce(&mut Driver) -> Result<(), E>) -> bool {
+    let before = driver.queue_usage();
+    let queued = queue(driver).is_ok();
+    let after = driver.queue_usage();
+    if !queued {
+        assert_eq!(after, before);
+    }
+    assert!(after.operations <= 40 && after.transactions <= 32 && after.bytes <= 128);
+    queued
+}
+
+proptest! {
+    #[test]
+    fn reads_come_out_in_the_order_queued(
+        indices in prop::collection::vec(0..REGISTERS.len(), 0..64)
+    ) {
+        let mut driver = ready_driver();
+        let mut reads = VecDeque::new();
+        for index in indices {
+            let register = REGISTERS[index];
+            if queue(&mut driver, |driver| driver.read_register(register)) {
+                reads.push_back(expected_read(register));
+            }
+        }
+
+        while poll(&mut driver, &mut reads).is_some() {}
+        prop_assert!(reads.is_empty());
+        prop_assert_eq!(driver.queue_usage(), QueueUsage::default());
+    }
+
+    #[test]
+    fn interleaved_queueing_and_polling_keeps_the_queue_consistent(
+        steps in prop::collection::vec(step(), 0..128)
+    ) {
+        let mut driver = ready_driver();
+        let mut reads = VecDeque::new();
+        for step in steps {
+            match step {
+                Step::Read(register) => {
+                    if queue(&mut driver, |driver| driver.read_register(register)) {
+                        reads.push_back(expected_read(register));
+                    }
+                }
+                Step::Transmit(len) => {
+                    let frame = vec![0xA5; len];
+                    queue(&mut driver, |driver| driver.transmit(&frame));
+                }
+                Step::WritePhy(value) => {
+                    // Followed by a MISTAT read, see Enc28j60::write_phy.
+                    if queue(&mut driver, |driver| driver.write_phy(PhyRegister::PHLCON, value)) {
+                        reads.push_back(expected_read(Register::MISTAT));
+                    }
+                }
+                Step::Poll => {
+                    poll(&mut driver, &mut reads);
+                }
+            }
+        }
+
+        while poll(&mut driver, &mut reads).is_some() {}
+        prop_assert!(reads.is_empty());
+        prop_assert_eq!(driver.queue_usage(), QueueUsage::default());
+    }
+
+    #[test]
+    fn a_full_queue_takes_nothing_until_drained(
+        register in 0..REGISTERS.len(),
+        len in 14usize..200,
+    ) {
+        let mut driver = ready_driver();
+        let register = REGISTERS[register];
+        let mut reads = VecDeque::new();
+        while queue(&mut driver, |driver| driver.read_register(register)) {
+            reads.push_back(expected_read(register));
+        }
+
+        // Whatever is queued next fails whole.
+        let frame = vec![0; len];
+        prop_assert!(!queue(&mut driver, |driver| driver.transmit(&frame)));
+        prop_assert!(!queue(&mut driver, |driver| driver.read_register(Register::EIE)));
+        prop_assert!(!queue(&mut driver, |driver| driver.write_phy(PhyRegister::PHLCON, 0)));
+
+        // Once a transaction is out, the next read fits again, and comes out last.
+        poll(&mut driver, &mut reads).unwrap();
+        prop_assert!(queue(&mut driver, |driver| driver.read_register(Register::EIE)));
+        reads.push_back(expected_read(Register::EIE));
+        while poll(&mut driver, &mut reads).is_some() {}
+        prop_assert!(reads.is_empty());
+    }
+}